@@ -16,9 +16,12 @@ type TotalLiquidity = {
   token_sym: string;
   contract_addr: string;
   number_of_transfers: number;
-  total_usd: number
+  // USD amounts are decimal strings to the cent, such as "1234.56"
+  total_usd: string
 }[];
 
+const usd = (amount: string) => Number(amount) || 0;
+
 export const StatGrid = ({ onlyMobile }: { onlyMobile?: boolean; }) => {
   const [inwardLiquidity, setInwardLiquidity] = useState<TotalLiquidity>([]);
 
@@ -32,11 +35,12 @@ export const StatGrid = ({ onlyMobile }: { onlyMobile?: boolean; }) => {
 
   const pieChartData: { title: string, value: number, color: string; symbol: string; }[] = [];
   for (let liquidity of inwardLiquidity) {
-    if (liquidity.total_usd < 1000) continue;
+    const value = usd(liquidity.total_usd);
+    if (value < 1000) continue;
 
     let d = {
       title: liquidity.token_name,
-      value: liquidity.total_usd,
+      value,
       color: '',
       symbol: liquidity.token_sym
     }
@@ -57,7 +61,7 @@ export const StatGrid = ({ onlyMobile }: { onlyMobile?: boolean; }) => {
     style: 'currency',
     currency: 'USD',
     maximumFractionDigits: 0
-  }).format(inwardLiquidity.reduce((acc, x) => x.total_usd + acc, 0));
+  }).format(inwardLiquidity.reduce((acc, x) => usd(x.total_usd) + acc, 0));
   const totalTransfers = inwardLiquidity.reduce((acc, x) => x.number_of_transfers + acc, 0);
 
   const cards = <>
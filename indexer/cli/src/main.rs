@@ -0,0 +1,220 @@
+use std::{env, fs::File, io, process::ExitCode, time::Duration};
+
+use reqwest::blocking::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+const DEFAULT_URL: &str = "https://mrl-indexer.projk.net";
+const TIMEOUT: Duration = Duration::from_secs(60);
+// Exports stream the whole table, so they get far longer than the other calls
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+const USAGE: &str = "\
+Usage: indexer-cli <command> [options]
+
+Commands:
+  status                              Show indexing lag, the last run and row counts
+  migrate                             Create missing tables and columns, rewrite the registry
+  reset --yes                         Delete every indexed transfer so indexing starts over
+  reindex --from-block N              Delete transfers from block N so they're indexed again
+  backfill [--from-block N] [--to-block N]
+                                      Decode the payloads of stored transfers again
+  export [--format csv|ndjson] [--token T] [--to-chain ID] [--from TS] [--to TS] [--output FILE]
+                                      Download transfers, to stdout unless --output is given
+
+Environment:
+  MRL_INDEXER_URL    The indexer to talk to (default https://mrl-indexer.projk.net)
+  MRL_ADMIN_TOKEN    The ADMIN_TOKEN secret, for migrate, reset, reindex and backfill
+  MRL_API_KEY        A partner API key, for export
+";
+
+/// Connection details, read from the environment so that tokens never end up in shell history.
+struct Config {
+    url: String,
+    admin_token: Option<String>,
+    api_key: Option<String>,
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Self {
+            url: env::var("MRL_INDEXER_URL")
+                .unwrap_or(DEFAULT_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            admin_token: env::var("MRL_ADMIN_TOKEN").ok(),
+            api_key: env::var("MRL_API_KEY").ok(),
+        }
+    }
+
+    fn admin(&self, client: &Client, path: &str) -> Result<RequestBuilder, String> {
+        let Some(token) = &self.admin_token else {
+            return Err("MRL_ADMIN_TOKEN must be set for admin commands".to_string())
+        };
+        Ok(client
+            .post(format!("{}{path}", self.url))
+            .bearer_auth(token))
+    }
+}
+
+/// `--name value` pairs following the command.
+struct Options(Vec<(String, String)>);
+
+impl Options {
+    fn parse(args: &[String], flags: &[&str]) -> Result<Self, String> {
+        let mut options = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                return Err(format!("Unexpected argument {arg}"))
+            };
+            if flags.contains(&name) {
+                options.push((name.to_string(), String::new()));
+                continue;
+            }
+            let Some(value) = args.next() else {
+                return Err(format!("--{name} needs a value"))
+            };
+            options.push((name.to_string(), value.clone()));
+        }
+        Ok(Self(options))
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn block(&self, name: &str) -> Result<Option<u64>, String> {
+        self.get(name)
+            .map(|b| {
+                b.parse::<u64>()
+                    .map_err(|_| format!("--{name} must be a block number"))
+            })
+            .transpose()
+    }
+
+    fn only(&self, allowed: &[&str]) -> Result<(), String> {
+        match self.0.iter().find(|(n, _)| !allowed.contains(&n.as_str())) {
+            Some((name, _)) => Err(format!("Unexpected option --{name}")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Sends the request and returns the response body, or the indexer's error message.
+fn send(request: RequestBuilder) -> Result<String, String> {
+    let response = request.send().map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.text().map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("The indexer responded {status}: {body}"));
+    }
+    Ok(body)
+}
+
+fn print_json(body: &str) -> Result<(), String> {
+    let value: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    let pretty = serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?;
+    println!("{pretty}");
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Err(USAGE.to_string())
+    };
+    let config = Config::from_env();
+    let client = Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    match command.as_str() {
+        "status" => {
+            Options::parse(rest, &[])?.only(&[])?;
+            print_json(&send(client.get(format!("{}/status", config.url)))?)
+        }
+        "migrate" => {
+            Options::parse(rest, &[])?.only(&[])?;
+            print_json(&send(config.admin(&client, "/admin/migrate")?)?)
+        }
+        "reset" => {
+            let options = Options::parse(rest, &["yes"])?;
+            options.only(&["yes"])?;
+            if options.get("yes").is_none() {
+                return Err("reset deletes every indexed transfer, pass --yes to go ahead".into());
+            }
+            print_json(&send(config.admin(&client, "/admin/reset")?)?)
+        }
+        "reindex" => {
+            let options = Options::parse(rest, &[])?;
+            options.only(&["from-block"])?;
+            let Some(from_block) = options.block("from-block")? else {
+                return Err("reindex needs --from-block".to_string())
+            };
+            let body = json!({ "from_block": from_block });
+            print_json(&send(config.admin(&client, "/admin/reindex")?.json(&body))?)
+        }
+        "backfill" => {
+            let options = Options::parse(rest, &[])?;
+            options.only(&["from-block", "to-block"])?;
+            let body = json!({
+                "from_block": options.block("from-block")?,
+                "to_block": options.block("to-block")?,
+            });
+            print_json(&send(
+                config.admin(&client, "/admin/backfill")?.json(&body),
+            )?)
+        }
+        "export" => {
+            let options = Options::parse(rest, &[])?;
+            let filters = ["format", "token", "to_chain", "from", "to"];
+            options.only(&["format", "token", "to-chain", "from", "to", "output"])?;
+            let Some(api_key) = &config.api_key else {
+                return Err("MRL_API_KEY must be set to a partner key for export".to_string())
+            };
+            let query: Vec<(&str, &str)> = filters
+                .iter()
+                .filter_map(|f| options.get(&f.replace('_', "-")).map(|v| (*f, v)))
+                .collect();
+            let mut response = client
+                .get(format!("{}/transfers/export", config.url))
+                .query(&query)
+                .header("X-API-Key", api_key)
+                .timeout(EXPORT_TIMEOUT)
+                .send()
+                .map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().unwrap_or_default();
+                return Err(format!("The indexer responded {status}: {body}"));
+            }
+            let written = match options.get("output") {
+                Some(path) => {
+                    let mut file = File::create(path).map_err(|e| e.to_string())?;
+                    response.copy_to(&mut file)
+                }
+                None => response.copy_to(&mut io::stdout().lock()),
+            };
+            written.map(|_| ()).map_err(|e| e.to_string())
+        }
+        "help" | "--help" | "-h" => {
+            print!("{USAGE}");
+            Ok(())
+        }
+        _ => Err(format!("Unknown command {command}\n\n{USAGE}")),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
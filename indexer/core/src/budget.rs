@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+// Never shrink below a useful amount of work, or grow past what the APIs allow
+const MIN_BUDGET: WorkBudget = WorkBudget {
+    max_transfers: 500,
+    max_log_queries: 5,
+    insert_chunk_size: 50,
+};
+const MAX_BUDGET: WorkBudget = WorkBudget {
+    max_transfers: 10_000,
+    max_log_queries: 200,
+    insert_chunk_size: 500,
+};
+
+/// How much work a single cron run takes on. These start from the old hardcoded constants and are
+/// tuned after every run so that runs stay under the target duration. Fetching fewer transfers
+/// also means fewer symbols to price.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct WorkBudget {
+    /// Transfers requested from the explorer per run
+    pub max_transfers: usize,
+    /// eth_getLogs calls made per run by the RPC fallback
+    pub max_log_queries: u64,
+    /// Rows per INSERT statement
+    pub insert_chunk_size: usize,
+}
+
+impl Default for WorkBudget {
+    fn default() -> Self {
+        Self {
+            max_transfers: 10_000,
+            max_log_queries: 50,
+            insert_chunk_size: 250,
+        }
+    }
+}
+
+impl WorkBudget {
+    /// Shrinks the budget proportionally when a run overshoots the target, and grows it when a run
+    /// used its whole budget in well under the target.
+    pub fn tune(&self, duration_ms: u64, target_ms: u64, saturated: bool) -> Self {
+        let factor = if duration_ms > target_ms {
+            (target_ms as f64 / duration_ms as f64).max(0.5)
+        } else if saturated && duration_ms < target_ms / 2 {
+            1.25
+        } else {
+            return *self;
+        };
+
+        Self {
+            max_transfers: scale(
+                self.max_transfers as f64,
+                factor,
+                MIN_BUDGET.max_transfers,
+                MAX_BUDGET.max_transfers,
+            ),
+            max_log_queries: scale(
+                self.max_log_queries as f64,
+                factor,
+                MIN_BUDGET.max_log_queries as usize,
+                MAX_BUDGET.max_log_queries as usize,
+            ) as u64,
+            insert_chunk_size: scale(
+                self.insert_chunk_size as f64,
+                factor,
+                MIN_BUDGET.insert_chunk_size,
+                MAX_BUDGET.insert_chunk_size,
+            ),
+        }
+    }
+}
+
+fn scale(value: f64, factor: f64, min: usize, max: usize) -> usize {
+    ((value * factor).round() as usize).clamp(min, max)
+}
+
+/// What a run actually did, used to decide how to tune the budget.
+#[derive(Default)]
+pub struct RunStats {
+    pub transfers: usize,
+    /// How many of the transfers were stored
+    pub inserted: usize,
+    /// How many blocks past the last indexed one the fetched transfers reach
+    pub blocks: u64,
+    /// Whether the run stopped because it hit the budget rather than running out of work
+    pub saturated: bool,
+}
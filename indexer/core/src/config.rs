@@ -0,0 +1,206 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::{
+    eth::Address,
+    native,
+    prices::{PriceStrategy, ProviderName, ProviderNames},
+};
+
+// The first block with an MRL transfer on Moonbeam
+const DEFAULT_START_BLOCK: u64 = 4164120;
+const DEFAULT_INSERT_CHUNK_SIZE: usize = 250;
+// Matches the most the work budget grows to
+const MAX_INSERT_CHUNK_SIZE: usize = 500;
+const DEFAULT_PRICE_QUOTE: &str = "USD";
+const DEFAULT_PRICE_PROVIDERS: [ProviderName; 1] = [ProviderName::TwelveData];
+
+/// A var that is set but can't be used.
+#[derive(Debug, Error)]
+#[error("{name} is set to {value:?}, but {expected}")]
+pub struct ConfigError {
+    name: &'static str,
+    value: String,
+    expected: &'static str,
+}
+
+/// Indexing parameters that differ between deployments, read from vars. Anything unset falls back
+/// to the Moonbeam mainnet deployment's value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Where indexing starts when nothing has been stored yet (START_BLOCK)
+    pub start_block: u64,
+    /// Where routed liquidity arrives (GMP_PRECOMPILE)
+    pub gmp_precompile: Address,
+    /// Rows per INSERT until the work budget has been tuned (INSERT_CHUNK_SIZE)
+    pub insert_chunk_size: usize,
+    /// The currency prices are fetched in, and so the one `usd` values are really in (PRICE_QUOTE)
+    pub price_quote: String,
+    /// Where prices are fetched from, in the order they're tried (PRICE_PROVIDERS)
+    pub price_providers: Vec<ProviderName>,
+    /// How the providers' series are combined (PRICE_STRATEGY)
+    pub price_strategy: PriceStrategy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            start_block: DEFAULT_START_BLOCK,
+            gmp_precompile: native::GMP_PRECOMPILE.parse().expect("valid address"),
+            insert_chunk_size: DEFAULT_INSERT_CHUNK_SIZE,
+            price_quote: DEFAULT_PRICE_QUOTE.to_string(),
+            price_providers: DEFAULT_PRICE_PROVIDERS.to_vec(),
+            price_strategy: PriceStrategy::First,
+        }
+    }
+}
+
+impl Config {
+    /// Reads each setting with `var`, validating whatever is set.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let defaults = Self::default();
+        Ok(Self {
+            start_block: parse(
+                &var,
+                "START_BLOCK",
+                defaults.start_block,
+                "a block number above 0",
+                |b| *b > 0,
+            )?,
+            gmp_precompile: parse(
+                &var,
+                "GMP_PRECOMPILE",
+                defaults.gmp_precompile,
+                "a non-zero 0x-prefixed address",
+                |a| !a.is_zero(),
+            )?,
+            insert_chunk_size: parse(
+                &var,
+                "INSERT_CHUNK_SIZE",
+                defaults.insert_chunk_size,
+                "a row count from 1 to 500",
+                |c| (1..=MAX_INSERT_CHUNK_SIZE).contains(c),
+            )?,
+            price_quote: parse(
+                &var,
+                "PRICE_QUOTE",
+                defaults.price_quote,
+                "an uppercase currency code such as USD",
+                |q| (3..=5).contains(&q.len()) && q.chars().all(|c| c.is_ascii_uppercase()),
+            )?,
+            price_providers: parse(
+                &var,
+                "PRICE_PROVIDERS",
+                ProviderNames(defaults.price_providers),
+                "a comma-separated list of twelve_data, coingecko and defillama without repeats",
+                |p| p.0.iter().enumerate().all(|(i, a)| !p.0[..i].contains(a)),
+            )?
+            .0,
+            price_strategy: parse(
+                &var,
+                "PRICE_STRATEGY",
+                defaults.price_strategy,
+                "first or median",
+                |_| true,
+            )?,
+        })
+    }
+
+    /// Whether stablecoins can be valued at 1 without fetching a price.
+    pub fn quotes_in_usd(&self) -> bool {
+        self.price_quote == "USD"
+    }
+}
+
+/// The var called `name` if it is set, or `default` if it isn't.
+fn parse<T: FromStr>(
+    var: &impl Fn(&str) -> Option<String>,
+    name: &'static str,
+    default: T,
+    expected: &'static str,
+    valid: impl Fn(&T) -> bool,
+) -> Result<T, ConfigError> {
+    let Some(value) = var(name) else {
+        return Ok(default)
+    };
+    match value.trim().parse::<T>() {
+        Ok(v) if valid(&v) => Ok(v),
+        _ => Err(ConfigError {
+            name,
+            value,
+            expected,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn from(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        Config::from_vars(|name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn unset_vars_use_the_mainnet_defaults() {
+        let config = from(&[]).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.start_block, 4164120);
+        assert_eq!(
+            format!("{:?}", config.gmp_precompile),
+            native::GMP_PRECOMPILE
+        );
+        assert!(config.quotes_in_usd());
+    }
+
+    #[test]
+    fn set_vars_override_the_defaults() {
+        let config = from(&[
+            ("START_BLOCK", "100"),
+            (
+                "GMP_PRECOMPILE",
+                "0x0000000000000000000000000000000000000817",
+            ),
+            ("INSERT_CHUNK_SIZE", "50"),
+            ("PRICE_QUOTE", "EUR"),
+            ("PRICE_PROVIDERS", "coingecko,twelve_data"),
+            ("PRICE_STRATEGY", "median"),
+        ])
+        .unwrap();
+        assert_eq!(config.start_block, 100);
+        assert_eq!(config.gmp_precompile, Address::from_low_u64_be(0x817));
+        assert_eq!(config.insert_chunk_size, 50);
+        assert!(!config.quotes_in_usd());
+        assert_eq!(
+            config.price_providers,
+            [ProviderName::CoinGecko, ProviderName::TwelveData]
+        );
+        assert_eq!(config.price_strategy, PriceStrategy::Median);
+    }
+
+    #[test]
+    fn invalid_vars_are_rejected() {
+        for vars in [
+            [("START_BLOCK", "0")],
+            [("START_BLOCK", "soon")],
+            [("GMP_PRECOMPILE", "0x816")],
+            [(
+                "GMP_PRECOMPILE",
+                "0x0000000000000000000000000000000000000000",
+            )],
+            [("INSERT_CHUNK_SIZE", "0")],
+            [("INSERT_CHUNK_SIZE", "501")],
+            [("PRICE_QUOTE", "usd")],
+            [("PRICE_PROVIDERS", "twelve_data,twelve_data")],
+            [("PRICE_PROVIDERS", "kraken")],
+            [("PRICE_STRATEGY", "mean")],
+        ] {
+            let e = from(&vars).unwrap_err();
+            assert_eq!(e.name, vars[0].0);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use serde::Serialize;
+
+use crate::{
+    errors::IndexerError,
+    eth::{self, U256},
+    model,
+};
+
+/// The decoder whose output is used for stored data and API responses.
+pub const ACTIVE_DECODER: &str = "mrl-v1";
+
+// Token bridge payload 3 is a transfer that carries an arbitrary payload for the recipient
+const TRANSFER_WITH_PAYLOAD: u8 = 3;
+const SIGNATURE_LENGTH: usize = 66;
+// keccak256("wormholeTransferERC20(bytes)")
+const WORMHOLE_TRANSFER_SELECTOR: [u8; 4] = [0xf5, 0x37, 0x74, 0xab];
+
+/// Decodes the calldata of a transaction that completed an MRL transfer.
+pub trait PayloadDecoder {
+    fn version(&self) -> &'static str;
+    fn decode(&self, calldata: &[u8]) -> Result<DecodedPayload, IndexerError>;
+}
+
+/// Every decoder the indexer knows about, keyed by `version()`.
+pub fn registry() -> Vec<Box<dyn PayloadDecoder>> {
+    vec![Box::new(MrlV1Decoder)]
+}
+
+pub fn decoder(version: &str) -> Option<Box<dyn PayloadDecoder>> {
+    registry().into_iter().find(|d| d.version() == version)
+}
+
+pub fn active_decoder() -> Box<dyn PayloadDecoder> {
+    decoder(ACTIVE_DECODER).unwrap_or(Box::new(MrlV1Decoder))
+}
+
+model! {
+    #[derive(Serialize, Clone, PartialEq, Debug)]
+    pub struct DecodedPayload {
+        pub decoder: &'static str,
+        /// Which user action the payload asked for, e.g. `XcmRoutingUserActionWithFee`
+        pub action: &'static str,
+        pub destination: Destination,
+        /// Fee paid to the relayer in the transferred token, for actions that carry one
+        pub fee: Option<String>,
+        /// Sender on the origin chain, as a 32 byte Wormhole address
+        pub sender: String,
+        pub amount: String,
+        pub token_address: String,
+        pub token_chain: u16,
+        pub emitter_chain: u16,
+        pub sequence: u64,
+    }
+}
+
+model! {
+    #[derive(Serialize, Clone, PartialEq, Debug)]
+    pub struct Destination {
+        pub parents: u8,
+        pub interior: Vec<Junction>,
+        /// The first parachain junction, which is where the liquidity is routed
+        pub parachain: Option<u32>,
+        /// The first account junction, which is who receives it
+        pub account: Option<String>,
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub enum Junction {
+    Parachain(u32),
+    AccountId32 {
+        network: Option<String>,
+        id: String,
+    },
+    AccountIndex64 {
+        network: Option<String>,
+        index: u64,
+    },
+    AccountKey20 {
+        network: Option<String>,
+        key: String,
+    },
+    PalletInstance(u8),
+    GeneralIndex(String),
+    GeneralKey(String),
+    OnlyChild,
+    GlobalConsensus(String),
+}
+
+/// Decodes `wormholeTransferERC20(bytes vaa)` calls to the GMP precompile, whose VAA carries a
+/// token bridge transfer with a SCALE encoded `VersionedUserAction` as its payload.
+pub struct MrlV1Decoder;
+
+impl PayloadDecoder for MrlV1Decoder {
+    fn version(&self) -> &'static str {
+        "mrl-v1"
+    }
+
+    fn decode(&self, calldata: &[u8]) -> Result<DecodedPayload, IndexerError> {
+        if calldata.len() < 4 || calldata[..4] != WORMHOLE_TRANSFER_SELECTOR {
+            return Err(decode_error("calldata that isn't a wormholeTransferERC20 call"));
+        }
+        let Some(vaa) = eth::abi_bytes(&calldata[4..]) else {
+            return Err(decode_error("the VAA argument"));
+        };
+
+        // VAA header and guardian signatures, then the body
+        let mut r = Reader::new(vaa);
+        r.u8()?;
+        r.take(4)?;
+        let signatures = r.u8()? as usize;
+        r.take(signatures * SIGNATURE_LENGTH)?;
+        r.take(4)?;
+        r.take(4)?;
+        let emitter_chain = r.u16_be()?;
+        r.take(32)?;
+        let sequence = r.u64_be()?;
+        r.u8()?;
+
+        // Token bridge transfer with payload
+        if r.u8()? != TRANSFER_WITH_PAYLOAD {
+            return Err(decode_error("a token bridge payload that isn't a transfer with payload"));
+        }
+        let amount = U256::from_big_endian(r.take(32)?);
+        let token_address = r.take(32)?;
+        let token_chain = r.u16_be()?;
+        r.take(32)?;
+        r.u16_be()?;
+        let sender = r.take(32)?;
+
+        // VersionedUserAction
+        let (action, destination, fee) = match r.u8()? {
+            0 => ("XcmRoutingUserAction", versioned_location(&mut r)?, None),
+            1 => {
+                let destination = versioned_location(&mut r)?;
+                let fee = U256::from_little_endian(r.take(32)?);
+                ("XcmRoutingUserActionWithFee", destination, Some(fee.to_string()))
+            }
+            v => return Err(decode_error(&format!("user action version {v}"))),
+        };
+
+        Ok(DecodedPayload {
+            decoder: self.version(),
+            action,
+            destination,
+            fee,
+            sender: hex_string(sender),
+            amount: amount.to_string(),
+            token_address: hex_string(token_address),
+            token_chain,
+            emitter_chain,
+            sequence,
+        })
+    }
+}
+
+fn decode_error(what: &str) -> IndexerError {
+    IndexerError::DecodeFailure(what.to_string())
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// Reads a `VersionedMultiLocation`. V2 and V3 differ only in how junctions name their network.
+fn versioned_location(r: &mut Reader) -> Result<Destination, IndexerError> {
+    let v3 = match r.u8()? {
+        1 => false,
+        3 => true,
+        v => return Err(decode_error(&format!("MultiLocation version {v}"))),
+    };
+    let parents = r.u8()?;
+    let count = r.u8()?;
+    if count > 8 {
+        return Err(decode_error(&format!("a MultiLocation with {count} junctions")));
+    }
+
+    let mut interior = vec![];
+    for _ in 0..count {
+        interior.push(junction(r, v3)?);
+    }
+    let parachain = interior.iter().find_map(|j| match j {
+        Junction::Parachain(id) => Some(*id),
+        _ => None,
+    });
+    let account = interior.iter().find_map(|j| match j {
+        Junction::AccountId32 { id, .. } => Some(id.clone()),
+        Junction::AccountKey20 { key, .. } => Some(key.clone()),
+        _ => None,
+    });
+    Ok(Destination {
+        parents,
+        interior,
+        parachain,
+        account,
+    })
+}
+
+fn junction(r: &mut Reader, v3: bool) -> Result<Junction, IndexerError> {
+    Ok(match r.u8()? {
+        0 => Junction::Parachain(r.compact()? as u32),
+        1 => Junction::AccountId32 {
+            network: network(r, v3)?,
+            id: hex_string(r.take(32)?),
+        },
+        2 => Junction::AccountIndex64 {
+            network: network(r, v3)?,
+            index: r.compact()? as u64,
+        },
+        3 => Junction::AccountKey20 {
+            network: network(r, v3)?,
+            key: hex_string(r.take(20)?),
+        },
+        4 => Junction::PalletInstance(r.u8()?),
+        5 => Junction::GeneralIndex(r.compact()?.to_string()),
+        6 if v3 => {
+            let length = r.u8()? as usize;
+            let data = r.take(32)?;
+            Junction::GeneralKey(hex_string(&data[..length.min(32)]))
+        }
+        6 => {
+            let length = r.compact()? as usize;
+            Junction::GeneralKey(hex_string(r.take(length)?))
+        }
+        7 => Junction::OnlyChild,
+        9 if v3 => Junction::GlobalConsensus(v3_network_id(r)?),
+        j => return Err(decode_error(&format!("junction type {j}"))),
+    })
+}
+
+/// V2 junctions always name a network (`Any` for none), V3 junctions have an optional one.
+fn network(r: &mut Reader, v3: bool) -> Result<Option<String>, IndexerError> {
+    if v3 {
+        return match r.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(v3_network_id(r)?)),
+            o => Err(decode_error(&format!("option tag {o}"))),
+        };
+    }
+    Ok(match r.u8()? {
+        0 => None,
+        1 => {
+            let length = r.compact()? as usize;
+            Some(format!("Named({})", hex_string(r.take(length)?)))
+        }
+        2 => Some("Polkadot".to_string()),
+        3 => Some("Kusama".to_string()),
+        n => return Err(decode_error(&format!("V2 network {n}"))),
+    })
+}
+
+fn v3_network_id(r: &mut Reader) -> Result<String, IndexerError> {
+    Ok(match r.u8()? {
+        0 => format!("ByGenesis({})", hex_string(r.take(32)?)),
+        1 => {
+            let block_number = u64::from_le_bytes(r.array()?);
+            format!("ByFork({}, {})", block_number, hex_string(r.take(32)?))
+        }
+        2 => "Polkadot".to_string(),
+        3 => "Kusama".to_string(),
+        4 => "Westend".to_string(),
+        5 => "Rococo".to_string(),
+        6 => "Wococo".to_string(),
+        7 => format!("Ethereum({})", r.compact()?),
+        8 => "BitcoinCore".to_string(),
+        9 => "BitcoinCash".to_string(),
+        n => return Err(decode_error(&format!("V3 network {n}"))),
+    })
+}
+
+/// Cursor over a byte slice that reports running out of bytes as a decode failure.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], IndexerError> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.data.len());
+        let Some(end) = end else {
+            return Err(decode_error("a payload that ended early"));
+        };
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], IndexerError> {
+        let mut array = [0_u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, IndexerError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16, IndexerError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u64_be(&mut self) -> Result<u64, IndexerError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    /// SCALE compact integer: the low two bits of the first byte give the width.
+    fn compact(&mut self) -> Result<u128, IndexerError> {
+        let first = self.u8()?;
+        Ok(match first & 0b11 {
+            0 => (first >> 2) as u128,
+            1 => (u16::from_le_bytes([first, self.u8()?]) >> 2) as u128,
+            2 => {
+                let rest = self.take(3)?;
+                (u32::from_le_bytes([first, rest[0], rest[1], rest[2]]) >> 2) as u128
+            }
+            _ => {
+                let length = (first >> 2) as usize + 4;
+                if length > 16 {
+                    return Err(decode_error("a compact integer wider than 128 bits"));
+                }
+                let mut bytes = [0_u8; 16];
+                bytes[..length].copy_from_slice(self.take(length)?);
+                u128::from_le_bytes(bytes)
+            }
+        })
+    }
+}
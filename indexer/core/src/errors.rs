@@ -0,0 +1,45 @@
+use thiserror::Error;
+
+/// Failures the indexer keeps a record of, so they can be monitored after the fact.
+// The variant names double as the stored `kind`, so they keep their suffix
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Error)]
+pub enum IndexerError {
+    #[error("etherscan query failed: {0}")]
+    EtherscanFailure(String),
+    #[error("node query failed: {0}")]
+    RpcFailure(String),
+    #[error("price fetch for {symbol} failed: {message}")]
+    PriceFetchFailure { symbol: String, message: String },
+    #[error("database operation failed: {0}")]
+    DbFailure(String),
+    #[error("database temporarily unavailable: {0}")]
+    DbUnavailable(String),
+    #[error("could not decode {0}")]
+    DecodeFailure(String),
+    #[error("invariant violated: {0}")]
+    InvariantViolation(String),
+    #[error("archiving fetched transfers failed: {0}")]
+    ArchiveFailure(String),
+}
+
+impl IndexerError {
+    /// The name the error is stored under.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IndexerError::EtherscanFailure(_) => "EtherscanFailure",
+            IndexerError::RpcFailure(_) => "RpcFailure",
+            IndexerError::PriceFetchFailure { .. } => "PriceFetchFailure",
+            IndexerError::DbFailure(_) => "DbFailure",
+            IndexerError::DbUnavailable(_) => "DbUnavailable",
+            IndexerError::DecodeFailure(_) => "DecodeFailure",
+            IndexerError::InvariantViolation(_) => "InvariantViolation",
+            IndexerError::ArchiveFailure(_) => "ArchiveFailure",
+        }
+    }
+
+    /// Whether the store was briefly unreachable, so the run should stop and try again later.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, IndexerError::DbUnavailable(_))
+    }
+}
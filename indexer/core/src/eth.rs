@@ -0,0 +1,65 @@
+use std::ops::Deref;
+
+pub use primitive_types::{H160 as Address, H256, U256};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// ABI words are 32 bytes
+const WORD: usize = 32;
+
+/// Bytes that travel over JSON-RPC as 0x-prefixed hex.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{}", hex::encode(&self.0)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s.trim_start_matches("0x"))
+            .map(Bytes)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A JSON-RPC quantity, such as a block number, which travels as 0x-prefixed hex.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantity(pub u64);
+
+impl<'de> Deserialize<'de> for Quantity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        u64::from_str_radix(s.trim_start_matches("0x"), 16)
+            .map(Quantity)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Reads the first ABI word of `data` as a `uint256`.
+pub fn abi_uint(data: &[u8]) -> Option<U256> {
+    data.get(..WORD).map(U256::from_big_endian)
+}
+
+/// Reads `data` as a single dynamic `bytes` or `string` argument: an offset to a length, followed
+/// by that many bytes.
+pub fn abi_bytes(data: &[u8]) -> Option<&[u8]> {
+    let offset = usize::try_from(abi_uint(data)?).ok()?;
+    let length = usize::try_from(abi_uint(data.get(offset..)?)?).ok()?;
+    let start = offset.checked_add(WORD)?;
+    data.get(start..start.checked_add(length)?)
+}
+
+pub fn abi_string(data: &[u8]) -> Option<String> {
+    String::from_utf8(abi_bytes(data)?.to_vec()).ok()
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::{errors::IndexerError, models::Token};
+
+/// Reports a broken pipeline invariant. Debug builds panic, so data drifting out of shape is
+/// noticed during development. Release builds return the error for the caller to record, having
+/// already skipped the offending items.
+pub fn violated(description: String) -> IndexerError {
+    if cfg!(debug_assertions) {
+        panic!("invariant violated: {description}");
+    }
+    IndexerError::InvariantViolation(description)
+}
+
+/// Skips items whose block is lower than one before them, or not after the `last_indexed` block,
+/// since later steps rely on oldest first input that only holds new blocks. Returns what was
+/// skipped and why.
+pub fn blocks_in_order<T>(
+    items: &mut Vec<T>,
+    last_indexed: Option<u64>,
+    block: impl Fn(&T) -> u64,
+) -> Vec<String> {
+    let mut violations = vec![];
+    let mut highest = None;
+    items.retain(|i| {
+        let b = block(i);
+        if let Some(last) = last_indexed.filter(|&last| b <= last) {
+            violations.push(format!(
+                "block {b} is not after the last indexed block {last}"
+            ));
+            return false;
+        }
+        if let Some(h) = highest.filter(|&h| b < h) {
+            violations.push(format!("block {b} follows block {h}"));
+            return false;
+        }
+        highest = Some(b);
+        true
+    });
+    violations
+}
+
+/// Skips tokens missing an address, name or symbol, which every price lookup and response relies
+/// on. Returns the address of each token skipped, with why.
+pub fn token_metadata(tokens: &mut HashMap<String, Token>) -> Vec<(String, String)> {
+    let mut violations = vec![];
+    tokens.retain(|addr, t| {
+        let missing = [
+            ("address", &t.contract_addr),
+            ("name", &t.token_name),
+            ("symbol", &t.token_sym),
+        ]
+        .into_iter()
+        .filter(|(_, value)| value.trim().is_empty())
+        .map(|(field, _)| field)
+        .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return true;
+        }
+        violations.push((
+            addr.clone(),
+            format!("token {addr} has no {}", missing.join(" or ")),
+        ));
+        false
+    });
+    violations.sort();
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_order_blocks_are_skipped() {
+        let mut blocks = vec![10, 11, 11, 9, 12];
+        let violations = blocks_in_order(&mut blocks, None, |&b| b);
+        assert_eq!(blocks, vec![10, 11, 11, 12]);
+        assert_eq!(violations, vec!["block 9 follows block 11".to_string()]);
+    }
+
+    #[test]
+    fn already_indexed_blocks_are_skipped() {
+        let mut blocks = vec![9, 10, 11];
+        let violations = blocks_in_order(&mut blocks, Some(10), |&b| b);
+        assert_eq!(blocks, vec![11]);
+        assert_eq!(violations.len(), 2);
+        assert!(blocks_in_order(&mut blocks, Some(10), |&b| b).is_empty());
+    }
+
+    #[test]
+    fn tokens_without_metadata_are_skipped() {
+        let token = |addr: &str, name: &str, symbol: &str| Token {
+            contract_addr: addr.to_string(),
+            token_name: name.to_string(),
+            token_sym: symbol.to_string(),
+            ..Token::default()
+        };
+        let mut tokens = HashMap::from([
+            ("0x1".to_string(), token("0x1", "Wrapped Ether", "WETH")),
+            ("0x2".to_string(), token("0x2", " ", "")),
+        ]);
+        let violations = token_metadata(&mut tokens);
+        assert_eq!(
+            violations,
+            vec![(
+                "0x2".to_string(),
+                "token 0x2 has no name or symbol".to_string()
+            )]
+        );
+        assert!(tokens.contains_key("0x1"));
+        assert_eq!(tokens.len(), 1);
+    }
+}
@@ -0,0 +1,20 @@
+//! The parts of the indexer that don't need the Workers runtime: decoding explorer and payload
+//! data, pricing transfers, the stored models and the pipeline that drives them through the
+//! `EventSource`, `PriceSource` and `Store` traits. The worker crate implements those traits over
+//! its bindings, and everything here builds and tests natively.
+
+pub mod budget;
+pub mod config;
+pub mod decoder;
+pub mod errors;
+pub mod eth;
+pub mod invariants;
+pub mod models;
+pub mod native;
+pub mod numeric;
+pub mod pipeline;
+pub mod prices;
+pub mod registry;
+pub mod scan;
+pub mod schemas;
+pub mod usd;
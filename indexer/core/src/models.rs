@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{model, usd::Usd};
+
+model! {
+    #[derive(Deserialize, Serialize, Clone)]
+    pub struct Token {
+        pub contract_addr: String,
+        pub token_name: String,
+        pub token_sym: String,
+        pub decimals: u32,
+        /// Only known for tokens in the bundled registry
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub category: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub logo_url: Option<String>,
+    }
+}
+
+impl Default for Token {
+    fn default() -> Self {
+        Self {
+            contract_addr: Default::default(),
+            token_name: Default::default(),
+            token_sym: Default::default(),
+            decimals: 18,
+            category: None,
+            logo_url: None,
+        }
+    }
+}
+
+/// The `to_chain` of transfers whose destination hasn't been decoded from their payload yet, or
+/// couldn't be. No parachain has this id.
+pub const UNDECODED_CHAIN: u32 = 0;
+
+/// A transfer of liquidity onwards to a parachain, as stored in TransfersForward.
+#[derive(Deserialize, Serialize)]
+pub struct TransferForward {
+    pub tx_hash: String,
+    pub token_addr: String,
+    pub token_count: u128,
+    pub usd: Usd,
+    // The range `usd` could be in given the high and low of the candles it was priced from
+    pub usd_min: Usd,
+    pub usd_max: Usd,
+    pub block_num: u64,
+    // Unix seconds
+    pub timestamp: u64,
+    // The destination parachain, UNDECODED_CHAIN until the GMP payload has been decoded
+    pub to_chain: u32,
+    // Set when the price series used for `usd` looked stale or flat
+    pub price_uncertain: bool,
+    // Account on the destination chain, once it can be decoded from the GMP payload
+    pub dest_account: Option<String>,
+    // Set when the scan API's timestamp was implausible for the block and the node's was used
+    pub timestamp_corrected: bool,
+    // The price of one whole token that `usd` was worked out at, if it was priced
+    #[serde(default)]
+    pub price: Option<f64>,
+}
+
+/// What a token's contract reports about itself. Calls that fail, or answer with something
+/// unusable, are None.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct TokenMetadata {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u32>,
+}
@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    eth::Address,
+    models::{TransferForward, UNDECODED_CHAIN},
+    numeric,
+    registry::{self, TransferPattern},
+    scan::{ScanClient, ScanError, TokenTransfer},
+    usd::Usd,
+};
+
+/// Receives the liquidity that MRL routes onwards to parachains.
+pub const GMP_PRECOMPILE: &str = "0x0000000000000000000000000000000000000816";
+
+/// Native GLMR has no token contract, so it's stored under the native balance ERC-20 precompile.
+pub const GLMR_ADDRESS: &str = "0x0000000000000000000000000000000000000802";
+
+/// XC-20s are precompiles whose addresses start with four 0xff bytes.
+pub fn is_xc20(address: &Address) -> bool {
+    address.as_bytes()[..4] == [0xff; 4]
+}
+
+/// Whether a token transfer is liquidity arriving at the GMP precompile to be routed onwards.
+/// Wormhole assets are minted straight to it, or released to it by their custodian when the
+/// registry says they are locked rather than burnt, while XC-20s are transferred in.
+pub fn is_forward(e: &TokenTransfer, gmp_precompile: Address) -> bool {
+    let arrived = match registry::transfer_pattern(&format!("{:?}", e.contract_address)) {
+        TransferPattern::MintBurn => e.from == Address::zero(),
+        TransferPattern::LockUnlock { custodian } => {
+            e.to == Some(gmp_precompile) && custodian.parse::<Address>().ok() == Some(e.from)
+        }
+    };
+    arrived || (is_xc20(&e.contract_address) && e.to == Some(gmp_precompile))
+}
+
+/// Native GLMR sent to the GMP precompile, which shows up as internal transactions rather than
+/// Transfer events. Value sent in several calls of the same transaction is summed.
+pub async fn native_transfers(
+    client: &ScanClient,
+    gmp_precompile: Address,
+    from_block: u64,
+    to_block: u64,
+    max_transfers: u64,
+) -> Result<Vec<TransferForward>, ScanError> {
+    let internal = client
+        .internal_transactions(gmp_precompile, from_block, to_block, max_transfers)
+        .await?;
+
+    let mut transfers: Vec<TransferForward> = vec![];
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+    for tx in internal {
+        if tx.to != Some(gmp_precompile) || tx.is_error != "0" || tx.value.is_zero() {
+            continue;
+        }
+
+        let tx_hash = format!("{:?}", tx.hash);
+        let value = numeric::to_u128(tx.value);
+        if let Some(i) = index_of.get(&tx_hash) {
+            transfers[*i].token_count = numeric::sum([transfers[*i].token_count, value]);
+            continue;
+        }
+        index_of.insert(tx_hash.clone(), transfers.len());
+        transfers.push(TransferForward {
+            tx_hash,
+            token_addr: GLMR_ADDRESS.to_string(),
+            token_count: value,
+            usd: Usd::default(),
+            usd_min: Usd::default(),
+            usd_max: Usd::default(),
+            block_num: tx.block_number,
+            timestamp: tx.time_stamp.parse().unwrap_or(0),
+            to_chain: UNDECODED_CHAIN, // Decoded from the payload later
+            price_uncertain: false,
+            dest_account: None,
+            timestamp_corrected: false,
+            price: None,
+        });
+    }
+    Ok(transfers)
+}
+
+/// Adds native transfers to the token transfers, skipping transactions that already routed a
+/// token since a transaction can only be stored once.
+pub fn merge(transfers: &mut Vec<TransferForward>, native: Vec<TransferForward>) {
+    let seen: HashSet<String> = transfers.iter().map(|t| t.tx_hash.clone()).collect();
+    transfers.extend(native.into_iter().filter(|t| !seen.contains(&t.tx_hash)));
+    // Prices are matched in timestamp order
+    transfers.sort_by_key(|t| t.block_num);
+}
@@ -0,0 +1,108 @@
+use crate::{eth::U256, usd::Usd};
+
+/// Converts an on-chain amount to the u128 that transfers are stored as. Anything larger can't be
+/// a real token supply, so it saturates rather than panicking.
+pub fn to_u128(value: U256) -> u128 {
+    u128::try_from(value).unwrap_or(u128::MAX)
+}
+
+/// 10^decimals, or None past the 38 decimals a u128 can scale by.
+pub fn scale(decimals: u32) -> Option<u128> {
+    10_u128.checked_pow(decimals)
+}
+
+/// Adds amounts together, saturating instead of overflowing.
+pub fn sum(amounts: impl IntoIterator<Item = u128>) -> u128 {
+    amounts
+        .into_iter()
+        .fold(0_u128, |total, amount| total.saturating_add(amount))
+}
+
+/// An amount in whole tokens. The integer and fractional parts are split before converting, so
+/// amounts too large for an f64 mantissa still keep their leading digits.
+pub fn whole_tokens(amount: u128, decimals: u32) -> f64 {
+    match scale(decimals) {
+        Some(scale) => (amount / scale) as f64 + (amount % scale) as f64 / scale as f64,
+        None => amount as f64 / 10_f64.powi(decimals.min(i32::MAX as u32) as i32),
+    }
+}
+
+/// Converts a raw amount that D1 has already summed, and so returns as an f64, to whole tokens.
+pub fn normalize(raw: f64, decimals: u32) -> f64 {
+    raw / 10_f64.powi(decimals.min(i32::MAX as u32) as i32)
+}
+
+/// The USD value of an amount at `price` dollars per whole token, to the nearest cent. Values too
+/// large for an i64 of cents saturate, and a price that isn't a finite number values the amount
+/// at 0.
+pub fn usd_value(amount: u128, decimals: u32, price: f32) -> Usd {
+    if !price.is_finite() {
+        return Usd::default();
+    }
+    Usd::from_dollars(whole_tokens(amount, decimals) * price as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_u128_saturates_past_u128() {
+        assert_eq!(to_u128(U256::from(42)), 42);
+        assert_eq!(to_u128(U256::from(u128::MAX)), u128::MAX);
+        assert_eq!(to_u128(U256::MAX), u128::MAX);
+    }
+
+    #[test]
+    fn scale_stops_at_38_decimals() {
+        assert_eq!(scale(0), Some(1));
+        assert_eq!(scale(18), Some(1_000_000_000_000_000_000));
+        assert!(scale(38).is_some());
+        assert_eq!(scale(39), None);
+    }
+
+    #[test]
+    fn sum_saturates() {
+        assert_eq!(sum([1, 2, 3]), 6);
+        assert_eq!(sum([u128::MAX, 1]), u128::MAX);
+        assert_eq!(sum([]), 0);
+    }
+
+    #[test]
+    fn whole_tokens_keeps_fractions() {
+        assert_eq!(whole_tokens(1_500_000_000_000_000_000, 18), 1.5);
+        assert_eq!(whole_tokens(1_234_567, 6), 1.234567);
+        // Fewer than six decimals used to truncate to whole tokens
+        assert_eq!(whole_tokens(125, 2), 1.25);
+        assert_eq!(whole_tokens(7, 0), 7.);
+    }
+
+    #[test]
+    fn whole_tokens_handles_extreme_inputs() {
+        assert_eq!(whole_tokens(u128::MAX, 18), u128::MAX as f64 / 1e18);
+        assert_eq!(whole_tokens(10_u128.pow(20), 40), 1e-20);
+        assert_eq!(whole_tokens(1, u32::MAX), 0.);
+    }
+
+    #[test]
+    fn normalize_divides_by_decimals() {
+        assert_eq!(normalize(2.5e18, 18), 2.5);
+        assert_eq!(normalize(100., 0), 100.);
+    }
+
+    #[test]
+    fn usd_value_multiplies_by_price() {
+        assert_eq!(usd_value(2_000_000_000_000_000_000, 18, 1800.), Usd(360000));
+        assert_eq!(usd_value(1_500_000, 6, 1.), Usd(150));
+        assert_eq!(usd_value(0, 18, 1800.), Usd(0));
+        // Sub-cent values round to the nearest cent
+        assert_eq!(usd_value(1_234_567, 6, 1.), Usd(123));
+    }
+
+    #[test]
+    fn usd_value_saturates_and_ignores_bad_prices() {
+        assert_eq!(usd_value(u128::MAX, 0, f32::MAX), Usd(i64::MAX));
+        assert_eq!(usd_value(1_000_000, 6, f32::NAN), Usd(0));
+        assert_eq!(usd_value(1_000_000, 6, f32::INFINITY), Usd(0));
+    }
+}
@@ -0,0 +1,1928 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    budget::{RunStats, WorkBudget},
+    config::Config,
+    errors::IndexerError,
+    eth::Address,
+    invariants,
+    models::{Token, TokenMetadata, TransferForward, UNDECODED_CHAIN},
+    native, numeric,
+    prices::{self, TimeSeries},
+    registry,
+    scan::TokenTransfer,
+    usd::Usd,
+};
+
+// The explorer's end block is inclusive, so this stands in for the chain head
+const LATEST_BLOCK: u64 = 999999999;
+// The most transfers an explorer page lists
+const MAX_EXPLORER_PAGE: usize = 10_000;
+
+/// Where transfers to the GMP precompile are read from.
+#[async_trait(?Send)]
+pub trait EventSource {
+    /// ERC-20 transfers to or from `precompile` from `from_block` on, oldest first, as listed by
+    /// the block explorer.
+    async fn token_transfers(
+        &self,
+        precompile: Address,
+        from_block: u64,
+        max: usize,
+    ) -> Result<Vec<TokenTransfer>, IndexerError>;
+
+    /// The same transfers read from a node's logs, for when the explorer is unavailable.
+    async fn log_transfers(
+        &self,
+        precompile: Address,
+        from_block: u64,
+        max_queries: u64,
+    ) -> Result<Vec<TokenTransfer>, IndexerError>;
+
+    /// Native GLMR sent to `precompile` between the blocks (both inclusive), which only the
+    /// explorer lists.
+    async fn native_transfers(
+        &self,
+        precompile: Address,
+        from_block: u64,
+        to_block: u64,
+        max: usize,
+    ) -> Result<Vec<TransferForward>, IndexerError>;
+
+    /// Replaces explorer timestamps that aren't plausible for their block, returning how many were
+    /// corrected.
+    async fn cross_check_timestamps(
+        &self,
+        transfers: &mut [TransferForward],
+    ) -> Result<usize, IndexerError>;
+
+    /// What the token's own contract says its name, symbol and decimals are.
+    async fn token_metadata(&self, token: Address) -> TokenMetadata;
+}
+
+/// Where historical USD prices come from.
+#[async_trait(?Send)]
+pub trait PriceSource {
+    /// Candles for `symbol` in USD, oldest first, reaching back to `since`.
+    async fn time_series(&self, symbol: &str, since: u64) -> Result<Vec<TimeSeries>, IndexerError>;
+}
+
+/// Where indexed transfers are kept.
+#[async_trait(?Send)]
+pub trait Store {
+    /// The block runs resume after: the highest block a transfer has been stored for, or the block
+    /// before the first chunk of transfers that failed to be stored if that's lower.
+    async fn last_indexed_block(&self) -> Result<Option<u64>, IndexerError>;
+
+    /// Stores any tokens that aren't known yet.
+    async fn insert_tokens(&self, tokens: &[&Token]) -> Result<(), IndexerError>;
+
+    /// Overwrites the name, symbol and decimals of stored tokens, except those corrected by hand.
+    async fn update_tokens(&self, tokens: &[&Token]) -> Result<(), IndexerError>;
+
+    /// Tokens whose metadata was corrected by hand, by address.
+    async fn overridden_tokens(&self) -> Result<HashMap<String, Token>, IndexerError>;
+
+    /// Stores the transfers `chunk_size` at a time, committing each chunk before the next and
+    /// stopping at the first that fails. Transfers that are already stored are skipped, since a
+    /// queued batch can be delivered more than once and a failed one is fetched again.
+    async fn insert_transfers(
+        &self,
+        transfers: &[TransferForward],
+        chunk_size: usize,
+    ) -> Result<(), PartialInsert>;
+
+    /// The timestamp of the candle each symbol was last priced at.
+    async fn price_cursors(&self) -> Result<HashMap<String, u64>, IndexerError>;
+
+    async fn save_price_cursors(&self, cursors: &HashMap<String, u64>) -> Result<(), IndexerError>;
+
+    /// Keeps a fetched batch as it was listed, so that it can be processed again later without
+    /// fetching it again.
+    async fn archive(&self, batch: &TransferBatch) -> Result<(), IndexerError>;
+
+    async fn record_error(&self, error: IndexerError, context: &str);
+}
+
+/// What a pass indexed, for the caller to log and alert on.
+#[derive(Default)]
+pub struct Indexed {
+    pub transfers: Vec<TransferForward>,
+    /// Symbols whose price series looked stale or flat, with why
+    pub stale: Vec<(String, String)>,
+    pub corrected_timestamps: usize,
+    /// Whether the store became unavailable partway, leaving the rest of the pass to a later run
+    pub deferred: bool,
+    /// Whether any of the transfers were left unstored by a failure, transient or not
+    pub failed: bool,
+    /// Whether every block up to the chain head was read and everything in them stored
+    pub caught_up: bool,
+}
+
+/// How far storing transfers got before it failed.
+pub struct PartialInsert {
+    /// How many of the transfers, from the first, were stored
+    pub stored: usize,
+    pub error: IndexerError,
+}
+
+/// The block runs resume after, given the highest block stored and the first block of the first
+/// chunk that failed to be stored. Chunks can split a block, so that block is fetched again whole.
+pub fn resume_after(stored_through: Option<u64>, failed_from: Option<u64>) -> Option<u64> {
+    match (stored_through, failed_from) {
+        (Some(stored), Some(failed)) => Some(stored.min(failed.saturating_sub(1))),
+        (None, Some(failed)) => Some(failed.saturating_sub(1)),
+        (stored, None) => stored,
+    }
+}
+
+/// Transfers read from the chain but not yet decoded, priced or stored. Scheduled runs send these
+/// through the ingestion queue when there is one.
+#[derive(Serialize, Deserialize, Default)]
+pub struct TransferBatch {
+    /// Token transfers as the explorer or node listed them, oldest first
+    pub events: Vec<TokenTransfer>,
+    /// Native GLMR sent to the precompile in the same blocks
+    pub native: Vec<TransferForward>,
+    /// Whether the events came from the explorer, whose timestamps are cross-checked
+    pub from_explorer: bool,
+    /// The block fetching carried on from, so that whoever stores the batch can tell it follows
+    /// on from what is already stored. None for batches queued before this was recorded
+    #[serde(default)]
+    pub after_block: Option<u64>,
+}
+
+impl TransferBatch {
+    pub fn len(&self) -> usize {
+        self.events.len() + self.native.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The lowest block the batch has anything from.
+    pub fn first_block(&self) -> Option<u64> {
+        self.blocks().min()
+    }
+
+    /// The highest block the batch has anything from.
+    pub fn last_block(&self) -> Option<u64> {
+        self.blocks().max()
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = u64> + '_ {
+        let events = self.events.iter().map(|e| e.block_number);
+        events.chain(self.native.iter().map(|t| t.block_num))
+    }
+
+    /// Splits the batch into batches of at most `max` transfers, oldest first. A block is never
+    /// split, since its native and token transfers are merged by transaction, so a block with
+    /// more than `max` gets a batch of its own. Each carries on from the last block of the one
+    /// before it.
+    pub fn split(self, max: usize) -> Vec<TransferBatch> {
+        let from_explorer = self.from_explorer;
+        let mut after_block = self.after_block;
+        let mut blocks: BTreeMap<u64, TransferBatch> = BTreeMap::new();
+        for e in self.events {
+            blocks.entry(e.block_number).or_default().events.push(e);
+        }
+        for t in self.native {
+            blocks.entry(t.block_num).or_default().native.push(t);
+        }
+
+        let mut batches: Vec<TransferBatch> = vec![];
+        for (_, block) in blocks {
+            match batches.last_mut() {
+                Some(b) if b.len() + block.len() <= max => {
+                    b.events.extend(block.events);
+                    b.native.extend(block.native);
+                }
+                _ => batches.push(TransferBatch {
+                    from_explorer,
+                    ..block
+                }),
+            }
+        }
+        for batch in batches.iter_mut() {
+            batch.after_block = after_block;
+            after_block = batch.last_block().or(after_block);
+        }
+        batches
+    }
+}
+
+/// What `fetch` read, or that the store was unavailable.
+pub struct Fetched {
+    pub batch: TransferBatch,
+    pub deferred: bool,
+    /// Whether every block up to the chain head was read, rather than stopping at the budget or
+    /// falling back to a source that misses some transfers
+    pub complete: bool,
+}
+
+/// Fetches, prices and stores every transfer since the last indexed block, within `budget`. `now`
+/// is in unix seconds.
+pub async fn index(
+    events: &impl EventSource,
+    prices: &impl PriceSource,
+    store: &impl Store,
+    config: &Config,
+    budget: &WorkBudget,
+    stats: &mut RunStats,
+    now: u64,
+) -> Indexed {
+    let fetched = fetch(events, store, config, budget, stats, None).await;
+    if fetched.deferred {
+        return Indexed {
+            deferred: true,
+            ..Indexed::default()
+        };
+    }
+    let mut indexed = process(
+        fetched.batch,
+        events,
+        prices,
+        store,
+        config,
+        budget,
+        stats,
+        now,
+    )
+    .await;
+    indexed.caught_up = fetched.complete && !indexed.failed && !indexed.deferred;
+    indexed
+}
+
+/// Reads every transfer after `queued_through`, or after the last indexed block if that's None,
+/// within `budget`.
+pub async fn fetch(
+    events: &impl EventSource,
+    store: &impl Store,
+    config: &Config,
+    budget: &WorkBudget,
+    stats: &mut RunStats,
+    queued_through: Option<u64>,
+) -> Fetched {
+    let mut fetched = Fetched {
+        batch: TransferBatch::default(),
+        deferred: false,
+        complete: false,
+    };
+
+    // 1. Get the last entry so that we know when to query from.
+    let last_indexed = match queued_through {
+        Some(b) => Some(b),
+        None => match store.last_indexed_block().await {
+            Ok(b) => b,
+            // Starting over from the start block would be worse than waiting
+            Err(e) if e.is_transient() => {
+                store.record_error(e, "Reading most_recent_block").await;
+                fetched.deferred = true;
+                return fetched;
+            }
+            Err(e) => {
+                store.record_error(e, "Reading most_recent_block").await;
+                None
+            }
+        },
+    };
+    let block = last_indexed.unwrap_or(config.start_block);
+    let precompile = config.gmp_precompile;
+
+    // 2. Query the explorer, keeping on through outages by reading the logs from a node instead
+    let mut from_explorer = true;
+    let mut events_found = match events
+        .token_transfers(precompile, block + 1, budget.max_transfers)
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            from_explorer = false;
+            store
+                .record_error(e, "Querying etherscan, falling back to RPC")
+                .await;
+            let logs = events.log_transfers(precompile, block + 1, budget.max_log_queries);
+            match logs.await {
+                Ok(r) => r,
+                Err(e) => {
+                    store
+                        .record_error(e, "Querying RPC logs after etherscan failed")
+                        .await;
+                    return fetched;
+                }
+            }
+        }
+    };
+    for v in invariants::blocks_in_order(&mut events_found, last_indexed, |e| e.block_number) {
+        store
+            .record_error(invariants::violated(v), "Checking token transfers")
+            .await;
+    }
+
+    // A full page may have cut the last block short, so leave that block for the next run. A page
+    // of a single block can't be cut, so that block is read whole from the node's logs instead
+    if events_found.len() >= budget.max_transfers {
+        stats.saturated = true;
+        if drop_last_block(&mut events_found, |e| e.block_number).is_none() {
+            let only = events_found[0].block_number;
+            match events.log_transfers(precompile, only, 1).await {
+                Ok(mut logs) => {
+                    logs.retain(|e| e.block_number == only);
+                    events_found = logs;
+                }
+                Err(e) => {
+                    store
+                        .record_error(e, "Reading a full page's only block from RPC logs")
+                        .await;
+                    return fetched;
+                }
+            }
+        }
+    }
+
+    // Native GLMR is only listed by the explorer, the RPC fallback can't see it
+    let mut native_found = vec![];
+    let mut complete = from_explorer;
+    if from_explorer {
+        let to_block = match stats.saturated {
+            true => events_found
+                .last()
+                .map(|e| e.block_number)
+                .unwrap_or(LATEST_BLOCK),
+            false => LATEST_BLOCK,
+        };
+        let native = events.native_transfers(precompile, block + 1, to_block, budget.max_transfers);
+        match native.await {
+            Ok(mut native_data) => {
+                let skipped =
+                    invariants::blocks_in_order(&mut native_data, last_indexed, |t| t.block_num);
+                for v in skipped {
+                    store
+                        .record_error(invariants::violated(v), "Checking native transfers")
+                        .await;
+                }
+                // Same as above, but everything after the cut has to wait for the next run. The
+                // logs have no native transfers, so a single block is listed again on its own
+                if native_data.len() >= budget.max_transfers {
+                    stats.saturated = true;
+                    match drop_last_block(&mut native_data, |t| t.block_num) {
+                        Some(cut) => events_found.retain(|e| e.block_number < cut),
+                        None => {
+                            let only = native_data[0].block_num;
+                            let Some(whole) = native_block(events, store, precompile, only).await
+                            else {
+                                return fetched
+                            };
+                            native_data = whole;
+                            events_found.retain(|e| e.block_number <= only);
+                        }
+                    }
+                }
+                native_found = native_data;
+            }
+            Err(e) => {
+                complete = false;
+                store
+                    .record_error(e, "Querying etherscan internal transactions")
+                    .await;
+            }
+        }
+    }
+
+    fetched.complete = complete && !stats.saturated;
+    fetched.batch = TransferBatch {
+        events: events_found,
+        native: native_found,
+        from_explorer,
+        after_block: Some(block),
+    };
+    if let Some(last_block) = fetched.batch.last_block() {
+        stats.blocks = last_block.saturating_sub(block);
+    }
+    if !fetched.batch.is_empty() {
+        if let Err(e) = store.archive(&fetched.batch).await {
+            store.record_error(e, "Archiving fetched transfers").await;
+        }
+    }
+    fetched
+}
+
+/// Decodes, prices and stores a batch of fetched transfers, skipping any already stored. `now` is
+/// in unix seconds.
+#[allow(clippy::too_many_arguments)]
+pub async fn process(
+    batch: TransferBatch,
+    events: &impl EventSource,
+    prices: &impl PriceSource,
+    store: &impl Store,
+    config: &Config,
+    budget: &WorkBudget,
+    stats: &mut RunStats,
+    now: u64,
+) -> Indexed {
+    let mut indexed = Indexed::default();
+    let precompile = config.gmp_precompile;
+    let TransferBatch {
+        events: events_found,
+        native: native_found,
+        from_explorer,
+        ..
+    } = batch;
+
+    // 3. Sort & format data (lowest timestamp are first)
+    let mut transfers = forward_transfers(&events_found, precompile);
+    native::merge(&mut transfers, native_found);
+    if transfers.is_empty() {
+        return indexed;
+    }
+
+    // The RPC fallback already reads timestamps from the node
+    if from_explorer {
+        match events.cross_check_timestamps(&mut transfers).await {
+            Ok(corrected) => indexed.corrected_timestamps = corrected,
+            Err(e) => store.record_error(e, "Cross checking timestamps").await,
+        }
+    }
+
+    // Whatever the cross-check couldn't fix is recorded, rather than silently stored as 0
+    for transfer in transfers.iter().filter(|t| t.timestamp == 0) {
+        let e = IndexerError::DecodeFailure(format!("timestamp of {}", transfer.tx_hash));
+        store.record_error(e, "Converting timestamps").await;
+    }
+
+    // 4. Ensure all of the tokens are already known, asking the contracts themselves when the
+    // explorer's metadata can't be trusted, and leaving out transfers of any that can't be
+    let mut tokens = tokens(&events_found, &transfers, precompile);
+    let overridden = match store.overridden_tokens().await {
+        Ok(o) => o,
+        Err(e) => {
+            indexed.deferred = e.is_transient();
+            indexed.failed = true;
+            store.record_error(e, "Reading overridden Tokens").await;
+            return indexed;
+        }
+    };
+    // Corrections made by hand win over both the registry and the explorer
+    for (addr, token) in tokens.iter_mut() {
+        if let Some(corrected) = overridden.get(addr) {
+            *token = corrected.clone();
+        }
+    }
+    let mut enriched = vec![];
+    for (address, decimals_known) in suspect_tokens(&events_found, precompile) {
+        let addr = format!("{:?}", address);
+        let Some(token) = tokens.get_mut(&addr) else {
+            continue
+        };
+        if overridden.contains_key(&addr) {
+            continue;
+        }
+        // The registry is kept right by hand, and seeded into the table every run
+        if let Some(known) = registry::token(&addr) {
+            *token = known;
+            continue;
+        }
+        let metadata = events.token_metadata(address).await;
+        if enrich(token, metadata, decimals_known) {
+            enriched.push(addr);
+            continue;
+        }
+        tokens.remove(&addr);
+        transfers.retain(|t| t.token_addr != addr);
+        let e = IndexerError::DecodeFailure(format!("decimals of token {addr}"));
+        store.record_error(e, "Enriching token metadata").await;
+    }
+    for (addr, v) in invariants::token_metadata(&mut tokens) {
+        transfers.retain(|t| t.token_addr != addr);
+        store
+            .record_error(invariants::violated(v), "Checking token metadata")
+            .await;
+    }
+    if transfers.is_empty() {
+        return indexed;
+    }
+    stats.transfers = transfers.len();
+    let token_list: Vec<&Token> = tokens.values().collect();
+    if let Err(e) = store.insert_tokens(&token_list).await {
+        indexed.deferred = e.is_transient();
+        indexed.failed = true;
+        store.record_error(e, "Inserting Tokens").await;
+        return indexed;
+    }
+    // Rows stored from bad explorer metadata before are corrected too
+    let enriched: Vec<&Token> = enriched.iter().filter_map(|a| tokens.get(a)).collect();
+    if !enriched.is_empty() {
+        if let Err(e) = store.update_tokens(&enriched).await {
+            indexed.deferred = e.is_transient();
+            indexed.failed = true;
+            store.record_error(e, "Updating enriched Tokens").await;
+            return indexed;
+        }
+    }
+
+    // 5. Query for historical prices, skipping stablecoins when they're worth 1 anyway
+    let at_par = config.quotes_in_usd();
+    // Each symbol's series has to reach back to its oldest transfer
+    let mut oldest: HashMap<&String, u64> = HashMap::new();
+    for tx in transfers.iter() {
+        if let Some(token) = tokens.get(&tx.token_addr) {
+            let since = oldest.entry(&token.token_sym).or_insert(tx.timestamp);
+            *since = (*since).min(tx.timestamp);
+        }
+    }
+    let mut series: HashMap<String, Vec<TimeSeries>> = HashMap::new();
+    for (symbol, since) in oldest {
+        if at_par && is_usd_stablecoin(symbol) {
+            continue;
+        }
+        let data = match prices.time_series(symbol, since).await {
+            Ok(d) => d,
+            Err(e) => {
+                store.record_error(e, "Fetching prices").await;
+                vec![]
+            }
+        };
+        // The transfers the series does cover are still priced from it
+        if let Some(first) = data.first().filter(|c| c.timestamp > since) {
+            let e = IndexerError::PriceFetchFailure {
+                symbol: symbol.clone(),
+                message: format!(
+                    "no price before {} for transfers from {since}",
+                    first.timestamp
+                ),
+            };
+            store.record_error(e, "Fetching prices").await;
+        }
+        series.insert(symbol.clone(), data);
+    }
+
+    // Catch feeds that have stopped updating, otherwise every valuation silently freezes
+    for (symbol, data) in series.iter() {
+        if let Some(reason) = prices::staleness(data, now) {
+            indexed.stale.push((symbol.clone(), reason));
+        }
+    }
+    indexed.stale.sort();
+    let stale: HashSet<&String> = indexed.stale.iter().map(|(symbol, _)| symbol).collect();
+
+    // Each symbol's matching resumes from where the last run left it
+    let mut cursors = match store.price_cursors().await {
+        Ok(c) => c,
+        // Matching from scratch would then overwrite the saved cursors
+        Err(e) if e.is_transient() => {
+            store.record_error(e, "Reading price cursors").await;
+            indexed.deferred = true;
+            indexed.failed = true;
+            return indexed;
+        }
+        Err(e) => {
+            store.record_error(e, "Reading price cursors").await;
+            HashMap::new()
+        }
+    };
+    let previous_cursors = cursors.clone();
+    value_transfers(
+        &mut transfers,
+        &tokens,
+        &series,
+        &stale,
+        at_par,
+        &mut cursors,
+    );
+
+    // 6. Insert into database. The cursors only move on once the transfers they priced are stored.
+    let inserted = store
+        .insert_transfers(&transfers, budget.insert_chunk_size)
+        .await;
+    if let Err(PartialInsert { stored, error }) = inserted {
+        indexed.deferred = error.is_transient();
+        indexed.failed = true;
+        store
+            .record_error(error, "Inserting new TransferForward txs")
+            .await;
+        // The rest are fetched again, from the first chunk that wasn't stored
+        stats.inserted = stored;
+        transfers.truncate(stored);
+        indexed.transfers = transfers;
+        return indexed;
+    }
+    stats.inserted = transfers.len();
+    if cursors != previous_cursors {
+        if let Err(e) = store.save_price_cursors(&cursors).await {
+            store.record_error(e, "Saving price cursors").await;
+        }
+    }
+
+    indexed.transfers = transfers;
+    indexed
+}
+
+/// Lists every native transfer in `block`, for when a full page held nothing else. None if the
+/// explorer couldn't list them.
+async fn native_block(
+    events: &impl EventSource,
+    store: &impl Store,
+    precompile: Address,
+    block: u64,
+) -> Option<Vec<TransferForward>> {
+    match events
+        .native_transfers(precompile, block, block, MAX_EXPLORER_PAGE)
+        .await
+    {
+        Ok(whole) => {
+            if whole.len() >= MAX_EXPLORER_PAGE {
+                let v = format!("block {block} has more native transfers than a page lists");
+                store
+                    .record_error(invariants::violated(v), "Listing a full page's only block")
+                    .await;
+            }
+            Some(whole)
+        }
+        Err(e) => {
+            store
+                .record_error(e, "Listing a full page's only block")
+                .await;
+            None
+        }
+    }
+}
+
+/// Drops the items in the last block, unless that would drop everything. Returns the dropped
+/// block. Items must be sorted by block.
+fn drop_last_block<T>(items: &mut Vec<T>, block: impl Fn(&T) -> u64) -> Option<u64> {
+    let first = block(items.first()?);
+    let last = block(items.last()?);
+    if first == last {
+        return None;
+    }
+    items.retain(|i| block(i) < last);
+    Some(last)
+}
+
+/// The token transfers that are liquidity being routed onwards, not yet priced.
+fn forward_transfers(events: &[TokenTransfer], precompile: Address) -> Vec<TransferForward> {
+    events
+        .iter()
+        .filter(|e| native::is_forward(e, precompile))
+        .map(|e| TransferForward {
+            tx_hash: format!("{:?}", e.hash),
+            token_addr: format!("{:?}", e.contract_address),
+            token_count: numeric::to_u128(e.value),
+            usd: Usd::default(),
+            usd_min: Usd::default(),
+            usd_max: Usd::default(),
+            block_num: e.block_number,
+            // Never plausible, so the cross-check replaces it if malformed
+            timestamp: e.time_stamp.parse().unwrap_or(0),
+            to_chain: UNDECODED_CHAIN, // Decoded from the payload later
+            price_uncertain: false,
+            dest_account: None, // Decoded from the payload later
+            timestamp_corrected: false,
+            price: None,
+        })
+        .collect()
+}
+
+/// Every token the transfers moved, keyed by address. GLMR comes from the registry, since the
+/// explorer only describes ERC-20s.
+fn tokens(
+    events: &[TokenTransfer],
+    transfers: &[TransferForward],
+    precompile: Address,
+) -> HashMap<String, Token> {
+    events
+        .iter()
+        .filter(|e| native::is_forward(e, precompile))
+        .map(|e| {
+            let addr = format!("{:?}", e.contract_address);
+            let token = Token {
+                contract_addr: addr.clone(),
+                token_name: e.token_name.clone(),
+                token_sym: e.token_symbol.clone(),
+                // Only a placeholder for suspect tokens, which are enriched or left out
+                decimals: explorer_decimals(&e.token_decimal).unwrap_or_default(),
+                category: None,
+                logo_url: None,
+            };
+            (addr, token)
+        })
+        .chain(
+            transfers
+                .iter()
+                .find(|t| t.token_addr == native::GLMR_ADDRESS)
+                .and_then(|_| registry::token(native::GLMR_ADDRESS))
+                .map(|t| (t.contract_addr.clone(), t)),
+        )
+        .collect()
+}
+
+/// Decimals as the explorer reports them, if they are a number a token amount can be scaled by.
+fn explorer_decimals(decimals: &str) -> Option<u32> {
+    decimals
+        .trim()
+        .parse::<u32>()
+        .ok()
+        .filter(|&d| numeric::scale(d).is_some())
+}
+
+/// The tokens whose explorer metadata can't be taken as is, because a name, symbol or decimals is
+/// missing or unusable, or their transfers disagree about one. Each comes with whether its
+/// decimals can be relied on all the same.
+fn suspect_tokens(events: &[TokenTransfer], precompile: Address) -> BTreeMap<Address, bool> {
+    let mut reported: HashMap<Address, [HashSet<&str>; 3]> = HashMap::new();
+    for e in events.iter().filter(|e| native::is_forward(e, precompile)) {
+        let [names, symbols, decimals] = reported.entry(e.contract_address).or_default();
+        names.insert(e.token_name.trim());
+        symbols.insert(e.token_symbol.trim());
+        decimals.insert(e.token_decimal.trim());
+    }
+    let clear = |values: &HashSet<&str>| values.len() == 1 && !values.contains("");
+    reported
+        .into_iter()
+        .filter_map(|(address, [names, symbols, decimals])| {
+            let decimals_known =
+                decimals.len() == 1 && decimals.iter().all(|d| explorer_decimals(d).is_some());
+            let trusted = clear(&names) && clear(&symbols) && decimals_known;
+            (!trusted).then_some((address, decimals_known))
+        })
+        .collect()
+}
+
+/// Overwrites a token's metadata with whatever its contract answered. Returns false if its
+/// decimals are still unknown, since guessing them would misvalue its transfers by orders of
+/// magnitude.
+fn enrich(token: &mut Token, metadata: TokenMetadata, decimals_known: bool) -> bool {
+    if let Some(name) = metadata.name {
+        token.token_name = name;
+    }
+    if let Some(symbol) = metadata.symbol {
+        token.token_sym = symbol;
+    }
+    match metadata.decimals.filter(|&d| numeric::scale(d).is_some()) {
+        Some(decimals) => token.decimals = decimals,
+        None if decimals_known => {}
+        None => return false,
+    }
+    true
+}
+
+fn is_usd_stablecoin(symbol: &str) -> bool {
+    symbol.contains("USDT") || symbol.contains("USDC") || symbol.contains("DAI")
+}
+
+/// Values each transfer at the price interpolated between the candles around its timestamp.
+/// Stablecoins are valued at a
+/// dollar when prices are `at_par`, and transfers priced from a `stale` series are flagged as
+/// uncertain. `matched` holds the timestamp of the candle each symbol was last priced from, and
+/// is updated as transfers are.
+fn value_transfers(
+    transfers: &mut [TransferForward],
+    tokens: &HashMap<String, Token>,
+    series: &HashMap<String, Vec<TimeSeries>>,
+    stale: &HashSet<&String>,
+    at_par: bool,
+    matched: &mut HashMap<String, u64>,
+) {
+    // Transfers and candles are both oldest first, so each symbol's search carries on from where
+    // its last match was
+    let mut cursors: HashMap<&String, usize> = HashMap::new();
+    for tx in transfers.iter_mut() {
+        let Some(token) = tokens.get(&tx.token_addr) else {
+            continue
+        };
+        if at_par && is_usd_stablecoin(&token.token_sym) {
+            tx.usd = numeric::usd_value(tx.token_count, token.decimals, 1.);
+            (tx.usd_min, tx.usd_max) = (tx.usd, tx.usd);
+            tx.price = Some(1.);
+            continue;
+        }
+        let Some(data) = series.get(&token.token_sym) else {
+            continue
+        };
+        // Transfers from before the series starts are left unpriced, rather than priced from a
+        // candle that could be months later
+        if data.first().map_or(true, |c| c.timestamp > tx.timestamp) {
+            tx.price_uncertain = true;
+            continue;
+        }
+
+        tx.price_uncertain = stale.contains(&token.token_sym);
+        let cursor = cursors
+            .entry(&token.token_sym)
+            .or_insert_with(|| resume_from(data, matched.get(&token.token_sym), tx.timestamp));
+        if let Some(price) = interpolated_price(data, cursor, tx.timestamp) {
+            tx.usd = numeric::usd_value(tx.token_count, token.decimals, price.estimate);
+            tx.usd_min = numeric::usd_value(tx.token_count, token.decimals, price.low);
+            tx.usd_max = numeric::usd_value(tx.token_count, token.decimals, price.high);
+            tx.price = Some(price.estimate as f64);
+            matched.insert(token.token_sym.clone(), data[*cursor].timestamp);
+        }
+    }
+}
+
+/// Where the search for a transfer at `timestamp` starts: the candle last matched, unless the
+/// transfer is older than that candle, as happens after a reindex.
+fn resume_from(series: &[TimeSeries], matched: Option<&u64>, timestamp: u64) -> usize {
+    match matched {
+        Some(&m) if m <= timestamp => series
+            .partition_point(|c| c.timestamp < m)
+            .min(series.len().saturating_sub(1)),
+        _ => 0,
+    }
+}
+
+/// A price along with the lowest and highest it could have been.
+#[derive(PartialEq, Debug)]
+struct Price {
+    estimate: f32,
+    low: f32,
+    high: f32,
+}
+
+/// The price at `timestamp`, interpolated linearly between the midpoints of the candles either
+/// side of it, and bounded by their lows and highs. Outside the series it is the candle at that
+/// end's. Searches forward from `cursor`, leaving it on the candle at or before `timestamp`.
+fn interpolated_price(series: &[TimeSeries], cursor: &mut usize, timestamp: u64) -> Option<Price> {
+    while series
+        .get(*cursor + 1)
+        .is_some_and(|next| next.timestamp <= timestamp)
+    {
+        *cursor += 1;
+    }
+    let before = series.get(*cursor)?;
+    let Some(after) = series
+        .get(*cursor + 1)
+        .filter(|_| before.timestamp < timestamp)
+    else {
+        return Some(Price {
+            estimate: before.midpoint(),
+            low: before.low,
+            high: before.high,
+        });
+    };
+    let elapsed =
+        (timestamp - before.timestamp) as f64 / (after.timestamp - before.timestamp) as f64;
+    let (from, to) = (before.midpoint() as f64, after.midpoint() as f64);
+    Some(Price {
+        estimate: (from + (to - from) * elapsed) as f32,
+        low: before.low.min(after.low),
+        high: before.high.max(after.high),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use futures_util::FutureExt;
+
+    use super::*;
+    use crate::eth::{H256, U256};
+
+    const WETH: &str = "0x1111111111111111111111111111111111111111";
+    const WBTC: &str = "0x2222222222222222222222222222222222222222";
+    const USDC: &str = "0x3333333333333333333333333333333333333333";
+
+    fn precompile() -> Address {
+        Config::default().gmp_precompile
+    }
+
+    /// A Wormhole mint of one whole `token`, which has 18 decimals.
+    fn mint(id: u64, block: u64, timestamp: u64, token: &str, symbol: &str) -> TokenTransfer {
+        TokenTransfer {
+            hash: H256::from_low_u64_be(id),
+            block_number: block,
+            time_stamp: timestamp.to_string(),
+            from: Address::zero(),
+            to: Some(precompile()),
+            contract_address: token.parse().unwrap(),
+            value: U256::exp10(18),
+            token_name: symbol.to_string(),
+            token_symbol: symbol.to_string(),
+            token_decimal: "18".to_string(),
+        }
+    }
+
+    fn candles(points: &[(u64, f32)]) -> Vec<TimeSeries> {
+        points
+            .iter()
+            .map(|&(timestamp, price)| TimeSeries {
+                timestamp,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+            })
+            .collect()
+    }
+
+    #[derive(Default)]
+    struct MockEvents {
+        transfers: Vec<TokenTransfer>,
+        native: RefCell<Vec<TransferForward>>,
+        explorer_down: bool,
+        queried_from: RefCell<Option<u64>>,
+        // What each token's contract answers, by address
+        metadata: HashMap<String, TokenMetadata>,
+    }
+
+    #[async_trait(?Send)]
+    impl EventSource for MockEvents {
+        async fn token_transfers(
+            &self,
+            _precompile: Address,
+            from_block: u64,
+            max: usize,
+        ) -> Result<Vec<TokenTransfer>, IndexerError> {
+            self.queried_from.replace(Some(from_block));
+            if self.explorer_down {
+                return Err(IndexerError::EtherscanFailure("down".to_string()));
+            }
+            Ok(self.transfers.iter().take(max).cloned().collect())
+        }
+
+        async fn log_transfers(
+            &self,
+            _precompile: Address,
+            _from_block: u64,
+            _max_queries: u64,
+        ) -> Result<Vec<TokenTransfer>, IndexerError> {
+            Ok(self.transfers.clone())
+        }
+
+        async fn native_transfers(
+            &self,
+            _precompile: Address,
+            _from_block: u64,
+            _to_block: u64,
+            _max: usize,
+        ) -> Result<Vec<TransferForward>, IndexerError> {
+            Ok(self.native.take())
+        }
+
+        async fn cross_check_timestamps(
+            &self,
+            _transfers: &mut [TransferForward],
+        ) -> Result<usize, IndexerError> {
+            Ok(0)
+        }
+
+        async fn token_metadata(&self, token: Address) -> TokenMetadata {
+            let addr = format!("{:?}", token);
+            self.metadata.get(&addr).cloned().unwrap_or_default()
+        }
+    }
+
+    #[derive(Default)]
+    struct MockPrices {
+        series: HashMap<String, Vec<(u64, f32)>>,
+        fetched: RefCell<Vec<String>>,
+    }
+
+    #[async_trait(?Send)]
+    impl PriceSource for MockPrices {
+        async fn time_series(
+            &self,
+            symbol: &str,
+            _since: u64,
+        ) -> Result<Vec<TimeSeries>, IndexerError> {
+            self.fetched.borrow_mut().push(symbol.to_string());
+            match self.series.get(symbol) {
+                Some(points) => Ok(candles(points)),
+                None => Err(IndexerError::PriceFetchFailure {
+                    symbol: symbol.to_string(),
+                    message: "unknown symbol".to_string(),
+                }),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct MockStore {
+        last_block: Option<u64>,
+        tokens: RefCell<Vec<String>>,
+        transfers: RefCell<Vec<(String, Usd, bool)>>,
+        cursors: RefCell<HashMap<String, u64>>,
+        errors: RefCell<Vec<String>>,
+        // Fails reads or inserts the way D1 does during a maintenance window
+        reads_unavailable: bool,
+        inserts_unavailable: bool,
+        // The chunk inserts fail from, as if D1 became unavailable partway
+        failing_chunk: Option<usize>,
+        updated_tokens: RefCell<Vec<(String, String, u32)>>,
+        overridden: HashMap<String, Token>,
+        // The block range of each archived batch
+        archived: RefCell<Vec<(Option<u64>, Option<u64>)>>,
+    }
+
+    fn unavailable() -> IndexerError {
+        IndexerError::DbUnavailable("D1_ERROR: Network connection lost.".to_string())
+    }
+
+    #[async_trait(?Send)]
+    impl Store for MockStore {
+        async fn last_indexed_block(&self) -> Result<Option<u64>, IndexerError> {
+            if self.reads_unavailable {
+                return Err(unavailable());
+            }
+            Ok(self.last_block)
+        }
+
+        async fn insert_tokens(&self, tokens: &[&Token]) -> Result<(), IndexerError> {
+            let mut stored = self.tokens.borrow_mut();
+            stored.extend(tokens.iter().map(|t| t.token_sym.clone()));
+            Ok(())
+        }
+
+        async fn update_tokens(&self, tokens: &[&Token]) -> Result<(), IndexerError> {
+            let mut updated = self.updated_tokens.borrow_mut();
+            updated.extend(
+                tokens
+                    .iter()
+                    .map(|t| (t.contract_addr.clone(), t.token_sym.clone(), t.decimals)),
+            );
+            Ok(())
+        }
+
+        async fn overridden_tokens(&self) -> Result<HashMap<String, Token>, IndexerError> {
+            Ok(self.overridden.clone())
+        }
+
+        async fn insert_transfers(
+            &self,
+            transfers: &[TransferForward],
+            chunk_size: usize,
+        ) -> Result<(), PartialInsert> {
+            let failing_chunk = match self.inserts_unavailable {
+                true => Some(0),
+                false => self.failing_chunk,
+            };
+            let mut stored = self.transfers.borrow_mut();
+            for (i, chunk) in transfers.chunks(chunk_size).enumerate() {
+                if failing_chunk == Some(i) {
+                    return Err(PartialInsert {
+                        stored: i * chunk_size,
+                        error: unavailable(),
+                    });
+                }
+                stored.extend(
+                    chunk
+                        .iter()
+                        .map(|t| (t.tx_hash.clone(), t.usd, t.price_uncertain)),
+                );
+            }
+            Ok(())
+        }
+
+        async fn price_cursors(&self) -> Result<HashMap<String, u64>, IndexerError> {
+            Ok(self.cursors.borrow().clone())
+        }
+
+        async fn save_price_cursors(
+            &self,
+            cursors: &HashMap<String, u64>,
+        ) -> Result<(), IndexerError> {
+            self.cursors.replace(cursors.clone());
+            Ok(())
+        }
+
+        async fn archive(&self, batch: &TransferBatch) -> Result<(), IndexerError> {
+            let range = (batch.first_block(), batch.last_block());
+            self.archived.borrow_mut().push(range);
+            Ok(())
+        }
+
+        async fn record_error(&self, _error: IndexerError, context: &str) {
+            self.errors.borrow_mut().push(context.to_string());
+        }
+    }
+
+    fn run(events: &MockEvents, prices: &MockPrices, store: &MockStore, now: u64) -> RunStats {
+        let budget = WorkBudget {
+            max_transfers: 3,
+            ..WorkBudget::default()
+        };
+        let mut stats = RunStats::default();
+        index(
+            events,
+            prices,
+            store,
+            &Config::default(),
+            &budget,
+            &mut stats,
+            now,
+        )
+        .now_or_never()
+        .expect("mocks never wait");
+        stats
+    }
+
+    fn usd_of(store: &MockStore, id: u64) -> Usd {
+        let hash = format!("{:?}", H256::from_low_u64_be(id));
+        let transfers = store.transfers.borrow();
+        transfers.iter().find(|t| t.0 == hash).expect("stored").1
+    }
+
+    /// Two hour WETH/USD candles as Twelve Data returns them.
+    fn weth_candles() -> Vec<TimeSeries> {
+        [
+            (1700000000, 2040., 2052., 2036., 2048.),
+            (1700007200, 2048., 2080., 2044., 2072.),
+            (1700014400, 2072., 2074., 2010., 2020.),
+        ]
+        .into_iter()
+        .map(|(timestamp, open, high, low, close)| TimeSeries {
+            timestamp,
+            open,
+            high,
+            low,
+            close,
+        })
+        .collect()
+    }
+
+    fn estimate(series: &[TimeSeries], cursor: &mut usize, timestamp: u64) -> Option<f32> {
+        interpolated_price(series, cursor, timestamp).map(|p| p.estimate)
+    }
+
+    #[test]
+    fn candles_are_priced_at_their_ohlc_midpoint() {
+        let midpoints: Vec<f32> = weth_candles().iter().map(|c| c.midpoint()).collect();
+        assert_eq!(midpoints, vec![2044., 2061., 2044.]);
+    }
+
+    #[test]
+    fn prices_are_interpolated_between_candles() {
+        let series = weth_candles();
+        let mut cursor = 0;
+        // Before and at the first candle
+        assert_eq!(estimate(&series, &mut cursor, 1699990000), Some(2044.));
+        assert_eq!(estimate(&series, &mut cursor, 1700000000), Some(2044.));
+        // Halfway to the second candle
+        assert_eq!(estimate(&series, &mut cursor, 1700003600), Some(2052.5));
+        assert_eq!(cursor, 0);
+        // A quarter of the way from the second to the third
+        assert_eq!(estimate(&series, &mut cursor, 1700009000), Some(2056.75));
+        assert_eq!(cursor, 1);
+        // Past the newest candle
+        assert_eq!(estimate(&series, &mut cursor, 1700020000), Some(2044.));
+        assert_eq!(cursor, 2);
+        assert!(interpolated_price(&[], &mut 0, 100).is_none());
+    }
+
+    #[test]
+    fn price_ranges_span_the_candles_priced_from() {
+        let series = weth_candles();
+        assert_eq!(
+            interpolated_price(&series, &mut 0, 1700003600),
+            Some(Price {
+                estimate: 2052.5,
+                low: 2036.,
+                high: 2080.,
+            })
+        );
+        assert_eq!(
+            interpolated_price(&series, &mut 0, 1700020000),
+            Some(Price {
+                estimate: 2044.,
+                low: 2010.,
+                high: 2074.,
+            })
+        );
+    }
+
+    #[test]
+    fn each_symbol_keeps_its_own_cursor() {
+        // A WBTC transfer late in the series used to drag WETH's search past its own match
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 10, 100, WBTC, "WBTC"),
+                mint(2, 11, 100, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([
+                ("WBTC".to_string(), vec![(100, 30000.), (200, 31000.)]),
+                ("WETH".to_string(), vec![(100, 1800.), (200, 1900.)]),
+            ]),
+            ..MockPrices::default()
+        };
+        let mut transfers = forward_transfers(&events.transfers, precompile());
+        transfers[0].timestamp = 200;
+        let tokens = tokens(&events.transfers, &transfers, precompile());
+        let series = prices
+            .series
+            .iter()
+            .map(|(s, p)| (s.clone(), candles(p)))
+            .collect();
+        let mut matched = HashMap::new();
+        value_transfers(
+            &mut transfers,
+            &tokens,
+            &series,
+            &HashSet::new(),
+            true,
+            &mut matched,
+        );
+        assert_eq!(transfers[0].usd, Usd(3100000));
+        assert_eq!(transfers[1].usd, Usd(180000));
+        assert_eq!(transfers[0].price, Some(31000.));
+        assert_eq!(transfers[1].price, Some(1800.));
+        assert_eq!(matched["WBTC"], 200);
+        assert_eq!(matched["WETH"], 100);
+    }
+
+    #[test]
+    fn matching_resumes_from_the_last_matched_candle() {
+        let series = candles(&[(100, 1.), (200, 2.), (300, 3.)]);
+        assert_eq!(resume_from(&series, None, 250), 0);
+        assert_eq!(resume_from(&series, Some(&200), 250), 1);
+        // A cursor past the end of a shorter series keeps to the newest candle
+        assert_eq!(resume_from(&series, Some(&400), 450), 2);
+        // Transfers older than the cursor, say after a reindex, search from the start
+        assert_eq!(resume_from(&series, Some(&300), 120), 0);
+    }
+
+    #[test]
+    fn price_cursors_carry_over_between_runs() {
+        let prices = MockPrices {
+            series: HashMap::from([(
+                "WETH".to_string(),
+                vec![(100, 1800.), (200, 1900.), (300, 2000.)],
+            )]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        let first = MockEvents {
+            transfers: vec![mint(1, 10, 190, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        run(&first, &prices, &store, 300);
+        assert_eq!(usd_of(&store, 1), Usd(189000));
+        assert_eq!(store.cursors.borrow()["WETH"], 100);
+
+        let second = MockEvents {
+            transfers: vec![mint(2, 11, 310, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        run(&second, &prices, &store, 300);
+        assert_eq!(usd_of(&store, 2), Usd(200000));
+        assert_eq!(store.cursors.borrow()["WETH"], 300);
+    }
+
+    #[test]
+    fn indexes_and_prices_new_transfers() {
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 10, 100, WETH, "WETH"),
+                mint(2, 11, 290, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([(
+                "WETH".to_string(),
+                vec![(100, 1800.), (200, 1900.), (300, 2000.)],
+            )]),
+            ..MockPrices::default()
+        };
+        let store = MockStore {
+            last_block: Some(9),
+            ..MockStore::default()
+        };
+        let stats = run(&events, &prices, &store, 300);
+
+        assert_eq!(*events.queried_from.borrow(), Some(10));
+        assert_eq!(stats.transfers, 2);
+        assert_eq!(stats.blocks, 2);
+        assert!(!stats.saturated);
+        assert_eq!(usd_of(&store, 1), Usd(180000));
+        assert_eq!(usd_of(&store, 2), Usd(199000));
+        assert_eq!(*store.tokens.borrow(), vec!["WETH".to_string()]);
+        assert!(store.errors.borrow().is_empty());
+    }
+
+    #[test]
+    fn an_unavailable_store_defers_instead_of_starting_over() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 10, 100, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        let store = MockStore {
+            reads_unavailable: true,
+            ..MockStore::default()
+        };
+        run(&events, &MockPrices::default(), &store, 100);
+        assert_eq!(*events.queried_from.borrow(), None);
+        assert!(store.tokens.borrow().is_empty());
+        assert_eq!(*store.errors.borrow(), vec!["Reading most_recent_block"]);
+    }
+
+    #[test]
+    fn failed_inserts_leave_the_price_cursors_alone() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 10, 190, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.), (200, 1900.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore {
+            inserts_unavailable: true,
+            ..MockStore::default()
+        };
+        run(&events, &prices, &store, 200);
+        assert!(store.transfers.borrow().is_empty());
+        assert!(store.cursors.borrow().is_empty());
+        assert_eq!(
+            *store.errors.borrow(),
+            vec!["Inserting new TransferForward txs"]
+        );
+    }
+
+    #[test]
+    fn transfers_stored_before_a_failed_chunk_are_reported() {
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 10, 150, WETH, "WETH"),
+                mint(2, 11, 160, WETH, "WETH"),
+                mint(3, 11, 170, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.), (200, 1900.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore {
+            failing_chunk: Some(1),
+            ..MockStore::default()
+        };
+        let budget = WorkBudget {
+            insert_chunk_size: 2,
+            ..WorkBudget::default()
+        };
+        let mut stats = RunStats::default();
+        let indexed = index(
+            &events,
+            &prices,
+            &store,
+            &Config::default(),
+            &budget,
+            &mut stats,
+            200,
+        )
+        .now_or_never()
+        .expect("mocks never wait");
+        assert!(indexed.deferred);
+        assert!(indexed.failed);
+        assert_eq!(stats.inserted, 2);
+        assert_eq!(indexed.transfers.len(), 2);
+        assert_eq!(store.transfers.borrow().len(), 2);
+        assert!(store.cursors.borrow().is_empty());
+    }
+
+    #[test]
+    fn runs_resume_from_the_first_chunk_that_failed() {
+        assert_eq!(resume_after(None, None), None);
+        assert_eq!(resume_after(Some(20), None), Some(20));
+        // The failed chunk started partway through the last stored block
+        assert_eq!(resume_after(Some(20), Some(20)), Some(19));
+        assert_eq!(resume_after(Some(20), Some(25)), Some(20));
+        assert_eq!(resume_after(None, Some(25)), Some(24));
+    }
+
+    #[test]
+    fn starts_from_the_first_block_when_empty() {
+        let events = MockEvents::default();
+        let store = MockStore::default();
+        run(&events, &MockPrices::default(), &store, 0);
+        assert_eq!(
+            *events.queried_from.borrow(),
+            Some(Config::default().start_block + 1)
+        );
+        assert!(store.transfers.borrow().is_empty());
+    }
+
+    /// GLMR sent straight to the precompile, worth one wei.
+    fn glmr(id: u64, block: u64, timestamp: u64) -> TransferForward {
+        TransferForward {
+            tx_hash: format!("{:?}", H256::from_low_u64_be(id)),
+            token_addr: native::GLMR_ADDRESS.to_string(),
+            token_count: 1,
+            usd: Usd::default(),
+            usd_min: Usd::default(),
+            usd_max: Usd::default(),
+            block_num: block,
+            timestamp,
+            to_chain: 1000,
+            price_uncertain: false,
+            dest_account: None,
+            timestamp_corrected: false,
+            price: None,
+        }
+    }
+
+    #[test]
+    fn queued_runs_fetch_from_the_watermark() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 21, 100, WETH, "WETH")],
+            native: RefCell::new(vec![glmr(2, 22, 110)]),
+            ..MockEvents::default()
+        };
+        let store = MockStore {
+            last_block: Some(9),
+            ..MockStore::default()
+        };
+        let mut stats = RunStats::default();
+        let fetched = fetch(
+            &events,
+            &store,
+            &Config::default(),
+            &WorkBudget::default(),
+            &mut stats,
+            Some(20),
+        )
+        .now_or_never()
+        .expect("mocks never wait");
+
+        assert_eq!(*events.queried_from.borrow(), Some(21));
+        assert!(!fetched.deferred);
+        assert_eq!(fetched.batch.len(), 2);
+        assert_eq!(fetched.batch.last_block(), Some(22));
+        assert_eq!(*store.archived.borrow(), vec![(Some(21), Some(22))]);
+        assert!(store.transfers.borrow().is_empty());
+    }
+
+    #[test]
+    fn batches_split_between_blocks() {
+        let batch = TransferBatch {
+            events: vec![
+                mint(1, 10, 100, WETH, "WETH"),
+                mint(2, 10, 100, WETH, "WETH"),
+                mint(3, 11, 110, WETH, "WETH"),
+                mint(4, 12, 120, WETH, "WETH"),
+                mint(5, 12, 120, WETH, "WETH"),
+                mint(6, 12, 120, WETH, "WETH"),
+            ],
+            native: vec![glmr(7, 11, 110)],
+            from_explorer: true,
+            after_block: Some(5),
+        };
+        let batches = batch.split(2);
+        let blocks: Vec<(Vec<u64>, usize)> = batches
+            .iter()
+            .map(|b| {
+                assert!(b.from_explorer);
+                let blocks = b.events.iter().map(|e| e.block_number).collect();
+                (blocks, b.native.len())
+            })
+            .collect();
+        // Block 12 doesn't fit in one message, but isn't split
+        assert_eq!(
+            blocks,
+            vec![(vec![10, 10], 0), (vec![11], 1), (vec![12, 12, 12], 0)]
+        );
+        // Each carries on from the one before it
+        let after: Vec<Option<u64>> = batches.iter().map(|b| b.after_block).collect();
+        assert_eq!(after, vec![Some(5), Some(10), Some(11)]);
+    }
+
+    #[test]
+    fn batches_are_stored_the_same_after_the_queue() {
+        let batch = TransferBatch {
+            events: vec![mint(1, 10, 100, WETH, "WETH")],
+            native: vec![],
+            from_explorer: true,
+            after_block: Some(9),
+        };
+        let queued: TransferBatch =
+            serde_json::from_str(&serde_json::to_string(&batch).unwrap()).unwrap();
+        assert_eq!(queued.events[0].value, U256::exp10(18));
+        assert_eq!(queued.events[0].to, Some(precompile()));
+        assert_eq!(queued.after_block, Some(9));
+
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        let indexed = process(
+            queued,
+            &MockEvents::default(),
+            &prices,
+            &store,
+            &Config::default(),
+            &WorkBudget::default(),
+            &mut RunStats::default(),
+            100,
+        )
+        .now_or_never()
+        .expect("mocks never wait");
+
+        assert_eq!(indexed.transfers.len(), 1);
+        assert_eq!(usd_of(&store, 1), Usd(180000));
+    }
+
+    #[test]
+    fn stablecoins_are_worth_a_dollar_without_a_price_query() {
+        let mut transfer = mint(1, 10, 100, USDC, "USDC");
+        transfer.value = U256::from(2_500_000);
+        transfer.token_decimal = "6".to_string();
+        let events = MockEvents {
+            transfers: vec![transfer],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices::default();
+        let store = MockStore::default();
+        run(&events, &prices, &store, 100);
+
+        assert_eq!(usd_of(&store, 1), Usd(250));
+        assert!(prices.fetched.borrow().is_empty());
+    }
+
+    #[test]
+    fn missing_decimals_are_read_from_the_contract() {
+        let mut transfer = mint(1, 10, 100, USDC, "USDC");
+        transfer.value = U256::from(2_500_000);
+        transfer.token_decimal = "".to_string();
+        let events = MockEvents {
+            transfers: vec![transfer],
+            metadata: HashMap::from([(
+                USDC.to_string(),
+                TokenMetadata {
+                    decimals: Some(6),
+                    ..TokenMetadata::default()
+                },
+            )]),
+            ..MockEvents::default()
+        };
+        let store = MockStore::default();
+        run(&events, &MockPrices::default(), &store, 100);
+
+        // Read as 18 decimals, this used to be worth a fraction of a cent
+        assert_eq!(usd_of(&store, 1), Usd(250));
+        assert_eq!(
+            *store.updated_tokens.borrow(),
+            vec![(USDC.to_string(), "USDC".to_string(), 6)]
+        );
+    }
+
+    #[test]
+    fn tokens_corrected_by_hand_are_priced_with_their_corrections() {
+        // The explorer misreports decimals, which the contract would normally be asked about
+        let mut transfer = mint(1, 10, 100, USDC, "USDC.e");
+        transfer.value = U256::from(2_500_000);
+        transfer.token_decimal = "".to_string();
+        let corrected = Token {
+            contract_addr: USDC.to_string(),
+            token_name: "USD Coin".to_string(),
+            token_sym: "USDC".to_string(),
+            decimals: 6,
+            ..Token::default()
+        };
+        let events = MockEvents {
+            transfers: vec![transfer],
+            ..MockEvents::default()
+        };
+        let store = MockStore {
+            overridden: HashMap::from([(USDC.to_string(), corrected)]),
+            ..MockStore::default()
+        };
+        run(&events, &MockPrices::default(), &store, 100);
+
+        assert_eq!(usd_of(&store, 1), Usd(250));
+        assert!(store.updated_tokens.borrow().is_empty());
+        assert!(store.errors.borrow().is_empty());
+    }
+
+    #[test]
+    fn tokens_with_unknown_decimals_are_left_out() {
+        let mut transfer = mint(1, 10, 100, USDC, "USDC");
+        transfer.token_decimal = "".to_string();
+        let events = MockEvents {
+            transfers: vec![transfer, mint(2, 11, 100, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        run(&events, &prices, &store, 100);
+
+        assert_eq!(store.transfers.borrow().len(), 1);
+        assert_eq!(usd_of(&store, 2), Usd(180000));
+        assert_eq!(*store.errors.borrow(), vec!["Enriching token metadata"]);
+    }
+
+    #[test]
+    fn inconsistent_or_missing_metadata_is_suspect() {
+        let mut renamed = mint(2, 11, 100, WETH, "WETH");
+        renamed.token_symbol = "WETH.wh".to_string();
+        let mut unnamed = mint(3, 11, 100, WBTC, "WBTC");
+        unnamed.token_name = " ".to_string();
+        let events = [
+            mint(1, 10, 100, WETH, "WETH"),
+            renamed,
+            unnamed,
+            mint(4, 12, 100, USDC, "USDC"),
+        ];
+        let suspect = suspect_tokens(&events, precompile());
+        assert_eq!(
+            suspect,
+            BTreeMap::from([(WETH.parse().unwrap(), true), (WBTC.parse().unwrap(), true)])
+        );
+
+        let mut token = Token {
+            token_name: "Wrapped Ether".to_string(),
+            token_sym: "WETH.wh".to_string(),
+            ..Token::default()
+        };
+        let metadata = TokenMetadata {
+            symbol: Some("WETH".to_string()),
+            ..TokenMetadata::default()
+        };
+        assert!(enrich(&mut token, metadata.clone(), true));
+        assert_eq!((token.token_sym.as_str(), token.decimals), ("WETH", 18));
+        assert!(!enrich(&mut token, metadata, false));
+    }
+
+    #[test]
+    fn stablecoins_are_priced_when_quoting_another_currency() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 10, 100, USDC, "USDC")],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("USDC".to_string(), vec![(100, 0.9)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        let config = Config {
+            price_quote: "EUR".to_string(),
+            ..Config::default()
+        };
+        let budget = WorkBudget::default();
+        index(
+            &events,
+            &prices,
+            &store,
+            &config,
+            &budget,
+            &mut RunStats::default(),
+            100,
+        )
+        .now_or_never()
+        .unwrap();
+
+        assert_eq!(usd_of(&store, 1), Usd(90));
+        assert_eq!(*prices.fetched.borrow(), vec!["USDC".to_string()]);
+    }
+
+    #[test]
+    fn failed_price_queries_leave_transfers_uncertain() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 10, 100, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        let store = MockStore::default();
+        run(&events, &MockPrices::default(), &store, 100);
+
+        assert_eq!(store.transfers.borrow()[0].1, Usd(0));
+        assert!(store.transfers.borrow()[0].2);
+        assert_eq!(*store.errors.borrow(), vec!["Fetching prices".to_string()]);
+    }
+
+    #[test]
+    fn batches_reaching_back_past_a_series_price_what_it_covers() {
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 10, 100, WETH, "WETH"),
+                mint(2, 11, 290, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(200, 1900.), (300, 2000.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        run(&events, &prices, &store, 300);
+
+        assert_eq!(usd_of(&store, 1), Usd(0));
+        assert!(store.transfers.borrow()[0].2);
+        assert_eq!(usd_of(&store, 2), Usd(199000));
+        assert!(!store.transfers.borrow()[1].2);
+        assert_eq!(*store.errors.borrow(), vec!["Fetching prices".to_string()]);
+    }
+
+    #[test]
+    fn only_runs_that_read_every_block_are_caught_up() {
+        let caught_up = |events: &MockEvents| {
+            let prices = MockPrices {
+                series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+                ..MockPrices::default()
+            };
+            let budget = WorkBudget {
+                max_transfers: 2,
+                ..WorkBudget::default()
+            };
+            index(
+                events,
+                &prices,
+                &MockStore::default(),
+                &Config::default(),
+                &budget,
+                &mut RunStats::default(),
+                100,
+            )
+            .now_or_never()
+            .unwrap()
+            .caught_up
+        };
+
+        let quiet = MockEvents::default();
+        assert!(caught_up(&quiet));
+        let one = MockEvents {
+            transfers: vec![mint(1, 10, 100, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        assert!(caught_up(&one));
+        let full_page = MockEvents {
+            transfers: vec![
+                mint(1, 10, 100, WETH, "WETH"),
+                mint(2, 11, 100, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        assert!(!caught_up(&full_page));
+        let explorer_down = MockEvents {
+            explorer_down: true,
+            ..one
+        };
+        assert!(!caught_up(&explorer_down));
+    }
+
+    #[test]
+    fn stale_series_are_reported() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 10, 100, WETH, "WETH")],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        let budget = WorkBudget::default();
+        let indexed = index(
+            &events,
+            &prices,
+            &store,
+            &Config::default(),
+            &budget,
+            &mut RunStats::default(),
+            1_000_000,
+        )
+        .now_or_never()
+        .unwrap();
+
+        assert_eq!(indexed.stale.len(), 1);
+        assert_eq!(indexed.stale[0].0, "WETH");
+        assert_eq!(usd_of(&store, 1), Usd(180000));
+        assert!(store.transfers.borrow()[0].2);
+    }
+
+    #[test]
+    fn full_pages_leave_the_last_block_for_the_next_run() {
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 10, 100, WETH, "WETH"),
+                mint(2, 11, 110, WETH, "WETH"),
+                mint(3, 12, 120, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        let stats = run(&events, &prices, &store, 100);
+
+        assert!(stats.saturated);
+        assert_eq!(stats.transfers, 2);
+        assert_eq!(stats.inserted, 2);
+        assert_eq!(store.transfers.borrow().len(), 2);
+    }
+
+    #[test]
+    fn a_full_page_of_one_block_is_read_whole_from_the_logs() {
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 10, 100, WETH, "WETH"),
+                mint(2, 10, 100, WETH, "WETH"),
+                mint(3, 10, 100, WETH, "WETH"),
+                mint(4, 10, 100, WETH, "WETH"),
+                mint(5, 11, 110, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        let stats = run(&events, &prices, &store, 200);
+
+        // The page only had room for three of block 10's four transfers
+        assert!(stats.saturated);
+        assert_eq!(stats.inserted, 4);
+        let stored = store.transfers.borrow();
+        let hash = |id| format!("{:?}", H256::from_low_u64_be(id));
+        assert!((1..=4).all(|id| stored.iter().any(|t| t.0 == hash(id))));
+        assert!(!stored.iter().any(|t| t.0 == hash(5)));
+    }
+
+    #[test]
+    fn a_full_page_of_one_block_is_kept() {
+        let mut transfers = vec![10, 10, 10];
+        assert_eq!(drop_last_block(&mut transfers, |b| *b), None);
+        assert_eq!(transfers.len(), 3);
+        let mut transfers = vec![10, 11, 11];
+        assert_eq!(drop_last_block(&mut transfers, |b| *b), Some(11));
+        assert_eq!(transfers, vec![10]);
+    }
+
+    #[test]
+    fn explorer_outages_fall_back_to_logs_without_native_transfers() {
+        let events = MockEvents {
+            transfers: vec![mint(1, 10, 100, WETH, "WETH")],
+            native: RefCell::new(vec![TransferForward {
+                tx_hash: format!("{:?}", H256::from_low_u64_be(2)),
+                token_addr: native::GLMR_ADDRESS.to_string(),
+                token_count: 1,
+                usd: Usd::default(),
+                usd_min: Usd::default(),
+                usd_max: Usd::default(),
+                block_num: 10,
+                timestamp: 100,
+                to_chain: 1000,
+                price_uncertain: false,
+                dest_account: None,
+                timestamp_corrected: false,
+                price: None,
+            }]),
+            explorer_down: true,
+            ..MockEvents::default()
+        };
+        let prices = MockPrices {
+            series: HashMap::from([("WETH".to_string(), vec![(100, 1800.)])]),
+            ..MockPrices::default()
+        };
+        let store = MockStore::default();
+        run(&events, &prices, &store, 100);
+
+        assert_eq!(store.transfers.borrow().len(), 1);
+        assert_eq!(usd_of(&store, 1), Usd(180000));
+        assert_eq!(
+            *store.errors.borrow(),
+            vec!["Querying etherscan, falling back to RPC".to_string()]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invariant violated: block 10 follows block 11")]
+    fn out_of_order_transfers_panic_in_debug_builds() {
+        let events = MockEvents {
+            transfers: vec![
+                mint(1, 11, 110, WETH, "WETH"),
+                mint(2, 10, 100, WETH, "WETH"),
+            ],
+            ..MockEvents::default()
+        };
+        run(&events, &MockPrices::default(), &MockStore::default(), 100);
+    }
+
+    #[test]
+    fn only_forwards_are_indexed() {
+        let mut outgoing = mint(1, 10, 100, WETH, "WETH");
+        outgoing.from = precompile();
+        outgoing.to = Some(Address::repeat_byte(0x44));
+        let incoming = mint(2, 10, 100, WETH, "WETH");
+        let transfers = forward_transfers(&[outgoing, incoming], precompile());
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(
+            transfers[0].tx_hash,
+            format!("{:?}", H256::from_low_u64_be(2))
+        );
+        assert_eq!(transfers[0].token_count, 10_u128.pow(18));
+    }
+}
@@ -0,0 +1,484 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use async_trait::async_trait;
+
+use crate::errors::IndexerError;
+
+/// A candle of a price series, in the quote currency.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimeSeries {
+    pub timestamp: u64,
+    pub open: f32,
+    pub high: f32,
+    pub low: f32,
+    pub close: f32,
+}
+
+// Must match the interval prices are requested at
+const INTERVAL_SECONDS: u64 = 2 * 60 * 60;
+// A day of candles with no movement at all means the feed has stopped updating
+const FLAT_WINDOW: usize = 12;
+// How many intervals the newest candle can lag behind before the series counts as stale
+const STALE_INTERVALS: u64 = 3;
+
+// CoinGecko ids of the assets registry tokens are priced as
+const COINGECKO_IDS: [(&str, &str); 7] = [
+    ("ETH", "ethereum"),
+    ("BTC", "bitcoin"),
+    ("USDC", "usd-coin"),
+    ("USDT", "tether"),
+    ("DAI", "dai"),
+    ("GLMR", "moonbeam"),
+    ("DOT", "polkadot"),
+];
+
+/// The providers that can be configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderName {
+    TwelveData,
+    CoinGecko,
+    DefiLlama,
+}
+
+impl FromStr for ProviderName {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "twelve_data" => Ok(Self::TwelveData),
+            "coingecko" => Ok(Self::CoinGecko),
+            "defillama" => Ok(Self::DefiLlama),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ProviderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TwelveData => "Twelve Data",
+            Self::CoinGecko => "CoinGecko",
+            Self::DefiLlama => "DefiLlama",
+        })
+    }
+}
+
+/// The providers to price from, in the order they're tried, as a comma-separated list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderNames(pub Vec<ProviderName>);
+
+impl FromStr for ProviderNames {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|p| p.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// How the series of several providers are turned into one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceStrategy {
+    /// The first provider that returns a series is used, and the rest are only tried if it fails
+    First,
+    /// Every provider is queried, and each candle is the median of those that returned one
+    Median,
+}
+
+impl FromStr for PriceStrategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "median" => Ok(Self::Median),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A service price series can be fetched from.
+#[async_trait(?Send)]
+pub trait PriceProvider {
+    /// What the provider is called in logs and errors.
+    fn name(&self) -> &str;
+
+    /// Candles for `symbol` in the quote currency, oldest first.
+    async fn time_series(&self, symbol: &str) -> Result<Vec<TimeSeries>, String>;
+}
+
+impl TimeSeries {
+    /// The candle's price as a single number, the mean of its open, high, low and close.
+    pub fn midpoint(&self) -> f32 {
+        (self.open + self.high + self.low + self.close) / 4.
+    }
+}
+
+/// Returns why a series can't be trusted for valuations, if it can't. A stale feed shows up either
+/// as the newest candle lagging far behind `now` or as the same candle repeated over and over.
+pub fn staleness(data: &[TimeSeries], now: u64) -> Option<String> {
+    let Some(last) = data.last() else {
+        return Some("series is empty".to_owned());
+    };
+    let lag = now.saturating_sub(last.timestamp);
+    if lag > INTERVAL_SECONDS * STALE_INTERVALS {
+        return Some(format!("newest candle is {} minutes old", lag / 60));
+    }
+
+    let window = &data[data.len().saturating_sub(FLAT_WINDOW)..];
+    if window.len() > 1
+        && window
+            .iter()
+            .all(|ts| ts.open == last.open && ts.close == last.close)
+    {
+        return Some(format!("last {} candles are identical", window.len()));
+    }
+
+    None
+}
+
+/// The asset a token symbol is priced as: wrapped tokens as what they wrap, e.g. WETH as ETH, and
+/// XC-20s as the asset they represent, e.g. xcDOT as DOT. Will be wrong if there is ever a normal
+/// coin that starts with "W".
+pub fn base_symbol(symbol: &str) -> &str {
+    symbol
+        .strip_prefix('W')
+        .or_else(|| symbol.strip_prefix("xc"))
+        .unwrap_or(symbol)
+}
+
+/// The CoinGecko id of the asset `symbol` is priced as, which DefiLlama prices by too.
+pub fn coingecko_id(symbol: &str) -> Option<&'static str> {
+    let base = base_symbol(symbol);
+    COINGECKO_IDS
+        .iter()
+        .find(|(s, _)| *s == base)
+        .map(|(_, id)| *id)
+}
+
+/// Buckets prices sampled at unix-second timestamps into candles of the interval prices are
+/// requested at, oldest first, for providers that only return points.
+pub fn candles(points: &[(u64, f32)]) -> Vec<TimeSeries> {
+    let mut buckets: BTreeMap<u64, TimeSeries> = BTreeMap::new();
+    let mut sorted = points.to_vec();
+    sorted.sort_by_key(|(timestamp, _)| *timestamp);
+    for (timestamp, price) in sorted {
+        let start = timestamp - timestamp % INTERVAL_SECONDS;
+        buckets
+            .entry(start)
+            .and_modify(|c| {
+                c.high = c.high.max(price);
+                c.low = c.low.min(price);
+                c.close = price;
+            })
+            .or_insert(TimeSeries {
+                timestamp: start,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+            });
+    }
+    buckets.into_values().collect()
+}
+
+fn median_of(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.
+    } else {
+        values[mid]
+    }
+}
+
+/// Combines several providers' series into one, oldest first. Each candle's open, high, low and
+/// close are the medians of those of the series with a candle at its timestamp.
+pub fn median(series: Vec<Vec<TimeSeries>>) -> Vec<TimeSeries> {
+    let mut by_timestamp: BTreeMap<u64, Vec<TimeSeries>> = BTreeMap::new();
+    for candle in series.into_iter().flatten() {
+        by_timestamp
+            .entry(candle.timestamp)
+            .or_default()
+            .push(candle);
+    }
+    by_timestamp
+        .into_iter()
+        .map(|(timestamp, candles)| TimeSeries {
+            timestamp,
+            open: median_of(candles.iter().map(|c| c.open).collect()),
+            high: median_of(candles.iter().map(|c| c.high).collect()),
+            low: median_of(candles.iter().map(|c| c.low).collect()),
+            close: median_of(candles.iter().map(|c| c.close).collect()),
+        })
+        .collect()
+}
+
+/// Prices `symbol` from `providers` in order. With `PriceStrategy::First`, the first series
+/// returned is used, and the next providers are only asked for the candles before it starts, until
+/// the series reaches back to `since`, the oldest transfer to price. With `PriceStrategy::Median`
+/// every provider is queried and the series returned combined. Only if no provider returns any
+/// candles at all is it a failure.
+pub async fn fetch(
+    providers: &[Box<dyn PriceProvider>],
+    strategy: PriceStrategy,
+    symbol: &str,
+    since: u64,
+) -> Result<Vec<TimeSeries>, IndexerError> {
+    let mut series: Vec<Vec<TimeSeries>> = vec![];
+    let mut failures = vec![];
+    for provider in providers {
+        let s = match provider.time_series(symbol).await {
+            Ok(s) if s.is_empty() => {
+                failures.push(format!("{}: no prices", provider.name()));
+                continue;
+            }
+            Ok(s) => s,
+            Err(e) => {
+                failures.push(format!("{}: {e}", provider.name()));
+                continue;
+            }
+        };
+        if strategy == PriceStrategy::Median {
+            series.push(s);
+            continue;
+        }
+        // Earlier providers' candles are kept over this one's wherever they have them
+        let merged = match series.pop() {
+            Some(mut merged) => {
+                let start = merged[0].timestamp;
+                let older = s.into_iter().take_while(|c| c.timestamp < start);
+                merged.splice(0..0, older);
+                merged
+            }
+            None => s,
+        };
+        let covered = merged[0].timestamp <= since;
+        series.push(merged);
+        if covered {
+            break;
+        }
+    }
+    if series.is_empty() {
+        return Err(IndexerError::PriceFetchFailure {
+            symbol: symbol.to_string(),
+            message: failures.join("; "),
+        });
+    }
+    Ok(median(series))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    // The providers that were queried, in order
+    type Queried = Rc<RefCell<Vec<&'static str>>>;
+
+    /// A provider that returns the same series for every symbol, or fails, and logs who was asked.
+    struct MockProvider {
+        name: &'static str,
+        series: Option<Vec<TimeSeries>>,
+        queried: Queried,
+    }
+
+    #[async_trait(?Send)]
+    impl PriceProvider for MockProvider {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn time_series(&self, _symbol: &str) -> Result<Vec<TimeSeries>, String> {
+            self.queried.borrow_mut().push(self.name);
+            self.series.clone().ok_or("unavailable".to_string())
+        }
+    }
+
+    fn providers(
+        series: Vec<(&'static str, Option<Vec<TimeSeries>>)>,
+    ) -> (Vec<Box<dyn PriceProvider>>, Queried) {
+        let queried = Rc::new(RefCell::new(vec![]));
+        let providers = series
+            .into_iter()
+            .map(|(name, series)| {
+                Box::new(MockProvider {
+                    name,
+                    series,
+                    queried: queried.clone(),
+                }) as Box<dyn PriceProvider>
+            })
+            .collect();
+        (providers, queried)
+    }
+
+    fn fetched(
+        providers: &[Box<dyn PriceProvider>],
+        strategy: PriceStrategy,
+        since: u64,
+    ) -> Result<Vec<TimeSeries>, IndexerError> {
+        fetch(providers, strategy, "WETH", since)
+            .now_or_never()
+            .unwrap()
+    }
+
+    fn flat(timestamp: u64, price: f32) -> TimeSeries {
+        TimeSeries {
+            timestamp,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+        }
+    }
+
+    #[test]
+    fn symbols_are_priced_as_their_base_asset() {
+        assert_eq!(base_symbol("WETH"), "ETH");
+        assert_eq!(base_symbol("xcDOT"), "DOT");
+        assert_eq!(base_symbol("USDC"), "USDC");
+        assert_eq!(coingecko_id("WBTC"), Some("bitcoin"));
+        assert_eq!(coingecko_id("xcDOT"), Some("polkadot"));
+        assert_eq!(coingecko_id("PEPE"), None);
+    }
+
+    #[test]
+    fn provider_lists_are_parsed_in_order() {
+        assert_eq!(
+            " defillama, twelve_data".parse(),
+            Ok(ProviderNames(vec![
+                ProviderName::DefiLlama,
+                ProviderName::TwelveData
+            ]))
+        );
+        assert!("twelve_data,binance".parse::<ProviderNames>().is_err());
+        assert!("".parse::<ProviderNames>().is_err());
+    }
+
+    #[test]
+    fn points_are_bucketed_into_candles() {
+        let start = 10 * INTERVAL_SECONDS;
+        let series = candles(&[
+            (start + 3600, 12.),
+            (start, 10.),
+            (start + 1800, 9.),
+            (start + INTERVAL_SECONDS, 11.),
+        ]);
+        assert_eq!(
+            series,
+            vec![
+                TimeSeries {
+                    timestamp: start,
+                    open: 10.,
+                    high: 12.,
+                    low: 9.,
+                    close: 12.,
+                },
+                flat(start + INTERVAL_SECONDS, 11.),
+            ]
+        );
+    }
+
+    #[test]
+    fn medians_ignore_an_outlying_provider() {
+        let series = median(vec![
+            vec![flat(0, 10.), flat(INTERVAL_SECONDS, 20.)],
+            vec![flat(0, 11.), flat(INTERVAL_SECONDS, 2000.)],
+            vec![flat(0, 12.), flat(INTERVAL_SECONDS, 21.)],
+        ]);
+        assert_eq!(series, vec![flat(0, 11.), flat(INTERVAL_SECONDS, 21.)]);
+    }
+
+    #[test]
+    fn medians_cover_candles_only_some_providers_have() {
+        let series = median(vec![
+            vec![flat(0, 10.)],
+            vec![flat(0, 12.), flat(INTERVAL_SECONDS, 30.)],
+        ]);
+        assert_eq!(series, vec![flat(0, 11.), flat(INTERVAL_SECONDS, 30.)]);
+    }
+
+    #[test]
+    fn failing_providers_fall_through_in_order() {
+        let (providers, queried) = providers(vec![
+            ("first", None),
+            ("second", Some(vec![flat(0, 10.)])),
+            ("third", Some(vec![flat(0, 20.)])),
+        ]);
+        let series = fetched(&providers, PriceStrategy::First, 0).unwrap();
+        assert_eq!(series, vec![flat(0, 10.)]);
+        assert_eq!(*queried.borrow(), ["first", "second"]);
+    }
+
+    #[test]
+    fn older_candles_are_filled_in_from_the_next_providers() {
+        let (providers, queried) = providers(vec![
+            ("recent", Some(vec![flat(2 * INTERVAL_SECONDS, 10.)])),
+            ("empty", Some(vec![])),
+            (
+                "older",
+                Some(vec![
+                    flat(INTERVAL_SECONDS, 11.),
+                    flat(2 * INTERVAL_SECONDS, 12.),
+                ]),
+            ),
+            ("oldest", Some(vec![flat(0, 13.)])),
+        ]);
+        let series = fetched(&providers, PriceStrategy::First, INTERVAL_SECONDS).unwrap();
+        assert_eq!(
+            series,
+            vec![flat(INTERVAL_SECONDS, 11.), flat(2 * INTERVAL_SECONDS, 10.)]
+        );
+        assert_eq!(*queried.borrow(), ["recent", "empty", "older"]);
+
+        // A transfer inside the first candle is covered by it
+        queried.borrow_mut().clear();
+        let series = fetched(&providers, PriceStrategy::First, 2 * INTERVAL_SECONDS).unwrap();
+        assert_eq!(series, vec![flat(2 * INTERVAL_SECONDS, 10.)]);
+        assert_eq!(*queried.borrow(), ["recent"]);
+    }
+
+    #[test]
+    fn series_that_start_after_the_oldest_transfer_are_kept() {
+        let (providers, _) = providers(vec![
+            ("down", None),
+            ("recent", Some(vec![flat(INTERVAL_SECONDS, 10.)])),
+        ]);
+        for strategy in [PriceStrategy::First, PriceStrategy::Median] {
+            let series = fetched(&providers, strategy, 0).unwrap();
+            assert_eq!(series, vec![flat(INTERVAL_SECONDS, 10.)]);
+        }
+    }
+
+    #[test]
+    fn medians_leave_out_providers_that_return_nothing() {
+        let (providers, queried) = providers(vec![
+            ("down", None),
+            ("empty", Some(vec![])),
+            ("low", Some(vec![flat(0, 10.)])),
+            ("high", Some(vec![flat(0, 14.)])),
+        ]);
+        let series = fetched(&providers, PriceStrategy::Median, 0).unwrap();
+        assert_eq!(series, vec![flat(0, 12.)]);
+        assert_eq!(queried.borrow().len(), 4);
+    }
+
+    #[test]
+    fn every_provider_failing_names_each_failure() {
+        let (providers, _) = providers(vec![("down", None), ("empty", Some(vec![]))]);
+        for strategy in [PriceStrategy::First, PriceStrategy::Median] {
+            let Err(IndexerError::PriceFetchFailure { symbol, message }) =
+                fetched(&providers, strategy, 0)
+            else {
+                panic!("expected a price fetch failure")
+            };
+            assert_eq!(symbol, "WETH");
+            assert_eq!(message, "down: unavailable; empty: no prices");
+        }
+    }
+}
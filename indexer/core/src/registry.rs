@@ -0,0 +1,187 @@
+use crate::{eth::Address, models::Token};
+
+/// How an asset's liquidity arrives at the GMP precompile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferPattern {
+    /// Minted straight to it, and burnt on the way out, as Wormhole assets are
+    MintBurn,
+    /// Released to it by a custodian that holds the bridged supply locked up
+    // No registry asset is locked yet
+    #[allow(dead_code)]
+    LockUnlock { custodian: &'static str },
+}
+
+/// Metadata for an asset that is known to be routed through MRL.
+pub struct RegistryToken {
+    /// Lowercase, as stored in the Token table
+    pub address: &'static str,
+    pub name: &'static str,
+    pub symbol: &'static str,
+    pub decimals: u32,
+    pub category: &'static str,
+    pub logo_url: &'static str,
+    pub pattern: TransferPattern,
+}
+
+/// Well-known assets routed through MRL on Moonbeam. Wormhole wrapped assets keep their Ethereum
+/// symbol and decimals, so their logos are the Ethereum originals'. Native GLMR is listed under
+/// the native balance precompile.
+pub const REGISTRY: [RegistryToken; 7] = [
+    RegistryToken {
+        address: "0xab3f0245b83feb11d15aaffefd7ad465a59817ed",
+        name: "Wrapped Ether (Wormhole)",
+        symbol: "WETH",
+        decimals: 18,
+        category: "eth",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/ethereum/assets/0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+    RegistryToken {
+        address: "0xe57ebd2d67b462e9926e04a8e33f01cd0d64346d",
+        name: "Wrapped BTC (Wormhole)",
+        symbol: "WBTC",
+        decimals: 8,
+        category: "btc",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/ethereum/assets/0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+    RegistryToken {
+        address: "0x931715fee2d06333043d11f658c8ce934ac61d0c",
+        name: "USD Coin (Wormhole)",
+        symbol: "USDC",
+        decimals: 6,
+        category: "stablecoin",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/ethereum/assets/0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+    RegistryToken {
+        address: "0xc30e9ca94cf52f3bf5692aacf81353a27052c46f",
+        name: "Tether USD (Wormhole)",
+        symbol: "USDT",
+        decimals: 6,
+        category: "stablecoin",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/ethereum/assets/0xdAC17F958D2ee523a2206206994597C13D831ec7/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+    RegistryToken {
+        address: "0x06e605775296e851ff43b4daa541bb0984e9d6fd",
+        name: "Dai Stablecoin (Wormhole)",
+        symbol: "DAI",
+        decimals: 18,
+        category: "stablecoin",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/ethereum/assets/0x6B175474E89094C44Da98b954EedeAC495271d0F/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+    RegistryToken {
+        address: "0x0000000000000000000000000000000000000802",
+        name: "Glimmer",
+        symbol: "GLMR",
+        decimals: 18,
+        category: "native",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/moonbeam/info/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+    RegistryToken {
+        address: "0xffffffff1fcacbd218edc0eba20fc2308c778080",
+        name: "xcDOT",
+        symbol: "xcDOT",
+        decimals: 10,
+        category: "xc20",
+        logo_url: "https://raw.githubusercontent.com/trustwallet/assets/master/blockchains/polkadot/info/logo.png",
+        pattern: TransferPattern::MintBurn,
+    },
+];
+
+/// A parachain liquidity is routed to.
+pub struct RegistryChain {
+    /// The parachain id, as stored in `to_chain`
+    pub id: u32,
+    pub name: &'static str,
+}
+
+/// Well-known Polkadot parachains that MRL routes liquidity to.
+pub const CHAINS: [RegistryChain; 13] = [
+    RegistryChain {
+        id: 1000,
+        name: "Polkadot Asset Hub",
+    },
+    RegistryChain {
+        id: 2000,
+        name: "Acala",
+    },
+    RegistryChain {
+        id: 2006,
+        name: "Astar",
+    },
+    RegistryChain {
+        id: 2030,
+        name: "Bifrost",
+    },
+    RegistryChain {
+        id: 2031,
+        name: "Centrifuge",
+    },
+    RegistryChain {
+        id: 2032,
+        name: "Interlay",
+    },
+    RegistryChain {
+        id: 2034,
+        name: "Hydration",
+    },
+    RegistryChain {
+        id: 2035,
+        name: "Phala",
+    },
+    RegistryChain {
+        id: 2037,
+        name: "Unique Network",
+    },
+    RegistryChain {
+        id: 2092,
+        name: "Zeitgeist",
+    },
+    RegistryChain {
+        id: 2094,
+        name: "Pendulum",
+    },
+    RegistryChain {
+        id: 2104,
+        name: "Manta",
+    },
+    RegistryChain {
+        id: 3338,
+        name: "peaq",
+    },
+];
+
+pub fn token(address: &str) -> Option<Token> {
+    REGISTRY.iter().find(|t| t.address == address).map(|t| Token {
+        contract_addr: t.address.to_string(),
+        token_name: t.name.to_string(),
+        token_sym: t.symbol.to_string(),
+        decimals: t.decimals,
+        category: Some(t.category.to_string()),
+        logo_url: Some(t.logo_url.to_string()),
+    })
+}
+/// How liquidity of the token at `address` arrives. Tokens outside the registry are assumed to be
+/// minted, like most Wormhole assets.
+pub fn transfer_pattern(address: &str) -> TransferPattern {
+    REGISTRY
+        .iter()
+        .find(|t| t.address == address)
+        .map(|t| t.pattern)
+        .unwrap_or(TransferPattern::MintBurn)
+}
+
+/// Every custodian that releases lock-unlock assets.
+pub fn custodians() -> Vec<Address> {
+    REGISTRY
+        .iter()
+        .filter_map(|t| match t.pattern {
+            TransferPattern::LockUnlock { custodian } => custodian.parse().ok(),
+            TransferPattern::MintBurn => None,
+        })
+        .collect()
+}
@@ -0,0 +1,264 @@
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::eth::{Address, H256, U256};
+
+const DEFAULT_SCAN_URL: &str = "https://api-moonbeam.moonscan.io/api";
+
+/// Why a block explorer query failed.
+#[derive(Debug, Error)]
+pub enum ScanError {
+    #[error("request failed: {0}")]
+    Request(String),
+    #[error("{message}: {result}")]
+    Api { message: String, result: String },
+    #[error("unexpected response: {0}")]
+    Decode(String),
+}
+
+/// The fields of a `tokentx` result that the indexer uses. Numbers arrive as decimal strings, and
+/// are serialized the same way for the ingestion queue.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenTransfer {
+    pub hash: H256,
+    #[serde(deserialize_with = "decimal_u64", serialize_with = "decimal")]
+    pub block_number: u64,
+    pub time_stamp: String,
+    pub from: Address,
+    #[serde(
+        deserialize_with = "optional_address",
+        serialize_with = "optional_address_string"
+    )]
+    pub to: Option<Address>,
+    pub contract_address: Address,
+    #[serde(deserialize_with = "decimal_u256", serialize_with = "decimal")]
+    pub value: U256,
+    pub token_name: String,
+    pub token_symbol: String,
+    pub token_decimal: String,
+}
+
+/// The fields of a `txlistinternal` result that the indexer uses.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InternalTransaction {
+    pub hash: H256,
+    #[serde(deserialize_with = "decimal_u64")]
+    pub block_number: u64,
+    pub time_stamp: String,
+    #[serde(deserialize_with = "optional_address")]
+    pub to: Option<Address>,
+    #[serde(deserialize_with = "decimal_u256")]
+    pub value: U256,
+    pub is_error: String,
+}
+
+#[derive(Deserialize)]
+struct ScanResponse {
+    status: String,
+    message: String,
+    result: Value,
+}
+
+/// A minimal client for the two Etherscan-compatible account endpoints the indexer reads from
+/// MoonScan.
+pub struct ScanClient {
+    url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl ScanClient {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            url: DEFAULT_SCAN_URL.to_string(),
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// ERC-20 transfers to or from `address` between the blocks (both inclusive), oldest first.
+    pub async fn token_transfers(
+        &self,
+        address: Address,
+        from_block: u64,
+        to_block: u64,
+        max: u64,
+    ) -> Result<Vec<TokenTransfer>, ScanError> {
+        self.account_list("tokentx", address, from_block, to_block, max)
+            .await
+    }
+
+    /// Internal transactions to or from `address` between the blocks (both inclusive), oldest
+    /// first.
+    pub async fn internal_transactions(
+        &self,
+        address: Address,
+        from_block: u64,
+        to_block: u64,
+        max: u64,
+    ) -> Result<Vec<InternalTransaction>, ScanError> {
+        self.account_list("txlistinternal", address, from_block, to_block, max)
+            .await
+    }
+
+    async fn account_list<T: DeserializeOwned>(
+        &self,
+        action: &str,
+        address: Address,
+        from_block: u64,
+        to_block: u64,
+        max: u64,
+    ) -> Result<Vec<T>, ScanError> {
+        let query = [
+            ("module", "account".to_string()),
+            ("action", action.to_string()),
+            ("address", format!("{:?}", address)),
+            ("startblock", from_block.to_string()),
+            ("endblock", to_block.to_string()),
+            ("page", "1".to_string()),
+            ("offset", max.to_string()),
+            ("sort", "asc".to_string()),
+            ("apikey", self.api_key.clone()),
+        ];
+        let response = self
+            .client
+            .get(&self.url)
+            .query(&query)
+            .send()
+            .await
+            .map_err(|e| ScanError::Request(e.to_string()))?
+            .json::<ScanResponse>()
+            .await
+            .map_err(|e| ScanError::Decode(e.to_string()))?;
+        results(response)
+    }
+}
+
+/// The rows of a list response, or why there aren't any.
+fn results<T: DeserializeOwned>(response: ScanResponse) -> Result<Vec<T>, ScanError> {
+    // An empty result is reported as a failure
+    if response.status != "1" {
+        if response.message.starts_with("No transactions found") {
+            return Ok(vec![]);
+        }
+        return Err(ScanError::Api {
+            message: response.message,
+            result: response.result.as_str().unwrap_or_default().to_string(),
+        });
+    }
+    serde_json::from_value(response.result).map_err(|e| ScanError::Decode(e.to_string()))
+}
+
+fn decimal_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn decimal_u256<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+    U256::from_dec_str(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Contract creations have an empty `to`.
+fn optional_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Address>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    if s.is_empty() {
+        return Ok(None);
+    }
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn decimal<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn optional_address_string<S: Serializer>(
+    address: &Option<Address>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match address {
+        Some(a) => serializer.collect_str(&format_args!("{:?}", a)),
+        None => serializer.serialize_str(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn response(body: Value) -> ScanResponse {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn transfer() -> Value {
+        json!({
+            "hash": "0x6b1a1f1f0fa0b7e0d1f4e1bd1cf1f0b1c2e2a0a9b4b07e1e2d1c2f0b3a4d5e6f",
+            "blockNumber": "4164120",
+            "timeStamp": "1700000000",
+            "from": "0x0000000000000000000000000000000000000000",
+            "to": "",
+            "contractAddress": "0xab3f0245b83feb11d15aaffefd7ad465a59817ed",
+            "value": "2500000000000000000",
+            "tokenName": "Wrapped Ether",
+            "tokenSymbol": "WETH",
+            "tokenDecimal": "18",
+            "gas": "21000"
+        })
+    }
+
+    #[test]
+    fn no_transactions_found_is_an_empty_list() {
+        let body = json!({ "status": "0", "message": "No transactions found", "result": [] });
+        let transfers: Vec<TokenTransfer> = results(response(body)).unwrap();
+        assert!(transfers.is_empty());
+    }
+
+    #[test]
+    fn list_results_are_decoded() {
+        let body = json!({ "status": "1", "message": "OK", "result": [transfer()] });
+        let transfers: Vec<TokenTransfer> = results(response(body)).unwrap();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].block_number, 4164120);
+        assert_eq!(transfers[0].to, None);
+        assert_eq!(transfers[0].value, U256::from(2_500_000_000_000_000_000u64));
+
+        // And are queued in the same form they arrive in
+        let queued = serde_json::to_value(&transfers[0]).unwrap();
+        assert_eq!(queued["blockNumber"], "4164120");
+        assert_eq!(queued["to"], "");
+        assert_eq!(queued["value"], "2500000000000000000");
+    }
+
+    #[test]
+    fn failures_are_mapped_to_their_kind() {
+        let body = json!({ "status": "0", "message": "NOTOK", "result": "Invalid API Key" });
+        match results::<TokenTransfer>(response(body)) {
+            Err(ScanError::Api { message, result }) => {
+                assert_eq!(message, "NOTOK");
+                assert_eq!(result, "Invalid API Key");
+            }
+            other => panic!("expected an API error, got {other:?}"),
+        }
+
+        let body = json!({ "status": "1", "message": "OK", "result": [{ "hash": "0x12" }] });
+        assert!(matches!(
+            results::<TokenTransfer>(response(body)),
+            Err(ScanError::Decode(_))
+        ));
+
+        let body = json!({ "status": "1", "message": "OK", "result": "not a list" });
+        assert!(matches!(
+            results::<InternalTransaction>(response(body)),
+            Err(ScanError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::{decoder::Junction, usd::Usd};
+
+/// Named schemas, which end up under `components.schemas` in the OpenAPI document.
+pub type Components = BTreeMap<String, Value>;
+
+/// Types that can describe their JSON form as an OpenAPI 3.0 schema.
+pub trait JsonSchema {
+    /// The type's schema. Models are added to `components` and referred to by `$ref`.
+    fn schema(components: &mut Components) -> Value;
+
+    /// Whether a field of this type can be left out of the JSON.
+    fn optional() -> bool {
+        false
+    }
+}
+
+/// Declares a serde model and implements `JsonSchema` for it. Doc comments on the struct and its
+/// fields become the schema's descriptions, and every field that isn't an `Option` is required.
+#[macro_export]
+macro_rules! model {
+    (@doc doc = $doc:literal) => {
+        Some($doc)
+    };
+    (@doc $($other:tt)*) => {
+        None
+    };
+    (
+        $(#[$($attr:tt)*])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$($field_attr:tt)*])*
+                $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$($attr)*])*
+        $vis struct $name {
+            $(
+                $(#[$($field_attr)*])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::schemas::JsonSchema for $name {
+            fn schema(components: &mut $crate::schemas::Components) -> serde_json::Value {
+                let name = stringify!($name);
+                if !components.contains_key(name) {
+                    // Claimed before the fields are visited, so a model that contains itself
+                    // doesn't recurse forever
+                    components.insert(name.to_string(), serde_json::Value::Null);
+                    let fields = vec![$(
+                        $crate::schemas::Field {
+                            name: stringify!($field),
+                            schema: <$ty as $crate::schemas::JsonSchema>::schema(components),
+                            required: !<$ty as $crate::schemas::JsonSchema>::optional(),
+                            description: $crate::schemas::description(&[
+                                $($crate::model!(@doc $($field_attr)*)),*
+                            ]),
+                        }
+                    ),*];
+                    let description = $crate::schemas::description(&[
+                        $($crate::model!(@doc $($attr)*)),*
+                    ]);
+                    let object = $crate::schemas::object(description, fields);
+                    components.insert(name.to_string(), object);
+                }
+                $crate::schemas::reference(name)
+            }
+        }
+    };
+}
+
+/// A property of a model's schema.
+pub struct Field {
+    pub name: &'static str,
+    pub schema: Value,
+    pub required: bool,
+    pub description: Option<String>,
+}
+
+/// Joins a model's doc comment lines into one description.
+pub fn description(docs: &[Option<&str>]) -> Option<String> {
+    let lines: Vec<&str> = docs.iter().flatten().map(|d| d.trim()).collect();
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+pub fn object(description: Option<String>, fields: Vec<Field>) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![];
+    for field in fields {
+        let schema = match field.description {
+            Some(d) => with(field.schema, "description", d.into()),
+            None => field.schema,
+        };
+        properties.insert(field.name.to_string(), schema);
+        if field.required {
+            required.push(field.name);
+        }
+    }
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    if let Some(d) = description {
+        schema["description"] = d.into();
+    }
+    schema
+}
+
+pub fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// Adds a keyword to a schema. Keywords next to a `$ref` are ignored in OpenAPI 3.0, so references
+/// are wrapped in an `allOf` first.
+fn with(schema: Value, key: &str, value: Value) -> Value {
+    let mut schema = if schema.get("$ref").is_some() {
+        json!({ "allOf": [schema] })
+    } else {
+        schema
+    };
+    schema[key] = value;
+    schema
+}
+
+macro_rules! primitive {
+    ($($ty:ty => $schema:tt),* $(,)?) => {
+        $(
+            impl JsonSchema for $ty {
+                fn schema(_: &mut Components) -> Value {
+                    json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+primitive! {
+    bool => { "type": "boolean" },
+    u8 => { "type": "integer", "minimum": 0 },
+    u16 => { "type": "integer", "minimum": 0 },
+    u32 => { "type": "integer", "format": "int32", "minimum": 0 },
+    u64 => { "type": "integer", "format": "int64", "minimum": 0 },
+    usize => { "type": "integer", "format": "int64", "minimum": 0 },
+    f32 => { "type": "number", "format": "float" },
+    f64 => { "type": "number", "format": "double" },
+    String => { "type": "string" },
+    &str => { "type": "string" },
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn schema(components: &mut Components) -> Value {
+        with(T::schema(components), "nullable", true.into())
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({ "type": "array", "items": T::schema(components) })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for BTreeMap<String, T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(components) })
+    }
+}
+
+// Any JSON at all
+impl JsonSchema for Value {
+    fn schema(_: &mut Components) -> Value {
+        json!({})
+    }
+}
+
+impl JsonSchema for Usd {
+    fn schema(_: &mut Components) -> Value {
+        json!({
+            "type": "string",
+            "description": "USD to the cent, as a decimal string",
+            "pattern": "^-?[0-9]+\\.[0-9]{2}$",
+            "example": "1234.56",
+        })
+    }
+}
+
+// Serde tags each junction with its variant name, e.g. `{ "Parachain": 2004 }`
+impl JsonSchema for Junction {
+    fn schema(components: &mut Components) -> Value {
+        let name = "Junction";
+        if !components.contains_key(name) {
+            let network = json!({ "type": "string", "nullable": true });
+            let tagged = |variant: &str, value: Value| {
+                json!({
+                    "type": "object",
+                    "properties": { variant: value },
+                    "required": [variant],
+                    "additionalProperties": false,
+                })
+            };
+            let account = |key: &str, value: Value| {
+                json!({
+                    "type": "object",
+                    "properties": { "network": network.clone(), key: value },
+                    "required": ["network", key],
+                })
+            };
+            let string = json!({ "type": "string" });
+            let junction = json!({
+                "description": "An XCM junction of a MultiLocation's interior",
+                "oneOf": [
+                    tagged("Parachain", u32::schema(components)),
+                    tagged("AccountId32", account("id", string.clone())),
+                    tagged("AccountIndex64", account("index", u64::schema(components))),
+                    tagged("AccountKey20", account("key", string.clone())),
+                    tagged("PalletInstance", u8::schema(components)),
+                    tagged("GeneralIndex", string.clone()),
+                    tagged("GeneralKey", string.clone()),
+                    tagged("GlobalConsensus", string),
+                    { "type": "string", "enum": ["OnlyChild"] },
+                ],
+            });
+            components.insert(name.to_string(), junction);
+        }
+        reference(name)
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fmt,
+    ops::{Add, Sub},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A USD amount in whole cents, so that sums are exact however many transfers go into them. It is
+/// stored as an INTEGER, and serialized as a decimal string such as "1234.56" because JSON
+/// numbers lose precision on large totals.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Usd(pub i64);
+
+impl Usd {
+    /// The nearest cent to `dollars`, saturating at the limits of an i64 of cents. Anything that
+    /// isn't a finite number is 0.
+    pub fn from_dollars(dollars: f64) -> Self {
+        if !dollars.is_finite() {
+            return Self(0);
+        }
+        // Float to int casts saturate
+        Self((dollars * 100.).round() as i64)
+    }
+}
+
+impl fmt::Display for Usd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{:02}", cents / 100, cents % 100)
+    }
+}
+
+impl Add for Usd {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Usd {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Serialize for Usd {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Reads the cents that D1 stores, which arrive as a whole number.
+impl<'de> Deserialize<'de> for Usd {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        i64::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dollars_round_to_the_nearest_cent() {
+        assert_eq!(Usd::from_dollars(1234.564), Usd(123456));
+        assert_eq!(Usd::from_dollars(0.005), Usd(1));
+        assert_eq!(Usd::from_dollars(-2.5), Usd(-250));
+        assert_eq!(Usd::from_dollars(f64::NAN), Usd(0));
+        assert_eq!(Usd::from_dollars(f64::MAX), Usd(i64::MAX));
+    }
+
+    #[test]
+    fn amounts_format_as_decimal_strings() {
+        assert_eq!(Usd(123456).to_string(), "1234.56");
+        assert_eq!(Usd(5).to_string(), "0.05");
+        assert_eq!(Usd(-5).to_string(), "-0.05");
+        assert_eq!(Usd(i64::MIN).to_string(), "-92233720368547758.08");
+        assert_eq!(serde_json::to_string(&Usd(180000)).unwrap(), "\"1800.00\"");
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        let total = (0..1000).fold(Usd::default(), |total, _| total + Usd(10));
+        assert_eq!(total, Usd(10000));
+        assert_eq!(Usd(i64::MAX) + Usd(1), Usd(i64::MAX));
+        assert_eq!(Usd(100) - Usd(250), Usd(-150));
+    }
+}
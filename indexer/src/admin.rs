@@ -0,0 +1,197 @@
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use worker::{D1Database, Env, Request, Response, Result, RouteContext};
+
+use crate::{
+    cache, config, ingest, migrate, payloads,
+    schemas::{BackfillRequest, OperationReport, ReindexRequest},
+};
+
+/// Admin routes require an `Authorization: Bearer <ADMIN_TOKEN>` header. If the ADMIN_TOKEN
+/// secret isn't set, every admin request is refused.
+pub(crate) fn is_authorized(req: &Request, env: &Env) -> bool {
+    let Ok(token) = env.secret("ADMIN_TOKEN") else {
+        return false
+    };
+    let Ok(Some(header)) = req.headers().get("Authorization") else {
+        return false
+    };
+    let Some(given) = header.strip_prefix("Bearer ") else {
+        return false
+    };
+    same_secret(given, &token.to_string())
+}
+
+/// Compares secrets in constant time. Their hashes are compared rather than the secrets, so that
+/// neither where they differ nor how long they are shows in the timing.
+fn same_secret(given: &str, secret: &str) -> bool {
+    let given = Sha256::digest(given.as_bytes());
+    let secret = Sha256::digest(secret.as_bytes());
+    given.ct_eq(&secret).into()
+}
+
+/// Runs the statements as one batch and counts the rows the first one returned, which is how
+/// DELETE and UPDATE statements report what they touched.
+async fn count_returned(d1: &D1Database, statements: Vec<String>) -> Result<usize> {
+    let results = d1
+        .batch(statements.iter().map(|s| d1.prepare(s)).collect())
+        .await?;
+    for result in &results {
+        if !result.success() {
+            return Err(worker::Error::JsError(
+                result.error().unwrap_or("No error given".to_string()),
+            ));
+        }
+    }
+    match results.first() {
+        Some(r) => Ok(r.results::<serde_json::Value>()?.len()),
+        None => Ok(0),
+    }
+}
+
+/// POST /admin/migrate creates any missing tables and columns and rewrites the token registry,
+/// without waiting for the next scheduled run.
+pub(crate) async fn migrate_schema(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    if let Err(e) = migrate(&d1).await {
+        return Response::error(e.to_string(), 500);
+    }
+    let report = OperationReport {
+        operation: "migrate",
+        affected_transfers: None,
+    };
+    Response::from_json(&report)
+}
+
+/// POST /admin/reset deletes every indexed transfer, token and liquidity snapshot along with the
+/// tuned work budget, so the next scheduled run starts over from the first MRL block. Tokens
+/// corrected by hand are kept, and so are sent alerts so that nothing is alerted on twice.
+pub(crate) async fn reset(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let statements = vec![
+        "DELETE FROM TransfersForward RETURNING tx_hash".to_string(),
+        "DELETE FROM ShadowTransfers".to_string(),
+        "DELETE FROM LiquiditySnapshots".to_string(),
+        "DELETE FROM Token WHERE overridden = 0".to_string(),
+        "DELETE FROM InsertChunks".to_string(),
+        "DELETE FROM IndexerState WHERE key IN ('work_budget', 'price_cursors', \
+         'queued_through_block', 'scanned_through_block')"
+            .to_string(),
+    ];
+    let deleted = match count_returned(&d1, statements).await {
+        Ok(d) => d,
+        Err(e) => return Response::error(e.to_string(), 500),
+    };
+    // Puts the registry tokens back
+    if let Err(e) = migrate(&d1).await {
+        return Response::error(e.to_string(), 500);
+    }
+    cache::invalidate(&ctx.env).await;
+
+    let report = OperationReport {
+        operation: "reset",
+        affected_transfers: Some(deleted),
+    };
+    Response::from_json(&report)
+}
+
+/// POST /admin/reindex with `{ "from_block": ... }` deletes the transfers indexed from that block
+/// onwards. Runs resume from the last indexed block, or the queue watermark which is moved back
+/// with it, so the next scheduled run indexes them again.
+pub(crate) async fn reindex(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(request) = req.json::<ReindexRequest>().await else {
+        return Response::error("Expected a JSON body with from_block", 400)
+    };
+    let config = match config::from_env(&ctx.env) {
+        Ok(c) => c,
+        Err(e) => return Response::error(e.to_string(), 500),
+    };
+    // Nothing before the start block is ever indexed again, so that would only lose transfers
+    if request.from_block < config.start_block {
+        let msg = format!("from_block must be at least START_BLOCK ({})", config.start_block);
+        return Response::error(msg, 400);
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let statements = vec![
+        format!(
+            "DELETE FROM TransfersForward WHERE block_num >= {} RETURNING tx_hash",
+            request.from_block
+        ),
+        "DELETE FROM ShadowTransfers WHERE tx_hash NOT IN (SELECT tx_hash FROM TransfersForward)"
+            .to_string(),
+        ingest::rewind(request.from_block),
+        format!(
+            "DELETE FROM InsertChunks WHERE first_block >= {}",
+            request.from_block
+        ),
+    ];
+    let deleted = match count_returned(&d1, statements).await {
+        Ok(d) => d,
+        Err(e) => return Response::error(e.to_string(), 500),
+    };
+    cache::invalidate(&ctx.env).await;
+
+    let report = OperationReport {
+        operation: "reindex",
+        affected_transfers: Some(deleted),
+    };
+    Response::from_json(&report)
+}
+
+/// POST /admin/backfill with `{ "from_block": ..., "to_block": ... }` (both optional and
+/// inclusive) marks stored transfers for their payloads to be decoded again, and decodes the
+/// first of them straight away. Scheduled runs work through the rest.
+pub(crate) async fn backfill(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(request) = req.json::<BackfillRequest>().await else {
+        let msg = "Expected a JSON body, optionally with from_block and to_block";
+        return Response::error(msg, 400)
+    };
+
+    let d1 = ctx.env.d1("DB")?;
+    let statements = vec![format!(
+        "UPDATE TransfersForward SET payload_checked = 0 WHERE block_num BETWEEN {} AND {}
+        RETURNING tx_hash",
+        request.from_block.unwrap_or(0),
+        request.to_block.unwrap_or(i64::MAX as u64)
+    )];
+    let marked = match count_returned(&d1, statements).await {
+        Ok(m) => m,
+        Err(e) => return Response::error(e.to_string(), 500),
+    };
+    payloads::decode_pending(&ctx.env, &d1).await;
+    cache::invalidate(&ctx.env).await;
+
+    let report = OperationReport {
+        operation: "backfill",
+        affected_transfers: Some(marked),
+    };
+    Response::from_json(&report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_same_secret_matches() {
+        assert!(same_secret("s3cret-token", "s3cret-token"));
+        assert!(!same_secret("s3cret-tokem", "s3cret-token"));
+        assert!(!same_secret("s3cret", "s3cret-token"));
+        assert!(!same_secret("", "s3cret-token"));
+    }
+}
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use worker::{console_error, console_warn, D1Database, Date, Env};
+
+use crate::{
+    retry::{retry, RetryPolicy},
+    usd::Usd,
+    TransferForward,
+};
+
+const DEFAULT_LARGE_TRANSFER_USD: f64 = 100_000.;
+
+#[derive(Serialize)]
+struct WebhookMessage<'a> {
+    // Discord reads `content` and Slack reads `text`, so send both
+    content: &'a str,
+    text: &'a str,
+}
+
+/// Logs an operator alert and forwards it to the ALERT_WEBHOOK_URL secret, if one is set.
+pub(crate) async fn send_alert(env: &Env, message: &str) {
+    console_warn!("ALERT: {}", message);
+    let Ok(url) = env.secret("ALERT_WEBHOOK_URL") else {
+        return
+    };
+    post_webhook(&url.to_string(), message).await;
+}
+
+/// Sends an alert for every transfer worth at least LARGE_TRANSFER_USD (100k by default). Each
+/// transfer is recorded in SentAlerts before its alert is sent, so that re-indexed transfers and
+/// overlapping runs don't alert twice.
+pub(crate) async fn alert_large_transfers(env: &Env, db: &D1Database, transfers: &[TransferForward]) {
+    let Ok(url) = env.secret("ALERT_WEBHOOK_URL") else {
+        return
+    };
+    let threshold = env
+        .var("LARGE_TRANSFER_USD")
+        .ok()
+        .and_then(|t| t.to_string().parse::<f64>().ok())
+        .map(Usd::from_dollars)
+        .unwrap_or(Usd::from_dollars(DEFAULT_LARGE_TRANSFER_USD));
+
+    for transfer in transfers.iter().filter(|t| t.usd >= threshold) {
+        if !claim(db, &transfer.tx_hash).await {
+            continue;
+        }
+
+        let message = format!(
+            "Large MRL transfer: ${} of {} sent to chain {} (tx {})",
+            transfer.usd, transfer.token_addr, transfer.to_chain, transfer.tx_hash
+        );
+        if post_webhook(&url.to_string(), &message).await {
+            continue;
+        }
+        // Released, so the next run will try again
+        let released = match db
+            .prepare("DELETE FROM SentAlerts WHERE tx_hash = ?1")
+            .bind(&[transfer.tx_hash.clone().into()])
+        {
+            Ok(s) => s.run().await.map(|r| r.success()).unwrap_or(false),
+            Err(_) => false,
+        };
+        if !released {
+            console_error!("Error releasing unsent alert for {}", transfer.tx_hash);
+        }
+    }
+}
+
+/// Records that the transfer's alert is being sent. False if it already was, or if it couldn't be
+/// recorded, since an alert that isn't recorded could be sent again.
+async fn claim(db: &D1Database, tx_hash: &str) -> bool {
+    let Ok(statement) = db
+        .prepare(
+            "INSERT OR IGNORE INTO SentAlerts (tx_hash, sent_at) VALUES (?1, ?2) RETURNING tx_hash",
+        )
+        .bind(&[
+            tx_hash.into(),
+            ((Date::now().as_millis() / 1000) as f64).into(),
+        ])
+    else {
+        return false
+    };
+    match statement.first::<String>(Some("tx_hash")).await {
+        Ok(claimed) => claimed.is_some(),
+        Err(e) => {
+            console_error!("Error recording alert for {}: {}", tx_hash, e);
+            false
+        }
+    }
+}
+
+/// POSTs a Discord/Slack compatible message, retrying on failure. Returns whether it was
+/// eventually delivered.
+async fn post_webhook(url: &str, message: &str) -> bool {
+    let client = reqwest::Client::new();
+    let res = retry("Alert webhook", &RetryPolicy::default(), || async {
+        let r = client
+            .post(url)
+            .json(&WebhookMessage {
+                content: message,
+                text: message,
+            })
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if r.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("status {}", r.status()))
+        }
+    })
+    .await;
+    if let Err(e) = &res {
+        console_error!("Giving up on alert webhook: {}", e);
+    }
+    res.is_ok()
+}
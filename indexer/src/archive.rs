@@ -0,0 +1,156 @@
+use serde::Deserialize;
+use worker::{Bucket, D1Database, Date, Env, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin, batch_with_retry,
+    budget::{self, RunStats},
+    cache, chain_events, config, d1,
+    errors::IndexerError,
+    pipeline::{self, TransferBatch},
+    price_source,
+    schemas::{ReplayReport, ReplayRequest},
+    sql_string, D1Store,
+};
+
+// Each request replays at most this many batches, so that it stays within its CPU limit
+const MAX_REPLAYED_BATCHES: usize = 10;
+
+#[derive(Deserialize)]
+struct ArchivedBatch {
+    key: String,
+    first_block: u64,
+    last_block: u64,
+}
+
+/// The R2 bucket fetched transfers are archived to, if ARCHIVE is bound. Without it, nothing is
+/// archived.
+pub(crate) fn bucket(env: &Env) -> Option<Bucket> {
+    env.bucket("ARCHIVE").ok()
+}
+
+/// Where a batch is archived. Blocks are zero padded so that keys sort in block order.
+fn key(first_block: u64, last_block: u64) -> String {
+    format!("transfers/{first_block:010}-{last_block:010}.json")
+}
+
+/// Writes the batch to the bucket under its block range, and records it in ArchivedBatches so
+/// that replays can find it by block. The batch is archived as parsed, so fields of the explorer's
+/// responses that TokenTransfer doesn't have aren't kept.
+pub(crate) async fn put(
+    bucket: &Bucket,
+    db: &D1Database,
+    batch: &TransferBatch,
+) -> std::result::Result<(), IndexerError> {
+    let (Some(first_block), Some(last_block)) = (batch.first_block(), batch.last_block()) else {
+        return Ok(())
+    };
+    let key = key(first_block, last_block);
+    let body =
+        serde_json::to_string(batch).map_err(|e| IndexerError::ArchiveFailure(e.to_string()))?;
+    bucket
+        .put(&key, body)
+        .execute()
+        .await
+        .map_err(|e| IndexerError::ArchiveFailure(e.to_string()))?;
+
+    let statement = format!(
+        "INSERT OR REPLACE INTO ArchivedBatches \
+         (key, first_block, last_block, transfers, archived_at) \
+         VALUES ({}, {first_block}, {last_block}, {}, {})",
+        sql_string(&key),
+        batch.len(),
+        Date::now().as_millis() / 1000
+    );
+    batch_with_retry(db, "Archived batch", &[statement])
+        .await
+        .map(|_| ())
+        .map_err(|e| d1::db_error(e.to_string()))
+}
+
+/// POST /admin/replay with `{ "from_block": ..., "to_block": ... }` (both optional and inclusive)
+/// decodes, prices and stores the archived batches in that range again, oldest first, replacing
+/// the transfers stored from their blocks. At most 10 batches are replayed per request.
+pub(crate) async fn replay(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(request) = req.json::<ReplayRequest>().await else {
+        let msg = "Expected a JSON body, optionally with from_block and to_block";
+        return Response::error(msg, 400)
+    };
+    let Some(bucket) = bucket(&ctx.env) else {
+        return Response::error("No ARCHIVE bucket is bound", 501)
+    };
+    let config = match config::from_env(&ctx.env) {
+        Ok(c) => c,
+        Err(e) => return Response::error(e.to_string(), 500),
+    };
+    let (Some(events), Some(prices)) = (chain_events(&ctx.env), price_source(&ctx.env, &config))
+    else {
+        let msg = "MOONSCAN_KEY and a usable price provider are needed to replay";
+        return Response::error(msg, 500);
+    };
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "SELECT key, first_block, last_block FROM ArchivedBatches
+        WHERE last_block >= ?1 AND first_block <= ?2
+        ORDER BY first_block
+        LIMIT ?3",
+        request.from_block.unwrap_or(0),
+        request.to_block.unwrap_or(i64::MAX as u64),
+        MAX_REPLAYED_BATCHES + 1
+    )?;
+    let result = statement.all().await?;
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+    let mut archived = result.results::<ArchivedBatch>()?;
+    let next_from_block = archived.get(MAX_REPLAYED_BATCHES).map(|a| a.first_block);
+    archived.truncate(MAX_REPLAYED_BATCHES);
+
+    let budget = budget::load(&d1, &config).await;
+    let mut report = ReplayReport {
+        batches: 0,
+        replayed_transfers: 0,
+        next_from_block,
+    };
+    for archived in archived {
+        let object = bucket.get(&archived.key).execute().await?;
+        let Some(body) = object.as_ref().and_then(|o| o.body()) else {
+            let msg = format!("{} is missing from the archive", archived.key);
+            return Response::error(msg, 500);
+        };
+        let batch = match serde_json::from_str::<TransferBatch>(&body.text().await?) {
+            Ok(b) => b,
+            Err(e) => return Response::error(format!("{}: {e}", archived.key), 500),
+        };
+
+        // Stored transfers from these blocks are deleted in the same transaction as the
+        // replayed ones are inserted
+        let store = D1Store {
+            db: &d1,
+            archive: None,
+            replaces: Some((archived.first_block, archived.last_block)),
+        };
+        let now = Date::now().as_millis() / 1000;
+        let mut stats = RunStats::default();
+        let indexed = pipeline::process(
+            batch, &events, &prices, &store, &config, &budget, &mut stats, now,
+        )
+        .await;
+        if indexed.deferred {
+            let block = archived.first_block;
+            let msg = format!("D1 is unavailable, replay again from block {block}");
+            return Response::error(msg, 503);
+        }
+        report.batches += 1;
+        report.replayed_transfers += indexed.transfers.len();
+    }
+
+    if report.batches > 0 {
+        cache::invalidate(&ctx.env).await;
+    }
+    Response::from_json(&report)
+}
@@ -0,0 +1,65 @@
+use worker::{Date, Request, Response, Result, RouteContext};
+
+use crate::{admin, schemas::AuditEntry, sql_string, sql_text};
+
+// How far back /admin/audit looks when no `since` is given
+const DEFAULT_LOOKBACK_SECONDS: u64 = 30 * 24 * 60 * 60;
+const MAX_LISTED_ENTRIES: u32 = 500;
+
+/// The statement that records a change to a stored transfer's `field` in the AuditLog table,
+/// reading the value it replaces from the transfer itself. It has to run before the change does,
+/// in the same batch.
+pub(crate) fn record_change(
+    action: &str,
+    tx_hash: &str,
+    field: &str,
+    new_value: &Option<String>,
+    proposal_id: Option<u32>,
+    performed_at: u64,
+) -> String {
+    format!(
+        "INSERT INTO AuditLog \
+         (action, tx_hash, field, old_value, new_value, proposal_id, performed_at) \
+         SELECT {}, tx_hash, {}, CAST({field} AS TEXT), {}, {}, {performed_at} \
+         FROM TransfersForward WHERE tx_hash = {}",
+        sql_string(action),
+        sql_string(field),
+        sql_text(new_value),
+        proposal_id.map_or("NULL".to_string(), |id| id.to_string()),
+        sql_string(tx_hash)
+    )
+}
+
+/// GET /admin/audit?since=TIMESTAMP lists changes made to stored data by hand, newest first.
+/// Defaults to the last 30 days.
+pub(crate) async fn list(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let mut since = (Date::now().as_millis() / 1000).saturating_sub(DEFAULT_LOOKBACK_SECONDS);
+    for (k, v) in req.url()?.query_pairs() {
+        if k != "since" {
+            return Response::error("Unexpected query parameter", 400);
+        }
+        let Ok(s) = v.parse::<u64>() else {
+            return Response::error("since must be a unix timestamp", 400);
+        };
+        since = s;
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "SELECT * FROM AuditLog WHERE performed_at >= ?1 ORDER BY id DESC LIMIT ?2",
+        since,
+        MAX_LISTED_ENTRIES
+    )?;
+    let result = statement.all().await?;
+
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+
+    let x = result.results::<AuditEntry>()?;
+    Response::from_json(&x)
+}
@@ -0,0 +1,68 @@
+use worker::{console_error, console_log, D1Database};
+
+pub(crate) use mrl_indexer_core::budget::{RunStats, WorkBudget};
+
+use crate::config::Config;
+
+const BUDGET_KEY: &str = "work_budget";
+pub(crate) const DEFAULT_TARGET_RUN_MS: u64 = 15_000;
+
+/// Loads the tuned budget, or the defaults with the configured chunk size if none has been stored
+/// yet.
+pub(crate) async fn load(db: &D1Database, config: &Config) -> WorkBudget {
+    let initial = WorkBudget {
+        insert_chunk_size: config.insert_chunk_size,
+        ..WorkBudget::default()
+    };
+    let statement = db
+        .prepare("SELECT value FROM IndexerState WHERE key = ?1")
+        .bind(&[BUDGET_KEY.into()]);
+    let Ok(statement) = statement else {
+        return initial
+    };
+    match statement.first::<String>(Some("value")).await {
+        Ok(Some(v)) => serde_json::from_str(&v).unwrap_or(initial),
+        _ => initial,
+    }
+}
+
+/// Records how long the run took and persists the budget tuned from it.
+pub(crate) async fn record_run(
+    db: &D1Database,
+    started_at: u64,
+    duration_ms: u64,
+    target_ms: u64,
+    budget: &WorkBudget,
+    stats: &RunStats,
+) {
+    let tuned = budget.tune(duration_ms, target_ms, stats.saturated);
+    console_log!(
+        "Run took {}ms for {} transfers. Next budget: {:?}",
+        duration_ms,
+        stats.transfers,
+        tuned
+    );
+
+    let Ok(tuned_json) = serde_json::to_string(&tuned) else {
+        return
+    };
+    let statements = [
+        db.prepare(
+            "INSERT INTO IndexerRuns (started_at, duration_ms, transfers) VALUES (?1, ?2, ?3)",
+        )
+        .bind(&[
+            (started_at as f64).into(),
+            (duration_ms as f64).into(),
+            (stats.transfers as f64).into(),
+        ]),
+        db.prepare("INSERT OR REPLACE INTO IndexerState (key, value) VALUES (?1, ?2)")
+            .bind(&[BUDGET_KEY.into(), tuned_json.into()]),
+    ];
+    let Ok(statements) = statements.into_iter().collect::<worker::Result<Vec<_>>>() else {
+        console_error!("Error binding run record statements!");
+        return
+    };
+    if let Err(e) = db.batch(statements).await {
+        console_error!("Error recording run duration: {}", e);
+    }
+}
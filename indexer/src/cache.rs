@@ -0,0 +1,116 @@
+use worker::{console_error, kv::KvStore, Date, Env, Headers, Method, Request, Response, Result};
+
+// Every indexing run invalidates the cache when it finishes, so this is only a backstop for runs
+// that don't. It matches the index cron, so a missed invalidation serves stale data for one run
+const DEFAULT_TTL_SECONDS: u64 = 5 * 60;
+// KV refuses shorter TTLs
+const MIN_TTL_SECONDS: u64 = 60;
+const GENERATION_KEY: &str = "generation";
+pub(crate) const CACHE_HEADER: &str = "X-Cache";
+
+/// JSON responses of the read endpoints, stored in the CACHE KV namespace keyed by path and
+/// query. Keys are prefixed with a generation that every scheduled run bumps, which invalidates
+/// everything cached before it without having to list and delete keys.
+pub(crate) struct ResponseCache {
+    kv: KvStore,
+    generation: String,
+    ttl: u64,
+}
+
+impl ResponseCache {
+    /// None when no CACHE namespace is bound, in which case every request goes to D1.
+    pub(crate) async fn from_env(env: &Env) -> Option<Self> {
+        let kv = env.kv("CACHE").ok()?;
+        let generation = match kv.get(GENERATION_KEY).text().await {
+            Ok(g) => g.unwrap_or_default(),
+            Err(e) => {
+                console_error!("Error reading cache generation: {}", e);
+                return None;
+            }
+        };
+        let ttl = env
+            .var("CACHE_TTL_SECONDS")
+            .ok()
+            .and_then(|t| t.to_string().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_TTL_SECONDS)
+            .max(MIN_TTL_SECONDS);
+        Some(Self { kv, generation, ttl })
+    }
+
+    /// The key a request's response is stored under, or None if it shouldn't be cached.
+    pub(crate) fn key(&self, req: &Request) -> Option<String> {
+        if req.method() != Method::Get || !is_cacheable(&req.path()) {
+            return None;
+        }
+        let url = req.url().ok()?;
+        Some(self.key_for(url.path(), url.query()))
+    }
+
+    fn key_for(&self, path: &str, query: Option<&str>) -> String {
+        match query {
+            Some(q) => format!("{}:{}?{}", self.generation, path, q),
+            None => format!("{}:{}", self.generation, path),
+        }
+    }
+
+    pub(crate) async fn get(&self, key: &str) -> Option<String> {
+        match self.kv.get(key).text().await {
+            Ok(body) => body,
+            Err(e) => {
+                console_error!("Error reading {} from the cache: {}", key, e);
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn put(&self, key: &str, body: &str) {
+        let put = match self.kv.put(key, body) {
+            Ok(p) => p.expiration_ttl(self.ttl).execute().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = put {
+            console_error!("Error caching {}: {}", key, e);
+        }
+    }
+
+    /// Stores the body a request for `path?query` would have been answered with, ahead of the
+    /// request.
+    pub(crate) async fn warm(&self, path: &str, query: Option<&str>, body: String) {
+        self.put(&self.key_for(path, query), &body).await;
+    }
+}
+
+// Reads that only change when the cron job writes. Exports stream, so they're never buffered.
+fn is_cacheable(path: &str) -> bool {
+    matches!(
+        path,
+        "/totalLiquidityForward" | "/getTokens" | "/liquidityByChain" | "/topTokens" | "/transfers"
+    )
+        || path.starts_with("/liquidityForward/")
+        || (path.starts_with("/transfers/")
+            && path != "/transfers/export"
+            && path != "/transfers/stream")
+}
+
+/// Rebuilds a JSON response around a body that was read from, or written to, the cache.
+pub(crate) fn json_response(body: String, hit: bool) -> Result<Response> {
+    let mut headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    headers.set(CACHE_HEADER, if hit { "HIT" } else { "MISS" })?;
+    Ok(Response::ok(body)?.with_headers(headers))
+}
+
+/// Starts a new cache generation, so nothing cached before this call is served again.
+pub(crate) async fn invalidate(env: &Env) {
+    let Ok(kv) = env.kv("CACHE") else {
+        return
+    };
+    let generation = Date::now().as_millis().to_string();
+    let put = match kv.put(GENERATION_KEY, generation) {
+        Ok(p) => p.execute().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = put {
+        console_error!("Error invalidating the response cache: {}", e);
+    }
+}
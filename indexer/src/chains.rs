@@ -0,0 +1,79 @@
+use mrl_indexer_core::models::UNDECODED_CHAIN;
+use worker::{Request, Response, Result, RouteContext};
+
+use crate::{
+    admin, cache,
+    schemas::{Chain, ChainName},
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Why a chain can't be given the name, if it can't. Transfers whose destination hasn't been
+/// decoded are stored with UNDECODED_CHAIN, which mustn't be named as if it were a parachain.
+fn invalid(chain_id: u32, chain_name: &str) -> Option<String> {
+    if chain_id == UNDECODED_CHAIN {
+        return Some(format!(
+            "{UNDECODED_CHAIN} is kept for transfers whose destination isn't known"
+        ));
+    }
+    if chain_name.is_empty() || chain_name.len() > MAX_NAME_LENGTH {
+        return Some(format!(
+            "chain_name must be between 1 and {MAX_NAME_LENGTH} characters"
+        ));
+    }
+    None
+}
+
+/// PUT /admin/chains/:id with `{ "chain_name": "..." }` names a destination parachain, or renames
+/// one. Transfers to it are returned with that name as their `to_chain_name` once their payload has
+/// been decoded, and the registry never overwrites it.
+pub(crate) async fn put(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(chain_id) = ctx.param("id").unwrap().parse::<u32>() else {
+        return Response::error("The chain id must be a parachain id", 400)
+    };
+    let Ok(body) = req.json::<ChainName>().await else {
+        return Response::error("Expected a JSON body with chain_name", 400)
+    };
+    let chain_name = body.chain_name.trim();
+    if let Some(msg) = invalid(chain_id, chain_name) {
+        return Response::error(msg, 400);
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "INSERT INTO Chains (chain_id, chain_name) VALUES (?1, ?2)
+        ON CONFLICT (chain_id) DO UPDATE SET chain_name = excluded.chain_name
+        RETURNING chain_id, chain_name",
+        chain_id,
+        chain_name
+    )?;
+    let Some(chain) = statement.first::<Chain>(None).await? else {
+        return Response::error("The chain wasn't stored", 500)
+    };
+    cache::invalidate(&ctx.env).await;
+    Response::from_json(&chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use mrl_indexer_core::registry::CHAINS;
+
+    use super::*;
+
+    #[test]
+    fn undecoded_destinations_are_never_named() {
+        assert!(invalid(UNDECODED_CHAIN, "Polkadot Asset Hub").is_some());
+        assert!(CHAINS.iter().all(|c| invalid(c.id, c.name).is_none()));
+    }
+
+    #[test]
+    fn names_must_fit() {
+        assert_eq!(invalid(2034, "Hydration"), None);
+        assert!(invalid(2034, "").is_some());
+        assert!(invalid(2034, &"X".repeat(MAX_NAME_LENGTH + 1)).is_some());
+    }
+}
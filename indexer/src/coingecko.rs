@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use worker::{console_log, Date, Result};
+
+use mrl_indexer_core::prices::{self, PriceProvider, TimeSeries};
+
+// CoinGecko only returns hourly prices for ranges of up to 90 days, and daily ones beyond
+const HISTORY_SECONDS: u64 = 90 * 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+struct MarketChart {
+    /// Unix milliseconds and prices
+    prices: Vec<(f64, f64)>,
+}
+
+/// Candles for `symbol` over the last 90 days, built from CoinGecko's hourly prices. Uses the demo
+/// API key if there is one, and the keyless public API otherwise.
+pub(crate) async fn get_coingecko(
+    api_key: Option<&str>,
+    symbol: &str,
+    quote: &str,
+) -> Result<Vec<TimeSeries>> {
+    let Some(id) = prices::coingecko_id(symbol) else {
+        return Err(worker::Error::RustError(format!(
+            "no CoinGecko id is known for {symbol}"
+        )))
+    };
+    let to = Date::now().as_millis() / 1000;
+    let from = to - HISTORY_SECONDS;
+    let vs_currency = quote.to_lowercase();
+
+    console_log!("Getting data from CoinGecko for {id}/{vs_currency}. Input was {symbol}");
+    let endpoint = format!(
+        "https://api.coingecko.com/api/v3/coins/{id}/market_chart/range?vs_currency={vs_currency}&from={from}&to={to}"
+    );
+    let mut request = reqwest::Client::new().get(endpoint);
+    if let Some(key) = api_key {
+        request = request.header("x-cg-demo-api-key", key);
+    }
+    let chart = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| worker::Error::JsError(e.to_string()))?
+        .json::<MarketChart>()
+        .await
+        .map_err(|e| worker::Error::JsError(e.to_string()))?;
+
+    let points: Vec<(u64, f32)> = chart
+        .prices
+        .iter()
+        .map(|(ms, price)| ((*ms / 1000.) as u64, *price as f32))
+        .collect();
+    if points.is_empty() {
+        return Err(worker::Error::JsError(
+            "Error: CoinGecko returned no data!".to_owned(),
+        ));
+    }
+    Ok(prices::candles(&points))
+}
+
+/// CoinGecko, with its demo API key if there is one.
+pub(crate) struct CoinGecko {
+    pub(crate) api_key: Option<String>,
+    pub(crate) quote: String,
+}
+
+#[async_trait(?Send)]
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &str {
+        "CoinGecko"
+    }
+
+    async fn time_series(&self, symbol: &str) -> std::result::Result<Vec<TimeSeries>, String> {
+        get_coingecko(self.api_key.as_deref(), symbol, &self.quote)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
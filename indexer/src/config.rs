@@ -0,0 +1,8 @@
+use worker::Env;
+
+pub(crate) use mrl_indexer_core::config::{Config, ConfigError};
+
+/// Reads the deployment's config from its vars.
+pub(crate) fn from_env(env: &Env) -> Result<Config, ConfigError> {
+    Config::from_vars(|name| env.var(name).ok().map(|v| v.to_string()))
+}
@@ -0,0 +1,141 @@
+use worker::{Cors, Env, Method, Request, Response, Result};
+
+use crate::{cache, signing, tiers};
+
+// Browsers cap how long they cache a preflight anyway, Chromium at two hours
+const PREFLIGHT_MAX_AGE_SECONDS: u32 = 2 * 60 * 60;
+// Every method a route is served on, checked against the OpenAPI routes
+pub(crate) const ALLOWED_METHODS: [Method; 5] = [
+    Method::Get,
+    Method::Post,
+    Method::Put,
+    Method::Delete,
+    Method::Options,
+];
+// Headers a browser only hands to scripts when they are listed
+const EXPOSED_HEADERS: [&str; 9] = [
+    cache::CACHE_HEADER,
+    signing::SIGNATURE_HEADER,
+    "X-RateLimit-Limit",
+    "X-RateLimit-Remaining",
+    "X-RateLimit-Reset",
+    "Retry-After",
+    "X-Attribution",
+    "Link",
+    "Content-Disposition",
+];
+
+/// Which origins browsers may call the API from, set by the CORS_ALLOWED_ORIGINS var as a comma
+/// separated list of origins such as `https://mrl.example.com`. Unset, or `*`, allows any origin.
+pub(crate) struct CorsPolicy {
+    origins: Vec<String>,
+}
+
+impl CorsPolicy {
+    pub(crate) fn from_env(env: &Env) -> Self {
+        let origins = env
+            .var("CORS_ALLOWED_ORIGINS")
+            .map(|o| o.to_string())
+            .unwrap_or("*".to_string());
+        Self::new(&origins)
+    }
+
+    fn new(origins: &str) -> Self {
+        Self {
+            origins: origins
+                .split(',')
+                .map(|o| o.trim().trim_end_matches('/').to_string())
+                .filter(|o| !o.is_empty())
+                .collect(),
+        }
+    }
+
+    fn allows_any(&self) -> bool {
+        self.origins.iter().any(|o| o == "*")
+    }
+
+    /// What Access-Control-Allow-Origin should say to a request from `origin`, or None if the
+    /// origin isn't allowed.
+    fn allowed_origin(&self, origin: Option<&str>) -> Option<String> {
+        if self.allows_any() {
+            return Some("*".to_string());
+        }
+        let origin = origin?;
+        self.origins
+            .iter()
+            .any(|o| o.eq_ignore_ascii_case(origin))
+            .then(|| origin.to_string())
+    }
+
+    /// Answers an OPTIONS preflight, allowing whatever headers the browser asked to send.
+    pub(crate) fn preflight(&self, req: &Request) -> Result<Response> {
+        let origin = req.headers().get("Origin")?;
+        let requested = req.headers().get("Access-Control-Request-Headers")?;
+        let allowed_headers = match requested {
+            Some(h) => vec![h],
+            None => vec![
+                "Authorization".to_string(),
+                "Content-Type".to_string(),
+                tiers::API_KEY_HEADER.to_string(),
+            ],
+        };
+        let cors = Cors::default()
+            .with_methods(ALLOWED_METHODS)
+            .with_allowed_headers(allowed_headers)
+            .with_max_age(PREFLIGHT_MAX_AGE_SECONDS);
+        let res = Response::empty()?.with_status(204);
+        self.apply_to(origin.as_deref(), res, cors)
+    }
+
+    /// Adds the CORS headers for a request from `origin` to its response.
+    pub(crate) fn apply(&self, origin: Option<&str>, res: Response) -> Result<Response> {
+        let cors = Cors::default().with_exposed_headers(EXPOSED_HEADERS);
+        self.apply_to(origin, res, cors)
+    }
+
+    fn apply_to(&self, origin: Option<&str>, res: Response, cors: Cors) -> Result<Response> {
+        let cors = match self.allowed_origin(origin) {
+            Some(allowed) => cors.with_origins([allowed]),
+            None => cors,
+        };
+        let mut res = res.with_cors(&cors)?;
+        // The answer depends on the origin unless every origin is allowed, so caches must key on it
+        if !self.allows_any() {
+            res.headers_mut().append("Vary", "Origin")?;
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_allows_every_origin() {
+        let policy = CorsPolicy::new("*");
+        assert_eq!(policy.allowed_origin(None), Some("*".to_string()));
+        assert_eq!(
+            policy.allowed_origin(Some("https://anywhere.example")),
+            Some("*".to_string())
+        );
+    }
+
+    #[test]
+    fn listed_origins_are_echoed_back() {
+        let policy = CorsPolicy::new(" https://mrl.example.com/, https://app.example.com");
+        assert_eq!(
+            policy.allowed_origin(Some("https://mrl.example.com")),
+            Some("https://mrl.example.com".to_string())
+        );
+        assert_eq!(
+            policy.allowed_origin(Some("https://app.example.com")),
+            Some("https://app.example.com".to_string())
+        );
+        assert_eq!(
+            policy.allowed_origin(Some("https://evil.example.com")),
+            None
+        );
+        assert_eq!(policy.allowed_origin(None), None);
+    }
+}
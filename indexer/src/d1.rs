@@ -0,0 +1,100 @@
+use std::{future::Future, time::Duration};
+
+use worker::{D1Database, Result};
+
+use crate::{
+    errors::IndexerError,
+    retry::{retry_if, RetryPolicy},
+};
+
+// What D1 says when it is restarting, overloaded or in a maintenance window, lowercased. These
+// clear up on their own, unlike SQL and constraint errors.
+const TRANSIENT_MARKERS: [&str; 10] = [
+    "network connection lost",
+    "object to be reset",
+    "reset because its code was updated",
+    "overloaded",
+    "too many requests queued",
+    "transient issue",
+    "internal error",
+    "timed out",
+    "exceeded timeout",
+    "temporarily unavailable",
+];
+
+// How long to keep retrying a D1 call within one invocation. A longer outage is left for the next
+// trigger rather than eating into the run's time limit.
+const RETRY_POLICY: RetryPolicy = RetryPolicy {
+    attempts: 4,
+    base_delay: Duration::from_secs(1),
+    max_delay: Duration::from_secs(4),
+    jitter: true,
+};
+
+/// Whether a D1 error is one that retrying later can fix.
+pub(crate) fn is_transient(message: &str) -> bool {
+    let message = message.to_lowercase();
+    TRANSIENT_MARKERS.iter().any(|m| message.contains(m))
+}
+
+/// The IndexerError for a failed D1 call, telling unavailability apart from other failures.
+pub(crate) fn db_error(message: String) -> IndexerError {
+    match is_transient(&message) {
+        true => IndexerError::DbUnavailable(message),
+        false => IndexerError::DbFailure(message),
+    }
+}
+
+/// Runs a D1 call, retrying it while D1 is briefly unavailable. Other errors are returned at once,
+/// since another attempt would fail the same way.
+pub(crate) async fn retry<T, F, Fut>(label: &str, op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_if(label, &RETRY_POLICY, |e| is_transient(&e.to_string()), op).await
+}
+
+/// Whether D1 answers a trivial query, retrying through brief blips. Scheduled runs check this
+/// first so that a maintenance window defers the whole run instead of failing it partway.
+pub(crate) async fn available(db: &D1Database) -> bool {
+    let probe = retry("D1 availability check", || async {
+        db.prepare("SELECT 1").run().await
+    })
+    .await;
+    matches!(probe, Ok(r) if r.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_errors_are_transient() {
+        assert!(is_transient("D1_ERROR: Network connection lost."));
+        assert!(is_transient(
+            "D1 DB reset because its code was updated. Please retry"
+        ));
+        assert!(is_transient(
+            "D1 DB is overloaded. Too many requests queued."
+        ));
+        assert!(matches!(
+            db_error("D1_ERROR: internal error".to_string()),
+            IndexerError::DbUnavailable(_)
+        ));
+    }
+
+    #[test]
+    fn sql_errors_are_permanent() {
+        assert!(!is_transient(
+            "D1_ERROR: no such table: TransfersForward: SQLITE_ERROR"
+        ));
+        assert!(!is_transient(
+            "UNIQUE constraint failed: TransfersForward.tx_hash: SQLITE_CONSTRAINT"
+        ));
+        assert!(matches!(
+            db_error("near \"SELEC\": syntax error".to_string()),
+            IndexerError::DbFailure(_)
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use worker::{console_log, Date, Result};
+
+use mrl_indexer_core::prices::{self, PriceProvider, TimeSeries};
+
+// How many 2 hour points are requested, about 90 days' worth
+const POINTS: u64 = 12 * 90;
+const INTERVAL_SECONDS: u64 = 2 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+struct Chart {
+    coins: HashMap<String, CoinChart>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinChart {
+    prices: Vec<PricePoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PricePoint {
+    timestamp: u64,
+    price: f64,
+}
+
+/// Candles for `symbol` over the last 90 days from DefiLlama's coin price charts, which are only
+/// in USD. Assets are looked up by their CoinGecko id.
+pub(crate) async fn get_defillama(symbol: &str, quote: &str) -> Result<Vec<TimeSeries>> {
+    if quote != "USD" {
+        return Err(worker::Error::RustError(format!(
+            "DefiLlama only has USD prices, not {quote}"
+        )));
+    }
+    let Some(id) = prices::coingecko_id(symbol) else {
+        return Err(worker::Error::RustError(format!(
+            "no CoinGecko id is known for {symbol}"
+        )))
+    };
+    let start = Date::now().as_millis() / 1000 - POINTS * INTERVAL_SECONDS;
+
+    console_log!("Getting data from DefiLlama for coingecko:{id}. Input was {symbol}");
+    let endpoint = format!(
+        "https://coins.llama.fi/chart/coingecko:{id}?start={start}&span={POINTS}&period=2h"
+    );
+    let chart = reqwest::get(endpoint)
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| worker::Error::JsError(e.to_string()))?
+        .json::<Chart>()
+        .await
+        .map_err(|e| worker::Error::JsError(e.to_string()))?;
+
+    let points: Vec<(u64, f32)> = chart
+        .coins
+        .into_values()
+        .flat_map(|c| c.prices)
+        .map(|p| (p.timestamp, p.price as f32))
+        .collect();
+    if points.is_empty() {
+        return Err(worker::Error::JsError(
+            "Error: DefiLlama returned no data!".to_owned(),
+        ));
+    }
+    Ok(prices::candles(&points))
+}
+
+/// DefiLlama, which needs no key.
+pub(crate) struct DefiLlama {
+    pub(crate) quote: String,
+}
+
+#[async_trait(?Send)]
+impl PriceProvider for DefiLlama {
+    fn name(&self) -> &str {
+        "DefiLlama"
+    }
+
+    async fn time_series(&self, symbol: &str) -> std::result::Result<Vec<TimeSeries>, String> {
+        get_defillama(symbol, &self.quote)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,60 @@
+use worker::{console_error, D1Database, Date, Request, Response, Result, RouteContext};
+
+pub(crate) use mrl_indexer_core::errors::IndexerError;
+
+use crate::schemas::RecordedError;
+
+// How far back /errors looks when no `since` is given
+const DEFAULT_LOOKBACK_SECONDS: u64 = 24 * 60 * 60;
+const MAX_LISTED_ERRORS: u32 = 500;
+
+/// Logs the error and stores it in the IndexerErrors table. `context` says what the indexer was
+/// doing at the time.
+pub(crate) async fn record(db: &D1Database, error: IndexerError, context: &str) {
+    console_error!("{}: {}", context, error);
+    let statement = worker::query!(
+        db,
+        "INSERT INTO IndexerErrors (kind, message, context, occurred_at) VALUES (?1, ?2, ?3, ?4)",
+        error.kind(),
+        error.to_string(),
+        context,
+        Date::now().as_millis() / 1000
+    );
+    let persisted = match statement {
+        Ok(s) => s.run().await.map(|r| r.success()).unwrap_or(false),
+        Err(_) => false,
+    };
+    if !persisted {
+        console_error!("Error persisting the previous error to IndexerErrors!");
+    }
+}
+
+/// GET /errors?since=TIMESTAMP lists recorded errors, newest first. Defaults to the last day.
+pub(crate) async fn list(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut since = (Date::now().as_millis() / 1000).saturating_sub(DEFAULT_LOOKBACK_SECONDS);
+    for (k, v) in req.url()?.query_pairs() {
+        if k != "since" {
+            return Response::error("Unexpected query parameter", 400);
+        }
+        let Ok(s) = v.parse::<u64>() else {
+            return Response::error("since must be a unix timestamp", 400);
+        };
+        since = s;
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "SELECT * FROM IndexerErrors WHERE occurred_at >= ?1 ORDER BY occurred_at DESC LIMIT ?2",
+        since,
+        MAX_LISTED_ERRORS
+    )?;
+    let result = statement.all().await?;
+
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+
+    let x = result.results::<RecordedError>()?;
+    Response::from_json(&x)
+}
@@ -0,0 +1,256 @@
+use std::{ops::RangeInclusive, time::Duration};
+
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+// The durable_object macro expands to paths into these crates
+use worker::{
+    console_warn, durable_object, js_sys, wasm_bindgen, wasm_bindgen::JsValue,
+    wasm_bindgen_futures, worker_sys, D1Database, Date, Delay, Env, Headers, Method, Request,
+    RequestInit, Response, Result, RouteContext, State, Stub,
+};
+
+use crate::{
+    schemas::TransferDetail,
+    transfers::{self, TransferFilter},
+};
+
+const HEAD_KEY: &str = "head";
+// How many stored batches the feed keeps for reconnecting clients to catch up from
+const RETAINED_ENTRIES: u64 = 100;
+// How often an open stream checks the feed, and sends a comment to keep the connection open
+const POLL_SECONDS: u64 = 5;
+// Streams are closed after this long, and browsers reconnect with the last event's id
+const MAX_STREAM_SECONDS: u64 = 15 * 60;
+// How long clients wait before reconnecting
+const RETRY_MS: u64 = 3000;
+
+#[derive(Serialize, Deserialize)]
+struct PublishRequest {
+    tx_hashes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReadRequest {
+    /// The last entry the reader has, or None to only learn the head
+    after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    tx_hashes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReadResponse {
+    head: u64,
+    entries: Vec<Entry>,
+}
+
+fn entry_key(seq: u64) -> String {
+    format!("entry/{seq:020}")
+}
+
+/// The retained entries after `after`, given the latest is `head`. Readers that fell further
+/// behind than the feed keeps pick up from the oldest one it still has.
+fn unread(after: u64, head: u64) -> RangeInclusive<u64> {
+    let oldest = head.saturating_sub(RETAINED_ENTRIES - 1).max(1);
+    (after + 1).max(oldest)..=head
+}
+
+/// A changelog of newly stored transfers' hashes, one entry per stored batch, numbered in the
+/// order they were stored. Streams tail it to find what to push.
+#[durable_object]
+pub struct TransferFeed {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for TransferFeed {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let mut storage = self.state.storage();
+        // Missing keys are an error rather than undefined
+        let head = storage.get::<u64>(HEAD_KEY).await.unwrap_or(0);
+
+        match req.path().as_str() {
+            "/publish" => {
+                let request: PublishRequest = req.json().await?;
+                let seq = head + 1;
+                storage.put(&entry_key(seq), request.tx_hashes).await?;
+                storage.put(HEAD_KEY, seq).await?;
+                if seq > RETAINED_ENTRIES {
+                    storage.delete(&entry_key(seq - RETAINED_ENTRIES)).await?;
+                }
+                Response::ok("Published")
+            }
+            "/read" => {
+                let request: ReadRequest = req.json().await?;
+                let mut entries = vec![];
+                if let Some(after) = request.after {
+                    for seq in unread(after, head) {
+                        if let Ok(tx_hashes) = storage.get::<Vec<String>>(&entry_key(seq)).await {
+                            entries.push(Entry { seq, tx_hashes });
+                        }
+                    }
+                }
+                Response::from_json(&ReadResponse { head, entries })
+            }
+            _ => Response::error("Not Found", 404),
+        }
+    }
+}
+
+fn feed(env: &Env) -> Result<Stub> {
+    env.durable_object("TRANSFER_FEED")?
+        .id_from_name("transfers")?
+        .get_stub()
+}
+
+async fn send<T: Serialize>(stub: &Stub, action: &str, body: &T) -> Result<Response> {
+    let body = serde_json::to_string(body)?;
+    let req = Request::new_with_init(
+        &format!("https://transfer-feed/{action}"),
+        RequestInit::new()
+            .with_method(Method::Post)
+            .with_body(Some(JsValue::from_str(&body))),
+    )?;
+    stub.fetch_with_request(req).await
+}
+
+async fn read(stub: &Stub, after: Option<u64>) -> Result<ReadResponse> {
+    send(stub, "read", &ReadRequest { after })
+        .await?
+        .json()
+        .await
+}
+
+/// Adds the newly stored transfers to the feed for open streams to push. Does nothing without the
+/// TRANSFER_FEED binding.
+pub(crate) async fn publish(env: &Env, tx_hashes: Vec<String>) {
+    let Ok(stub) = feed(env) else {
+        return
+    };
+    if let Err(e) = send(&stub, "publish", &PublishRequest { tx_hashes }).await {
+        console_warn!("Error publishing to the transfer feed: {}", e);
+    }
+}
+
+/// A transfer as a server-sent event, with its feed entry as the id browsers reconnect with.
+fn event(seq: u64, transfer: &TransferDetail) -> Result<String> {
+    let data = serde_json::to_string(transfer)?;
+    Ok(format!("id: {seq}\nevent: transfer\ndata: {data}\n\n"))
+}
+
+struct StreamState {
+    d1: D1Database,
+    stub: Stub,
+    filter: TransferFilter,
+    /// The last feed entry pushed
+    after: u64,
+    // Unix milliseconds
+    opened_at: u64,
+    opened: bool,
+    done: bool,
+}
+
+/// GET /transfers/stream is a server-sent event stream of transfers as they're stored, each
+/// pushed as a `transfer` event with the same JSON as /transfers. Takes the /transfers filters.
+/// Clients that reconnect with `Last-Event-ID` get what they missed, as far as the feed goes back.
+pub(crate) async fn stream(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut filter = TransferFilter::default();
+    for (k, v) in req.url()?.query_pairs() {
+        match filter.apply(&k, &v) {
+            Ok(true) => {}
+            Ok(false) => return Response::error("Unexpected query parameter", 400),
+            Err(msg) => return Response::error(msg, 400),
+        }
+    }
+    let Ok(stub) = feed(&ctx.env) else {
+        return Response::error("No TRANSFER_FEED is bound", 501)
+    };
+    let head = read(&stub, None).await?.head;
+    let last_event_id = req.headers().get("Last-Event-ID")?;
+    let after = match last_event_id.and_then(|id| id.parse::<u64>().ok()) {
+        Some(seq) => seq.min(head),
+        None => head,
+    };
+
+    let state = StreamState {
+        d1: ctx.env.d1("DB")?,
+        stub,
+        filter,
+        after,
+        opened_at: Date::now().as_millis(),
+        opened: false,
+        done: false,
+    };
+    let body = stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        let chunk = next_events(&mut state).await;
+        if chunk.is_err() {
+            state.done = true;
+        }
+        match chunk {
+            Ok(None) => None,
+            Ok(Some(c)) => Some((Ok(c.into_bytes()), state)),
+            Err(e) => Some((Err(e), state)),
+        }
+    });
+
+    let mut headers = Headers::new();
+    headers.set("Content-Type", "text/event-stream")?;
+    headers.set("Cache-Control", "no-cache")?;
+    Ok(Response::from_stream(body)?.with_headers(headers))
+}
+
+/// Waits for the next entries in the feed and renders their transfers as events, or a comment if
+/// there were none. None once the stream has been open for long enough.
+async fn next_events(state: &mut StreamState) -> Result<Option<String>> {
+    if !state.opened {
+        state.opened = true;
+        return Ok(Some(format!("retry: {RETRY_MS}\n\n")));
+    }
+    if Date::now().as_millis() - state.opened_at > MAX_STREAM_SECONDS * 1000 {
+        return Ok(None);
+    }
+    Delay::from(Duration::from_secs(POLL_SECONDS)).await;
+
+    let read = read(&state.stub, Some(state.after)).await?;
+    let mut events = String::new();
+    for entry in read.entries {
+        let found = transfers::find_all(&state.d1, &state.filter, &entry.tx_hashes).await?;
+        for transfer in &found {
+            events.push_str(&event(entry.seq, transfer)?);
+        }
+        state.after = entry.seq;
+    }
+    if events.is_empty() {
+        events.push_str(": keep-alive\n\n");
+    }
+    Ok(Some(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_get_the_entries_after_theirs() {
+        assert_eq!(unread(3, 5), 4..=5);
+        assert!(unread(5, 5).is_empty());
+        assert!(unread(0, 0).is_empty());
+        assert_eq!(unread(0, 2), 1..=2);
+    }
+
+    #[test]
+    fn readers_that_fell_behind_start_from_the_oldest_retained_entry() {
+        assert_eq!(unread(10, 500), 401..=500);
+        assert_eq!(unread(0, RETAINED_ENTRIES), 1..=RETAINED_ENTRIES);
+    }
+}
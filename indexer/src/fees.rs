@@ -0,0 +1,99 @@
+use serde::Deserialize;
+use worker::{Date, Request, Response, Result, RouteContext};
+
+use crate::{
+    numeric,
+    schemas::{Fees, TokenFees},
+    usd::Usd,
+};
+
+// How far back /fees looks when no `since` is given
+const DEFAULT_LOOKBACK_SECONDS: u64 = 30 * 24 * 60 * 60;
+// Gas is paid in GLMR, which has 18 decimals
+const GLMR_DECIMALS: u32 = 18;
+
+#[derive(Deserialize)]
+struct TokenFeesRow {
+    contract_addr: String,
+    token_sym: String,
+    decimals: u32,
+    transfers: u32,
+    // D1 hands back sums as f64
+    gas_wei: f64,
+    bridge_fees: f64,
+    bridge_fees_usd: Usd,
+}
+
+/// GET /fees?since=TIMESTAMP sums what routing the transfers made since then cost, in gas and in
+/// bridge fees, per token. Only transfers whose receipts have been read are counted. Defaults to
+/// the last 30 days.
+pub(crate) async fn get(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut since = (Date::now().as_millis() / 1000).saturating_sub(DEFAULT_LOOKBACK_SECONDS);
+    for (k, v) in req.url()?.query_pairs() {
+        if k != "since" {
+            return Response::error("Unexpected query parameter", 400);
+        }
+        let Ok(s) = v.parse::<u64>() else {
+            return Response::error("since must be a unix timestamp", 400);
+        };
+        since = s;
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    // Bridge fees come out of the transfer, so they're valued at the transfer's own price
+    let statement = worker::query!(
+        &d1,
+        "
+        SELECT
+            t.contract_addr,
+            t.token_sym,
+            t.decimals,
+            COUNT(tf.tx_hash) AS transfers,
+            SUM(tf.gas_fee) AS gas_wei,
+            COALESCE(SUM(tf.bridge_fee), 0) AS bridge_fees,
+            CAST(ROUND(COALESCE(SUM(
+                CASE WHEN tf.token_count > 0
+                THEN CAST(tf.usd_cents AS REAL) * tf.bridge_fee / tf.token_count
+                END
+            ), 0)) AS INTEGER) AS bridge_fees_usd
+        FROM TransfersForward AS tf
+        INNER JOIN Token AS t ON t.contract_addr = tf.token_addr
+        WHERE tf.timestamp >= ?1 AND tf.gas_fee IS NOT NULL
+        GROUP BY t.contract_addr
+        ORDER BY bridge_fees_usd DESC, transfers DESC
+        ",
+        since
+    )?;
+    let result = statement.all().await?;
+
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+
+    let tokens: Vec<TokenFees> = result
+        .results::<TokenFeesRow>()?
+        .into_iter()
+        .map(|row| {
+            let gas_glmr = numeric::normalize(row.gas_wei, GLMR_DECIMALS);
+            TokenFees {
+                contract_addr: row.contract_addr,
+                token_sym: row.token_sym,
+                transfers: row.transfers,
+                gas_glmr,
+                average_gas_glmr: gas_glmr / row.transfers.max(1) as f64,
+                bridge_fees: numeric::normalize(row.bridge_fees, row.decimals),
+                bridge_fees_usd: row.bridge_fees_usd,
+            }
+        })
+        .collect();
+    let fees = Fees {
+        since,
+        transfers: tokens.iter().map(|t| t.transfers).sum(),
+        gas_glmr: tokens.iter().map(|t| t.gas_glmr).sum(),
+        bridge_fees_usd: tokens
+            .iter()
+            .fold(Usd::default(), |total, t| total + t.bridge_fees_usd),
+        tokens,
+    };
+    Response::from_json(&fees)
+}
@@ -0,0 +1,760 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use worker::{D1Database, Request, Response, Result, RouteContext};
+
+use crate::{
+    leaderboard::{self, Ranking},
+    liquidity_forward_totals,
+    pagination::{Page, PageParams},
+    schemas::{
+        Components, GraphQlError, GraphQlRequest, GraphQlResponse, JsonSchema, LiquidityForward,
+        Token, TokenVolume, TransferDetail,
+    },
+    token_page,
+    transfers::{self, TransferFilter},
+    Denomination,
+};
+
+// Selections nested deeper than this are rejected before they're parsed any further
+const MAX_DEPTH: usize = 10;
+// Each root, aliases included, is its own D1 query, so a request can only ask for this many
+const MAX_ROOT_FIELDS: usize = 5;
+
+#[derive(Debug, PartialEq)]
+enum Lexeme {
+    Punct(char),
+    Spread,
+    Name(String),
+    Str(String),
+    Number(String),
+}
+
+/// An argument as written in the query. Strings, numbers, booleans and enum values are all kept as
+/// text, since the roots take them the way the REST endpoints take query parameters.
+#[derive(Debug, PartialEq)]
+enum Argument {
+    Value(String),
+    Null,
+    Variable(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, Argument)>,
+    selection: Vec<Field>,
+}
+
+impl Field {
+    /// The key the field's value is returned under.
+    fn key(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Operation {
+    /// Declared variables, with their default values
+    variables: BTreeMap<String, Argument>,
+    selection: Vec<Field>,
+}
+
+/// Why a query couldn't be answered.
+enum QueryError {
+    /// The query itself is at fault
+    Invalid(String),
+    Database(worker::Error),
+}
+
+impl From<String> for QueryError {
+    fn from(message: String) -> Self {
+        Self::Invalid(message)
+    }
+}
+
+impl From<worker::Error> for QueryError {
+    fn from(e: worker::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+fn lex(source: &str) -> std::result::Result<Vec<Lexeme>, String> {
+    let mut lexemes = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // Commas are insignificant, like whitespace
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '{' | '}' | '(' | ')' | ':' | '$' | '!' | '=' | '[' | ']' | '@' => {
+                lexemes.push(Lexeme::Punct(c))
+            }
+            '.' => {
+                if chars.next() != Some('.') || chars.next() != Some('.') {
+                    return Err("Unexpected \".\"".to_string());
+                }
+                lexemes.push(Lexeme::Spread);
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None | Some('\n') => return Err("Unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                let Some(c) =
+                                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                                else {
+                                    return Err(format!("Invalid escape \\u{hex}"));
+                                };
+                                s.push(c);
+                            }
+                            _ => return Err("Invalid escape in a string".to_string()),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
+                lexemes.push(Lexeme::Str(s));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut n = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+                {
+                    n.push(c);
+                }
+                if n.parse::<f64>().is_err() {
+                    return Err(format!("Invalid number {n}"));
+                }
+                lexemes.push(Lexeme::Number(n));
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some(c) = chars.next_if(|c| *c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                lexemes.push(Lexeme::Name(name));
+            }
+            c => return Err(format!("Unexpected {c:?}")),
+        }
+    }
+    Ok(lexemes)
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.next)
+    }
+
+    fn bump(&mut self) -> Option<&Lexeme> {
+        self.next += 1;
+        self.lexemes.get(self.next - 1)
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            None => "the end of the query".to_string(),
+            Some(Lexeme::Punct(c)) => format!("\"{c}\""),
+            Some(Lexeme::Spread) => "\"...\"".to_string(),
+            Some(Lexeme::Name(n) | Lexeme::Number(n)) => n.clone(),
+            Some(Lexeme::Str(_)) => "a string".to_string(),
+        }
+    }
+
+    /// Moves past `c` if it's next.
+    fn eat(&mut self, c: char) -> bool {
+        let next = self.peek() == Some(&Lexeme::Punct(c));
+        if next {
+            self.next += 1;
+        }
+        next
+    }
+
+    fn expect(&mut self, c: char) -> std::result::Result<(), String> {
+        if !self.eat(c) {
+            return Err(format!("Expected \"{c}\", found {}", self.found()));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> std::result::Result<String, String> {
+        let Some(Lexeme::Name(name)) = self.peek() else {
+            return Err(format!("Expected a name, found {}", self.found()))
+        };
+        let name = name.clone();
+        self.next += 1;
+        Ok(name)
+    }
+
+    fn value(&mut self) -> std::result::Result<Argument, String> {
+        let found = self.found();
+        match self.bump() {
+            Some(Lexeme::Punct('$')) => Ok(Argument::Variable(self.name()?)),
+            Some(Lexeme::Str(s) | Lexeme::Number(s)) => Ok(Argument::Value(s.clone())),
+            Some(Lexeme::Name(n)) if n == "null" => Ok(Argument::Null),
+            // Booleans and enum values
+            Some(Lexeme::Name(n)) => Ok(Argument::Value(n.clone())),
+            Some(Lexeme::Punct('[' | '{')) => {
+                Err("Lists and objects aren't supported as arguments".to_string())
+            }
+            _ => Err(format!("Expected a value, found {found}")),
+        }
+    }
+
+    /// Skips a variable's type, such as `String!` or `[Int]`. Arguments check their own values.
+    fn skip_type(&mut self) -> std::result::Result<(), String> {
+        if self.eat('[') {
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.eat('!');
+        Ok(())
+    }
+
+    fn selection_set(&mut self, depth: usize) -> std::result::Result<Vec<Field>, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "Selections can't be nested more than {MAX_DEPTH} deep"
+            ));
+        }
+        self.expect('{')?;
+        let mut fields = vec![];
+        while !self.eat('}') {
+            if self.peek() == Some(&Lexeme::Spread) {
+                return Err("Fragments aren't supported".to_string());
+            }
+            let mut name = self.name()?;
+            let mut alias = None;
+            if self.eat(':') {
+                alias = Some(name);
+                name = self.name()?;
+            }
+            let mut arguments = vec![];
+            if self.eat('(') {
+                while !self.eat(')') {
+                    let argument = self.name()?;
+                    self.expect(':')?;
+                    arguments.push((argument, self.value()?));
+                }
+            }
+            if self.peek() == Some(&Lexeme::Punct('@')) {
+                return Err("Directives aren't supported".to_string());
+            }
+            let selection = if self.peek() == Some(&Lexeme::Punct('{')) {
+                self.selection_set(depth + 1)?
+            } else {
+                vec![]
+            };
+            fields.push(Field {
+                alias,
+                name,
+                arguments,
+                selection,
+            });
+        }
+        if fields.is_empty() {
+            return Err("Selections can't be empty".to_string());
+        }
+        Ok(fields)
+    }
+}
+
+/// Parses a document with a single query, either the `{ ... }` shorthand or `query Name($var:
+/// Type = default) { ... }`.
+fn parse(source: &str) -> std::result::Result<Operation, String> {
+    let mut parser = Parser {
+        lexemes: lex(source)?,
+        next: 0,
+    };
+    let mut variables = BTreeMap::new();
+    match parser.peek() {
+        Some(Lexeme::Punct('{')) => {}
+        Some(Lexeme::Name(n)) if n == "query" => {
+            parser.next += 1;
+            if let Some(Lexeme::Name(_)) = parser.peek() {
+                parser.next += 1;
+            }
+            if parser.eat('(') {
+                while !parser.eat(')') {
+                    parser.expect('$')?;
+                    let name = parser.name()?;
+                    parser.expect(':')?;
+                    parser.skip_type()?;
+                    let default = if parser.eat('=') {
+                        parser.value()?
+                    } else {
+                        Argument::Null
+                    };
+                    if let Argument::Variable(_) = default {
+                        return Err(format!("${name} can't default to a variable"));
+                    }
+                    variables.insert(name, default);
+                }
+            }
+        }
+        Some(Lexeme::Name(n)) if n == "mutation" || n == "subscription" => {
+            return Err(format!("Only queries are supported, not {n}s"));
+        }
+        Some(Lexeme::Name(n)) if n == "fragment" => {
+            return Err("Fragments aren't supported".to_string());
+        }
+        _ => return Err(format!("Expected a query, found {}", parser.found())),
+    }
+    let selection = parser.selection_set(0)?;
+    if parser.peek().is_some() {
+        return Err("Only one operation per request is supported".to_string());
+    }
+    if selection.len() > MAX_ROOT_FIELDS {
+        return Err(format!(
+            "A query can select at most {MAX_ROOT_FIELDS} roots, aliases included"
+        ));
+    }
+    Ok(Operation {
+        variables,
+        selection,
+    })
+}
+
+/// A root's arguments as text, with variables substituted. Null arguments are left out, as if they
+/// weren't given.
+fn bind(
+    field: &Field,
+    operation: &Operation,
+    variables: &BTreeMap<String, Value>,
+) -> std::result::Result<Vec<(String, String)>, String> {
+    let mut bound = vec![];
+    for (name, argument) in &field.arguments {
+        let value = match argument {
+            Argument::Value(v) => Some(v.clone()),
+            Argument::Null => None,
+            Argument::Variable(v) => {
+                let Some(default) = operation.variables.get(v) else {
+                    return Err(format!("${v} isn't declared"))
+                };
+                match (variables.get(v), default) {
+                    (Some(Value::String(s)), _) => Some(s.clone()),
+                    (Some(Value::Number(n)), _) => Some(n.to_string()),
+                    (Some(Value::Bool(b)), _) => Some(b.to_string()),
+                    (Some(Value::Null), _) => None,
+                    (Some(_), _) => {
+                        return Err(format!("${v} must be a string, number or boolean"))
+                    }
+                    (None, Argument::Value(d)) => Some(d.clone()),
+                    (None, _) => None,
+                }
+            }
+        };
+        if let Some(value) = value {
+            bound.push((name.clone(), value));
+        }
+    }
+    Ok(bound)
+}
+
+/// Follows `$ref`s, and the `allOf`s that nullable references are wrapped in, to the schema they
+/// stand for.
+fn resolve_schema<'a>(mut schema: &'a Value, components: &'a Components) -> &'a Value {
+    loop {
+        let name = schema
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|r| r.rsplit('/').next());
+        if let Some(referred) = name.and_then(|n| components.get(n)) {
+            schema = referred;
+        } else if let Some(first) = schema.get("allOf").and_then(|a| a.get(0)) {
+            schema = first;
+        } else {
+            return schema;
+        }
+    }
+}
+
+/// Picks the selected fields out of a resolved value, checking them against its schema. `path`
+/// names the value in errors.
+fn select(
+    value: &Value,
+    schema: &Value,
+    components: &Components,
+    selection: &[Field],
+    path: &str,
+) -> std::result::Result<Value, String> {
+    let schema = resolve_schema(schema, components);
+    if let Some(items) = schema.get("items") {
+        let Value::Array(values) = value else {
+            return Ok(Value::Null)
+        };
+        return values
+            .iter()
+            .map(|v| select(v, items, components, selection, path))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map(Value::Array);
+    }
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        if !selection.is_empty() {
+            return Err(format!("{path} has no fields to select"));
+        }
+        return Ok(value.clone());
+    };
+    if selection.is_empty() {
+        return Err(format!("{path} needs a selection of its fields"));
+    }
+
+    let mut selected = Map::new();
+    for field in selection {
+        let field_path = format!("{path}.{}", field.name);
+        let Some(property) = properties.get(&field.name) else {
+            return Err(format!("{path} has no field {}", field.name))
+        };
+        if !field.arguments.is_empty() {
+            return Err(format!("{field_path} takes no arguments"));
+        }
+        // Fields serialized without their empty values come back as null
+        let v = value.get(&field.name).unwrap_or(&Value::Null);
+        let v = select(v, property, components, &field.selection, &field_path)?;
+        selected.insert(field.key().to_string(), v);
+    }
+    Ok(Value::Object(selected))
+}
+
+/// Takes the outcome of applying an argument the way a REST endpoint applies a query parameter.
+fn taken<E: ToString>(
+    root: &str,
+    argument: &str,
+    result: std::result::Result<bool, E>,
+) -> std::result::Result<(), QueryError> {
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("{root} has no argument {argument}").into()),
+        Err(e) => Err(e.to_string().into()),
+    }
+}
+
+/// Selects from what a root resolved to, as described by `T`'s schema.
+fn answer<T: Serialize + JsonSchema>(
+    resolved: &T,
+    field: &Field,
+) -> std::result::Result<Value, QueryError> {
+    let mut components = Components::new();
+    let schema = T::schema(&mut components);
+    let value = serde_json::to_value(resolved).map_err(worker::Error::from)?;
+    Ok(select(
+        &value,
+        &schema,
+        &components,
+        &field.selection,
+        &field.name,
+    )?)
+}
+
+/// Resolves a query root with one D1 query, taking the same arguments as its REST endpoint.
+async fn resolve(
+    d1: &D1Database,
+    field: &Field,
+    arguments: Vec<(String, String)>,
+) -> std::result::Result<Value, QueryError> {
+    let root = field.name.as_str();
+    match root {
+        // Like /transfers, and /transfers/byAddress with `address`
+        "transfers" => {
+            let mut filter = TransferFilter::default();
+            let mut page = PageParams::<(u64, String)>::default();
+            for (k, v) in &arguments {
+                if k == "address" {
+                    filter.address = Some(v.clone());
+                    continue;
+                }
+                let result = match filter.apply(k, v) {
+                    Ok(false) => page.apply(k, v),
+                    result => result,
+                };
+                taken(root, k, result)?;
+            }
+            let transfers: Page<TransferDetail> = transfers::query_page(d1, &filter, &page).await?;
+            answer(&transfers, field)
+        }
+        // Like /getTokens
+        "tokens" => {
+            let mut page = PageParams::<String>::default();
+            for (k, v) in &arguments {
+                taken(root, k, page.apply(k, v))?;
+            }
+            let tokens: Page<Token> = token_page(d1, &page).await?;
+            answer(&tokens, field)
+        }
+        // Like /totalLiquidityForward, optionally for one token
+        "liquidity" => {
+            let mut denomination = Denomination::Usd;
+            let mut token = None;
+            for (k, v) in &arguments {
+                match k.as_str() {
+                    "denomination" => {
+                        let Some(d) = Denomination::parse(v) else {
+                            return Err("denomination must be usd or token".to_string().into())
+                        };
+                        denomination = d;
+                    }
+                    "token" => token = Some(v),
+                    _ => taken(root, k, Ok::<bool, String>(false))?,
+                }
+            }
+            let liquidity: Vec<LiquidityForward> = liquidity_forward_totals(d1)
+                .await?
+                .into_iter()
+                .filter(|l| {
+                    token.map_or(true, |t| {
+                        l.contract_addr.eq_ignore_ascii_case(t) || l.token_sym == *t
+                    })
+                })
+                .map(|l| l.denominate(denomination))
+                .collect();
+            answer(&liquidity, field)
+        }
+        // Like /topTokens
+        "volume" => {
+            let mut ranking = Ranking::default();
+            for (k, v) in &arguments {
+                taken(root, k, ranking.apply(k, v))?;
+            }
+            let volume: Vec<TokenVolume> = leaderboard::rank(d1, &ranking).await?;
+            answer(&volume, field)
+        }
+        _ => Err(format!(
+            "There's no query root {root}, only transfers, tokens, liquidity and volume"
+        )
+        .into()),
+    }
+}
+
+fn errors(message: String, status: u16) -> Result<Response> {
+    let response = GraphQlResponse {
+        data: None,
+        errors: Some(vec![GraphQlError { message }]),
+    };
+    Ok(Response::from_json(&response)?.with_status(status))
+}
+
+/// POST /graphql with `{ "query": ..., "variables": ... }` answers a query over the `transfers`,
+/// `tokens`, `liquidity` and `volume` roots. Each root takes the query parameters of its REST
+/// endpoint as arguments and is resolved with one parameterized D1 query, so a query can select
+/// at most `MAX_ROOT_FIELDS` of them. Fragments, directives and mutations aren't supported.
+pub(crate) async fn post(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let Ok(request) = req.json::<GraphQlRequest>().await else {
+        return errors("Expected a JSON body with a query".to_string(), 400)
+    };
+    let operation = match parse(&request.query) {
+        Ok(o) => o,
+        Err(e) => return errors(e, 400),
+    };
+    let variables = request.variables.unwrap_or_default();
+
+    let d1 = ctx.env.d1("DB")?;
+    let mut data = BTreeMap::new();
+    for field in &operation.selection {
+        let resolved = match bind(field, &operation, &variables) {
+            Ok(arguments) => resolve(&d1, field, arguments).await,
+            Err(e) => Err(QueryError::Invalid(e)),
+        };
+        match resolved {
+            Ok(value) => {
+                data.insert(field.key().to_string(), value);
+            }
+            Err(QueryError::Invalid(e)) => return errors(e, 400),
+            Err(QueryError::Database(e)) => return errors(e.to_string(), 500),
+        }
+    }
+    let response = GraphQlResponse {
+        data: Some(data),
+        errors: None,
+    };
+    Response::from_json(&response)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn field(name: &str, selection: Vec<Field>) -> Field {
+        Field {
+            alias: None,
+            name: name.to_string(),
+            arguments: vec![],
+            selection,
+        }
+    }
+
+    #[test]
+    fn parses_roots_with_aliases_arguments_and_selections() {
+        let operation = parse(
+            r#"
+            # The newest WETH transfers
+            {
+                weth: transfers(token: "WETH", limit: 5, to_chain: null) {
+                    items { tx_hash usd }
+                    next_cursor
+                }
+                liquidity(denomination: token) { token_sym }
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert!(operation.variables.is_empty());
+        assert_eq!(operation.selection.len(), 2);
+        let weth = &operation.selection[0];
+        assert_eq!(weth.key(), "weth");
+        assert_eq!(weth.name, "transfers");
+        assert_eq!(
+            weth.arguments,
+            vec![
+                ("token".to_string(), Argument::Value("WETH".to_string())),
+                ("limit".to_string(), Argument::Value("5".to_string())),
+                ("to_chain".to_string(), Argument::Null),
+            ]
+        );
+        assert_eq!(
+            weth.selection,
+            vec![
+                field(
+                    "items",
+                    vec![field("tx_hash", vec![]), field("usd", vec![])]
+                ),
+                field("next_cursor", vec![]),
+            ]
+        );
+        assert_eq!(
+            operation.selection[1].arguments,
+            vec![(
+                "denomination".to_string(),
+                Argument::Value("token".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn variables_take_their_defaults() {
+        let operation = parse(
+            "query Top($window: String! = \"7d\", $limit: Int) {
+                volume(window: $window, limit: $limit) { token_sym }
+            }",
+        )
+        .unwrap();
+        let volume = &operation.selection[0];
+
+        let bound = bind(volume, &operation, &BTreeMap::new()).unwrap();
+        assert_eq!(bound, vec![("window".to_string(), "7d".to_string())]);
+
+        let variables = BTreeMap::from([
+            ("window".to_string(), json!("30d")),
+            ("limit".to_string(), json!(3)),
+        ]);
+        let bound = bind(volume, &operation, &variables).unwrap();
+        assert_eq!(
+            bound,
+            vec![
+                ("window".to_string(), "30d".to_string()),
+                ("limit".to_string(), "3".to_string()),
+            ]
+        );
+
+        let variables = BTreeMap::from([("limit".to_string(), json!([3]))]);
+        assert!(bind(volume, &operation, &variables).is_err());
+    }
+
+    #[test]
+    fn undeclared_variables_are_rejected() {
+        let operation = parse("{ tokens(limit: $limit) { items { token_sym } } }").unwrap();
+        let err = bind(&operation.selection[0], &operation, &BTreeMap::new()).unwrap_err();
+        assert_eq!(err, "$limit isn't declared");
+    }
+
+    #[test]
+    fn unsupported_documents_are_rejected() {
+        assert!(parse("mutation { reset }").is_err());
+        assert!(parse("{ tokens { ...TokenFields } }").is_err());
+        assert!(parse("{ tokens(limit: [1]) { items { token_sym } } }").is_err());
+        assert!(parse("{ tokens { items { token_sym } }").is_err());
+        assert!(parse("{ tokens { } }").is_err());
+        assert!(parse("{ a } { b }").is_err());
+        assert!(parse("{ tokens(token: \"WETH) { items } }").is_err());
+
+        let deep = format!(
+            "{}{}",
+            "{ a ".repeat(MAX_DEPTH + 2),
+            "}".repeat(MAX_DEPTH + 2)
+        );
+        assert!(parse(&deep).is_err());
+    }
+
+    #[test]
+    fn root_fields_are_capped() {
+        let roots = |n: usize| {
+            let aliases: Vec<String> = (0..n)
+                .map(|i| format!("t{i}: tokens {{ total }}"))
+                .collect();
+            format!("{{ {} }}", aliases.join(" "))
+        };
+        assert_eq!(
+            parse(&roots(MAX_ROOT_FIELDS)).unwrap().selection.len(),
+            MAX_ROOT_FIELDS
+        );
+        let Err(e) = parse(&roots(MAX_ROOT_FIELDS + 1)) else {
+            panic!("too many roots were accepted")
+        };
+        assert!(e.contains("at most"));
+    }
+
+    #[test]
+    fn selections_are_checked_against_the_schema() {
+        let mut components = Components::new();
+        let schema = Page::<Token>::schema(&mut components);
+        let value = json!({
+            "items": [
+                { "contract_addr": "0xab", "token_name": "Wrapped Ether", "token_sym": "WETH", "decimals": 18 },
+            ],
+            "next_cursor": null,
+        });
+        let query = |selection: &str| {
+            let operation = parse(&format!("{{ tokens {selection} }}")).unwrap();
+            let tokens = &operation.selection[0];
+            select(&value, &schema, &components, &tokens.selection, "tokens")
+        };
+
+        assert_eq!(
+            query("{ items { symbol: token_sym category } next_cursor }").unwrap(),
+            json!({
+                "items": [{ "symbol": "WETH", "category": null }],
+                "next_cursor": null,
+            })
+        );
+        assert_eq!(
+            query("{ items { price } }").unwrap_err(),
+            "tokens.items has no field price"
+        );
+        assert_eq!(
+            query("{ items }").unwrap_err(),
+            "tokens.items needs a selection of its fields"
+        );
+        assert_eq!(
+            query("{ items { token_sym { length } } }").unwrap_err(),
+            "tokens.items.token_sym has no fields to select"
+        );
+    }
+}
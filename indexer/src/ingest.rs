@@ -0,0 +1,148 @@
+use worker::{console_log, D1Database, Env, Queue};
+
+use crate::{d1, errors::IndexerError, pipeline::TransferBatch};
+
+// IndexerState key of the last block whose queued transfers have all been stored
+const QUEUED_THROUGH_KEY: &str = "queued_through_block";
+// Queue messages are limited to 128 KB, and a serialized transfer is well under 500 bytes
+const MAX_TRANSFERS_PER_MESSAGE: usize = 200;
+
+/// The queue scheduled runs send fetched transfers to, if TRANSFER_QUEUE is bound. Without it,
+/// runs price and store transfers themselves.
+pub(crate) fn queue(env: &Env) -> Option<Queue> {
+    env.queue("TRANSFER_QUEUE").ok()
+}
+
+/// The last block the consumer has stored every queued transfer up to, which runs fetch from
+/// instead of the last stored block, since a batch that failed can leave gaps below that.
+pub(crate) async fn queued_through(db: &D1Database) -> Result<Option<u64>, IndexerError> {
+    let value = d1::retry("Reading the queue watermark", || async {
+        db.prepare("SELECT value FROM IndexerState WHERE key = ?1")
+            .bind(&[QUEUED_THROUGH_KEY.into()])?
+            .first::<String>(Some("value"))
+            .await
+    })
+    .await
+    .map_err(|e| d1::db_error(e.to_string()))?;
+    Ok(value.and_then(|v| v.parse().ok()))
+}
+
+/// Sends the batch to the queue in messages small enough for it, oldest first. Returns how many
+/// messages were sent. The watermark is left for the consumer to move once it has stored them, so
+/// until then later runs queue the same blocks again, which the consumer skips once stored.
+pub(crate) async fn enqueue(
+    queue: &Queue,
+    db: &D1Database,
+    batch: TransferBatch,
+) -> worker::Result<usize> {
+    // The consumer only moves a watermark that is there, so the first batch queued starts one
+    if let Some(after_block) = batch.after_block {
+        let statement = db
+            .prepare("INSERT OR IGNORE INTO IndexerState (key, value) VALUES (?1, ?2)")
+            .bind(&[QUEUED_THROUGH_KEY.into(), after_block.to_string().into()])?;
+        d1::retry("Starting the queue watermark", || statement.run()).await?;
+    }
+
+    let last_block = batch.last_block();
+    let mut sent = 0;
+    for message in batch.split(MAX_TRANSFERS_PER_MESSAGE) {
+        queue.send(&message).await?;
+        sent += 1;
+    }
+    if let Some(block) = last_block {
+        console_log!("Queued transfers through block {}", block);
+    }
+    Ok(sent)
+}
+
+/// Whether everything in a queued batch was stored by an earlier delivery, or another copy of it.
+pub(crate) fn already_stored(queued_through: Option<u64>, batch: &TransferBatch) -> bool {
+    matches!(
+        (queued_through, batch.last_block()),
+        (Some(stored), Some(last)) if last <= stored
+    )
+}
+
+/// Moves the watermark past a batch the consumer has stored, if the batch carries on from it. A
+/// batch stored after an earlier one failed leaves it where it is, so the failed one's blocks are
+/// fetched and queued again. Returns the watermark.
+pub(crate) async fn stored(
+    db: &D1Database,
+    batch_after: Option<u64>,
+    batch_last: Option<u64>,
+) -> Result<Option<u64>, IndexerError> {
+    if let (Some(after), Some(last)) = (batch_after, batch_last) {
+        let statement = db
+            .prepare(format!(
+                "UPDATE IndexerState SET value = ?2 \
+                 WHERE key = '{QUEUED_THROUGH_KEY}' AND CAST(value AS INTEGER) = ?1"
+            ))
+            .bind(&[(after as f64).into(), last.to_string().into()])
+            .map_err(|e| d1::db_error(e.to_string()))?;
+        d1::retry("Moving the queue watermark", || statement.run())
+            .await
+            .map_err(|e| d1::db_error(e.to_string()))?;
+    }
+    queued_through(db).await
+}
+
+/// The statement that moves the queue watermark back to just before `block`, for when stored
+/// transfers from there on are deleted to be indexed again.
+pub(crate) fn rewind(block: u64) -> String {
+    format!(
+        "UPDATE IndexerState SET value = '{}' \
+         WHERE key = '{QUEUED_THROUGH_KEY}' AND CAST(value AS INTEGER) >= {block}",
+        block.saturating_sub(1)
+    )
+}
+
+/// Drops the queue watermark, so that a run with the queue resumes from the last stored block.
+/// Runs without the queue do this, since their transfers move past it.
+pub(crate) async fn forget(db: &D1Database) {
+    let _ = db
+        .prepare(format!(
+            "DELETE FROM IndexerState WHERE key = '{QUEUED_THROUGH_KEY}'"
+        ))
+        .run()
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use mrl_indexer_core::{models::TransferForward, usd::Usd};
+
+    use super::*;
+
+    fn batch(blocks: &[u64]) -> TransferBatch {
+        let native = blocks
+            .iter()
+            .map(|b| TransferForward {
+                tx_hash: format!("0x{b}"),
+                token_addr: String::new(),
+                token_count: 1,
+                usd: Usd::default(),
+                usd_min: Usd::default(),
+                usd_max: Usd::default(),
+                block_num: *b,
+                timestamp: 0,
+                to_chain: 0,
+                price_uncertain: false,
+                dest_account: None,
+                timestamp_corrected: false,
+                price: None,
+            })
+            .collect();
+        TransferBatch {
+            native,
+            ..TransferBatch::default()
+        }
+    }
+
+    #[test]
+    fn batches_below_the_watermark_were_stored() {
+        assert!(already_stored(Some(20), &batch(&[15, 20])));
+        assert!(!already_stored(Some(20), &batch(&[15, 21])));
+        assert!(!already_stored(None, &batch(&[15])));
+        assert!(!already_stored(Some(20), &batch(&[])));
+    }
+}
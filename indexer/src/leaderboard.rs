@@ -0,0 +1,155 @@
+use serde::Deserialize;
+use worker::{D1Database, Date, Request, Response, Result, RouteContext};
+
+use crate::{numeric, schemas::TokenVolume, usd::Usd};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 100;
+
+/// How far back /topTokens looks.
+#[derive(Clone, Copy)]
+enum Window {
+    Day,
+    Week,
+    Month,
+}
+
+impl Window {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "24h" => Some(Self::Day),
+            "7d" => Some(Self::Week),
+            "30d" => Some(Self::Month),
+            _ => None,
+        }
+    }
+
+    fn seconds(self) -> u64 {
+        let days = match self {
+            Self::Day => 1,
+            Self::Week => 7,
+            Self::Month => 30,
+        };
+        days * 24 * 60 * 60
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenVolumeRow {
+    contract_addr: String,
+    token_name: String,
+    token_sym: String,
+    decimals: u32,
+    category: Option<String>,
+    logo_url: Option<String>,
+    total_usd: Usd,
+    total_tokens: f64,
+    number_of_transfers: u32,
+}
+
+/// What /topTokens ranks over, from its `window` and `limit` query parameters.
+pub(crate) struct Ranking {
+    window: Window,
+    limit: u32,
+}
+
+impl Default for Ranking {
+    fn default() -> Self {
+        Self {
+            window: Window::Day,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl Ranking {
+    /// Takes a query parameter if it's a ranking one. Returns whether it was one, or why its value
+    /// is invalid.
+    pub(crate) fn apply(&mut self, key: &str, value: &str) -> std::result::Result<bool, String> {
+        match key {
+            "window" => {
+                let Some(w) = Window::parse(value) else {
+                    return Err("window must be 24h, 7d or 30d".to_string())
+                };
+                self.window = w;
+            }
+            "limit" => match value.parse::<u32>() {
+                Ok(l) if (1..=MAX_LIMIT).contains(&l) => self.limit = l,
+                _ => return Err(format!("limit must be between 1 and {MAX_LIMIT}")),
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// GET /topTokens?window=24h|7d|30d&limit=N ranks tokens by the USD volume routed through MRL over
+/// the window, breaking ties by transfer count. Defaults to the last 24 hours and the top 10.
+pub(crate) async fn top_tokens(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut ranking = Ranking::default();
+    for (k, v) in req.url()?.query_pairs() {
+        match ranking.apply(&k, &v) {
+            Ok(true) => {}
+            Ok(false) => return Response::error("Unexpected query parameter", 400),
+            Err(msg) => return Response::error(msg, 400),
+        }
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    match rank(&d1, &ranking).await {
+        Ok(x) => Response::from_json(&x),
+        Err(e) => Response::error(e.to_string(), 500),
+    }
+}
+
+/// The tokens with the most USD volume over the ranking's window, highest first.
+pub(crate) async fn rank(d1: &D1Database, ranking: &Ranking) -> Result<Vec<TokenVolume>> {
+    let since = (Date::now().as_millis() / 1000).saturating_sub(ranking.window.seconds());
+    let statement = worker::query!(
+        d1,
+        "
+        SELECT
+            t.contract_addr,
+            t.token_name,
+            t.token_sym,
+            t.decimals,
+            t.category,
+            t.logo_url,
+            SUM(tf.usd_cents) AS total_usd,
+            SUM(tf.token_count) AS total_tokens,
+            COUNT(tf.tx_hash) AS number_of_transfers
+        FROM TransfersForward AS tf
+        INNER JOIN Token AS t ON t.contract_addr = tf.token_addr
+        WHERE tf.timestamp >= ?1
+        GROUP BY t.contract_addr
+        ORDER BY total_usd DESC, number_of_transfers DESC
+        LIMIT ?2
+        ",
+        since,
+        ranking.limit
+    )?;
+    let result = statement.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+
+    Ok(result
+        .results::<TokenVolumeRow>()?
+        .into_iter()
+        .zip(1..)
+        .map(|(row, rank)| TokenVolume {
+            rank,
+            total_tokens: numeric::normalize(row.total_tokens, row.decimals),
+            contract_addr: row.contract_addr,
+            token_name: row.token_name,
+            token_sym: row.token_sym,
+            decimals: row.decimals,
+            category: row.category,
+            logo_url: row.logo_url,
+            total_usd: row.total_usd,
+            number_of_transfers: row.number_of_transfers,
+        })
+        .collect())
+}
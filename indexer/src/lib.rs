@@ -1,117 +1,212 @@
 use std::{collections::HashMap, vec};
 
-use ethers_core::types::{Chain, H160, U64};
-use ethers_etherscan::{
-    account::{Sort, TokenQueryOption, TxListParams},
-    Client,
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use mrl_indexer_core::{
+    decoder, eth,
+    models::{TokenMetadata, TransferForward},
+    native, numeric, pipeline, scan,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use worker::{
-    console_error, console_log, console_warn, event, Cors, Date, Env, Request, Response, Result,
-    Router, ScheduleContext, ScheduledEvent,
+    console_error, console_log, console_warn, event, Bucket, D1Database, D1Result, Date, Env,
+    MessageBatch, Method, Request, Response, Result, Router, ScheduleContext, ScheduledEvent,
 };
 
+mod admin;
+mod alerts;
+mod archive;
+mod audit;
+mod budget;
+mod cache;
+mod chains;
+mod coingecko;
+mod config;
+mod cors;
+mod d1;
+mod defillama;
+mod errors;
+mod fees;
+mod feed;
+mod graphql;
+mod ingest;
+mod leaderboard;
+mod lock;
+mod metrics;
+mod openapi;
+mod pagination;
+mod payloads;
+mod price_providers;
+mod proposals;
+mod quotas;
+mod ratelimit;
+mod registry;
+mod reorg;
+mod retry;
+mod rpc;
+mod schedules;
+mod schemas;
+mod shadow;
+mod signing;
+mod slo;
+mod snapshots;
+mod status;
+mod subscan;
+mod tiers;
+mod timestamps;
+mod tokens;
+mod transfers;
 mod twelve_data;
-use twelve_data::get_twelve_data;
+mod usd;
+mod webhooks;
+use budget::{RunStats, WorkBudget};
+use config::Config;
+use errors::IndexerError;
+use pagination::{Page, PageParams};
+use pipeline::PartialInsert;
+use retry::{retry, RetryPolicy};
+use schedules::Task;
+use schemas::{ChainLiquidity, LiquidityForward, RunMetrics, Token, TokenTotal};
+use usd::Usd;
 
-use crate::twelve_data::TimeSeries;
-
-#[derive(Deserialize, Serialize)]
-struct LiquidityForward {
-    contract_addr: String,
-    token_name: String,
-    token_sym: String,
-    decimals: u32,
-    total_usd: f32,
-    // total_tokens: u128,          // Likes to give an error :(
-    number_of_transfers: u32,
+impl LiquidityForward {
+    fn denominate(mut self, denomination: Denomination) -> Self {
+        match denomination {
+            Denomination::Usd => self.total_tokens = None,
+            Denomination::Token => {
+                self.total_usd = None;
+                self.total_usd_min = None;
+                self.total_usd_max = None;
+                self.total_tokens = self
+                    .total_tokens
+                    .map(|t| numeric::normalize(t, self.decimals));
+            }
+        }
+        self
+    }
 }
 
-#[derive(Deserialize, Serialize)]
-struct Token {
-    contract_addr: String,
-    token_name: String,
-    token_sym: String,
-    decimals: u32,
+/// Which unit aggregate endpoints report totals in, chosen with `?denomination=usd|token`.
+#[derive(Clone, Copy, PartialEq)]
+enum Denomination {
+    Usd,
+    Token,
 }
 
-impl Default for Token {
-    fn default() -> Self {
-        Self {
-            contract_addr: Default::default(),
-            token_name: Default::default(),
-            token_sym: Default::default(),
-            decimals: 18,
+impl Denomination {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "usd" => Some(Self::Usd),
+            "token" => Some(Self::Token),
+            _ => None,
         }
     }
 }
 
-#[derive(Deserialize, Serialize)]
-struct TransferForward {
-    tx_hash: String,
-    token_addr: String,
-    token_count: u128,
-    usd: f32,
-    block_num: u64,
-    timestamp: String,
+// The query strings dashboards request the warmed endpoints with
+const WARMED_QUERIES: [(Option<&str>, Denomination); 3] = [
+    (None, Denomination::Usd),
+    (Some("denomination=usd"), Denomination::Usd),
+    (Some("denomination=token"), Denomination::Token),
+];
+
+#[derive(Deserialize, Serialize, Clone)]
+struct ChainTokenLiquidity {
     to_chain: u32,
+    chain_name: Option<String>,
+    unique_recipients: u32,
+    contract_addr: String,
+    token_sym: String,
+    decimals: u32,
+    total_usd: Usd,
+    total_usd_min: Usd,
+    total_usd_max: Usd,
+    total_tokens: f64,
+    number_of_transfers: u32,
 }
 
 #[event(fetch)]
-pub async fn fetch(req: Request, _env: Env, _ctx: worker::Context) -> Result<Response> {
+pub async fn fetch(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
+    let cors = cors::CorsPolicy::from_env(&env);
+    if req.method() == Method::Options {
+        return cors.preflight(&req);
+    }
+    let origin = req.headers().get("Origin")?;
+    // Errors get the CORS headers too, so browsers can read them
+    let res = match respond(req, env).await {
+        Ok(res) => res,
+        Err(e) => {
+            console_error!("Error handling request: {}", e);
+            Response::error(e.to_string(), 500)?
+        }
+    };
+    cors.apply(origin.as_deref(), res)
+}
+
+async fn respond(req: Request, env: Env) -> Result<Response> {
     let router = Router::new();
+    let signing_key = signing::signing_key(&env);
+    let notices = quotas::Notices::from_env(&env);
+    let usage = match tiers::check(&req, &env).await? {
+        tiers::Access::Granted(usage) => usage,
+        tiers::Access::Denied(res) => return Ok(res),
+    };
+    // Keyed requests are metered by their quota instead
+    if usage.is_none() {
+        if let Some(res) = ratelimit::check(&req, &env).await? {
+            return notices.apply(res, None);
+        }
+    }
 
+    // Reads only change when the cron job writes, so serve them from KV where possible
+    let response_cache = cache::ResponseCache::from_env(&env).await;
+    let cache_key = response_cache.as_ref().and_then(|c| c.key(&req));
+    if let (Some(response_cache), Some(key)) = (&response_cache, &cache_key) {
+        if let Some(body) = response_cache.get(key).await {
+            let res = notices.apply(cache::json_response(body, true)?, usage.as_ref())?;
+            return signing::sign_if_configured(&signing_key, res).await;
+        }
+    }
 
-    router
+    let mut res = router
         .get_async("/totalLiquidityForward", |_req: Request, ctx| async move {
-            let cors = Cors::default().with_origins(vec!["*"]).with_allowed_headers(vec!["*"]);
+            let Some(denomination) = denomination_param(&_req)? else {
+                return Response::error("Unexpected denomination", 400);
+            };
             let d1 = ctx.env.d1("DB")?;
-            let statement = worker::query!(
-                &d1,
-                "
-                SELECT 
-                    t.contract_addr,
-                    t.token_name,
-                    t.token_sym,
-                    t.decimals,
-                    SUM(tf.usd) AS total_usd,
-                    SUM(tf.token_count) AS total_tokens,
-                    COUNT(tf.token_addr) AS number_of_transfers
-                FROM Token AS t
-                INNER JOIN TransfersForward AS tf ON t.contract_addr = tf.token_addr
-                GROUP BY t.contract_addr, t.token_name, t.token_sym, t.decimals
-            "
-            );
-
-            let result = statement.all().await?;
-
-            if !result.success() {
-                return Response::error(
-                    result.error().unwrap_or("No error given".to_string()),
-                    500,
-                )?
-                .with_cors(&cors);
-            }
-
-            let x = result.results::<LiquidityForward>()?;
-            Response::from_json(&x)?.with_cors(&cors)
+            let totals = match liquidity_forward_totals(&d1).await {
+                Ok(t) => t,
+                Err(e) => return Response::error(e.to_string(), 500),
+            };
+            let x: Vec<LiquidityForward> = totals
+                .into_iter()
+                .map(|l| l.denominate(denomination))
+                .collect();
+            Response::from_json(&x)
         })
         .get_async(
             "/liquidityForward/:contract",
             |_req: Request, ctx| async move {
-                let cors = Cors::default().with_origins(vec!["*"]).with_allowed_headers(vec!["*"]);
                 let contract = ctx.param("contract").unwrap();
                 let d1 = ctx.env.d1("DB")?;
 
                 // Get query params
-                let mut timestamp = (Date::now().as_millis() / 1000).to_string();
+                let mut timestamp = Date::now().as_millis() / 1000;
                 for (k, v) in _req.url()?.query_pairs() {
-                    if k != "timestamp" {
-                        return Response::error("Unexpected query parameter", 400)?
-                            .with_cors(&Cors::default());
+                    match k.as_ref() {
+                        "timestamp" => {
+                            let Ok(t) = v.parse::<u64>() else {
+                                return Response::error("timestamp must be a unix timestamp", 400)
+                            };
+                            timestamp = t;
+                        }
+                        "denomination" => {}
+                        _ => return Response::error("Unexpected query parameter", 400),
                     }
-                    timestamp = v.to_string();
                 }
+                let Some(denomination) = denomination_param(&_req)? else {
+                    return Response::error("Unexpected denomination", 400);
+                };
                 console_log!("Timestamp was {}", timestamp);
 
                 // Prepare statement
@@ -123,7 +218,9 @@ pub async fn fetch(req: Request, _env: Env, _ctx: worker::Context) -> Result<Res
                         t.token_name,
                         t.token_sym,
                         t.decimals,
-                        SUM(tf.usd) AS total_usd,
+                        SUM(tf.usd_cents) AS total_usd,
+                        SUM(COALESCE(tf.usd_min_cents, tf.usd_cents)) AS total_usd_min,
+                        SUM(COALESCE(tf.usd_max_cents, tf.usd_cents)) AS total_usd_max,
                         SUM(tf.token_count) AS total_tokens,
                         COUNT(tf.token_addr) AS number_of_transfers
                     FROM Token AS t
@@ -132,369 +229,1208 @@ pub async fn fetch(req: Request, _env: Env, _ctx: worker::Context) -> Result<Res
                     GROUP BY t.contract_addr, t.token_name, t.token_sym, t.decimals
                 "
                 )
-                .bind(&[contract.into(), timestamp.into()]);
+                .bind(&[contract.into(), (timestamp as f64).into()]);
 
                 let result = statement?.first::<LiquidityForward>(None).await?;
 
                 if let Some(liquidity) = result {
-                    Response::from_json(&liquidity)?.with_cors(&cors)
+                    Response::from_json(&liquidity.denominate(denomination))
                 } else {
-                    Response::error("Error when querying results".to_string(), 500)?
-                        .with_cors(&cors)
+                    Response::error("Error when querying results".to_string(), 500)
                 }
             },
         )
-        .get_async("/getTokens", |_req, ctx| async move {
-            let cors = Cors::default().with_origins(vec!["*"]).with_allowed_headers(vec!["*"]);
-            let d1 = ctx.env.d1("DB")?;
-            let statement = worker::query!(&d1, "SELECT * FROM Token");
-            let result = statement.all().await?;
-
-            if !result.success() {
-                return Response::error(
-                    result.error().unwrap_or("No error given".to_string()),
-                    500,
-                )?
-                .with_cors(&cors);
+        .get_async("/getTokens", |req, ctx| async move {
+            // Paged by contract address
+            let mut page = PageParams::<String>::default();
+            for (k, v) in req.url()?.query_pairs() {
+                match page.apply(&k, &v) {
+                    Ok(true) => {}
+                    Ok(false) => return Response::error("Unexpected query parameter", 400),
+                    Err(msg) => return Response::error(msg, 400),
+                }
             }
 
-            let x = result.results::<Token>()?;
-            Response::from_json(&x)?.with_cors(&cors)
-        })
-        /* .post_async("/reset", |_req, ctx| async move {
             let d1 = ctx.env.d1("DB")?;
-            let statements = vec![
-                query!(&d1, "DROP TABLE IF EXISTS TransfersForward"),
-                query!(&d1, "DROP TABLE IF EXISTS Token"),
-            ];
-            let result = d1.batch(statements).await?;
-            let statements = vec![
-                d1.prepare(
-                    "
-                    CREATE TABLE IF NOT EXISTS Token (
-                        contract_addr TEXT NOT NULL PRIMARY KEY,
-                        token_name TEXT NOT NULL,
-                        token_sym TEXT NOT NULL,
-                        decimals UNSIGNED INT NOT NULL
-                    );
-                ",
-                ),
-                d1.prepare(
-                    "
-                    CREATE TABLE IF NOT EXISTS TransfersForward (
-                        tx_hash TEXT PRIMARY KEY,
-                        token_addr TEXT NOT NULL REFERENCES Token(contract_addr),
-                        token_count UNSIGNED INT NOT NULL,
-                        usd REAL NOT NULL,
-                        block_num UNSIGNED INT NOT NULL,
-                        timestamp TEXT,
-                        to_chain UNSIGNED INT NOT NULL
-                    );
-                ",
-                ),
-            ];
-            let result = d1.batch(statements).await?;
-            let mut message: String = "".to_owned();
-            for r in result {
-                if r.success() {
-                    message += "Success; ";
-                } else {
-                    message += &(r.error().unwrap_or("No error given".to_string()) + "; ");
-                }
+            match token_page(&d1, &page).await {
+                Ok(x) => Response::from_json(&x),
+                Err(e) => Response::error(e.to_string(), 500),
             }
-            Response::ok(message)
         })
-        */
-        .run(req, _env)
-        .await
+        .get_async("/liquidityByChain", |_req, ctx| async move {
+            let Some(denomination) = denomination_param(&_req)? else {
+                return Response::error("Unexpected denomination", 400);
+            };
+            let d1 = ctx.env.d1("DB")?;
+            let rows = match chain_liquidity_rows(&d1).await {
+                Ok(r) => r,
+                Err(e) => return Response::error(e.to_string(), 500),
+            };
+            let chains = group_by_chain(rows, denomination);
+            Response::from_json(&chains)
+        })
+        .get_async("/topTokens", leaderboard::top_tokens)
+        .get_async("/liquidityHistory", snapshots::history)
+        .get_async("/transfers", transfers::list)
+        .get_async("/transfers/export", transfers::export)
+        .get_async("/transfers/stream", feed::stream)
+        .get_async("/transfers/byAddress/:addr", transfers::by_address)
+        .get_async("/transfers/:hash", transfers::get)
+        .get_async("/transfer/:tx_hash", transfers::lookup)
+        .get_async("/errors", errors::list)
+        .get_async("/status", status::get)
+        .get_async("/metrics", metrics::get)
+        .get_async("/slo", slo::get)
+        .get_async("/fees", fees::get)
+        .post_async("/graphql", graphql::post)
+        .post_async("/proposals", proposals::submit)
+        .get_async("/proposals", proposals::mine)
+        .post_async("/admin/webhooks", webhooks::register)
+        .post_async("/admin/webhooks/:id/test", webhooks::test)
+        .post_async("/admin/keys", tiers::create)
+        .get_async("/admin/shadow", shadow::report)
+        .post_async("/admin/migrate", admin::migrate_schema)
+        .post_async("/admin/reset", admin::reset)
+        .post_async("/admin/reindex", admin::reindex)
+        .post_async("/admin/backfill", admin::backfill)
+        .post_async("/admin/replay", archive::replay)
+        .put_async("/admin/tokens/:addr", tokens::put)
+        .delete_async("/admin/tokens/:addr", tokens::delete)
+        .put_async("/admin/chains/:id", chains::put)
+        .post_async("/admin/extrinsics/:hash", transfers::resolve_extrinsic)
+        .get_async("/admin/proposals", proposals::list)
+        .post_async("/admin/proposals/:id/apply", proposals::apply)
+        .post_async("/admin/proposals/:id/reject", proposals::reject)
+        .get_async("/admin/audit", audit::list)
+        .get_async("/openapi.json", openapi::get)
+        .run(req, env)
+        .await?;
+
+    if let (Some(response_cache), Some(key)) = (&response_cache, &cache_key) {
+        if res.status_code() == 200 {
+            let body = res.text().await?;
+            response_cache.put(key, &body).await;
+            res = cache::json_response(body, false)?;
+        }
+    }
+    let res = notices.apply(res, usage.as_ref())?;
+    signing::sign_if_configured(&signing_key, res).await
 }
 
 #[event(scheduled)]
-pub async fn scheduled(_event: ScheduledEvent, _env: Env, _ctx: ScheduleContext) {
-    console_log!("Beginning CRON scheduler event.");
+pub async fn scheduled(event: ScheduledEvent, _env: Env, _ctx: ScheduleContext) {
+    let cron = event.cron();
+    let tasks = match schedules::Schedules::from_env(&_env) {
+        Ok(s) => s.tasks_for(&cron),
+        Err(e) => {
+            console_error!("Invalid configuration: {}", e);
+            return;
+        }
+    };
+    let names: Vec<&str> = tasks.iter().map(|t| t.name()).collect();
+    console_log!("Beginning CRON scheduler event for {} ({}).", cron, names.join(", "));
     let Ok(db) = _env.d1("DB") else {
         println!("Error occurred with getting the DB during a scheduled event!");
         return
     };
+    // Nothing is written during a maintenance window, so the next trigger picks up from here
+    if !d1::available(&db).await {
+        console_warn!("D1 is unavailable, deferring this run to the next trigger.");
+        return;
+    }
+    let config = match config::from_env(&_env) {
+        Ok(c) => c,
+        Err(e) => {
+            console_error!("Invalid configuration: {}", e);
+            return;
+        }
+    };
 
     // 0. Ensure that the tables exist
-    let statements = vec![
-        db.prepare(
-            "
-            CREATE TABLE IF NOT EXISTS Token (
-                contract_addr TEXT NOT NULL PRIMARY KEY,
-                token_name TEXT NOT NULL,
-                token_sym TEXT NOT NULL,
-                decimals UNSIGNED INT NOT NULL
-            );
-        ",
-        ),
-        db.prepare(
-            "
-            CREATE TABLE IF NOT EXISTS TransfersForward (
-                tx_hash TEXT PRIMARY KEY,
-                token_addr TEXT NOT NULL REFERENCES Token(contract_addr),
-                token_count UNSIGNED INT NOT NULL,
-                usd REAL NOT NULL,
-                block_num UNSIGNED INT NOT NULL,
-                timestamp TEXT,
-                to_chain UNSIGNED INT NOT NULL
-            );
-        ",
-        ),
-    ];
-    let Ok(_) = db.batch(statements).await else {
+    let Ok(_) = migrate(&db).await else {
         console_error!("Error sending the table creation!");
         return
     };
 
-    // 1. Get the last entry so that we know when to query from.
-    let statement = db.prepare("SELECT MAX(block_num) AS most_recent_block FROM TransfersForward");
-    let result = statement.first::<u64>(Some("most_recent_block")).await;
-    let block: u64 = match result {
+    for task in tasks {
+        match task {
+            Task::Index => run_indexing(&_env, &db, &config).await,
+            Task::Decode => payloads::decode_pending(&_env, &db).await,
+            Task::Maintenance => {
+                quotas::reset_monthly(&db).await;
+                snapshots::thin(&db, Date::now().as_millis() / 1000).await;
+            }
+        }
+    }
+}
+
+/// Indexes new transfers under the run lock, then tunes the work budget from how long that took,
+/// snapshots every token's liquidity, checks freshness against the SLA and refreshes the response
+/// cache. What the run did is recorded as its metrics.
+async fn run_indexing(env: &Env, db: &D1Database, config: &Config) {
+    let started_at = Date::now().as_millis();
+    // Overlapping runs would index the same blocks twice
+    let lease = match lock::acquire(env).await {
+        Ok(Some(lease)) => Some(lease),
+        Ok(None) => {
+            console_log!("Skipping this run, the previous one still holds the run lock.");
+            return;
+        }
         Err(e) => {
-            console_error!("Error with most_recent_block: {:?}", e);
-            4164120
+            console_warn!("Running without the run lock: {}", e);
+            None
         }
-        Ok(Some(r)) => r,
-        Ok(None) => 4164120,
     };
 
-    // 2. Query etherscan
-    let Ok(moonscan_key) = _env.var("MOONSCAN_KEY") else {
-        console_error!("Error discovering MoonScan API key!");
-        return
-    };
-    let Ok(client) = Client::new(Chain::Moonbeam, moonscan_key.to_string()) else {
-        console_error!("Error occurred with creating an etherscan client!");
-        return
-    };
-    let Ok(gmp_precompile) = "0x0000000000000000000000000000000000000816".parse() else {
-        console_error!("Error occurred when parsing GMP precompile address!");
-        return
-    };
-    let etherscan_result = client
-        .get_erc20_token_transfer_events(
-            TokenQueryOption::ByAddress(gmp_precompile),
-            // None
-            Some(TxListParams::new(block + 1, 999999999, 0, 0, Sort::Asc)),
-        )
-        .await;
-    let Ok(etherscan_result) = etherscan_result else {
-        console_error!("Error occurred when querying etherscan!");
-        return
+    // Index within the tuned budget, then tune it again from how long that took
+    let budget = budget::load(db, config).await;
+    let mut stats = RunStats::default();
+    let mut run_metrics = RunMetrics::default();
+    reorg::reconcile(env, db, config, &budget).await;
+    let deferred = index_transfers(env, db, config, &budget, &mut stats, &mut run_metrics).await;
+
+    // A run cut short by D1 says nothing about the budget, and stored nothing to refresh
+    if deferred {
+        console_warn!("D1 became unavailable, deferring the rest of this run to the next trigger.");
+    } else {
+        let target_ms = env
+            .var("TARGET_RUN_MS")
+            .ok()
+            .and_then(|t| t.to_string().parse::<u64>().ok())
+            .unwrap_or(budget::DEFAULT_TARGET_RUN_MS);
+        let duration_ms = Date::now().as_millis() - started_at;
+        budget::record_run(db, started_at / 1000, duration_ms, target_ms, &budget, &stats).await;
+        snapshots::record(db, started_at / 1000).await;
+        slo::check(env, db).await;
+        cache::invalidate(env).await;
+        warm_cache(env, db).await;
+    }
+
+    let run_metrics = RunMetrics {
+        started_at: started_at / 1000,
+        duration_ms: Date::now().as_millis() - started_at,
+        blocks_processed: stats.blocks,
+        transfers_fetched: stats.transfers,
+        transfers_inserted: stats.inserted,
+        errors: metrics::errors_since(db, started_at / 1000).await,
+        deferred,
+        ..run_metrics
     };
-    console_log!("No transactions discovered after block {}.", block);
-    if etherscan_result.len() == 0 {
-        return;
+    metrics::record(env, db, &run_metrics).await;
+
+    if let Some(lease) = lease {
+        if let Err(e) = lease.release().await {
+            console_error!("Error releasing the run lock: {}", e);
+        }
     }
+}
 
-    // 3. Sort & format data (lowest timestamp are first)
-    let mut filtered_etherscan_data: Vec<TransferForward> = etherscan_result
-        .iter()
-        .filter_map(|e| {
-            if e.from == H160::default() {
-                Some(TransferForward {
-                    tx_hash: format!("{:?}", e.hash),
-                    token_addr: format!("{:?}", e.contract_address),
-                    token_count: e.value.as_u128(), // Possibility of panicking if MRL allows for custom tokens with super high values
-                    usd: 0.,                        // TODO: query for USD value at the timestamp
-                    block_num: e.block_number.as_number().unwrap_or(U64::from(0)).as_u64(),
-                    timestamp: e.time_stamp.to_owned(),
-                    to_chain: 1000, // TODO: parse the transaction data
-                })
-            } else {
-                None
-            }
+/// Creates any missing tables and columns, then writes the token registry. Every statement is
+/// idempotent, so this runs at the start of each scheduled run.
+async fn migrate(db: &D1Database) -> Result<()> {
+    let statements: Vec<String> = [
+        "
+        CREATE TABLE IF NOT EXISTS Token (
+            contract_addr TEXT NOT NULL PRIMARY KEY,
+            token_name TEXT NOT NULL,
+            token_sym TEXT NOT NULL,
+            decimals UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS TransfersForward (
+            tx_hash TEXT PRIMARY KEY,
+            token_addr TEXT NOT NULL REFERENCES Token(contract_addr),
+            token_count UNSIGNED INT NOT NULL,
+            usd_cents INTEGER NOT NULL DEFAULT 0,
+            usd_min_cents INTEGER,
+            usd_max_cents INTEGER,
+            block_num UNSIGNED INT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            to_chain UNSIGNED INT NOT NULL,
+            price_uncertain INTEGER NOT NULL DEFAULT 0,
+            dest_account TEXT,
+            timestamp_corrected INTEGER NOT NULL DEFAULT 0,
+            sender TEXT,
+            payload_checked INTEGER NOT NULL DEFAULT 0,
+            indexed_at INTEGER,
+            gas_fee UNSIGNED INT,
+            bridge_fee UNSIGNED INT,
+            price REAL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS Chains (
+            chain_id UNSIGNED INT NOT NULL PRIMARY KEY,
+            chain_name TEXT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS SentAlerts (
+            tx_hash TEXT NOT NULL PRIMARY KEY,
+            sent_at UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS Webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS IndexerState (
+            key TEXT NOT NULL PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS IndexerRuns (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at UNSIGNED INT NOT NULL,
+            duration_ms UNSIGNED INT NOT NULL,
+            transfers UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS IndexerErrors (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            message TEXT NOT NULL,
+            context TEXT NOT NULL,
+            occurred_at UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS ApiKeys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            key_hash TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            tier TEXT NOT NULL,
+            daily_quota UNSIGNED INT,
+            created_at UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS ApiKeyUsage (
+            key_id INTEGER NOT NULL REFERENCES ApiKeys(id),
+            day UNSIGNED INT NOT NULL,
+            requests UNSIGNED INT NOT NULL,
+            PRIMARY KEY (key_id, day)
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS LiquiditySnapshots (
+            token_addr TEXT NOT NULL REFERENCES Token(contract_addr),
+            taken_at UNSIGNED INT NOT NULL,
+            total_tokens REAL NOT NULL,
+            total_usd_cents INTEGER NOT NULL,
+            number_of_transfers UNSIGNED INT NOT NULL,
+            PRIMARY KEY (token_addr, taken_at)
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS CorrectionProposals (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tx_hash TEXT NOT NULL,
+            field TEXT NOT NULL,
+            value TEXT NOT NULL,
+            evidence TEXT NOT NULL,
+            submitted_by INTEGER NOT NULL REFERENCES ApiKeys(id),
+            submitted_at UNSIGNED INT NOT NULL,
+            status TEXT NOT NULL,
+            reviewed_at UNSIGNED INT,
+            review_note TEXT,
+            audit_id INTEGER REFERENCES AuditLog(id)
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS AuditLog (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            action TEXT NOT NULL,
+            tx_hash TEXT,
+            field TEXT,
+            old_value TEXT,
+            new_value TEXT,
+            proposal_id INTEGER,
+            performed_at UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS ArchivedBatches (
+            key TEXT NOT NULL PRIMARY KEY,
+            first_block UNSIGNED INT NOT NULL,
+            last_block UNSIGNED INT NOT NULL,
+            transfers UNSIGNED INT NOT NULL,
+            archived_at UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS InsertChunks (
+            chunk UNSIGNED INT NOT NULL PRIMARY KEY,
+            first_block UNSIGNED INT NOT NULL,
+            last_block UNSIGNED INT NOT NULL,
+            transfers UNSIGNED INT NOT NULL,
+            stored INTEGER NOT NULL DEFAULT 0,
+            error TEXT,
+            attempted_at UNSIGNED INT NOT NULL
+        );
+        ",
+        "
+        CREATE TABLE IF NOT EXISTS ShadowTransfers (
+            tx_hash TEXT NOT NULL,
+            decoder TEXT NOT NULL,
+            active_decoder TEXT NOT NULL,
+            output TEXT NOT NULL,
+            active_output TEXT NOT NULL,
+            diverged INTEGER NOT NULL,
+            checked_at UNSIGNED INT NOT NULL,
+            PRIMARY KEY (tx_hash, decoder)
+        );
+        ",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    batch_with_retry(db, "Table creation", &statements).await?;
+    add_column(db, "TransfersForward", "price_uncertain INTEGER NOT NULL DEFAULT 0").await;
+    add_column(db, "TransfersForward", "dest_account TEXT").await;
+    add_column(db, "TransfersForward", "timestamp_corrected INTEGER NOT NULL DEFAULT 0").await;
+    add_column(db, "TransfersForward", "sender TEXT").await;
+    add_column(db, "TransfersForward", "usd_cents INTEGER NOT NULL DEFAULT 0").await;
+    add_column(db, "TransfersForward", "usd_min_cents INTEGER").await;
+    add_column(db, "TransfersForward", "usd_max_cents INTEGER").await;
+    add_column(db, "TransfersForward", "payload_checked INTEGER NOT NULL DEFAULT 0").await;
+    add_column(db, "TransfersForward", "extrinsic_hash TEXT").await;
+    add_column(db, "TransfersForward", "indexed_at INTEGER").await;
+    add_column(db, "TransfersForward", "gas_fee UNSIGNED INT").await;
+    add_column(db, "TransfersForward", "bridge_fee UNSIGNED INT").await;
+    add_column(db, "TransfersForward", "price REAL").await;
+    add_column(db, "Token", "category TEXT").await;
+    add_column(db, "Token", "logo_url TEXT").await;
+    add_column(db, "Token", "overridden INTEGER NOT NULL DEFAULT 0").await;
+    add_column(db, "ApiKeys", "daily_quota UNSIGNED INT").await;
+    timestamps::convert_to_integer(db).await?;
+    usd::convert_to_cents(db).await?;
+    payloads::reset_placeholder_chains(db).await?;
+    webhooks::forget_stored_secrets(db).await?;
+    batch_with_retry(
+        db,
+        "Index creation",
+        &[
+            "CREATE INDEX IF NOT EXISTS TransfersForwardTimestamp ON TransfersForward (timestamp)",
+            "CREATE INDEX IF NOT EXISTS TransfersForwardExtrinsic ON TransfersForward \
+             (extrinsic_hash)",
+        ]
+        .map(|s| s.to_string()),
+    )
+    .await?;
+    registry::seed(db).await;
+    Ok(())
+}
+
+/// Reads transfers from MoonScan, falling back to the node's logs.
+struct ChainEvents<'a> {
+    env: &'a Env,
+    client: scan::ScanClient,
+    latency: metrics::Latency,
+}
+
+#[async_trait(?Send)]
+impl pipeline::EventSource for ChainEvents<'_> {
+    async fn token_transfers(
+        &self,
+        precompile: eth::Address,
+        from_block: u64,
+        max: usize,
+    ) -> std::result::Result<Vec<scan::TokenTransfer>, IndexerError> {
+        self.latency
+            .time(retry("Etherscan query", &RetryPolicy::default(), || {
+                self.client
+                    .token_transfers(precompile, from_block, 999999999, max as u64)
+            }))
+            .await
+            .map_err(|e| IndexerError::EtherscanFailure(e.to_string()))
+    }
+
+    async fn log_transfers(
+        &self,
+        precompile: eth::Address,
+        from_block: u64,
+        max_queries: u64,
+    ) -> std::result::Result<Vec<scan::TokenTransfer>, IndexerError> {
+        let rpc = rpc::RpcClient::from_env(self.env);
+        let query = rpc::get_mint_transfer_events(&rpc, precompile, from_block, max_queries);
+        self.latency
+            .time(query)
+            .await
+            .map_err(|e| IndexerError::RpcFailure(e.to_string()))
+    }
+
+    async fn native_transfers(
+        &self,
+        precompile: eth::Address,
+        from_block: u64,
+        to_block: u64,
+        max: usize,
+    ) -> std::result::Result<Vec<TransferForward>, IndexerError> {
+        self.latency
+            .time(retry("Etherscan internal tx query", &RetryPolicy::default(), || {
+                native::native_transfers(&self.client, precompile, from_block, to_block, max as u64)
+            }))
+            .await
+            .map_err(|e| IndexerError::EtherscanFailure(e.to_string()))
+    }
+
+    async fn cross_check_timestamps(
+        &self,
+        transfers: &mut [TransferForward],
+    ) -> std::result::Result<usize, IndexerError> {
+        timestamps::cross_check(self.env, transfers)
+            .await
+            .map_err(|e| IndexerError::RpcFailure(format!("block timestamps ({e})")))
+    }
+
+    async fn token_metadata(&self, token: eth::Address) -> TokenMetadata {
+        let rpc = rpc::RpcClient::from_env(self.env);
+        rpc::token_metadata(&rpc, token).await
+    }
+}
+
+// IndexerState key of the timestamp each symbol was last priced at, as JSON
+const PRICE_CURSORS_KEY: &str = "price_cursors";
+
+// When a transfer is stored, in unix seconds, which freshness is measured to
+const INDEXED_AT: &str = "CAST(strftime('%s', 'now') AS INTEGER)";
+
+/// Keeps transfers in D1.
+struct D1Store<'a> {
+    db: &'a D1Database,
+    /// Where fetched batches are archived, if anywhere
+    archive: Option<Bucket>,
+    /// The blocks (both inclusive) whose stored transfers the inserted ones replace, when
+    /// replaying archived batches
+    replaces: Option<(u64, u64)>,
+}
+
+#[async_trait(?Send)]
+impl pipeline::Store for D1Store<'_> {
+    async fn last_indexed_block(&self) -> std::result::Result<Option<u64>, IndexerError> {
+        let progress = d1::retry("Reading most_recent_block", || async {
+            self.db
+                .prepare(
+                    "SELECT
+                        (SELECT MAX(block_num) FROM TransfersForward) AS most_recent_block,
+                        (SELECT MIN(first_block) FROM InsertChunks WHERE stored = 0)
+                            AS failed_from",
+                )
+                .first::<InsertProgress>(None)
+                .await
         })
-        .collect();
+        .await
+        .map_err(|e| d1::db_error(e.to_string()))?;
+        Ok(progress.and_then(|p| pipeline::resume_after(p.most_recent_block, p.failed_from)))
+    }
 
-    // 4. Ensure all of the tokens are already known
-    let token_hash: HashMap<String, Token> = etherscan_result
-        .iter()
-        .filter_map(|e: &ethers_etherscan::account::ERC20TokenTransferEvent| {
-            if e.from == H160::default() {
-                let addr = format!("{:?}", e.contract_address);
-                Some((
-                    addr.clone(),
-                    Token {
-                        contract_addr: addr,
-                        token_name: e.token_name.clone(),
-                        token_sym: e.token_symbol.clone(),
-                        decimals: e.token_decimal.parse::<u32>().unwrap_or(18),
-                    },
-                ))
-            } else {
-                None
-            }
+    async fn insert_tokens(&self, tokens: &[&Token]) -> std::result::Result<(), IndexerError> {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let values: Vec<String> = tokens
+            .iter()
+            .map(|token| {
+                format!(
+                    "('{}', {}, {}, {})",
+                    token.contract_addr,
+                    sql_string(&token.token_name),
+                    sql_string(&token.token_sym),
+                    token.decimals
+                )
+            })
+            .collect();
+        let statement = format!(
+            "INSERT OR IGNORE INTO Token (contract_addr, token_name, token_sym, decimals) \
+             VALUES {}",
+            values.join(", ")
+        );
+        let result = self.db.prepare(statement).run().await;
+        match result {
+            Ok(r) if r.success() => Ok(()),
+            Ok(r) => Err(d1::db_error(
+                r.error().unwrap_or("No error given".to_string()),
+            )),
+            Err(e) => Err(d1::db_error(e.to_string())),
+        }
+    }
+
+    async fn update_tokens(&self, tokens: &[&Token]) -> std::result::Result<(), IndexerError> {
+        let statements: Vec<String> = tokens
+            .iter()
+            .map(|token| {
+                format!(
+                    "UPDATE Token SET token_name = {}, token_sym = {}, decimals = {} \
+                     WHERE contract_addr = '{}' AND overridden = 0",
+                    sql_string(&token.token_name),
+                    sql_string(&token.token_sym),
+                    token.decimals,
+                    token.contract_addr
+                )
+            })
+            .collect();
+        let results = batch_with_retry(self.db, "Token metadata update", &statements)
+            .await
+            .map_err(|e| d1::db_error(e.to_string()))?;
+        match results.into_iter().find(|r| !r.success()) {
+            Some(r) => Err(d1::db_error(
+                r.error().unwrap_or("No error given".to_string()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    async fn overridden_tokens(&self) -> std::result::Result<HashMap<String, Token>, IndexerError> {
+        let result = d1::retry("Reading overridden Tokens", || async {
+            self.db
+                .prepare("SELECT * FROM Token WHERE overridden = 1")
+                .all()
+                .await
         })
-        .collect::<HashMap<String, Token>>();
-    let token_statement: String = token_hash
+        .await
+        .map_err(|e| d1::db_error(e.to_string()))?;
+        if !result.success() {
+            return Err(d1::db_error(
+                result.error().unwrap_or("No error given".to_string()),
+            ));
+        }
+        let tokens = result
+            .results::<Token>()
+            .map_err(|e| d1::db_error(e.to_string()))?;
+        Ok(tokens
+            .into_iter()
+            .map(|t| (t.contract_addr.clone(), t))
+            .collect())
+    }
+
+    async fn insert_transfers(
+        &self,
+        transfers: &[TransferForward],
+        chunk_size: usize,
+    ) -> std::result::Result<(), PartialInsert> {
+        let chunks: Vec<&[TransferForward]> = transfers.chunks(chunk_size).collect();
+        if let Some((first_block, last_block)) = self.replaces {
+            // Replays swap the stored transfers out in a single transaction
+            let mut statements = vec![format!(
+                "DELETE FROM TransfersForward WHERE block_num BETWEEN {first_block} AND \
+                 {last_block}"
+            )];
+            statements.extend(chunks.iter().map(|c| insert_statement(c)));
+            statements.push(
+                "DELETE FROM ShadowTransfers WHERE tx_hash NOT IN \
+                 (SELECT tx_hash FROM TransfersForward)"
+                    .to_string(),
+            );
+            return run_batch(self.db, "TransferForward replay", &statements)
+                .await
+                .map_err(|error| PartialInsert { stored: 0, error });
+        }
+        if chunks.is_empty() {
+            return Ok(());
+        }
+
+        // Every chunk is recorded as pending first, so that a run which fails or is cut short
+        // partway resumes from the first chunk that wasn't stored
+        let now = Date::now().as_millis() / 1000;
+        let pending: Vec<String> = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                format!(
+                    "({i}, {}, {}, {}, 0, {now})",
+                    chunk[0].block_num,
+                    chunk[chunk.len() - 1].block_num,
+                    chunk.len()
+                )
+            })
+            .collect();
+        let statements = [
+            "DELETE FROM InsertChunks".to_string(),
+            format!(
+                "INSERT INTO InsertChunks \
+                 (chunk, first_block, last_block, transfers, stored, attempted_at) VALUES {}",
+                pending.join(", ")
+            ),
+        ];
+        run_batch(self.db, "InsertChunks reset", &statements)
+            .await
+            .map_err(|error| PartialInsert { stored: 0, error })?;
+
+        let mut stored = 0;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let statements = [
+                insert_statement(chunk),
+                format!("UPDATE InsertChunks SET stored = 1 WHERE chunk = {i}"),
+            ];
+            if let Err(error) = run_batch(self.db, "TransferForward insert", &statements).await {
+                let _ = self
+                    .db
+                    .prepare(format!(
+                        "UPDATE InsertChunks SET error = {} WHERE chunk = {i}",
+                        sql_string(&error.to_string())
+                    ))
+                    .run()
+                    .await;
+                return Err(PartialInsert { stored, error });
+            }
+            stored += chunk.len();
+        }
+        Ok(())
+    }
+
+    async fn price_cursors(&self) -> std::result::Result<HashMap<String, u64>, IndexerError> {
+        let cursors = self
+            .db
+            .prepare("SELECT value FROM IndexerState WHERE key = ?1")
+            .bind(&[PRICE_CURSORS_KEY.into()])
+            .map_err(|e| d1::db_error(e.to_string()))?
+            .first::<String>(Some("value"))
+            .await
+            .map_err(|e| d1::db_error(e.to_string()))?;
+        match cursors {
+            Some(c) => serde_json::from_str(&c)
+                .map_err(|e| IndexerError::DecodeFailure(format!("price cursors ({e})"))),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    async fn save_price_cursors(
+        &self,
+        cursors: &HashMap<String, u64>,
+    ) -> std::result::Result<(), IndexerError> {
+        let cursors = serde_json::to_string(cursors)
+            .map_err(|e| IndexerError::InvariantViolation(format!("price cursors ({e})")))?;
+        self.db
+            .prepare("INSERT OR REPLACE INTO IndexerState (key, value) VALUES (?1, ?2)")
+            .bind(&[PRICE_CURSORS_KEY.into(), cursors.into()])
+            .map_err(|e| d1::db_error(e.to_string()))?
+            .run()
+            .await
+            .map(|_| ())
+            .map_err(|e| d1::db_error(e.to_string()))
+    }
+
+    async fn archive(&self, batch: &pipeline::TransferBatch) -> std::result::Result<(), IndexerError> {
+        match &self.archive {
+            Some(bucket) => archive::put(bucket, self.db, batch).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn record_error(&self, error: IndexerError, context: &str) {
+        errors::record(self.db, error, context).await;
+    }
+}
+
+#[derive(Deserialize)]
+struct InsertProgress {
+    most_recent_block: Option<u64>,
+    failed_from: Option<u64>,
+}
+
+/// A statement inserting the transfers, skipping any that are already stored.
+fn insert_statement(transfers: &[TransferForward]) -> String {
+    let values: Vec<String> = transfers
         .iter()
-        .map(|e| {
-            let token = e.1;
+        .map(|transfer| {
             format!(
-                "('{}', '{}', '{}', {})",
-                token.contract_addr, token.token_name, token.token_sym, token.decimals
+                "('{}', '{}', {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {})",
+                transfer.tx_hash,
+                transfer.token_addr,
+                transfer.token_count,
+                transfer.usd.0,
+                transfer.usd_min.0,
+                transfer.usd_max.0,
+                transfer.block_num,
+                transfer.timestamp,
+                transfer.to_chain,
+                transfer.price_uncertain as u8,
+                sql_text(&transfer.dest_account),
+                transfer.timestamp_corrected as u8,
+                sql_real(transfer.price),
+                INDEXED_AT
             )
         })
-        .collect::<Vec<String>>()
-        .join(", ");
-    let token_statement: String =
-        "INSERT OR IGNORE INTO Token (contract_addr, token_name, token_sym, decimals) VALUES "
-            .to_string()
-            + &token_statement;
-    let token_res = db.prepare(token_statement).run().await;
-    match token_res {
-        Ok(r) => {
-            if !r.success() {
-                console_error!(
-                    "Internal error when inserting Tokens into DB: {:?}",
-                    r.error()
-                );
-            }
+        .collect();
+    format!(
+        "INSERT OR IGNORE INTO TransfersForward (tx_hash, token_addr, token_count, usd_cents, \
+         usd_min_cents, usd_max_cents, block_num, timestamp, to_chain, price_uncertain, \
+         dest_account, timestamp_corrected, price, indexed_at) VALUES {}",
+        values.join(", ")
+    )
+}
+
+/// Runs the statements as a single transaction, failing if any of them did.
+async fn run_batch(
+    db: &D1Database,
+    label: &str,
+    statements: &[String],
+) -> std::result::Result<(), IndexerError> {
+    let results = batch_with_retry(db, label, statements)
+        .await
+        .map_err(|e| d1::db_error(e.to_string()))?;
+    match results.into_iter().find(|r| !r.success()) {
+        Some(r) => Err(d1::db_error(
+            r.error().unwrap_or("No error given".to_string()),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Reads transfers from MoonScan, or None if its API key isn't set.
+fn chain_events(env: &Env) -> Option<ChainEvents<'_>> {
+    let Ok(moonscan_key) = env.var("MOONSCAN_KEY") else {
+        console_error!("Error discovering MoonScan API key!");
+        return None
+    };
+    Some(ChainEvents {
+        env,
+        client: scan::ScanClient::new(moonscan_key.to_string()),
+        latency: metrics::Latency::default(),
+    })
+}
+
+/// Prices tokens from the configured providers, or None if none of them can be queried.
+fn price_source(env: &Env, config: &Config) -> Option<price_providers::Prices> {
+    price_providers::Prices::new(
+        env,
+        &config.price_providers,
+        config.price_strategy,
+        &config.price_quote,
+    )
+}
+
+/// Fetches every transfer since the last indexed block, within `budget`. With a TRANSFER_QUEUE
+/// bound they are sent to it for `consume` to price and store, otherwise that's done here. The
+/// time spent on external APIs goes into `run_metrics`. Returns whether D1 became unavailable,
+/// deferring the rest of the pass to the next run.
+async fn index_transfers(
+    _env: &Env,
+    db: &D1Database,
+    config: &Config,
+    budget: &WorkBudget,
+    stats: &mut RunStats,
+    run_metrics: &mut RunMetrics,
+) -> bool {
+    let Some(events) = chain_events(_env) else {
+        return false
+    };
+    let store = D1Store {
+        db,
+        archive: archive::bucket(_env),
+        replaces: None,
+    };
+    let Some(queue) = ingest::queue(_env) else {
+        let Some(prices) = price_source(_env, config) else {
+            return false
+        };
+        // Read before fetching, so that a run which catches up has scanned every block up to it
+        let head = rpc::RpcClient::from_env(_env).block_number().await.ok();
+        let now = Date::now().as_millis() / 1000;
+        let indexed = pipeline::index(&events, &prices, &store, config, budget, stats, now).await;
+        run_metrics.explorer_requests = events.latency.requests();
+        run_metrics.explorer_ms = events.latency.total_ms();
+        run_metrics.price_requests = prices.latency.requests();
+        run_metrics.price_ms = prices.latency.total_ms();
+        if !indexed.deferred {
+            ingest::forget(db).await;
+        }
+        if let Some(head) = head.filter(|_| indexed.caught_up) {
+            status::record_scanned(db, head).await;
         }
+        return report_indexed(_env, db, indexed).await;
+    };
+
+    let queued_through = match ingest::queued_through(db).await {
+        Ok(b) => b,
+        Err(e) if e.is_transient() => return true,
         Err(e) => {
-            console_error!("Error when inserting tokens: {}", e);
-            return;
+            errors::record(db, e, "Reading the queue watermark").await;
+            None
         }
     };
+    let fetched = pipeline::fetch(&events, &store, config, budget, stats, queued_through).await;
+    run_metrics.explorer_requests = events.latency.requests();
+    run_metrics.explorer_ms = events.latency.total_ms();
+    if fetched.deferred {
+        return true;
+    }
+    if fetched.batch.is_empty() {
+        console_log!("No new transactions discovered.");
+        return false;
+    }
+    stats.transfers = fetched.batch.len();
+    if let Err(e) = ingest::enqueue(&queue, db, fetched.batch).await {
+        let e = d1::db_error(e.to_string());
+        errors::record(db, e, "Queueing fetched transfers").await;
+    }
+    false
+}
 
-    // 4. Query for historical prices
-    let Ok(twelve_key) = _env.var("TWELVE_DATA_KEY") else {
-        console_error!("Error discovering Twelve Data API key!");
-        return
+/// Prices, decodes and stores the transfer batches that scheduled runs queue, oldest first,
+/// moving the queue watermark past each one stored. If any of them fails, the whole batch is
+/// handed back for the queue to deliver again, or to its dead letter queue once it runs out of
+/// retries. Whatever was stored is skipped when it comes round again.
+#[event(queue)]
+async fn consume(
+    batch: MessageBatch<pipeline::TransferBatch>,
+    env: Env,
+    _ctx: worker::Context,
+) -> Result<()> {
+    let db = env.d1("DB")?;
+    if !d1::available(&db).await {
+        console_warn!("D1 is unavailable, retrying the queued transfers later.");
+        batch.retry_all();
+        return Ok(());
+    }
+    let config = match config::from_env(&env) {
+        Ok(c) => c,
+        Err(e) => return Err(worker::Error::RustError(e.to_string())),
     };
-    let mut twelve_queries: HashMap<String, Vec<TimeSeries>> =
-        HashMap::<String, Vec<TimeSeries>>::new();
-    for (_, token) in token_hash.iter() {
-        // Skip stablecoins
-        if is_usd_stablecoin(&token_hash, &token.token_sym) {
-            continue;
+    let (Some(events), Some(prices)) = (chain_events(&env), price_source(&env, &config)) else {
+        return Err(worker::Error::RustError("Missing API keys".to_string()));
+    };
+    let budget = budget::load(&db, &config).await;
+    let store = D1Store {
+        db: &db,
+        archive: None,
+        replaces: None,
+    };
+    let mut queued_through = match ingest::queued_through(&db).await {
+        Ok(b) => b,
+        Err(e) => {
+            errors::record(&db, e, "Reading the queue watermark").await;
+            batch.retry_all();
+            return Ok(());
         }
+    };
 
-        // Query for the other coins
-        let twelve_data =
-            match get_twelve_data(twelve_key.to_string(), token.token_sym.clone()).await {
-                Ok(x) => x,
-                Err(e) => {
-                    console_error!("Error fetching Twelve Data!");
-                    vec![TimeSeries::default()]
-                }
-            };
-        twelve_queries.insert(token.token_sym.clone(), twelve_data);
-    }
-    let mut twelve_index = 0;
-    for tx in &mut filtered_etherscan_data {
-        let token_decimals = token_hash
-            .get(&tx.token_addr)
-            .unwrap_or(&Token::default())
-            .decimals;
-
-        // Skips if it's a USD stablecoin
-        if is_usd_stablecoin(&token_hash, &tx.token_addr) {
-            tx.usd = calculate_usd(1., tx.token_count, token_decimals);
+    // Messages can be delivered in any order, but the watermark only moves over them in order
+    let mut messages = batch.messages()?;
+    messages.sort_by_key(|m| m.body.first_block());
+    let mut stored = false;
+    for message in messages {
+        if ingest::already_stored(queued_through, &message.body) {
             continue;
         }
-
-        // Gets the data relevant to the token hash
-        let token_symbol_key = token_hash
-            .get(&tx.token_addr)
-            .unwrap_or(&Token::default())
-            .token_sym
-            .clone();
-        let Some(twelve_data) = twelve_queries.get(&token_symbol_key) else {
-            // If it can't find the token, that's bad. We continue anyways.
-            console_warn!("Couldn't find TimeSeries data for token with symbol {}!", token_symbol_key);
-            continue;
+        let (after_block, last_block) = (message.body.after_block, message.body.last_block());
+        let mut stats = RunStats::default();
+        let now = Date::now().as_millis() / 1000;
+        let indexed = pipeline::process(
+            message.body,
+            &events,
+            &prices,
+            &store,
+            &config,
+            &budget,
+            &mut stats,
+            now,
+        )
+        .await;
+        stored |= !indexed.transfers.is_empty();
+        let failed = indexed.failed;
+        if report_indexed(&env, &db, indexed).await {
+            console_warn!("D1 became unavailable, retrying the queued transfers later.");
+            batch.retry_all();
+            break;
+        }
+        if failed {
+            console_warn!("Storing queued transfers failed, retrying them later.");
+            batch.retry_all();
+            break;
+        }
+        queued_through = match ingest::stored(&db, after_block, last_block).await {
+            Ok(b) => b,
+            Err(e) => {
+                errors::record(&db, e, "Moving the queue watermark").await;
+                batch.retry_all();
+                break;
+            }
         };
+    }
+    if stored {
+        cache::invalidate(&env).await;
+        warm_cache(&env, &db).await;
+    }
+    Ok(())
+}
 
-        // We get the TimeSeries that is closest to the TransferForward index
-        let ts: &TimeSeries = loop {
-            // Ensures that we are always returning at least the most recent value
-            if twelve_index >= twelve_data.len() {
-                break twelve_data.last().unwrap();
-            }
+/// Logs and alerts on what a pass indexed. Returns whether D1 became unavailable partway.
+async fn report_indexed(_env: &Env, db: &D1Database, indexed: pipeline::Indexed) -> bool {
+    // Transfers stored before D1 became unavailable are still reported
+    if indexed.transfers.is_empty() {
+        if !indexed.deferred {
+            console_log!("No new transactions discovered.");
+        }
+        return indexed.deferred;
+    }
+
+    if indexed.corrected_timestamps > 0 {
+        console_warn!("Corrected {} implausible timestamps", indexed.corrected_timestamps);
+    }
+    if !indexed.stale.is_empty() {
+        for (symbol, reason) in indexed.stale.iter() {
+            console_warn!("Price series for {} looks stale: {}", symbol, reason);
+        }
+        let uncertain = indexed
+            .transfers
+            .iter()
+            .filter(|tx| tx.price_uncertain)
+            .count();
+        let symbols: Vec<&String> = indexed.stale.iter().map(|(symbol, _)| symbol).collect();
+        alerts::send_alert(
+            _env,
+            &format!(
+                "Stale price series for {:?}; {} transfers were valued with uncertain prices.",
+                symbols, uncertain
+            ),
+        )
+        .await;
+    }
+    console_log!(
+        "Indexed {} transactions into the TransferForward table.",
+        indexed.transfers.len()
+    );
 
-            // Current timeseries
-            let cur = &twelve_data[twelve_index];
-            let nxt = twelve_data.get(twelve_index + 1).unwrap_or(&cur);
-            let tx_timestamp = tx.timestamp.parse().unwrap_or(0);
+    shadow::compare(_env, db, &indexed.transfers).await;
+    alerts::alert_large_transfers(_env, db, &indexed.transfers).await;
+    let tx_hashes = indexed.transfers.iter().map(|t| t.tx_hash.clone()).collect();
+    feed::publish(_env, tx_hashes).await;
+    indexed.deferred
+}
 
-            // If the current is closer to the tx timestamp, use current.
-            if cur.timestamp.abs_diff(tx_timestamp) <= nxt.timestamp.abs_diff(tx_timestamp) {
-                break cur;
-            }
+/// Runs the statements as a single D1 batch, retrying the whole batch while D1 is briefly
+/// unavailable. Batches are transactional, so a failed attempt never leaves rows half-inserted.
+async fn batch_with_retry(
+    db: &D1Database,
+    label: &str,
+    statements: &[String],
+) -> Result<Vec<D1Result>> {
+    d1::retry(label, || {
+        db.batch(statements.iter().map(|s| db.prepare(s)).collect())
+    })
+    .await
+}
 
-            // Otherwise, we move on
-            twelve_index += 1;
-        };
+/// D1 returns SQLite booleans as 0 or 1.
+fn int_as_bool<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    Ok(f64::deserialize(deserializer)? != 0.)
+}
 
-        tx.usd = calculate_usd(ts.estimate(), tx.token_count, token_decimals);
-    }
-
-    // Prepare statement(s) to insert data
-    let base_statement = "INSERT INTO TransfersForward (tx_hash, token_addr, token_count, usd, block_num, timestamp, to_chain) VALUES ".to_string();
-    let statements: Vec<worker::D1PreparedStatement> = filtered_etherscan_data
-        .chunks(250)
-        .map(|chunk| {
-            let values: Vec<String> = chunk
-                .iter()
-                .map(|transfer| {
-                    format!(
-                        "('{}', '{}', {}, {}, {}, '{}', {})",
-                        transfer.tx_hash,
-                        transfer.token_addr,
-                        transfer.token_count,
-                        transfer.usd,
-                        transfer.block_num,
-                        transfer.timestamp,
-                        transfer.to_chain
-                    )
-                })
-                .collect::<Vec<String>>();
-            let statement = format!("{}{}", base_statement, values.join(", "));
-            db.prepare(statement)
-        })
-        .collect();
+/// Formats a string as a quoted SQL literal.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
 
-    // Insert into database
-    let db_res = db.batch(statements).await;
-    match db_res {
-        Ok(res) => {
-            for r in res {
-                if !r.success() {
-                    console_error!(
-                        "Internal error when inserting new TransferForward txs into DB: {:?}",
-                        r.error()
-                    );
-                }
-            }
-        }
-        Err(e) => {
-            console_error!("Error when batching statements: {}", e);
+/// Formats an optional string as a quoted SQL literal, or NULL.
+fn sql_text(value: &Option<String>) -> String {
+    match value {
+        Some(v) => sql_string(v),
+        None => "NULL".to_string(),
+    }
+}
+
+/// Formats an optional number as an SQL literal, or NULL if there isn't a finite one.
+fn sql_real(value: Option<f64>) -> String {
+    match value {
+        Some(v) if v.is_finite() => v.to_string(),
+        _ => "NULL".to_string(),
+    }
+}
+
+/// SQLite has no ADD COLUMN IF NOT EXISTS, so this is expected to fail once the column exists.
+async fn add_column(db: &D1Database, table: &str, column: &str) {
+    let _ = db
+        .prepare(format!("ALTER TABLE {table} ADD COLUMN {column}"))
+        .run()
+        .await;
+}
+
+/// Reads `?denomination=`, defaulting to USD. Returns None for values that aren't recognized.
+fn denomination_param(req: &Request) -> Result<Option<Denomination>> {
+    for (k, v) in req.url()?.query_pairs() {
+        if k == "denomination" {
+            return Ok(Denomination::parse(&v));
         }
     }
-    console_log!(
-        "Successfully inserted {} transactions into the TransferForward table.",
-        filtered_etherscan_data.len()
+    Ok(Some(Denomination::Usd))
+}
+
+/// A page of the indexed tokens, ordered by contract address.
+async fn token_page(db: &D1Database, page: &PageParams<String>) -> Result<Page<Token>> {
+    let statement = worker::query!(
+        db,
+        "SELECT * FROM Token WHERE contract_addr > ?1 ORDER BY contract_addr LIMIT ?2",
+        page.cursor.clone().unwrap_or_default(),
+        page.query_limit()
+    )?;
+    let result = statement.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+    Ok(pagination::paginate(
+        result.results::<Token>()?,
+        page.limit,
+        |t| t.contract_addr.clone(),
+    ))
+}
+
+/// Each token's totals across every forward transfer, in both USD and base units.
+async fn liquidity_forward_totals(db: &D1Database) -> Result<Vec<LiquidityForward>> {
+    let statement = worker::query!(
+        db,
+        "
+        SELECT 
+            t.contract_addr,
+            t.token_name,
+            t.token_sym,
+            t.decimals,
+            SUM(tf.usd_cents) AS total_usd,
+            SUM(COALESCE(tf.usd_min_cents, tf.usd_cents)) AS total_usd_min,
+            SUM(COALESCE(tf.usd_max_cents, tf.usd_cents)) AS total_usd_max,
+            SUM(tf.token_count) AS total_tokens,
+            COUNT(tf.token_addr) AS number_of_transfers
+        FROM Token AS t
+        INNER JOIN TransfersForward AS tf ON t.contract_addr = tf.token_addr
+        GROUP BY t.contract_addr, t.token_name, t.token_sym, t.decimals
+    "
+    );
+    let result = statement.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+    result.results::<LiquidityForward>()
+}
+
+/// Each destination chain's totals per token, ordered by chain.
+async fn chain_liquidity_rows(db: &D1Database) -> Result<Vec<ChainTokenLiquidity>> {
+    let statement = worker::query!(
+        db,
+        "
+        SELECT 
+            tf.to_chain,
+            c.chain_name,
+            r.unique_recipients,
+            t.contract_addr,
+            t.token_sym,
+            t.decimals,
+            SUM(tf.usd_cents) AS total_usd,
+            SUM(COALESCE(tf.usd_min_cents, tf.usd_cents)) AS total_usd_min,
+            SUM(COALESCE(tf.usd_max_cents, tf.usd_cents)) AS total_usd_max,
+            SUM(tf.token_count) AS total_tokens,
+            COUNT(tf.tx_hash) AS number_of_transfers
+        FROM TransfersForward AS tf
+        INNER JOIN Token AS t ON t.contract_addr = tf.token_addr
+        LEFT JOIN Chains AS c ON c.chain_id = tf.to_chain
+        INNER JOIN (
+            SELECT to_chain, COUNT(DISTINCT dest_account) AS unique_recipients
+            FROM TransfersForward
+            GROUP BY to_chain
+        ) AS r ON r.to_chain = tf.to_chain
+        GROUP BY tf.to_chain, c.chain_name, r.unique_recipients, t.contract_addr, t.token_sym,
+            t.decimals
+        ORDER BY tf.to_chain
+    "
     );
+    let result = statement.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+    result.results::<ChainTokenLiquidity>()
 }
 
-fn is_usd_stablecoin(token_hash: &HashMap<String, Token>, token_addr: &String) -> bool {
-    let sym = token_hash
-        .get(token_addr)
-        .unwrap_or(&Token::default())
-        .token_sym
-        .clone();
-    sym.contains("USDT") || sym.contains("USDC") || sym.contains("DAI")
+fn group_by_chain(
+    rows: Vec<ChainTokenLiquidity>,
+    denomination: Denomination,
+) -> Vec<ChainLiquidity> {
+    // Rows are ordered by chain, so each destination's tokens are contiguous
+    let mut chains: Vec<ChainLiquidity> = vec![];
+    for row in rows {
+        if chains.last().map(|c| c.to_chain) != Some(row.to_chain) {
+            chains.push(ChainLiquidity {
+                to_chain: row.to_chain,
+                chain_name: row.chain_name,
+                total_usd: None,
+                total_usd_min: None,
+                total_usd_max: None,
+                number_of_transfers: 0,
+                unique_recipients: row.unique_recipients,
+                tokens: vec![],
+            });
+        }
+        let Some(chain) = chains.last_mut() else {
+            continue;
+        };
+        let (total_usd, total_usd_min, total_usd_max) = match denomination {
+            Denomination::Usd => (
+                Some(row.total_usd),
+                Some(row.total_usd_min),
+                Some(row.total_usd_max),
+            ),
+            Denomination::Token => (None, None, None),
+        };
+        let add = |total: Option<Usd>, usd: Option<Usd>| usd.map(|u| total.unwrap_or_default() + u);
+        chain.total_usd = add(chain.total_usd, total_usd);
+        chain.total_usd_min = add(chain.total_usd_min, total_usd_min);
+        chain.total_usd_max = add(chain.total_usd_max, total_usd_max);
+        chain.number_of_transfers += row.number_of_transfers;
+        chain.tokens.push(TokenTotal {
+            contract_addr: row.contract_addr,
+            token_sym: row.token_sym,
+            decimals: row.decimals,
+            total_usd,
+            total_usd_min,
+            total_usd_max,
+            total_tokens: numeric::normalize(row.total_tokens, row.decimals),
+            number_of_transfers: row.number_of_transfers,
+        });
+    }
+    chains
 }
 
-fn calculate_usd(exchange_rate: f32, token_count: u128, token_decimals: u32) -> f32 {
-    if token_decimals >= 6 {
-        let six_sig_figs = (token_count / 10_u128.pow(token_decimals - 6)) as f32;
-        exchange_rate * six_sig_figs / 10_f32.powf(6.)
-    } else {
-        exchange_rate * (token_count / 10_u128.pow(token_decimals)) as f32
+/// Caches the heaviest aggregate responses under the current generation, in every denomination a
+/// dashboard asks for, so the first request after a run is served from KV rather than D1.
+async fn warm_cache(env: &Env, db: &D1Database) {
+    let Some(response_cache) = cache::ResponseCache::from_env(env).await else {
+        return
+    };
+    let totals = liquidity_forward_totals(db).await;
+    let (totals, rows) = match (totals, chain_liquidity_rows(db).await) {
+        (Ok(t), Ok(r)) => (t, r),
+        (Err(e), _) | (_, Err(e)) => {
+            console_error!("Error warming the response cache: {}", e);
+            return;
+        }
+    };
+
+    let mut entries = vec![];
+    for (query, denomination) in WARMED_QUERIES {
+        let totals: Vec<LiquidityForward> =
+            totals.iter().cloned().map(|l| l.denominate(denomination)).collect();
+        let chains = group_by_chain(rows.clone(), denomination);
+        entries.push(("/totalLiquidityForward", query, serde_json::to_string(&totals)));
+        entries.push(("/liquidityByChain", query, serde_json::to_string(&chains)));
     }
+    let puts = entries.into_iter().filter_map(|(path, query, body)| match body {
+        Ok(b) => Some(response_cache.warm(path, query, b)),
+        Err(e) => {
+            console_error!("Error serializing {} for the response cache: {}", path, e);
+            None
+        }
+    });
+    join_all(puts).await;
 }
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+// The durable_object macro expands to paths into these crates
+use worker::{
+    durable_object, js_sys, wasm_bindgen, wasm_bindgen::JsValue, wasm_bindgen_futures, worker_sys,
+    Date, Env, Method, Request, RequestInit, Response, Result, State, Stub,
+};
+
+const LEASE_KEY: &str = "lease";
+// Longer than a scheduled run is allowed to take, so a live run never loses its lease
+const DEFAULT_LEASE_SECONDS: u64 = 15 * 60;
+
+#[derive(Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    // Unix milliseconds
+    expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LeaseRequest {
+    holder: String,
+    seconds: u64,
+}
+
+/// A mutex over scheduled runs. Durable Objects handle one request at a time, so two runs can't
+/// both take the lease. It expires on its own, so a run that dies before releasing it only holds
+/// up the runs after it until then.
+#[durable_object]
+pub struct RunLock {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for RunLock {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let request: LeaseRequest = req.json().await?;
+        let now = Date::now().as_millis();
+        let mut storage = self.state.storage();
+        // Missing keys are an error rather than undefined
+        let current = storage.get::<Lease>(LEASE_KEY).await.ok();
+        let held_by_other = current
+            .as_ref()
+            .map(|l| l.holder != request.holder && l.expires_at > now);
+
+        match req.path().as_str() {
+            "/acquire" => {
+                if held_by_other == Some(true) {
+                    return Response::error("Locked", 409);
+                }
+                let lease = Lease {
+                    holder: request.holder,
+                    expires_at: now + request.seconds * 1000,
+                };
+                storage.put(LEASE_KEY, lease).await?;
+                Response::ok("Acquired")
+            }
+            "/release" => {
+                if held_by_other == Some(false) {
+                    storage.delete(LEASE_KEY).await?;
+                }
+                Response::ok("Released")
+            }
+            _ => Response::error("Not Found", 404),
+        }
+    }
+}
+
+/// The lease a scheduled run holds while it works.
+pub(crate) struct RunLease {
+    stub: Stub,
+    holder: String,
+}
+
+impl RunLease {
+    /// Lets the next run start straight away rather than waiting for the lease to expire.
+    pub(crate) async fn release(self) -> Result<()> {
+        send(&self.stub, "release", &self.holder, 0).await?;
+        Ok(())
+    }
+}
+
+/// Takes the RUN_LOCK lease for RUN_LOCK_SECONDS, or returns None if another run holds it.
+pub(crate) async fn acquire(env: &Env) -> Result<Option<RunLease>> {
+    let seconds = env
+        .var("RUN_LOCK_SECONDS")
+        .ok()
+        .and_then(|s| s.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_LEASE_SECONDS);
+    let stub = env
+        .durable_object("RUN_LOCK")?
+        .id_from_name("scheduled")?
+        .get_stub()?;
+
+    let mut random = [0_u8; 16];
+    getrandom::getrandom(&mut random).map_err(|e| worker::Error::RustError(e.to_string()))?;
+    let holder = hex::encode(random);
+
+    let res = send(&stub, "acquire", &holder, seconds).await?;
+    match res.status_code() {
+        200 => Ok(Some(RunLease { stub, holder })),
+        409 => Ok(None),
+        code => {
+            let message = format!("run lock returned {code}");
+            Err(worker::Error::RustError(message))
+        }
+    }
+}
+
+async fn send(stub: &Stub, action: &str, holder: &str, seconds: u64) -> Result<Response> {
+    let body = serde_json::to_string(&LeaseRequest {
+        holder: holder.to_string(),
+        seconds,
+    })?;
+    let req = Request::new_with_init(
+        &format!("https://run-lock/{action}"),
+        RequestInit::new()
+            .with_method(Method::Post)
+            .with_body(Some(JsValue::from_str(&body))),
+    )?;
+    stub.fetch_with_request(req).await
+}
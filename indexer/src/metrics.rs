@@ -0,0 +1,144 @@
+use std::{cell::Cell, future::Future};
+
+use serde_json::{json, Value};
+use worker::{
+    console_error, js_sys, wasm_bindgen::JsCast, wasm_bindgen::JsValue, D1Database, Date, Env,
+    Request, Response, Result, RouteContext,
+};
+
+use crate::schemas::RunMetrics;
+
+// IndexerState key of the most recent indexing run's metrics, as JSON
+const LAST_RUN_KEY: &str = "last_run_metrics";
+
+/// How many external API requests were made and how long they were waited on.
+#[derive(Default)]
+pub(crate) struct Latency {
+    requests: Cell<u32>,
+    total_ms: Cell<u64>,
+}
+
+impl Latency {
+    /// Waits on the request, counting it and the time it took. Retries count as one request.
+    pub(crate) async fn time<T>(&self, request: impl Future<Output = T>) -> T {
+        let started_at = Date::now().as_millis();
+        let result = request.await;
+        self.requests.set(self.requests.get() + 1);
+        let elapsed = Date::now().as_millis().saturating_sub(started_at);
+        self.total_ms.set(self.total_ms.get() + elapsed);
+        result
+    }
+
+    pub(crate) fn requests(&self) -> u32 {
+        self.requests.get()
+    }
+
+    pub(crate) fn total_ms(&self) -> u64 {
+        self.total_ms.get()
+    }
+}
+
+/// The run as an Analytics Engine data point. Its index is the task, and the doubles are in the
+/// order the README lists them, since data points only have positions.
+fn data_point(metrics: &RunMetrics) -> Value {
+    json!({
+        "indexes": ["index"],
+        "blobs": ["index", if metrics.deferred { "deferred" } else { "completed" }],
+        "doubles": [
+            metrics.blocks_processed as f64,
+            metrics.transfers_fetched as f64,
+            metrics.transfers_inserted as f64,
+            metrics.explorer_requests as f64,
+            metrics.explorer_ms as f64,
+            metrics.price_requests as f64,
+            metrics.price_ms as f64,
+            metrics.errors as f64,
+            metrics.duration_ms as f64,
+        ],
+    })
+}
+
+/// Writes a data point to the Analytics Engine dataset bound as METRICS. Does nothing without it.
+fn write_data_point(env: &Env, point: &Value) -> std::result::Result<(), JsValue> {
+    let dataset = js_sys::Reflect::get(env, &JsValue::from_str("METRICS"))?;
+    if dataset.is_undefined() {
+        return Ok(());
+    }
+    let write = js_sys::Reflect::get(&dataset, &JsValue::from_str("writeDataPoint"))?
+        .dyn_into::<js_sys::Function>()?;
+    let point = js_sys::JSON::parse(&point.to_string())?;
+    write.call1(&dataset, &point).map(|_| ())
+}
+
+/// How many errors have been recorded since `since`, in unix seconds.
+pub(crate) async fn errors_since(db: &D1Database, since: u64) -> u64 {
+    let statement = worker::query!(
+        db,
+        "SELECT COUNT(*) AS count FROM IndexerErrors WHERE occurred_at >= ?1",
+        since
+    );
+    let count = match statement {
+        Ok(s) => s.first::<u64>(Some("count")).await,
+        Err(e) => Err(e),
+    };
+    count.ok().flatten().unwrap_or(0)
+}
+
+/// Emits the run's metrics to Analytics Engine, and keeps them for GET /metrics.
+pub(crate) async fn record(env: &Env, db: &D1Database, metrics: &RunMetrics) {
+    if let Err(e) = write_data_point(env, &data_point(metrics)) {
+        console_error!("Error writing run metrics to Analytics Engine: {:?}", e);
+    }
+
+    let Ok(json) = serde_json::to_string(metrics) else {
+        return
+    };
+    let statement = db
+        .prepare("INSERT OR REPLACE INTO IndexerState (key, value) VALUES (?1, ?2)")
+        .bind(&[LAST_RUN_KEY.into(), json.into()]);
+    let result = match statement {
+        Ok(s) => s.run().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        console_error!("Error saving run metrics: {}", e);
+    }
+}
+
+/// GET /metrics returns what the most recent scheduled indexing run did.
+pub(crate) async fn get(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "SELECT value FROM IndexerState WHERE key = ?1",
+        LAST_RUN_KEY
+    )?;
+    let Some(json) = statement.first::<String>(Some("value")).await? else {
+        return Response::error("No indexing run has been recorded yet", 404)
+    };
+    let metrics: RunMetrics = serde_json::from_str(&json)?;
+    Response::from_json(&metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_points_keep_their_positions() {
+        let metrics = RunMetrics {
+            blocks_processed: 40,
+            transfers_fetched: 12,
+            transfers_inserted: 10,
+            errors: 1,
+            deferred: true,
+            ..RunMetrics::default()
+        };
+        let point = data_point(&metrics);
+        assert_eq!(point["blobs"], json!(["index", "deferred"]));
+        assert_eq!(
+            point["doubles"],
+            json!([40., 12., 10., 0., 0., 0., 0., 1., 0.])
+        );
+    }
+}
@@ -0,0 +1,551 @@
+use serde_json::{json, Map, Value};
+use worker::{Request, Response, Result, RouteContext};
+
+use crate::{
+    pagination::Page,
+    schemas::{
+        AuditEntry, BackfillRequest, Chain, ChainLiquidity, ChainName, Components, CreatedApiKey,
+        CreatedWebhook, Fees, Freshness, GraphQlRequest, GraphQlResponse, JsonSchema,
+        LiquidityForward, LiquidityHistory, ManagedToken, NewApiKey, NewProposal, NewWebhook,
+        OperationReport, Proposal, ProposalReview, RecordedError, ReindexRequest, ReplayReport,
+        ReplayRequest, RunMetrics, ShadowReport, Status, Token, TokenOverride, TokenVolume,
+        TransferDetail, TransferLookup, TransferResponse, WebhookTestReport,
+    },
+    tiers::{self, Tier, API_KEY_HEADER},
+};
+
+/// A path or query parameter.
+struct Param {
+    name: &'static str,
+    location: &'static str,
+    description: &'static str,
+    schema: Value,
+    required: bool,
+}
+
+fn query(name: &'static str, description: &'static str, schema: Value) -> Param {
+    Param {
+        name,
+        location: "query",
+        description,
+        schema,
+        required: false,
+    }
+}
+
+fn path(name: &'static str, description: &'static str) -> Param {
+    Param {
+        name,
+        location: "path",
+        description,
+        schema: json!({ "type": "string" }),
+        required: true,
+    }
+}
+
+/// A route as the router sees it, with `:param` placeholders, and what it takes and returns.
+struct Route {
+    method: &'static str,
+    path: &'static str,
+    operation_id: &'static str,
+    summary: &'static str,
+    params: Vec<Param>,
+    body: Option<Value>,
+    /// Media types of a successful response, mapped to their schemas
+    content: Value,
+}
+
+impl Route {
+    fn new(method: &'static str, path: &'static str, operation_id: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            operation_id,
+            summary: "",
+            params: vec![],
+            body: None,
+            content: json!({}),
+        }
+    }
+
+    fn summary(mut self, summary: &'static str) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    fn params(mut self, params: impl IntoIterator<Item = Param>) -> Self {
+        self.params.extend(params);
+        self
+    }
+
+    fn body<T: JsonSchema>(mut self, components: &mut Components) -> Self {
+        self.body = Some(T::schema(components));
+        self
+    }
+
+    fn returns<T: JsonSchema>(mut self, components: &mut Components) -> Self {
+        self.content = json!({ "application/json": { "schema": T::schema(components) } });
+        self
+    }
+}
+
+fn unix_timestamp() -> Value {
+    json!({ "type": "integer", "format": "int64", "minimum": 0 })
+}
+
+fn one_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn denomination() -> Param {
+    query(
+        "denomination",
+        "usd (default) reports USD totals, token reports whole token totals instead",
+        one_of(&["usd", "token"]),
+    )
+}
+
+fn page() -> [Param; 2] {
+    [
+        query(
+            "cursor",
+            "The previous page's next_cursor",
+            json!({ "type": "string" }),
+        ),
+        query(
+            "limit",
+            "Items per page, up to 1000 (100 by default)",
+            json!({ "type": "integer", "minimum": 1, "maximum": 1000 }),
+        ),
+    ]
+}
+
+fn transfer_filters() -> [Param; 4] {
+    [
+        query(
+            "token",
+            "Contract address or symbol",
+            json!({ "type": "string" }),
+        ),
+        query(
+            "to_chain",
+            "Destination parachain id",
+            json!({ "type": "integer", "minimum": 0 }),
+        ),
+        query("from", "Inclusive unix timestamp", unix_timestamp()),
+        query("to", "Inclusive unix timestamp", unix_timestamp()),
+    ]
+}
+
+/// Every route the router serves, in the same order.
+fn routes(c: &mut Components) -> Vec<Route> {
+    vec![
+        Route::new("get", "/totalLiquidityForward", "totalLiquidityForward")
+            .summary("Every token's total forward liquidity")
+            .params([denomination()])
+            .returns::<Vec<LiquidityForward>>(c),
+        Route::new("get", "/liquidityForward/:contract", "liquidityForward")
+            .summary("One token's forward liquidity, optionally up to a point in time")
+            .params([
+                path("contract", "The token's contract address"),
+                query(
+                    "timestamp",
+                    "Only transfers before this unix timestamp are counted",
+                    unix_timestamp(),
+                ),
+                denomination(),
+            ])
+            .returns::<LiquidityForward>(c),
+        Route::new("get", "/getTokens", "getTokens")
+            .summary("The indexed tokens, ordered by contract address")
+            .params(page())
+            .returns::<Page<Token>>(c),
+        Route::new("get", "/liquidityByChain", "liquidityByChain")
+            .summary("Liquidity routed to each destination chain, broken down by token")
+            .params([denomination()])
+            .returns::<Vec<ChainLiquidity>>(c),
+        Route::new("get", "/topTokens", "topTokens")
+            .summary("Tokens ranked by USD volume over a recent window")
+            .params([
+                query(
+                    "window",
+                    "24h (default), 7d or 30d",
+                    one_of(&["24h", "7d", "30d"]),
+                ),
+                query(
+                    "limit",
+                    "How many tokens to return (10 by default)",
+                    json!({ "type": "integer", "minimum": 1, "maximum": 100 }),
+                ),
+            ])
+            .returns::<Vec<TokenVolume>>(c),
+        Route::new("get", "/liquidityHistory", "liquidityHistory")
+            .summary("A token's cumulative liquidity as of each indexing run, oldest first")
+            .params([
+                Param {
+                    required: true,
+                    ..query(
+                        "token",
+                        "Contract address or symbol",
+                        json!({ "type": "string" }),
+                    )
+                },
+                query("from", "Inclusive unix timestamp", unix_timestamp()),
+                query("to", "Inclusive unix timestamp", unix_timestamp()),
+            ])
+            .returns::<LiquidityHistory>(c),
+        Route::new("get", "/transfers", "listTransfers")
+            .summary("Stored transfers, newest first")
+            .params(transfer_filters())
+            .params(page())
+            .returns::<Page<TransferDetail>>(c),
+        Route {
+            summary: "Every matching transfer, oldest first, streamed as CSV or NDJSON",
+            content: json!({
+                "text/csv": { "schema": { "type": "string" } },
+                "application/x-ndjson": { "schema": { "type": "string" } },
+            }),
+            ..Route::new("get", "/transfers/export", "exportTransfers")
+        }
+        .params(transfer_filters())
+        .params([query(
+            "format",
+            "csv (default) or ndjson",
+            one_of(&["csv", "ndjson"]),
+        )]),
+        Route {
+            summary: "Transfers as they're stored, as server-sent transfer events",
+            content: json!({ "text/event-stream": { "schema": { "type": "string" } } }),
+            ..Route::new("get", "/transfers/stream", "streamTransfers")
+        }
+        .params(transfer_filters()),
+        Route::new("get", "/transfers/byAddress/:addr", "transfersByAddress")
+            .summary("Transfers an address sent or received, newest first")
+            .params([path("addr", "The sender or destination account")])
+            .params(transfer_filters())
+            .params(page())
+            .returns::<Page<TransferDetail>>(c),
+        Route::new("get", "/transfers/:hash", "getTransfer")
+            .summary("A stored transfer, by transaction or extrinsic hash")
+            .params([
+                path(
+                    "hash",
+                    "The Ethereum transaction hash or substrate extrinsic hash",
+                ),
+                query(
+                    "include",
+                    "payload also returns the calldata and its decoded form",
+                    one_of(&["payload"]),
+                ),
+            ])
+            .returns::<TransferResponse>(c),
+        Route::new("get", "/transfer/:tx_hash", "lookUpTransfer")
+            .summary("A stored transfer with its price, destination and when it was indexed")
+            .params([path("tx_hash", "The Ethereum transaction hash")])
+            .returns::<TransferLookup>(c),
+        Route::new("get", "/errors", "listErrors")
+            .summary("Recorded indexer errors, newest first")
+            .params([query(
+                "since",
+                "Unix timestamp, defaulting to a day ago",
+                unix_timestamp(),
+            )])
+            .returns::<Vec<RecordedError>>(c),
+        Route::new("get", "/slo", "freshness")
+            .summary("Percentiles of how long transfers took to appear after their block")
+            .params([query(
+                "since",
+                "Unix timestamp of the first indexing to measure. Defaults to 7 days ago",
+                unix_timestamp(),
+            )])
+            .returns::<Freshness>(c),
+        Route::new("get", "/fees", "fees")
+            .summary("Gas and bridge fees paid routing transfers, per token")
+            .params([query(
+                "since",
+                "Unix timestamp of the first transfer to count. Defaults to 30 days ago",
+                unix_timestamp(),
+            )])
+            .returns::<Fees>(c),
+        Route::new("post", "/graphql", "graphql")
+            .summary("A GraphQL query over the transfers, tokens, liquidity and volume roots")
+            .body::<GraphQlRequest>(c)
+            .returns::<GraphQlResponse>(c),
+        Route::new("get", "/status", "status")
+            .summary("Indexer lag, last run and table sizes")
+            .returns::<Status>(c),
+        Route::new("get", "/metrics", "runMetrics")
+            .summary("What the most recent scheduled indexing run did")
+            .returns::<RunMetrics>(c),
+        Route::new("post", "/proposals", "submitProposal")
+            .summary("Proposes a correction to a stored transfer for review")
+            .body::<NewProposal>(c)
+            .returns::<Proposal>(c),
+        Route::new("get", "/proposals", "listOwnProposals")
+            .summary("Proposals made with the caller's key, newest first")
+            .returns::<Vec<Proposal>>(c),
+        Route::new("post", "/admin/webhooks", "registerWebhook")
+            .summary("Registers a webhook receiver")
+            .body::<NewWebhook>(c)
+            .returns::<CreatedWebhook>(c),
+        Route::new("post", "/admin/webhooks/:id/test", "testWebhook")
+            .summary("Sends a signed test event to a webhook")
+            .params([path("id", "The webhook's id")])
+            .returns::<WebhookTestReport>(c),
+        Route::new("post", "/admin/keys", "createApiKey")
+            .summary("Issues an API key")
+            .body::<NewApiKey>(c)
+            .returns::<CreatedApiKey>(c),
+        Route::new("get", "/admin/shadow", "shadowReport")
+            .summary("How often the shadow decoder disagrees with the active one")
+            .returns::<ShadowReport>(c),
+        Route::new("post", "/admin/migrate", "migrate")
+            .summary("Creates missing tables and columns and rewrites the token registry")
+            .returns::<OperationReport>(c),
+        Route::new("post", "/admin/reset", "reset")
+            .summary("Deletes every indexed transfer and token")
+            .returns::<OperationReport>(c),
+        Route::new("post", "/admin/reindex", "reindex")
+            .summary("Deletes transfers from a block onwards so they are indexed again")
+            .body::<ReindexRequest>(c)
+            .returns::<OperationReport>(c),
+        Route::new("post", "/admin/backfill", "backfill")
+            .summary("Decodes stored transfers' payloads again")
+            .body::<BackfillRequest>(c)
+            .returns::<OperationReport>(c),
+        Route::new("post", "/admin/replay", "replay")
+            .summary("Processes archived batches again, replacing the transfers stored from them")
+            .body::<ReplayRequest>(c)
+            .returns::<ReplayReport>(c),
+        Route::new("put", "/admin/tokens/:addr", "overrideToken")
+            .summary("Corrects a token's metadata by hand, so that indexing leaves it alone")
+            .params([path("addr", "The token's contract address")])
+            .body::<TokenOverride>(c)
+            .returns::<ManagedToken>(c),
+        Route::new("delete", "/admin/tokens/:addr", "dropTokenOverride")
+            .summary("Lets indexing and the registry keep a corrected token's metadata again")
+            .params([path("addr", "The token's contract address")])
+            .returns::<ManagedToken>(c),
+        Route::new("put", "/admin/chains/:id", "nameChain")
+            .summary("Names a destination parachain, or renames one")
+            .params([path("id", "The parachain id")])
+            .body::<ChainName>(c)
+            .returns::<Chain>(c),
+        Route::new("post", "/admin/extrinsics/:hash", "resolveExtrinsic")
+            .summary("Stores which transfer an extrinsic executed, looked up on Subscan")
+            .params([path("hash", "The substrate extrinsic hash")])
+            .returns::<TransferDetail>(c),
+        Route::new("get", "/admin/proposals", "listProposals")
+            .summary("Proposals in a review state, oldest first")
+            .params([query(
+                "status",
+                "pending (default), applied or rejected",
+                one_of(&["pending", "applied", "rejected"]),
+            )])
+            .returns::<Vec<Proposal>>(c),
+        Route::new("post", "/admin/proposals/:id/apply", "applyProposal")
+            .summary("Writes a pending proposal's value to its transfer and audits the change")
+            .params([path("id", "The proposal's id")])
+            .returns::<Proposal>(c),
+        Route::new("post", "/admin/proposals/:id/reject", "rejectProposal")
+            .summary("Closes a pending proposal without changing its transfer")
+            .params([path("id", "The proposal's id")])
+            .body::<ProposalReview>(c)
+            .returns::<Proposal>(c),
+        Route::new("get", "/admin/audit", "auditLog")
+            .summary("Changes made to stored transfers by hand, newest first")
+            .params([query(
+                "since",
+                "Unix timestamp, defaulting to 30 days ago",
+                unix_timestamp(),
+            )])
+            .returns::<Vec<AuditEntry>>(c),
+        Route {
+            summary: "This document",
+            content: json!({ "application/json": { "schema": { "type": "object" } } }),
+            ..Route::new("get", "/openapi.json", "openapi")
+        },
+    ]
+}
+
+fn operation(route: Route) -> Value {
+    let parameters: Vec<Value> = route
+        .params
+        .into_iter()
+        .map(|p| {
+            json!({
+                "name": p.name,
+                "in": p.location,
+                "description": p.description,
+                "required": p.required,
+                "schema": p.schema,
+            })
+        })
+        .collect();
+    let mut operation = json!({
+        "operationId": route.operation_id,
+        "summary": route.summary,
+        "parameters": parameters,
+        "responses": {
+            "200": { "description": "Success", "content": route.content },
+            // Every error is a plain text message
+            "default": {
+                "description": "Error",
+                "content": { "text/plain": { "schema": { "type": "string" } } },
+            },
+        },
+    });
+    if let Some(body) = route.body {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": body } },
+        });
+    }
+    if route.path.starts_with("/admin/") {
+        operation["security"] = json!([{ "adminToken": [] }]);
+    } else if tiers::required_tier(route.path) == Tier::Partner {
+        operation["security"] = json!([{ "apiKey": [] }]);
+    }
+    operation
+}
+
+/// `/transfers/:hash` as OpenAPI writes it, `/transfers/{hash}`.
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|s| match s.strip_prefix(':') {
+            Some(param) => format!("{{{param}}}"),
+            None => s.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The OpenAPI 3.0 document for the API served at `server`.
+fn document(server: &str) -> Value {
+    let mut components = Components::new();
+    let mut paths = Map::new();
+    for route in routes(&mut components) {
+        let operations = paths
+            .entry(openapi_path(route.path))
+            .or_insert_with(|| json!({}));
+        let method = route.method;
+        operations[method] = operation(route);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "MRL indexer",
+            "description": "Liquidity routed through Moonbeam Routed Liquidity (MRL)",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": server }],
+        "paths": paths,
+        "components": {
+            "schemas": components,
+            "securitySchemes": {
+                "apiKey": { "type": "apiKey", "in": "header", "name": API_KEY_HEADER },
+                "adminToken": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+/// GET /openapi.json describes every route, its parameters and the models it returns, so clients
+/// can be generated from it.
+pub(crate) async fn get(req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+    let server = req.url()?.origin().ascii_serialization();
+    Response::from_json(&document(&server))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cors::ALLOWED_METHODS;
+
+    use super::*;
+
+    fn references(value: &Value, found: &mut Vec<String>) {
+        match value {
+            Value::Object(o) => {
+                if let Some(Value::String(r)) = o.get("$ref") {
+                    found.push(r.clone());
+                }
+                o.values().for_each(|v| references(v, found));
+            }
+            Value::Array(a) => a.iter().for_each(|v| references(v, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn every_reference_resolves() {
+        let document = document("https://example.com");
+        let mut found = vec![];
+        references(&document, &mut found);
+        assert!(!found.is_empty());
+        for r in found {
+            let name = r.strip_prefix("#/components/schemas/").unwrap();
+            let schema = &document["components"]["schemas"][name];
+            assert!(schema.is_object(), "{r} doesn't resolve");
+        }
+    }
+
+    #[test]
+    fn path_params_are_declared() {
+        let document = document("https://example.com");
+        let hash = &document["paths"]["/transfers/{hash}"]["get"];
+        assert_eq!(hash["parameters"][0]["name"], "hash");
+        assert_eq!(hash["parameters"][0]["in"], "path");
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            assert!(!path.contains(':'), "{path}");
+            for operation in operations.as_object().unwrap().values() {
+                let declared = operation["parameters"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .filter(|p| p["in"] == "path")
+                    .count();
+                assert_eq!(declared, path.matches('{').count(), "{path}");
+            }
+        }
+    }
+
+    #[test]
+    fn routes_carry_their_security() {
+        let document = document("https://example.com");
+        let paths = &document["paths"];
+        assert_eq!(
+            paths["/admin/keys"]["post"]["security"],
+            json!([{ "adminToken": [] }])
+        );
+        assert_eq!(
+            paths["/transfers/export"]["get"]["security"],
+            json!([{ "apiKey": [] }])
+        );
+        assert!(paths["/status"]["get"].get("security").is_none());
+    }
+
+    #[test]
+    fn partner_routes_are_served() {
+        let routes = routes(&mut Components::new());
+        for gated in tiers::PARTNER_ROUTES {
+            let served = routes
+                .iter()
+                .any(|r| r.path == gated || r.path.starts_with(&format!("{gated}/")));
+            assert!(served, "{gated} doesn't match any route");
+        }
+    }
+
+    #[test]
+    fn preflights_allow_every_method_served() {
+        for route in routes(&mut Components::new()) {
+            assert!(
+                ALLOWED_METHODS
+                    .iter()
+                    .any(|m| m.as_ref().eq_ignore_ascii_case(route.method)),
+                "{} {} isn't allowed by CORS preflights",
+                route.method,
+                route.path
+            );
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Serialize};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+/// The envelope every paginated list endpoint returns. `next_cursor` is passed back as `?cursor=`
+/// to get the following page, and is absent on the last one.
+#[derive(Serialize)]
+pub(crate) struct Page<T> {
+    pub(crate) items: Vec<T>,
+    pub(crate) next_cursor: Option<String>,
+}
+
+/// The `cursor` and `limit` query parameters. `C` is the sort key of the last row on the previous
+/// page, which callers turn into a `WHERE` condition so that paging stays cheap however deep it
+/// goes.
+pub(crate) struct PageParams<C> {
+    pub(crate) cursor: Option<C>,
+    pub(crate) limit: u32,
+}
+
+impl<C> Default for PageParams<C> {
+    fn default() -> Self {
+        Self {
+            cursor: None,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl<C: DeserializeOwned> PageParams<C> {
+    /// Takes a query parameter if it's a pagination one. Returns whether it was one, or why its
+    /// value is invalid.
+    pub(crate) fn apply(&mut self, key: &str, value: &str) -> Result<bool, &'static str> {
+        match key {
+            "cursor" => {
+                let Some(cursor) = decode_cursor(value) else {
+                    return Err("Invalid cursor");
+                };
+                self.cursor = Some(cursor);
+            }
+            "limit" => {
+                let Ok(limit) = value.parse::<u32>() else {
+                    return Err("limit must be a number");
+                };
+                self.limit = limit.clamp(1, MAX_LIMIT);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// How many rows to query: one more than the limit, to find out whether there's another page.
+    pub(crate) fn query_limit(&self) -> u32 {
+        self.limit + 1
+    }
+}
+
+/// Builds a page from rows queried with `query_limit()`. `key` gives a row's sort key.
+pub(crate) fn paginate<T, C: Serialize>(
+    mut rows: Vec<T>,
+    limit: u32,
+    key: impl Fn(&T) -> C,
+) -> Page<T> {
+    let next_cursor = if rows.len() > limit as usize {
+        rows.truncate(limit as usize);
+        rows.last().and_then(|last| encode_cursor(&key(last)))
+    } else {
+        None
+    };
+    Page {
+        items: rows,
+        next_cursor,
+    }
+}
+
+// Cursors are opaque to clients, but are just the JSON sort key in URL safe base64
+fn encode_cursor<C: Serialize>(key: &C) -> Option<String> {
+    serde_json::to_vec(key).ok().map(|json| URL_SAFE_NO_PAD.encode(json))
+}
+
+fn decode_cursor<C: DeserializeOwned>(cursor: &str) -> Option<C> {
+    let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    serde_json::from_slice(&json).ok()
+}
@@ -0,0 +1,164 @@
+use mrl_indexer_core::models::UNDECODED_CHAIN;
+use serde::Deserialize;
+use worker::{console_log, D1Database, Env, Result};
+
+use crate::{batch_with_retry, d1, decoder, errors, errors::IndexerError, rpc, sql_text};
+
+// Each transfer needs its calldata from the node, so only this many are decoded per run
+const DEFAULT_DECODES_PER_RUN: u32 = 200;
+// What to_chain was stored as before destinations were decoded
+const PLACEHOLDER_CHAIN: u32 = 1000;
+// IndexerState key set once placeholder destinations have been queued for decoding
+const PLACEHOLDERS_RESET_KEY: &str = "placeholder_chains_reset";
+
+#[derive(Deserialize)]
+struct PendingTransfer {
+    tx_hash: String,
+}
+
+/// Wormhole addresses are 32 bytes. Those from EVM chains are 20 byte addresses padded with zeros,
+/// and are stored as such so they can be looked up the way users know them.
+pub(crate) fn normalize_address(wormhole_address: &str) -> String {
+    let hex = wormhole_address.trim_start_matches("0x").to_lowercase();
+    match hex.strip_prefix(&"0".repeat(24)) {
+        Some(evm) if hex.len() == 64 => format!("0x{evm}"),
+        _ => format!("0x{hex}"),
+    }
+}
+
+/// Formats an amount as a SQL literal, or NULL.
+fn sql_amount(amount: Option<u128>) -> String {
+    amount.map_or("NULL".to_string(), |a| a.to_string())
+}
+
+/// Transfers used to be stored with a placeholder `to_chain` of 1000 that no payload was decoded
+/// into. The first migration after the change marks them as undecoded and queues their payloads to
+/// be checked again, leaving destinations that were corrected by hand alone.
+pub(crate) async fn reset_placeholder_chains(db: &D1Database) -> Result<()> {
+    let done = worker::query!(
+        db,
+        "SELECT value FROM IndexerState WHERE key = ?1",
+        PLACEHOLDERS_RESET_KEY
+    )?
+    .first::<String>(Some("value"))
+    .await?;
+    if done.is_some() {
+        return Ok(());
+    }
+    let statements = [
+        format!(
+            "UPDATE TransfersForward SET to_chain = {UNDECODED_CHAIN}, payload_checked = 0 \
+             WHERE to_chain = {PLACEHOLDER_CHAIN} AND tx_hash NOT IN \
+             (SELECT tx_hash FROM AuditLog WHERE field = 'to_chain' AND tx_hash IS NOT NULL)"
+        ),
+        format!(
+            "INSERT OR REPLACE INTO IndexerState (key, value) \
+             VALUES ('{PLACEHOLDERS_RESET_KEY}', '1')"
+        ),
+    ];
+    batch_with_retry(db, "Placeholder destination reset", &statements).await?;
+    console_log!("Queued transfers with a placeholder destination for decoding");
+    Ok(())
+}
+
+/// Decodes the GMP payloads of stored transfers that haven't been checked yet, recording who sent
+/// each one, the parachain and account it was routed to and the fee the GMP precompile paid out of
+/// it, along with the gas its transaction paid. Transfers whose payload can't be decoded, such as
+/// native GLMR, only get their gas recorded.
+pub(crate) async fn decode_pending(env: &Env, db: &D1Database) {
+    let limit = env
+        .var("DECODES_PER_RUN")
+        .ok()
+        .and_then(|l| l.to_string().parse::<u32>().ok())
+        .unwrap_or(DEFAULT_DECODES_PER_RUN);
+    let pending = worker::query!(
+        db,
+        "SELECT tx_hash FROM TransfersForward WHERE payload_checked = 0
+        ORDER BY block_num LIMIT ?1",
+        limit
+    );
+    let pending = match pending {
+        Ok(s) => s.all().await.and_then(|r| r.results::<PendingTransfer>()),
+        Err(e) => Err(e),
+    };
+    let hashes: Vec<String> = match pending {
+        Ok(p) => p.into_iter().map(|p| p.tx_hash).collect(),
+        Err(e) => {
+            let e = d1::db_error(e.to_string());
+            errors::record(db, e, "Reading undecoded transfers").await;
+            return;
+        }
+    };
+    if hashes.is_empty() {
+        return;
+    }
+
+    let rpc = rpc::RpcClient::from_env(env);
+    let inputs = match rpc.transaction_inputs(&hashes).await {
+        Ok(i) => i,
+        Err(e) => {
+            errors::record(
+                db,
+                IndexerError::RpcFailure(format!("calldata ({e})")),
+                "Fetching calldata of undecoded transfers",
+            )
+            .await;
+            return;
+        }
+    };
+
+    // Fees aren't worth holding back the payloads for, and are filled in by a later backfill
+    let fees = match rpc.transaction_fees(&hashes).await {
+        Ok(f) => f,
+        Err(e) => {
+            errors::record(
+                db,
+                IndexerError::RpcFailure(format!("receipts ({e})")),
+                "Fetching gas fees of undecoded transfers",
+            )
+            .await;
+            vec![None; hashes.len()]
+        }
+    };
+
+    let active = decoder::active_decoder();
+    let mut decoded = 0;
+    let statements: Vec<String> = hashes
+        .iter()
+        .zip(inputs)
+        .zip(fees)
+        .map(|((hash, input), gas_fee)| {
+            let payload = input.and_then(|i| active.decode(&i).ok());
+            if payload.is_some() {
+                decoded += 1;
+            }
+            let sender = payload.as_ref().map(|p| normalize_address(&p.sender));
+            let bridge_fee = payload
+                .as_ref()
+                .and_then(|p| p.fee.as_ref()?.parse::<u128>().ok());
+            let parachain = payload.as_ref().and_then(|p| p.destination.parachain);
+            let account = payload.and_then(|p| p.destination.account);
+            // Destinations corrected by hand are kept over whatever the payload decodes to
+            format!(
+                "UPDATE TransfersForward SET sender = {}, \
+                 to_chain = CASE WHEN EXISTS (SELECT 1 FROM AuditLog \
+                     WHERE tx_hash = '{hash}' AND field = 'to_chain') \
+                     THEN to_chain ELSE COALESCE({}, to_chain) END, \
+                 dest_account = CASE WHEN EXISTS (SELECT 1 FROM AuditLog \
+                     WHERE tx_hash = '{hash}' AND field = 'dest_account') \
+                     THEN dest_account ELSE COALESCE({}, dest_account) END, \
+                 gas_fee = COALESCE({}, gas_fee), bridge_fee = {}, payload_checked = 1 \
+                 WHERE tx_hash = '{hash}'",
+                sql_text(&sender),
+                parachain.map_or("NULL".to_string(), |c| c.to_string()),
+                sql_text(&account),
+                sql_amount(gas_fee),
+                sql_amount(bridge_fee),
+            )
+        })
+        .collect();
+    match batch_with_retry(db, "Storing decoded payloads", &statements).await {
+        Ok(_) => console_log!("Decoded {} of {} pending payloads", decoded, hashes.len()),
+        Err(e) => errors::record(db, d1::db_error(e.to_string()), "Storing decoded payloads").await,
+    }
+}
@@ -0,0 +1,117 @@
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use worker::{console_error, console_warn, Env};
+
+use mrl_indexer_core::{
+    pipeline,
+    prices::{self, PriceProvider, PriceStrategy, ProviderName, TimeSeries},
+};
+
+use crate::{
+    coingecko::CoinGecko,
+    defillama::DefiLlama,
+    errors::IndexerError,
+    metrics,
+    retry::{retry, RetryPolicy},
+    twelve_data::TwelveData,
+};
+
+/// Prices tokens from each configured provider in turn, so that an outage or exhausted quota at
+/// one of them falls back to the next rather than leaving the run unpriced.
+pub(crate) struct Prices {
+    /// The providers that can be queried, in the order they're tried
+    providers: Vec<Box<dyn PriceProvider>>,
+    strategy: PriceStrategy,
+    pub(crate) latency: Rc<metrics::Latency>,
+}
+
+impl Prices {
+    /// Prices from the providers in `names` that can be queried, or None if none of them can.
+    pub(crate) fn new(
+        env: &Env,
+        names: &[ProviderName],
+        strategy: PriceStrategy,
+        quote: &str,
+    ) -> Option<Self> {
+        let latency = Rc::new(metrics::Latency::default());
+        let providers: Vec<Box<dyn PriceProvider>> = names
+            .iter()
+            .filter_map(|&name| provider(env, name, quote))
+            .map(|provider| {
+                Box::new(Retried {
+                    provider,
+                    latency: latency.clone(),
+                }) as Box<dyn PriceProvider>
+            })
+            .collect();
+        if providers.is_empty() {
+            return None
+        }
+        Some(Self {
+            providers,
+            strategy,
+            latency,
+        })
+    }
+}
+
+/// The provider configured as `name`, or None if it's missing what it needs to be queried.
+fn provider(env: &Env, name: ProviderName, quote: &str) -> Option<Box<dyn PriceProvider>> {
+    let quote = quote.to_string();
+    Some(match name {
+        ProviderName::TwelveData => {
+            let Ok(api_key) = env.var("TWELVE_DATA_KEY") else {
+                console_error!("Error discovering Twelve Data API key!");
+                return None
+            };
+            Box::new(TwelveData {
+                api_key: api_key.to_string(),
+                quote,
+            })
+        }
+        ProviderName::CoinGecko => Box::new(CoinGecko {
+            api_key: env.var("COINGECKO_KEY").ok().map(|k| k.to_string()),
+            quote,
+        }),
+        ProviderName::DefiLlama => Box::new(DefiLlama { quote }),
+    })
+}
+
+/// Retries a provider's failed requests, and counts the time spent on them.
+struct Retried {
+    provider: Box<dyn PriceProvider>,
+    latency: Rc<metrics::Latency>,
+}
+
+#[async_trait(?Send)]
+impl PriceProvider for Retried {
+    fn name(&self) -> &str {
+        self.provider.name()
+    }
+
+    async fn time_series(&self, symbol: &str) -> Result<Vec<TimeSeries>, String> {
+        let label = format!("{} query", self.name());
+        let fetched = self
+            .latency
+            .time(retry(&label, &RetryPolicy::default(), || {
+                self.provider.time_series(symbol)
+            }))
+            .await;
+        if let Err(e) = &fetched {
+            console_warn!("Error pricing {} from {}: {}", symbol, self.name(), e);
+        }
+        fetched
+    }
+}
+
+#[async_trait(?Send)]
+impl pipeline::PriceSource for Prices {
+    async fn time_series(
+        &self,
+        symbol: &str,
+        since: u64,
+    ) -> std::result::Result<Vec<TimeSeries>, IndexerError> {
+        prices::fetch(&self.providers, self.strategy, symbol, since).await
+    }
+}
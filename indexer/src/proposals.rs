@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use worker::{D1Database, Date, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin, audit, batch_with_retry, cache,
+    schemas::{NewProposal, Proposal, ProposalReview},
+    sql_string, tiers,
+};
+
+const MAX_EVIDENCE_LENGTH: usize = 4000;
+const MAX_NOTE_LENGTH: usize = 1000;
+// Substrate accounts are at most 32 bytes, so even hex with a prefix fits
+const MAX_ACCOUNT_LENGTH: usize = 128;
+const MAX_LISTED_PROPOSALS: u32 = 500;
+
+/// The fields of a stored transfer that partners can propose corrections to.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CorrectableField {
+    ToChain,
+    DestAccount,
+}
+
+impl CorrectableField {
+    /// The TransfersForward column the field is stored in.
+    fn column(self) -> &'static str {
+        match self {
+            Self::ToChain => "to_chain",
+            Self::DestAccount => "dest_account",
+        }
+    }
+
+    /// The proposed value as it would be stored, or why the field can't take it.
+    fn stored_value(self, value: &str) -> std::result::Result<String, &'static str> {
+        let value = value.trim();
+        match self {
+            Self::ToChain => match value.parse::<u32>() {
+                Ok(chain) => Ok(chain.to_string()),
+                Err(_) => Err("to_chain must be a parachain id"),
+            },
+            Self::DestAccount => match value.len() {
+                0 => Err("dest_account can't be empty"),
+                n if n > MAX_ACCOUNT_LENGTH => Err("dest_account is too long"),
+                _ => Ok(value.to_string()),
+            },
+        }
+    }
+
+    /// `value` as a SQL literal for the field's column. Values are checked when proposed.
+    fn sql_value(self, value: &str) -> String {
+        match self {
+            Self::ToChain => value.to_string(),
+            Self::DestAccount => sql_string(value),
+        }
+    }
+}
+
+/// Where a proposal is in review.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ProposalStatus {
+    Pending,
+    Applied,
+    Rejected,
+}
+
+impl ProposalStatus {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "applied" => Some(Self::Applied),
+            "rejected" => Some(Self::Rejected),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Applied => "applied",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+/// POST /proposals with `{ "tx_hash": ..., "field": ..., "value": ..., "evidence": ... }` queues
+/// a correction to a stored transfer for an admin to review. Needs a partner key.
+pub(crate) async fn submit(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let d1 = ctx.env.d1("DB")?;
+    let Some(caller) = tiers::caller(&req, &d1).await? else {
+        return Response::error("Unknown API key", 401);
+    };
+    let Ok(proposal) = req.json::<NewProposal>().await else {
+        let msg = "Expected a JSON body with tx_hash, field, value and evidence";
+        return Response::error(msg, 400)
+    };
+    let value = match proposal.field.stored_value(&proposal.value) {
+        Ok(v) => v,
+        Err(msg) => return Response::error(msg, 400),
+    };
+    let evidence = proposal.evidence.trim();
+    if evidence.is_empty() || evidence.len() > MAX_EVIDENCE_LENGTH {
+        let msg = format!("evidence must be between 1 and {MAX_EVIDENCE_LENGTH} characters");
+        return Response::error(msg, 400);
+    }
+
+    let statement = worker::query!(
+        &d1,
+        "SELECT tx_hash FROM TransfersForward WHERE tx_hash = ?1",
+        &proposal.tx_hash
+    )?;
+    if statement.first::<String>(Some("tx_hash")).await?.is_none() {
+        return Response::error("Transfer not found", 404);
+    }
+
+    let statement = worker::query!(
+        &d1,
+        "INSERT INTO CorrectionProposals
+            (tx_hash, field, value, evidence, submitted_by, submitted_at, status)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'pending')
+        RETURNING *",
+        &proposal.tx_hash,
+        proposal.field,
+        &value,
+        evidence,
+        caller.id,
+        Date::now().as_millis() / 1000
+    )?;
+    match statement.first::<Proposal>(None).await? {
+        Some(p) => Response::from_json(&p),
+        None => Response::error("Error when submitting proposal", 500),
+    }
+}
+
+/// GET /proposals lists the proposals made with the caller's key, newest first.
+pub(crate) async fn mine(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if req.url()?.query_pairs().next().is_some() {
+        return Response::error("Unexpected query parameter", 400);
+    }
+    let d1 = ctx.env.d1("DB")?;
+    let Some(caller) = tiers::caller(&req, &d1).await? else {
+        return Response::error("Unknown API key", 401);
+    };
+    let statement = worker::query!(
+        &d1,
+        "SELECT * FROM CorrectionProposals WHERE submitted_by = ?1 ORDER BY id DESC LIMIT ?2",
+        caller.id,
+        MAX_LISTED_PROPOSALS
+    )?;
+    let result = statement.all().await?;
+
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+
+    let x = result.results::<Proposal>()?;
+    Response::from_json(&x)
+}
+
+/// GET /admin/proposals?status=pending|applied|rejected lists proposals in that state, oldest
+/// first so the review queue is worked through in order. Defaults to pending.
+pub(crate) async fn list(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let mut status = ProposalStatus::Pending;
+    for (k, v) in req.url()?.query_pairs() {
+        if k != "status" {
+            return Response::error("Unexpected query parameter", 400);
+        }
+        let Some(s) = ProposalStatus::parse(&v) else {
+            return Response::error("status must be pending, applied or rejected", 400);
+        };
+        status = s;
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "SELECT * FROM CorrectionProposals WHERE status = ?1 ORDER BY id LIMIT ?2",
+        status.name(),
+        MAX_LISTED_PROPOSALS
+    )?;
+    let result = statement.all().await?;
+
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+
+    let x = result.results::<Proposal>()?;
+    Response::from_json(&x)
+}
+
+/// Reads the pending proposal named by the route's `id`, or the response to send instead.
+async fn pending(
+    ctx: &RouteContext<()>,
+    d1: &D1Database,
+) -> Result<std::result::Result<Proposal, Response>> {
+    let Some(Ok(id)) = ctx.param("id").map(|id| id.parse::<u32>()) else {
+        return Ok(Err(Response::error("Proposal id must be a number", 400)?));
+    };
+    let statement = worker::query!(d1, "SELECT * FROM CorrectionProposals WHERE id = ?1", id)?;
+    let Some(proposal) = statement.first::<Proposal>(None).await? else {
+        return Ok(Err(Response::error("Proposal not found", 404)?));
+    };
+    if proposal.status != ProposalStatus::Pending {
+        return Ok(Err(Response::error("Proposal was already reviewed", 409)?));
+    }
+    Ok(Ok(proposal))
+}
+
+async fn reviewed(d1: &D1Database, id: u32) -> Result<Response> {
+    let statement = worker::query!(d1, "SELECT * FROM CorrectionProposals WHERE id = ?1", id)?;
+    match statement.first::<Proposal>(None).await? {
+        Some(p) => Response::from_json(&p),
+        None => Response::error("Proposal not found", 404),
+    }
+}
+
+/// POST /admin/proposals/:id/apply writes a pending proposal's value to its transfer, recording
+/// the change in the audit log, which the proposal then links to.
+pub(crate) async fn apply(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let d1 = ctx.env.d1("DB")?;
+    let proposal = match pending(&ctx, &d1).await? {
+        Ok(p) => p,
+        Err(res) => return Ok(res),
+    };
+    let statement = worker::query!(
+        &d1,
+        "SELECT tx_hash FROM TransfersForward WHERE tx_hash = ?1",
+        &proposal.tx_hash
+    )?;
+    if statement.first::<String>(Some("tx_hash")).await?.is_none() {
+        return Response::error("The transfer is no longer indexed", 409);
+    }
+
+    let now = Date::now().as_millis() / 1000;
+    let column = proposal.field.column();
+    let statements = vec![
+        audit::record_change(
+            "apply_proposal",
+            &proposal.tx_hash,
+            column,
+            &Some(proposal.value.clone()),
+            Some(proposal.id),
+            now,
+        ),
+        format!(
+            "UPDATE TransfersForward SET {column} = {} WHERE tx_hash = {}",
+            proposal.field.sql_value(&proposal.value),
+            sql_string(&proposal.tx_hash)
+        ),
+        format!(
+            "UPDATE CorrectionProposals SET status = 'applied', reviewed_at = {now}, \
+             audit_id = (SELECT MAX(id) FROM AuditLog WHERE proposal_id = {id}) \
+             WHERE id = {id}",
+            id = proposal.id
+        ),
+    ];
+    if let Err(e) = batch_with_retry(&d1, "Applying proposal", &statements).await {
+        return Response::error(e.to_string(), 500);
+    }
+    cache::invalidate(&ctx.env).await;
+    reviewed(&d1, proposal.id).await
+}
+
+/// POST /admin/proposals/:id/reject with `{ "note": ... }` closes a pending proposal without
+/// changing its transfer.
+pub(crate) async fn reject(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(review) = req.json::<ProposalReview>().await else {
+        return Response::error("Expected a JSON body with a note", 400);
+    };
+    let note = review.note.trim();
+    if note.is_empty() || note.len() > MAX_NOTE_LENGTH {
+        let msg = format!("note must be between 1 and {MAX_NOTE_LENGTH} characters");
+        return Response::error(msg, 400);
+    }
+    let d1 = ctx.env.d1("DB")?;
+    let proposal = match pending(&ctx, &d1).await? {
+        Ok(p) => p,
+        Err(res) => return Ok(res),
+    };
+
+    let statement = worker::query!(
+        &d1,
+        "UPDATE CorrectionProposals SET status = 'rejected', reviewed_at = ?1, review_note = ?2
+        WHERE id = ?3",
+        Date::now().as_millis() / 1000,
+        note,
+        proposal.id
+    )?;
+    let result = statement.run().await?;
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+    reviewed(&d1, proposal.id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chains_must_be_parachain_ids() {
+        let field = CorrectableField::ToChain;
+        assert_eq!(field.stored_value(" 2004 "), Ok("2004".to_string()));
+        assert!(field.stored_value("Moonbeam").is_err());
+        assert!(field.stored_value("-1").is_err());
+        assert_eq!(field.sql_value("2004"), "2004");
+    }
+
+    #[test]
+    fn accounts_are_quoted() {
+        let field = CorrectableField::DestAccount;
+        assert_eq!(field.stored_value(" 0xab' "), Ok("0xab'".to_string()));
+        assert!(field.stored_value("  ").is_err());
+        let too_long = "a".repeat(MAX_ACCOUNT_LENGTH + 1);
+        assert!(field.stored_value(&too_long).is_err());
+        assert_eq!(field.sql_value("0xab'"), "'0xab'''");
+    }
+
+    #[test]
+    fn fields_use_their_column_names() {
+        let field: CorrectableField = serde_json::from_str("\"dest_account\"").unwrap();
+        assert_eq!(field, CorrectableField::DestAccount);
+        assert!(serde_json::from_str::<CorrectableField>("\"token_addr\"").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use worker::{
+    console_error, console_log, js_sys, wasm_bindgen::JsValue, D1Database, Date, Env, Response,
+    Result,
+};
+
+use crate::tiers::Tier;
+
+const DEFAULT_PUBLIC_DAILY_QUOTA: u64 = 1_000;
+const DEFAULT_PARTNER_DAILY_QUOTA: u64 = 100_000;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const RESET_KEY: &str = "usage_reset_month";
+const DEFAULT_ATTRIBUTION: &str = "Moonbeam Routed Liquidity indexer";
+
+/// How much of its daily quota an API key has used, counting the current request.
+pub(crate) struct Usage {
+    pub(crate) limit: u64,
+    pub(crate) used: u64,
+    /// Unix timestamp of the next UTC midnight, when the count starts over
+    pub(crate) reset_at: u64,
+}
+
+impl Usage {
+    pub(crate) fn exceeded(&self) -> bool {
+        self.used > self.limit
+    }
+}
+
+/// A key's own quota if it has one, otherwise its tier's, which the PUBLIC_DAILY_QUOTA and
+/// PARTNER_DAILY_QUOTA vars can override.
+pub(crate) fn daily_quota(env: &Env, tier: Tier, key_quota: Option<u64>) -> u64 {
+    if let Some(quota) = key_quota {
+        return quota;
+    }
+    let (var, default) = match tier {
+        Tier::Public => ("PUBLIC_DAILY_QUOTA", DEFAULT_PUBLIC_DAILY_QUOTA),
+        Tier::Partner => ("PARTNER_DAILY_QUOTA", DEFAULT_PARTNER_DAILY_QUOTA),
+    };
+    env.var(var)
+        .ok()
+        .and_then(|q| q.to_string().parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
+/// Counts a request against the key's usage for the current UTC day.
+pub(crate) async fn record_request(db: &D1Database, key_id: u32, limit: u64) -> Result<Usage> {
+    let day = Date::now().as_millis() / 1000 / SECONDS_PER_DAY;
+    let statement = worker::query!(
+        db,
+        "INSERT INTO ApiKeyUsage (key_id, day, requests) VALUES (?1, ?2, 1)
+        ON CONFLICT (key_id, day) DO UPDATE SET requests = requests + 1
+        RETURNING requests",
+        key_id,
+        day
+    )?;
+    let used = statement.first::<u64>(Some("requests")).await?.unwrap_or(1);
+    Ok(Usage {
+        limit,
+        used,
+        reset_at: (day + 1) * SECONDS_PER_DAY,
+    })
+}
+
+/// The terms of use and attribution headers that every response carries, read from the TERMS_URL
+/// and ATTRIBUTION vars.
+pub(crate) struct Notices {
+    terms_url: Option<String>,
+    attribution: String,
+}
+
+impl Notices {
+    pub(crate) fn from_env(env: &Env) -> Self {
+        Self {
+            terms_url: env.var("TERMS_URL").ok().map(|t| t.to_string()),
+            attribution: env
+                .var("ATTRIBUTION")
+                .map(|a| a.to_string())
+                .unwrap_or(DEFAULT_ATTRIBUTION.to_string()),
+        }
+    }
+
+    /// Adds the notices, and the rate limit headers for keyed requests.
+    pub(crate) fn apply(&self, mut res: Response, usage: Option<&Usage>) -> Result<Response> {
+        let headers = res.headers_mut();
+        if let Some(usage) = usage {
+            headers.set("X-RateLimit-Limit", &usage.limit.to_string())?;
+            headers.set(
+                "X-RateLimit-Remaining",
+                &usage.limit.saturating_sub(usage.used).to_string(),
+            )?;
+            headers.set("X-RateLimit-Reset", &usage.reset_at.to_string())?;
+        }
+        if let Some(terms) = &self.terms_url {
+            headers.set("Link", &format!("<{terms}>; rel=\"terms-of-service\""))?;
+        }
+        headers.set("X-Attribution", &self.attribution)?;
+        Ok(res)
+    }
+}
+
+/// The response sent once a key has used up its quota for the day.
+pub(crate) fn exceeded_response(usage: &Usage, notices: &Notices) -> Result<Response> {
+    let now = Date::now().as_millis() / 1000;
+    let mut res = Response::error("Daily quota exceeded", 429)?;
+    res.headers_mut()
+        .set("Retry-After", &usage.reset_at.saturating_sub(now).to_string())?;
+    notices.apply(res, Some(usage))
+}
+
+/// Clears usage from before the current month, once a month. Daily counts are all that quotas
+/// need, so older rows only take up space.
+pub(crate) async fn reset_monthly(db: &D1Database) {
+    let now = js_sys::Date::new(&JsValue::from_f64(Date::now().as_millis() as f64));
+    let month = format!("{}-{:02}", now.get_utc_full_year(), now.get_utc_month() + 1);
+    let last_reset = db
+        .prepare("SELECT value FROM IndexerState WHERE key = ?1")
+        .bind(&[RESET_KEY.into()]);
+    if let Ok(statement) = last_reset {
+        if let Ok(Some(last)) = statement.first::<String>(Some("value")).await {
+            if last == month {
+                return;
+            }
+        }
+    }
+
+    let month_start =
+        js_sys::Date::utc(now.get_utc_full_year() as f64, now.get_utc_month() as f64);
+    let first_day = month_start as u64 / 1000 / SECONDS_PER_DAY;
+    let statements = [
+        db.prepare("DELETE FROM ApiKeyUsage WHERE day < ?1")
+            .bind(&[(first_day as f64).into()]),
+        db.prepare("INSERT OR REPLACE INTO IndexerState (key, value) VALUES (?1, ?2)")
+            .bind(&[RESET_KEY.into(), month.clone().into()]),
+    ];
+    let Ok(statements) = statements.into_iter().collect::<Result<Vec<_>>>() else {
+        console_error!("Error binding usage reset statements!");
+        return
+    };
+    match db.batch(statements).await {
+        Ok(_) => console_log!("Reset API key usage for {}", month),
+        Err(e) => console_error!("Error resetting API key usage: {}", e),
+    }
+}
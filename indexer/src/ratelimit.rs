@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+// The durable_object macro expands to paths into these crates
+use worker::{
+    durable_object, js_sys, wasm_bindgen, wasm_bindgen::JsValue, wasm_bindgen_futures, worker_sys,
+    Date, Env, Method, Request, RequestInit, Response, Result, State,
+};
+
+const DEFAULT_READS_PER_MINUTE: u32 = 120;
+const DEFAULT_SEARCHES_PER_MINUTE: u32 = 30;
+// What a request costs from a bucket, which refills by the limit per minute every millisecond, so
+// that buckets only ever need integer arithmetic
+const REQUEST_COST: u64 = 60_000;
+
+/// Groups of routes that share a limit, by how much a request costs the database.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum RouteClass {
+    /// Aggregates and lookups, which are mostly served from the cache
+    Read,
+    /// The /transfers family, whose filters scan the whole table
+    Search,
+}
+
+impl RouteClass {
+    /// The class a path is limited as, or None for routes that aren't limited. Admin routes are
+    /// already behind the admin token.
+    fn of(path: &str) -> Option<Self> {
+        if path.starts_with("/admin/") {
+            None
+        } else if path == "/transfers" || path.starts_with("/transfers/") {
+            Some(Self::Search)
+        } else {
+            Some(Self::Read)
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Search => "search",
+        }
+    }
+
+    /// Requests a client may make per minute, set by the RATE_LIMIT_READS and
+    /// RATE_LIMIT_SEARCHES vars.
+    fn per_minute(self, env: &Env) -> u32 {
+        let (var, default) = match self {
+            Self::Read => ("RATE_LIMIT_READS", DEFAULT_READS_PER_MINUTE),
+            Self::Search => ("RATE_LIMIT_SEARCHES", DEFAULT_SEARCHES_PER_MINUTE),
+        };
+        env.var(var)
+            .ok()
+            .and_then(|l| l.to_string().parse::<u32>().ok())
+            .unwrap_or(default)
+    }
+}
+
+/// A token bucket holding up to a minute's worth of requests, refilled continuously.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+struct Bucket {
+    credit: u64,
+    // Unix milliseconds
+    updated_at: u64,
+}
+
+impl Bucket {
+    fn full(per_minute: u32, now: u64) -> Self {
+        Self {
+            credit: per_minute as u64 * REQUEST_COST,
+            updated_at: now,
+        }
+    }
+
+    /// Takes a token for a request at `now`. Returns None if there was one, otherwise how many
+    /// seconds until there will be.
+    fn take(&mut self, per_minute: u32, now: u64) -> Option<u64> {
+        let per_minute = per_minute as u64;
+        let elapsed = now.saturating_sub(self.updated_at);
+        self.credit = (self.credit + elapsed * per_minute).min(per_minute * REQUEST_COST);
+        self.updated_at = now;
+        if self.credit >= REQUEST_COST {
+            self.credit -= REQUEST_COST;
+            return None;
+        }
+        if per_minute == 0 {
+            return Some(60);
+        }
+        let wait_ms = (REQUEST_COST - self.credit).div_ceil(per_minute);
+        Some(wait_ms.div_ceil(1000))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TakeRequest {
+    class: String,
+    per_minute: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TakeResponse {
+    retry_after: Option<u64>,
+}
+
+/// One client's buckets, one per route class. Each client gets its own object, and objects
+/// handle one request at a time, so two requests can't both take the last token.
+#[durable_object]
+pub struct RateLimiter {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for RateLimiter {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let request: TakeRequest = req.json().await?;
+        let now = Date::now().as_millis();
+        let mut storage = self.state.storage();
+        // Missing keys are an error rather than undefined
+        let mut bucket = storage
+            .get::<Bucket>(&request.class)
+            .await
+            .unwrap_or(Bucket::full(request.per_minute, now));
+        let retry_after = bucket.take(request.per_minute, now);
+        storage.put(&request.class, bucket).await?;
+        Response::from_json(&TakeResponse { retry_after })
+    }
+}
+
+/// Counts a request against its client's limit for the route, identifying clients by the
+/// CF-Connecting-IP header. Returns the 429 to send instead if the client is over it. Requests
+/// go through unlimited without the RATE_LIMITER binding, or when the client can't be identified.
+pub(crate) async fn check(req: &Request, env: &Env) -> Result<Option<Response>> {
+    let Some(class) = RouteClass::of(&req.path()) else {
+        return Ok(None)
+    };
+    let Some(ip) = req.headers().get("CF-Connecting-IP")? else {
+        return Ok(None)
+    };
+    let Ok(namespace) = env.durable_object("RATE_LIMITER") else {
+        return Ok(None)
+    };
+    let stub = namespace.id_from_name(&ip)?.get_stub()?;
+
+    let body = serde_json::to_string(&TakeRequest {
+        class: class.name().to_string(),
+        per_minute: class.per_minute(env),
+    })?;
+    let limiter_req = Request::new_with_init(
+        "https://rate-limiter/take",
+        RequestInit::new()
+            .with_method(Method::Post)
+            .with_body(Some(JsValue::from_str(&body))),
+    )?;
+    let taken: TakeResponse = stub.fetch_with_request(limiter_req).await?.json().await?;
+    let Some(retry_after) = taken.retry_after else {
+        return Ok(None)
+    };
+    let mut res = Response::error("Too many requests", 429)?;
+    res.headers_mut().set("Retry-After", &retry_after.to_string())?;
+    Ok(Some(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_are_limited_as_searches() {
+        assert_eq!(RouteClass::of("/transfers"), Some(RouteClass::Search));
+        assert_eq!(
+            RouteClass::of("/transfers/byAddress/0xabc"),
+            Some(RouteClass::Search)
+        );
+        assert_eq!(RouteClass::of("/transfersX"), Some(RouteClass::Read));
+        assert_eq!(RouteClass::of("/topTokens"), Some(RouteClass::Read));
+        assert_eq!(RouteClass::of("/admin/keys"), None);
+    }
+
+    #[test]
+    fn an_empty_bucket_says_when_to_retry() {
+        let mut bucket = Bucket::full(2, 0);
+        assert_eq!(bucket.take(2, 0), None);
+        assert_eq!(bucket.take(2, 0), None);
+        // Two a minute refills one token every 30 seconds
+        assert_eq!(bucket.take(2, 0), Some(30));
+        assert_eq!(bucket.take(2, 20_000), Some(10));
+        assert_eq!(bucket.take(2, 30_000), None);
+    }
+
+    #[test]
+    fn buckets_refill_up_to_a_minutes_worth() {
+        let mut bucket = Bucket::full(3, 0);
+        assert_eq!(bucket.take(3, 0), None);
+        assert_eq!(bucket.take(3, 10 * 60_000), None);
+        assert_eq!(bucket.credit, 2 * REQUEST_COST);
+    }
+}
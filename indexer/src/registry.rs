@@ -0,0 +1,55 @@
+use worker::{console_log, D1Database};
+
+pub(crate) use mrl_indexer_core::registry::*;
+
+use crate::{batch_with_retry, d1, errors, sql_string};
+
+/// Writes the registry into the Token and Chains tables. Registry entries overwrite whatever the
+/// explorer reported, so editing an entry here corrects its metadata on the next run. Tokens
+/// corrected by hand are left alone, and so are chains already named, so that names set through
+/// the admin API stick.
+pub(crate) async fn seed(db: &D1Database) {
+    let values = REGISTRY
+        .iter()
+        .map(|t| {
+            format!(
+                "('{}', {}, {}, {}, {}, {})",
+                t.address,
+                sql_string(t.name),
+                sql_string(t.symbol),
+                t.decimals,
+                sql_string(t.category),
+                sql_string(t.logo_url)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let statement = format!(
+        "
+        INSERT INTO Token (contract_addr, token_name, token_sym, decimals, category, logo_url)
+        VALUES {values}
+        ON CONFLICT (contract_addr) DO UPDATE SET
+            token_name = excluded.token_name,
+            token_sym = excluded.token_sym,
+            decimals = excluded.decimals,
+            category = excluded.category,
+            logo_url = excluded.logo_url
+        WHERE Token.overridden = 0
+        "
+    );
+    let chains = CHAINS
+        .iter()
+        .map(|c| format!("({}, {})", c.id, sql_string(c.name)))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let chain_statement =
+        format!("INSERT OR IGNORE INTO Chains (chain_id, chain_name) VALUES {chains}");
+    match batch_with_retry(db, "Token registry seeding", &[statement, chain_statement]).await {
+        Ok(_) => console_log!(
+            "Seeded {} registry tokens and {} chains",
+            REGISTRY.len(),
+            CHAINS.len()
+        ),
+        Err(e) => errors::record(db, d1::db_error(e.to_string()), "Seeding token registry").await,
+    }
+}
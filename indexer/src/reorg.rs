@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use worker::{console_log, D1Database, Env};
+
+use crate::{
+    alerts, batch_with_retry, budget::WorkBudget, config::Config, d1, errors, errors::IndexerError,
+    ingest, native, scan::ScanClient,
+};
+
+// Moonbeam finalizes within a few blocks, so this comfortably covers any realistic reorg
+const DEFAULT_REORG_DEPTH: u64 = 20;
+
+#[derive(Deserialize)]
+struct StoredTransfer {
+    tx_hash: String,
+    block_num: u64,
+}
+
+/// The first block holding a stored transfer that the explorer no longer reports in that block,
+/// because it was orphaned or moved to another block. Only blocks with stored transfers are
+/// compared, since most blocks have no MRL transfers, and canonical transfers that were never
+/// stored, such as suspect tokens, aren't a sign of a reorg either.
+fn first_divergent(stored: &[StoredTransfer], canonical: &HashMap<String, u64>) -> Option<u64> {
+    stored
+        .iter()
+        .filter(|s| canonical.get(&s.tx_hash) != Some(&s.block_num))
+        .map(|s| s.block_num)
+        .min()
+}
+
+/// Compares the transfers stored for the last REORG_DEPTH indexed blocks with what the explorer
+/// reports for them now. From the first block where the two disagree, stored transfers are deleted
+/// so that the indexing pass which follows inserts the canonical ones in their place.
+pub(crate) async fn reconcile(env: &Env, db: &D1Database, config: &Config, budget: &WorkBudget) {
+    let depth = env
+        .var("REORG_DEPTH")
+        .ok()
+        .and_then(|d| d.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_REORG_DEPTH);
+    if depth == 0 {
+        return;
+    }
+
+    let last_block = db
+        .prepare("SELECT MAX(block_num) AS most_recent_block FROM TransfersForward")
+        .first::<u64>(Some("most_recent_block"))
+        .await;
+    let last_block = match last_block {
+        Ok(Some(b)) => b,
+        Ok(None) => return,
+        Err(e) => {
+            let e = d1::db_error(e.to_string());
+            errors::record(db, e, "Reading most_recent_block for reorg detection").await;
+            return;
+        }
+    };
+    let from_block = last_block.saturating_sub(depth - 1);
+
+    let Ok(moonscan_key) = env.var("MOONSCAN_KEY") else {
+        return
+    };
+    let gmp_precompile = config.gmp_precompile;
+    let client = ScanClient::new(moonscan_key.to_string());
+    let max = budget.max_transfers as u64;
+    let canonical = match client
+        .token_transfers(gmp_precompile, from_block, last_block, max)
+        .await
+    {
+        Ok(events) => events
+            .iter()
+            .filter(|e| native::is_forward(e, gmp_precompile))
+            .map(|e| (format!("{:?}", e.hash), e.block_number))
+            .collect::<Vec<(String, u64)>>(),
+        Err(e) => {
+            let e = IndexerError::EtherscanFailure(e.to_string());
+            errors::record(db, e, "Re-fetching recent blocks for reorg detection").await;
+            return;
+        }
+    };
+    let native = native::native_transfers(&client, gmp_precompile, from_block, last_block, max);
+    let native = match native.await {
+        Ok(n) => n,
+        Err(e) => {
+            let e = IndexerError::EtherscanFailure(e.to_string());
+            errors::record(
+                db,
+                e,
+                "Re-fetching recent native transfers for reorg detection",
+            )
+            .await;
+            return;
+        }
+    };
+    // A full page may not cover the whole range, which would look like missing transfers
+    if canonical.len() as u64 >= max || native.len() as u64 >= max {
+        console_log!(
+            "Too many transfers in the last {} blocks to check for reorgs.",
+            depth
+        );
+        return;
+    }
+    let canonical: HashMap<String, u64> = canonical
+        .into_iter()
+        .chain(native.into_iter().map(|t| (t.tx_hash, t.block_num)))
+        .collect();
+
+    let stored = worker::query!(
+        db,
+        "SELECT tx_hash, block_num FROM TransfersForward WHERE block_num >= ?1",
+        from_block
+    );
+    let stored = match stored {
+        Ok(s) => s.all().await.and_then(|r| r.results::<StoredTransfer>()),
+        Err(e) => Err(e),
+    };
+    let stored = match stored {
+        Ok(s) => s,
+        Err(e) => {
+            let e = d1::db_error(e.to_string());
+            errors::record(db, e, "Reading recent transfers for reorg detection").await;
+            return;
+        }
+    };
+
+    let Some(first_divergent) = first_divergent(&stored, &canonical) else {
+        return
+    };
+
+    let statements = vec![
+        format!("DELETE FROM TransfersForward WHERE block_num >= {first_divergent}"),
+        "DELETE FROM ShadowTransfers WHERE tx_hash NOT IN (SELECT tx_hash FROM TransfersForward)"
+            .to_string(),
+        ingest::rewind(first_divergent),
+    ];
+    if let Err(e) = batch_with_retry(db, "Deleting reorged transfers", &statements).await {
+        let e = d1::db_error(e.to_string());
+        errors::record(db, e, "Deleting reorged transfers").await;
+        return;
+    }
+    let deleted = stored
+        .iter()
+        .filter(|s| s.block_num >= first_divergent)
+        .count();
+    alerts::send_alert(
+        env,
+        &format!(
+            "Reorg detected at block {first_divergent}: deleted {deleted} stored transfers to be \
+             indexed again."
+        ),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(tx_hash: &str, block_num: u64) -> StoredTransfer {
+        StoredTransfer {
+            tx_hash: tx_hash.to_string(),
+            block_num,
+        }
+    }
+
+    fn canonical(transfers: &[(&str, u64)]) -> HashMap<String, u64> {
+        transfers.iter().map(|(h, b)| (h.to_string(), *b)).collect()
+    }
+
+    #[test]
+    fn an_unchanged_window_has_no_divergence() {
+        let stored = [stored("0xa", 100), stored("0xb", 105)];
+        // Transfers in other blocks that weren't stored don't count against the window
+        let canonical = canonical(&[("0xa", 100), ("0xb", 105), ("0xc", 103), ("0xd", 110)]);
+        assert_eq!(first_divergent(&stored, &canonical), None);
+        assert_eq!(first_divergent(&[], &canonical), None);
+    }
+
+    #[test]
+    fn orphaned_and_moved_transfers_diverge_from_their_block() {
+        let stored = [stored("0xa", 100), stored("0xb", 105), stored("0xc", 108)];
+        let canonical = canonical(&[("0xa", 100), ("0xb", 106)]);
+        assert_eq!(first_divergent(&stored, &canonical), Some(105));
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+use worker::{console_warn, Delay};
+
+/// How often and how patiently to retry a fallible call.
+#[derive(Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub(crate) attempts: u32,
+    pub(crate) base_delay: Duration,
+    pub(crate) max_delay: Duration,
+    // Randomizes each delay between half and all of its value so retries don't line up
+    pub(crate) jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry` (starting at 1), doubling each time.
+    fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(retry - 1))
+            .min(self.max_delay);
+        if !self.jitter {
+            return exponential;
+        }
+
+        let mut random = [0_u8; 2];
+        let fraction = match getrandom::getrandom(&mut random) {
+            Ok(_) => u16::from_le_bytes(random) as f64 / u16::MAX as f64,
+            Err(_) => 1.,
+        };
+        exponential.mul_f64(0.5 + fraction / 2.)
+    }
+}
+
+/// Runs `op` until it succeeds or the policy's attempts are used up, logging every retry. The last
+/// error is returned if every attempt fails.
+pub(crate) async fn retry<T, E, F, Fut>(label: &str, policy: &RetryPolicy, op: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_if(label, policy, |_| true, op).await
+}
+
+/// Like `retry`, but gives up straight away on errors that `retryable` says another attempt won't
+/// fix.
+pub(crate) async fn retry_if<T, E, F, Fut>(
+    label: &str,
+    policy: &RetryPolicy,
+    retryable: impl Fn(&E) -> bool,
+    mut op: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(x) => return Ok(x),
+            Err(e) if attempt >= policy.attempts || !retryable(&e) => return Err(e),
+            Err(e) => {
+                let delay = policy.delay(attempt);
+                console_warn!(
+                    "{} failed (attempt {}/{}), retrying in {}ms: {}",
+                    label,
+                    attempt,
+                    policy.attempts,
+                    delay.as_millis(),
+                    e
+                );
+                Delay::from(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use mrl_indexer_core::models::TokenMetadata;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use worker::{console_log, console_warn, Env, Result};
+
+use crate::{
+    eth::{self, Address, Bytes, Quantity, H256, U256},
+    registry,
+    scan::TokenTransfer,
+};
+
+const DEFAULT_RPC_URL: &str = "https://rpc.api.moonbeam.network";
+// keccak256("Transfer(address,address,uint256)")
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+// Public Moonbeam endpoints reject eth_getLogs over large ranges
+const LOG_BLOCK_RANGE: u64 = 1000;
+// Calls per JSON-RPC batch, which public endpoints also cap
+const MAX_BATCH_SIZE: usize = 50;
+
+const NAME_SELECTOR: [u8; 4] = [0x06, 0xfd, 0xde, 0x03];
+const SYMBOL_SELECTOR: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    #[serde(default)]
+    id: usize,
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RpcBlock {
+    timestamp: Quantity,
+}
+
+#[derive(Deserialize)]
+struct RpcTransaction {
+    input: Bytes,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcReceipt {
+    gas_used: Quantity,
+    // Left out by nodes that predate EIP-1559
+    effective_gas_price: Option<Quantity>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcLog {
+    address: Address,
+    topics: Vec<H256>,
+    data: Bytes,
+    // Pending logs have neither
+    block_number: Option<Quantity>,
+    transaction_hash: Option<H256>,
+    #[serde(default)]
+    removed: bool,
+}
+
+/// A minimal Ethereum JSON-RPC client, used when the block explorer API is unavailable.
+pub(crate) struct RpcClient {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl RpcClient {
+    pub(crate) fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Connects to MOONBEAM_RPC_URL, or the public Moonbeam endpoint if it isn't set.
+    pub(crate) fn from_env(env: &Env) -> Self {
+        let url = env
+            .var("MOONBEAM_RPC_URL")
+            .map(|u| u.to_string())
+            .unwrap_or(DEFAULT_RPC_URL.to_string());
+        Self::new(url)
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        self.request_nullable(method, params).await?.ok_or_else(|| {
+            worker::Error::JsError(format!("Error: RPC {} returned no result!", method))
+        })
+    }
+
+    /// Like `request`, for methods that answer with a null result when nothing is found.
+    async fn request_nullable<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<Option<T>> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| worker::Error::JsError(e.to_string()))?
+            .json::<RpcResponse<T>>()
+            .await
+            .map_err(|e| worker::Error::JsError(e.to_string()))?;
+
+        if let Some(e) = response.error {
+            return Err(worker::Error::JsError(format!(
+                "Error: RPC {} returned {}: {}",
+                method, e.code, e.message
+            )));
+        }
+        Ok(response.result)
+    }
+
+    /// Sends one call per entry of `params` as JSON-RPC batches. Results are in the same order as
+    /// `params`, with None where the node answered null.
+    async fn batch_nullable<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<Vec<Option<T>>> {
+        let mut results = Vec::with_capacity(params.len());
+        for (chunk_index, chunk) in params.chunks(MAX_BATCH_SIZE).enumerate() {
+            let offset = chunk_index * MAX_BATCH_SIZE;
+            let body: Vec<Value> = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    json!({ "jsonrpc": "2.0", "id": offset + i, "method": method, "params": p })
+                })
+                .collect();
+            let mut responses = self
+                .client
+                .post(&self.url)
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| worker::Error::JsError(e.to_string()))?
+                .json::<Vec<RpcResponse<T>>>()
+                .await
+                .map_err(|e| worker::Error::JsError(e.to_string()))?;
+
+            // Batch responses may come back in any order
+            responses.sort_by_key(|r| r.id);
+            if responses.len() != chunk.len() {
+                return Err(worker::Error::JsError(format!(
+                    "Error: RPC {} batch of {} returned {} results!",
+                    method,
+                    chunk.len(),
+                    responses.len()
+                )));
+            }
+            for response in responses {
+                if let Some(e) = response.error {
+                    return Err(worker::Error::JsError(format!(
+                        "Error: RPC {} returned {}: {}",
+                        method, e.code, e.message
+                    )));
+                }
+                results.push(response.result);
+            }
+        }
+        Ok(results)
+    }
+
+    pub(crate) async fn block_number(&self) -> Result<u64> {
+        let block: Quantity = self.request("eth_blockNumber", json!([])).await?;
+        Ok(block.0)
+    }
+
+    pub(crate) async fn block_timestamp(&self, block: u64) -> Result<u64> {
+        let block: RpcBlock = self
+            .request(
+                "eth_getBlockByNumber",
+                json!([format!("{:#x}", block), false]),
+            )
+            .await?;
+        Ok(block.timestamp.0)
+    }
+
+    /// The calldata of a transaction, or None if the node doesn't know the transaction.
+    pub(crate) async fn transaction_input(&self, hash: &str) -> Result<Option<Bytes>> {
+        let tx: Option<RpcTransaction> = self
+            .request_nullable("eth_getTransactionByHash", json!([hash]))
+            .await?;
+        Ok(tx.map(|t| t.input))
+    }
+
+    /// Like `transaction_input` for many transactions, fetched in batches.
+    pub(crate) async fn transaction_inputs(&self, hashes: &[String]) -> Result<Vec<Option<Bytes>>> {
+        let params = hashes.iter().map(|h| json!([h])).collect();
+        let txs: Vec<Option<RpcTransaction>> =
+            self.batch_nullable("eth_getTransactionByHash", params).await?;
+        Ok(txs.into_iter().map(|t| t.map(|t| t.input)).collect())
+    }
+
+    /// What each transaction paid for gas, in wei, from its receipt. None where the node doesn't
+    /// know the transaction or the price it paid.
+    pub(crate) async fn transaction_fees(&self, hashes: &[String]) -> Result<Vec<Option<u128>>> {
+        let params = hashes.iter().map(|h| json!([h])).collect();
+        let receipts: Vec<Option<RpcReceipt>> =
+            self.batch_nullable("eth_getTransactionReceipt", params).await?;
+        Ok(receipts
+            .into_iter()
+            .map(|r| {
+                let r = r?;
+                Some(r.gas_used.0 as u128 * r.effective_gas_price?.0 as u128)
+            })
+            .collect())
+    }
+
+    async fn get_logs(&self, filter: Value) -> Result<Vec<RpcLog>> {
+        self.request("eth_getLogs", json!([filter])).await
+    }
+
+    async fn call(&self, to: Address, data: &[u8]) -> Result<Bytes> {
+        self.request(
+            "eth_call",
+            json!([{ "to": to, "data": Bytes(data.to_vec()) }, "latest"]),
+        )
+        .await
+    }
+}
+
+/// Reads ERC-20 mints and custodian releases to `recipient` from `from_block` onwards straight
+/// from the chain's logs, shaped like the block explorer's token transfers so they can be
+/// processed identically. At most `max_log_queries` ranges are read, so a long outage doesn't
+/// blow the CPU budget.
+pub(crate) async fn get_mint_transfer_events(
+    rpc: &RpcClient,
+    recipient: Address,
+    from_block: u64,
+    max_log_queries: u64,
+) -> Result<Vec<TokenTransfer>> {
+    let head = rpc.block_number().await?;
+    let to_block = head.min(from_block + LOG_BLOCK_RANGE * max_log_queries - 1);
+    console_log!("Reading Transfer logs from RPC for blocks {} to {}.", from_block, to_block);
+
+    // Topics in a list match any of them
+    let senders: Vec<H256> = std::iter::once(H256::zero())
+        .chain(registry::custodians().into_iter().map(H256::from))
+        .collect();
+    let mut logs: Vec<RpcLog> = vec![];
+    let mut start = from_block;
+    while start <= to_block {
+        let end = to_block.min(start + LOG_BLOCK_RANGE - 1);
+        let filter = json!({
+            "fromBlock": format!("{:#x}", start),
+            "toBlock": format!("{:#x}", end),
+            "topics": [TRANSFER_TOPIC, senders, H256::from(recipient)],
+        });
+        logs.extend(rpc.get_logs(filter).await?);
+        start = end + 1;
+    }
+
+    // Logs carry neither timestamps nor token metadata, so look each up once
+    let mut timestamps: HashMap<u64, u64> = HashMap::new();
+    let mut tokens: HashMap<Address, (String, String, String)> = HashMap::new();
+    let mut events = vec![];
+    for log in logs {
+        let (Some(block), Some(hash)) = (log.block_number, log.transaction_hash) else {
+            continue;
+        };
+        if log.topics.len() < 3 || log.removed {
+            continue;
+        }
+
+        let block = block.0;
+        if let Entry::Vacant(e) = timestamps.entry(block) {
+            e.insert(rpc.block_timestamp(block).await?);
+        }
+        if let Entry::Vacant(e) = tokens.entry(log.address) {
+            // Left blank when unknown, for the indexer to treat like missing explorer metadata
+            let metadata = token_metadata(rpc, log.address).await;
+            e.insert((
+                metadata.name.unwrap_or_default(),
+                metadata.symbol.unwrap_or_default(),
+                metadata.decimals.map(|d| d.to_string()).unwrap_or_default(),
+            ));
+        }
+        let (token_name, token_symbol, token_decimal) = tokens[&log.address].clone();
+
+        events.push(TokenTransfer {
+            hash,
+            block_number: block,
+            time_stamp: timestamps[&block].to_string(),
+            from: Address::from(log.topics[1]),
+            to: Some(Address::from(log.topics[2])),
+            contract_address: log.address,
+            value: eth::abi_uint(&log.data).unwrap_or_default(),
+            token_name,
+            token_symbol,
+            token_decimal,
+        });
+    }
+
+    Ok(events)
+}
+
+/// Calls `name()`, `symbol()` and `decimals()` on a token.
+pub(crate) async fn token_metadata(rpc: &RpcClient, token: Address) -> TokenMetadata {
+    let metadata = TokenMetadata {
+        name: call_string(rpc, token, NAME_SELECTOR).await,
+        symbol: call_string(rpc, token, SYMBOL_SELECTOR).await,
+        decimals: call_uint(rpc, token, DECIMALS_SELECTOR)
+            .await
+            .filter(|d| *d <= U256::from(u32::MAX))
+            .map(|d| d.as_u32()),
+    };
+    if metadata.name.is_none() || metadata.symbol.is_none() || metadata.decimals.is_none() {
+        console_warn!("Couldn't read full token metadata for {:?} over RPC.", token);
+    }
+    metadata
+}
+
+async fn call_string(rpc: &RpcClient, token: Address, selector: [u8; 4]) -> Option<String> {
+    let bytes = rpc.call(token, &selector).await.ok()?;
+    eth::abi_string(&bytes).filter(|s| !s.trim().is_empty())
+}
+
+async fn call_uint(rpc: &RpcClient, token: Address, selector: [u8; 4]) -> Option<U256> {
+    let bytes = rpc.call(token, &selector).await.ok()?;
+    eth::abi_uint(&bytes)
+}
@@ -0,0 +1,152 @@
+use thiserror::Error;
+use worker::Env;
+
+// What each cron trigger in wrangler.toml does unless CRON_TASKS says otherwise
+const DEFAULT_SCHEDULES: [(&str, &[Task]); 3] = [
+    ("*/5 * * * *", &[Task::Index]),
+    ("0 * * * *", &[Task::Decode]),
+    ("0 3 * * *", &[Task::Maintenance]),
+];
+
+/// The jobs a scheduled run can be given, in the order a run that has several does them.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum Task {
+    /// Reorg checks, indexing new transfers, snapshotting liquidity and refreshing the response
+    /// cache
+    Index,
+    /// Decoding the GMP payloads of stored transfers, backfilling older ones
+    Decode,
+    /// Housekeeping that only needs doing now and then, like clearing old API key usage
+    Maintenance,
+}
+
+impl Task {
+    const ALL: [Task; 3] = [Task::Index, Task::Decode, Task::Maintenance];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "index" => Some(Self::Index),
+            "decode" => Some(Self::Decode),
+            "maintenance" => Some(Self::Maintenance),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Index => "index",
+            Self::Decode => "decode",
+            Self::Maintenance => "maintenance",
+        }
+    }
+}
+
+/// An entry of CRON_TASKS that can't be used.
+#[derive(Debug, Error)]
+#[error("CRON_TASKS has {entry:?}, but expected `CRON=TASK,...` with index, decode or maintenance")]
+pub(crate) struct ScheduleError {
+    entry: String,
+}
+
+/// Which tasks each cron trigger runs. The CRON_TASKS var overrides the defaults with `;`
+/// separated entries like `*/10 * * * *=index;0 4 * * *=decode,maintenance`.
+pub(crate) struct Schedules {
+    tasks: Vec<(String, Vec<Task>)>,
+}
+
+impl Schedules {
+    pub(crate) fn from_env(env: &Env) -> Result<Self, ScheduleError> {
+        match env.var("CRON_TASKS") {
+            Ok(v) => Self::parse(&v.to_string()),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, ScheduleError> {
+        let mut tasks = vec![];
+        for entry in value.split(';').filter(|e| !e.trim().is_empty()) {
+            let error = || ScheduleError {
+                entry: entry.trim().to_string(),
+            };
+            let (cron, names) = entry.split_once('=').ok_or_else(error)?;
+            let names = names
+                .split(',')
+                .map(|n| Task::parse(n.trim()))
+                .collect::<Option<Vec<Task>>>()
+                .ok_or_else(error)?;
+            if cron.trim().is_empty() {
+                return Err(error());
+            }
+            tasks.push((normalize(cron), names));
+        }
+        Ok(Self { tasks })
+    }
+
+    /// The tasks for the trigger `cron`, in the order to run them. Triggers that aren't
+    /// configured run every task, so a run is never silently a no-op.
+    pub(crate) fn tasks_for(&self, cron: &str) -> Vec<Task> {
+        let cron = normalize(cron);
+        let Some((_, tasks)) = self.tasks.iter().find(|(c, _)| *c == cron) else {
+            return Task::ALL.to_vec()
+        };
+        let mut tasks = tasks.clone();
+        tasks.sort();
+        tasks.dedup();
+        tasks
+    }
+}
+
+impl Default for Schedules {
+    fn default() -> Self {
+        Self {
+            tasks: DEFAULT_SCHEDULES
+                .iter()
+                .map(|(cron, tasks)| (cron.to_string(), tasks.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+fn normalize(cron: &str) -> String {
+    cron.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_the_wrangler_triggers() {
+        let schedules = Schedules::default();
+        assert_eq!(schedules.tasks_for("*/5 * * * *"), vec![Task::Index]);
+        assert_eq!(schedules.tasks_for("0 * * * *"), vec![Task::Decode]);
+        assert_eq!(schedules.tasks_for("0 3 * * *"), vec![Task::Maintenance]);
+    }
+
+    #[test]
+    fn unknown_triggers_run_everything() {
+        let schedules = Schedules::default();
+        assert_eq!(schedules.tasks_for("0 0/4 * * *"), Task::ALL.to_vec());
+    }
+
+    #[test]
+    fn tasks_can_be_configured() {
+        let schedules =
+            Schedules::parse(" */10 * * * * = index ; 0 4 * * *=maintenance,decode,decode;")
+                .unwrap();
+        assert_eq!(schedules.tasks_for("*/10  * * * *"), vec![Task::Index]);
+        assert_eq!(
+            schedules.tasks_for("0 4 * * *"),
+            vec![Task::Decode, Task::Maintenance]
+        );
+        // Configuring CRON_TASKS replaces the defaults
+        assert_eq!(schedules.tasks_for("*/5 * * * *"), Task::ALL.to_vec());
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        assert!(Schedules::parse("*/5 * * * *").is_err());
+        assert!(Schedules::parse("*/5 * * * *=reindex").is_err());
+        assert!(Schedules::parse("=index").is_err());
+    }
+}
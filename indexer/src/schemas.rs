@@ -0,0 +1,732 @@
+use std::collections::BTreeMap;
+
+pub(crate) use mrl_indexer_core::{
+    model,
+    models::Token,
+    schemas::{object, Components, Field, JsonSchema},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    decoder::DecodedPayload,
+    int_as_bool,
+    pagination::Page,
+    proposals::{CorrectableField, ProposalStatus},
+    tiers::Tier,
+    usd::Usd,
+};
+
+// Generic, so each list endpoint's page is described inline rather than as a shared model
+impl<T: JsonSchema> JsonSchema for Page<T> {
+    fn schema(components: &mut Components) -> Value {
+        let fields = vec![
+            Field {
+                name: "items",
+                schema: Vec::<T>::schema(components),
+                required: true,
+                description: None,
+            },
+            Field {
+                name: "next_cursor",
+                schema: Option::<String>::schema(components),
+                required: false,
+                description: Some("Passed back as `cursor` for the next page".to_string()),
+            },
+        ];
+        object(None, fields)
+    }
+}
+
+impl JsonSchema for Tier {
+    fn schema(_: &mut Components) -> Value {
+        json!({ "type": "string", "enum": ["public", "partner"] })
+    }
+}
+
+impl JsonSchema for CorrectableField {
+    fn schema(_: &mut Components) -> Value {
+        json!({ "type": "string", "enum": ["to_chain", "dest_account"] })
+    }
+}
+
+impl JsonSchema for ProposalStatus {
+    fn schema(_: &mut Components) -> Value {
+        json!({ "type": "string", "enum": ["pending", "applied", "rejected"] })
+    }
+}
+
+model! {
+    /// A token's total forward liquidity, in USD or in tokens depending on the denomination.
+    #[derive(Deserialize, Serialize, Clone)]
+    pub(crate) struct LiquidityForward {
+        pub(crate) contract_addr: String,
+        pub(crate) token_name: String,
+        pub(crate) token_sym: String,
+        pub(crate) decimals: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd: Option<Usd>,
+        /// The range `total_usd` could be in given the highs and lows of the candles it was priced
+        /// from
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd_min: Option<Usd>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd_max: Option<Usd>,
+        // D1 hands back numbers as f64, so a u128 here fails to deserialize
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_tokens: Option<f64>,
+        pub(crate) number_of_transfers: u32,
+    }
+}
+
+model! {
+    /// One token's share of the liquidity routed to a chain.
+    #[derive(Serialize)]
+    pub(crate) struct TokenTotal {
+        pub(crate) contract_addr: String,
+        pub(crate) token_sym: String,
+        pub(crate) decimals: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd: Option<Usd>,
+        /// The range `total_usd` could be in given the highs and lows of the candles it was priced
+        /// from
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd_min: Option<Usd>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd_max: Option<Usd>,
+        /// Whole tokens
+        pub(crate) total_tokens: f64,
+        pub(crate) number_of_transfers: u32,
+    }
+}
+
+model! {
+    /// The liquidity routed to one destination chain.
+    #[derive(Serialize)]
+    pub(crate) struct ChainLiquidity {
+        pub(crate) to_chain: u32,
+        pub(crate) chain_name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd: Option<Usd>,
+        /// The range `total_usd` could be in given the highs and lows of the candles it was priced
+        /// from
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd_min: Option<Usd>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) total_usd_max: Option<Usd>,
+        pub(crate) number_of_transfers: u32,
+        /// Only counts transfers whose destination account has been decoded
+        pub(crate) unique_recipients: u32,
+        pub(crate) tokens: Vec<TokenTotal>,
+    }
+}
+
+model! {
+    /// A token's place in the /topTokens ranking.
+    #[derive(Serialize)]
+    pub(crate) struct TokenVolume {
+        pub(crate) rank: u32,
+        pub(crate) contract_addr: String,
+        pub(crate) token_name: String,
+        pub(crate) token_sym: String,
+        pub(crate) decimals: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) category: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) logo_url: Option<String>,
+        pub(crate) total_usd: Usd,
+        /// Whole tokens
+        pub(crate) total_tokens: f64,
+        pub(crate) number_of_transfers: u32,
+    }
+}
+
+model! {
+    /// A token's cumulative totals as of one indexing run.
+    #[derive(Serialize)]
+    pub(crate) struct LiquiditySnapshot {
+        /// Unix seconds
+        pub(crate) taken_at: u64,
+        pub(crate) total_usd: Usd,
+        /// Whole tokens
+        pub(crate) total_tokens: f64,
+        pub(crate) number_of_transfers: u32,
+    }
+}
+
+model! {
+    /// A token's liquidity snapshots, oldest first.
+    #[derive(Serialize)]
+    pub(crate) struct LiquidityHistory {
+        pub(crate) contract_addr: String,
+        pub(crate) token_sym: String,
+        pub(crate) decimals: u32,
+        pub(crate) snapshots: Vec<LiquiditySnapshot>,
+    }
+}
+
+model! {
+    /// How long a token's transfers took to appear in the API after their block.
+    #[derive(Serialize)]
+    pub(crate) struct TokenFreshness {
+        pub(crate) contract_addr: String,
+        pub(crate) token_sym: String,
+        /// Transfers measured
+        pub(crate) transfers: usize,
+        pub(crate) p50_seconds: u64,
+        pub(crate) p95_seconds: u64,
+        pub(crate) p99_seconds: u64,
+        pub(crate) max_seconds: u64,
+        /// Whether the 95th percentile is within the SLA
+        pub(crate) within_sla: bool,
+    }
+}
+
+model! {
+    /// Freshness of the transfers indexed since `since`, per token.
+    #[derive(Serialize)]
+    pub(crate) struct Freshness {
+        /// How long a transfer may take to appear, in seconds
+        pub(crate) sla_seconds: u64,
+        /// Unix seconds
+        pub(crate) since: u64,
+        /// Whether every token is within the SLA
+        pub(crate) within_sla: bool,
+        pub(crate) tokens: Vec<TokenFreshness>,
+    }
+}
+
+model! {
+    /// What routing a token's transfers cost.
+    #[derive(Serialize)]
+    pub(crate) struct TokenFees {
+        pub(crate) contract_addr: String,
+        pub(crate) token_sym: String,
+        /// Transfers whose fees are known
+        pub(crate) transfers: u32,
+        /// Gas paid by their transactions, in GLMR
+        pub(crate) gas_glmr: f64,
+        pub(crate) average_gas_glmr: f64,
+        /// Fees the GMP precompile paid relayers out of them, in whole tokens
+        pub(crate) bridge_fees: f64,
+        /// The bridge fees valued at the same price as the transfers they came out of
+        pub(crate) bridge_fees_usd: Usd,
+    }
+}
+
+model! {
+    /// What routing the transfers made since `since` cost, per token.
+    #[derive(Serialize)]
+    pub(crate) struct Fees {
+        /// Unix seconds
+        pub(crate) since: u64,
+        pub(crate) transfers: u32,
+        pub(crate) gas_glmr: f64,
+        pub(crate) bridge_fees_usd: Usd,
+        pub(crate) tokens: Vec<TokenFees>,
+    }
+}
+
+model! {
+    /// A GraphQL query over the indexed data.
+    #[derive(Deserialize)]
+    pub(crate) struct GraphQlRequest {
+        pub(crate) query: String,
+        /// Values of the query's `$variables`, which must be strings, numbers or booleans
+        pub(crate) variables: Option<BTreeMap<String, Value>>,
+    }
+}
+
+model! {
+    #[derive(Serialize)]
+    pub(crate) struct GraphQlError {
+        pub(crate) message: String,
+    }
+}
+
+model! {
+    /// The answer to a GraphQL query, with a key in `data` per query root, or the `errors` that
+    /// kept it from being answered.
+    #[derive(Serialize)]
+    pub(crate) struct GraphQlResponse {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) data: Option<BTreeMap<String, Value>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) errors: Option<Vec<GraphQlError>>,
+    }
+}
+
+model! {
+    /// A stored transfer along with its token's metadata.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct TransferDetail {
+        pub(crate) tx_hash: String,
+        pub(crate) token_addr: String,
+        pub(crate) token_name: String,
+        pub(crate) token_sym: String,
+        pub(crate) decimals: u32,
+        /// The amount in the token's smallest unit, as a string so large amounts keep every digit
+        pub(crate) token_count: String,
+        pub(crate) usd: Usd,
+        /// The range `usd` could be in given the highs and lows of the candles it was priced from.
+        /// Null for transfers indexed before ranges were recorded
+        pub(crate) usd_min: Option<Usd>,
+        pub(crate) usd_max: Option<Usd>,
+        pub(crate) block_num: u64,
+        pub(crate) timestamp: u64,
+        pub(crate) timestamp_iso: String,
+        pub(crate) to_chain: u32,
+        /// The destination parachain's name, for parachains in the Chains table
+        pub(crate) to_chain_name: Option<String>,
+        /// Set when the price series used for `usd` looked stale or flat
+        #[serde(deserialize_with = "int_as_bool")]
+        pub(crate) price_uncertain: bool,
+        pub(crate) dest_account: Option<String>,
+        /// Set when the explorer's timestamp was implausible for the block and the node's was used
+        #[serde(deserialize_with = "int_as_bool")]
+        pub(crate) timestamp_corrected: bool,
+        /// Who sent the transfer on the origin chain, once its payload has been decoded
+        pub(crate) sender: Option<String>,
+        /// The substrate extrinsic that carried the transaction, once it has been looked up
+        pub(crate) extrinsic_hash: Option<String>,
+        /// What the transaction paid for gas, in wei of GLMR, once its receipt has been read
+        pub(crate) gas_fee: Option<String>,
+        /// The fee the GMP precompile paid the relayer out of the transfer, in the token's
+        /// smallest unit, for payloads that carry one
+        pub(crate) bridge_fee: Option<String>,
+    }
+}
+
+model! {
+    #[derive(Serialize)]
+    pub(crate) struct Payload {
+        /// The transaction's raw calldata
+        pub(crate) calldata: String,
+        pub(crate) decoded: Option<DecodedPayload>,
+        /// Why the active decoder couldn't decode the calldata
+        pub(crate) decode_error: Option<String>,
+    }
+}
+
+model! {
+    #[derive(Serialize)]
+    pub(crate) struct TransferResponse {
+        pub(crate) transfer: TransferDetail,
+        /// Only returned with `?include=payload`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) payload: Option<Payload>,
+    }
+}
+
+model! {
+    /// Where a transfer was routed to.
+    #[derive(Serialize)]
+    pub(crate) struct Destination {
+        pub(crate) chain_id: u32,
+        /// Null for parachains that haven't been named
+        pub(crate) chain_name: Option<String>,
+        /// The account on the destination chain, once known
+        pub(crate) account: Option<String>,
+        /// Whether the GMP payload has been decoded, which fills in the account when the explorer
+        /// didn't give it
+        pub(crate) payload_decoded: bool,
+    }
+}
+
+model! {
+    /// A stored transfer along with how it was indexed.
+    #[derive(Serialize)]
+    pub(crate) struct TransferLookup {
+        pub(crate) transfer: TransferDetail,
+        /// The USD price of one whole token that `usd` was worked out at. Null for transfers
+        /// that weren't priced, or were stored before prices were recorded
+        pub(crate) price_usd: Option<f64>,
+        pub(crate) destination: Destination,
+        /// Unix timestamp of when the transfer was stored. Null for transfers stored before this
+        /// was recorded
+        pub(crate) indexed_at: Option<u64>,
+    }
+}
+
+model! {
+    /// Returned with a 404 when a looked up transaction hasn't been indexed.
+    #[derive(Serialize)]
+    pub(crate) struct TransferNotIndexed {
+        /// Always `not_indexed`
+        pub(crate) error: &'static str,
+        pub(crate) tx_hash: String,
+        /// The highest block indexed so far. Transactions in later blocks haven't been reached yet
+        pub(crate) last_indexed_block: Option<u64>,
+        pub(crate) message: &'static str,
+    }
+}
+
+model! {
+    /// A failure the indexer recorded.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct RecordedError {
+        pub(crate) id: u32,
+        /// EtherscanFailure, RpcFailure, PriceFetchFailure, DbFailure, DbUnavailable,
+        /// DecodeFailure, InvariantViolation or ArchiveFailure
+        pub(crate) kind: String,
+        pub(crate) message: String,
+        /// What the indexer was doing at the time
+        pub(crate) context: String,
+        pub(crate) occurred_at: u64,
+    }
+}
+
+model! {
+    /// What a scheduled indexing run did.
+    #[derive(Deserialize, Serialize, Default)]
+    pub(crate) struct RunMetrics {
+        /// Unix timestamp of when the run started
+        pub(crate) started_at: u64,
+        pub(crate) duration_ms: u64,
+        /// How many blocks past the last indexed one the run's transfers reached
+        pub(crate) blocks_processed: u64,
+        pub(crate) transfers_fetched: usize,
+        pub(crate) transfers_inserted: usize,
+        /// Requests to MoonScan, or to the node when MoonScan failed
+        pub(crate) explorer_requests: u32,
+        pub(crate) explorer_ms: u64,
+        /// Requests to the price providers, counting each one tried
+        pub(crate) price_requests: u32,
+        pub(crate) price_ms: u64,
+        /// Errors recorded while the run was going
+        pub(crate) errors: u64,
+        /// Whether D1 became unavailable partway, leaving the rest to the next run
+        pub(crate) deferred: bool,
+    }
+}
+
+model! {
+    /// How far behind the chain head the indexer is, and how many rows each table holds.
+    #[derive(Serialize)]
+    pub(crate) struct Status {
+        /// The chain head as of the last run that caught up with it, or the last block a transfer
+        /// was stored from if that's later
+        pub(crate) last_processed_block: Option<u64>,
+        /// Absent when the node can't be reached
+        pub(crate) chain_head_block: Option<u64>,
+        pub(crate) lag_blocks: Option<u64>,
+        pub(crate) lag_minutes: Option<u64>,
+        pub(crate) last_run_at: Option<u64>,
+        pub(crate) last_run_duration_ms: Option<u64>,
+        pub(crate) last_run_transfers: Option<u64>,
+        pub(crate) row_counts: BTreeMap<String, u64>,
+    }
+}
+
+model! {
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct Webhook {
+        pub(crate) id: u32,
+        pub(crate) url: String,
+    }
+}
+
+model! {
+    /// A newly registered webhook.
+    #[derive(Serialize)]
+    pub(crate) struct CreatedWebhook {
+        pub(crate) id: u32,
+        pub(crate) url: String,
+        /// Signs every event sent to the webhook. Only ever returned here
+        pub(crate) secret: String,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct NewWebhook {
+        pub(crate) url: String,
+    }
+}
+
+model! {
+    /// How a webhook receiver responded to a test event.
+    #[derive(Serialize)]
+    pub(crate) struct WebhookTestReport {
+        pub(crate) webhook_id: u32,
+        pub(crate) url: String,
+        pub(crate) delivered: bool,
+        pub(crate) status: Option<u16>,
+        /// The start of the receiver's reply
+        pub(crate) response_body: Option<String>,
+        /// Why the event couldn't be sent at all
+        pub(crate) error: Option<String>,
+    }
+}
+
+model! {
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct ApiKey {
+        pub(crate) id: u32,
+        pub(crate) name: String,
+        pub(crate) tier: Tier,
+        /// Requests per UTC day, or None for the tier's default
+        pub(crate) daily_quota: Option<u64>,
+        pub(crate) created_at: u64,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct NewApiKey {
+        pub(crate) name: String,
+        pub(crate) tier: Tier,
+        /// Requests per UTC day, defaulting to the tier's quota
+        pub(crate) daily_quota: Option<u64>,
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CreatedApiKey {
+    #[serde(flatten)]
+    pub(crate) api_key: ApiKey,
+    /// Only returned here, the table stores a hash of it
+    pub(crate) key: String,
+}
+
+// Flattening can't be expressed by `model!`, so the key is added to the ApiKey schema by hand
+impl JsonSchema for CreatedApiKey {
+    fn schema(components: &mut Components) -> Value {
+        let key = Field {
+            name: "key",
+            schema: String::schema(components),
+            required: true,
+            description: Some("Only ever returned here".to_string()),
+        };
+        json!({ "allOf": [ApiKey::schema(components), object(None, vec![key])] })
+    }
+}
+
+model! {
+    /// One newly indexed transfer, decoded by both the shadow and the active decoder.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct ShadowTransfer {
+        pub(crate) tx_hash: String,
+        pub(crate) decoder: String,
+        pub(crate) active_decoder: String,
+        /// JSON of the decoded payload, or the decode error
+        pub(crate) output: String,
+        pub(crate) active_output: String,
+        #[serde(deserialize_with = "int_as_bool")]
+        pub(crate) diverged: bool,
+        pub(crate) checked_at: u64,
+    }
+}
+
+model! {
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct DivergenceRate {
+        pub(crate) decoder: String,
+        pub(crate) compared: u64,
+        pub(crate) diverged: u64,
+        #[serde(skip_deserializing)]
+        pub(crate) divergence_rate: f64,
+    }
+}
+
+model! {
+    #[derive(Serialize)]
+    pub(crate) struct ShadowReport {
+        pub(crate) shadow_decoder: Option<String>,
+        pub(crate) active_decoder: &'static str,
+        pub(crate) rates: Vec<DivergenceRate>,
+        pub(crate) recent_divergences: Vec<ShadowTransfer>,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct NewProposal {
+        pub(crate) tx_hash: String,
+        pub(crate) field: CorrectableField,
+        /// What the field should hold instead, as a string even for to_chain
+        pub(crate) value: String,
+        /// Why the stored value is wrong, such as links to the destination chain's explorer
+        pub(crate) evidence: String,
+    }
+}
+
+model! {
+    /// A correction to a stored transfer, proposed by a partner.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct Proposal {
+        pub(crate) id: u32,
+        pub(crate) tx_hash: String,
+        pub(crate) field: CorrectableField,
+        pub(crate) value: String,
+        pub(crate) evidence: String,
+        /// The id of the API key it was proposed with
+        pub(crate) submitted_by: u32,
+        pub(crate) submitted_at: u64,
+        pub(crate) status: ProposalStatus,
+        pub(crate) reviewed_at: Option<u64>,
+        /// Why it was rejected
+        pub(crate) review_note: Option<String>,
+        /// The audit log entry of the change, once applied
+        pub(crate) audit_id: Option<u32>,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct ProposalReview {
+        /// Why the proposal is rejected
+        pub(crate) note: String,
+    }
+}
+
+model! {
+    /// A change made to a stored transfer by hand.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct AuditEntry {
+        pub(crate) id: u32,
+        /// What made the change, such as apply_proposal
+        pub(crate) action: String,
+        pub(crate) tx_hash: Option<String>,
+        pub(crate) field: Option<String>,
+        pub(crate) old_value: Option<String>,
+        pub(crate) new_value: Option<String>,
+        /// The proposal the change applied, if any
+        pub(crate) proposal_id: Option<u32>,
+        pub(crate) performed_at: u64,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct ReindexRequest {
+        /// Transfers from this block onwards are deleted and indexed again
+        pub(crate) from_block: u64,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct BackfillRequest {
+        /// Inclusive, defaulting to the first block
+        pub(crate) from_block: Option<u64>,
+        /// Inclusive, defaulting to the last block
+        pub(crate) to_block: Option<u64>,
+    }
+}
+
+model! {
+    #[derive(Deserialize)]
+    pub(crate) struct ReplayRequest {
+        /// Inclusive, defaulting to the first block
+        pub(crate) from_block: Option<u64>,
+        /// Inclusive, defaulting to the last block
+        pub(crate) to_block: Option<u64>,
+    }
+}
+
+model! {
+    /// What a replay processed again.
+    #[derive(Serialize)]
+    pub(crate) struct ReplayReport {
+        /// Archived batches replayed
+        pub(crate) batches: usize,
+        /// Transfers stored from them
+        pub(crate) replayed_transfers: usize,
+        /// Where to replay from next, if the range had more batches than one request replays
+        pub(crate) next_from_block: Option<u64>,
+    }
+}
+
+model! {
+    /// Corrections to a token's metadata. Fields that are left out keep their current value.
+    #[derive(Deserialize)]
+    pub(crate) struct TokenOverride {
+        pub(crate) token_name: Option<String>,
+        pub(crate) token_sym: Option<String>,
+        pub(crate) decimals: Option<u32>,
+        /// The token's classification, such as `stablecoin` or `eth`
+        pub(crate) category: Option<String>,
+    }
+}
+
+model! {
+    /// A stored token's metadata, and whether it was corrected by hand.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct ManagedToken {
+        pub(crate) contract_addr: String,
+        pub(crate) token_name: String,
+        pub(crate) token_sym: String,
+        pub(crate) decimals: u32,
+        pub(crate) category: Option<String>,
+        pub(crate) logo_url: Option<String>,
+        /// Set while the metadata is corrected by hand, which keeps indexing and the registry
+        /// from overwriting it
+        #[serde(deserialize_with = "int_as_bool")]
+        pub(crate) overridden: bool,
+    }
+}
+
+model! {
+    /// What to name a destination parachain.
+    #[derive(Deserialize)]
+    pub(crate) struct ChainName {
+        pub(crate) chain_name: String,
+    }
+}
+
+model! {
+    /// A destination parachain, by the id transfers store in `to_chain`.
+    #[derive(Deserialize, Serialize)]
+    pub(crate) struct Chain {
+        pub(crate) chain_id: u32,
+        pub(crate) chain_name: String,
+    }
+}
+
+model! {
+    /// What an admin operation did.
+    #[derive(Serialize)]
+    pub(crate) struct OperationReport {
+        pub(crate) operation: &'static str,
+        /// Transfers deleted or marked by the operation, if it touches any
+        pub(crate) affected_transfers: Option<usize>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    model! {
+        /// A model
+        /// over two lines
+        #[allow(dead_code)]
+        struct Example {
+            /// Always there
+            required: u32,
+            maybe: Option<String>,
+            nested: Option<Payload>,
+        }
+    }
+
+    #[test]
+    fn models_are_registered_and_referenced() {
+        let mut components = Components::new();
+        let schema = Example::schema(&mut components);
+        assert_eq!(schema, json!({ "$ref": "#/components/schemas/Example" }));
+
+        let example = &components["Example"];
+        assert_eq!(example["description"], "A model over two lines");
+        assert_eq!(example["required"], json!(["required"]));
+        let properties = &example["properties"];
+        assert_eq!(properties["required"]["description"], "Always there");
+        assert_eq!(properties["maybe"]["nullable"], true);
+        // A reference can't be nullable itself
+        assert_eq!(
+            properties["nested"],
+            json!({ "allOf": [{ "$ref": "#/components/schemas/Payload" }], "nullable": true })
+        );
+        assert!(components.contains_key("Payload"));
+    }
+}
@@ -0,0 +1,162 @@
+use worker::{console_log, D1Database, Date, Env, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin,
+    decoder::{self, DecodedPayload, PayloadDecoder},
+    errors,
+    errors::IndexerError,
+    rpc,
+    schemas::{DivergenceRate, ShadowReport, ShadowTransfer},
+    TransferForward,
+};
+
+// Every shadowed transfer costs an RPC call, so only a sample of each run is compared
+const DEFAULT_SHADOW_SAMPLE: usize = 25;
+const MAX_LISTED_DIVERGENCES: u32 = 20;
+
+/// The decoder named by the SHADOW_DECODER var, as long as it's registered and isn't the active
+/// one.
+fn shadow_decoder(env: &Env) -> Option<Box<dyn PayloadDecoder>> {
+    let version = env.var("SHADOW_DECODER").ok()?.to_string();
+    if version == decoder::ACTIVE_DECODER {
+        return None;
+    }
+    decoder::decoder(&version)
+}
+
+fn describe(decoded: &std::result::Result<DecodedPayload, IndexerError>) -> String {
+    match decoded {
+        Ok(d) => serde_json::to_string(d).unwrap_or_default(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+/// Outputs match when both decoders fail, or both succeed with the same payload. The version
+/// stamped on each payload is expected to differ, so it's left out.
+fn diverges(
+    active: &std::result::Result<DecodedPayload, IndexerError>,
+    shadow: &std::result::Result<DecodedPayload, IndexerError>,
+) -> bool {
+    match (active, shadow) {
+        (Ok(a), Ok(s)) => {
+            let mut s = s.clone();
+            s.decoder = a.decoder;
+            *a != s
+        }
+        (Err(_), Err(_)) => false,
+        _ => true,
+    }
+}
+
+/// Runs the shadow decoder next to the active one over a sample of newly indexed transfers and
+/// stores both outputs in ShadowTransfers. Does nothing unless SHADOW_DECODER is set.
+pub(crate) async fn compare(env: &Env, db: &D1Database, transfers: &[TransferForward]) {
+    let Some(shadow) = shadow_decoder(env) else {
+        return
+    };
+    let active = decoder::active_decoder();
+    let sample = env
+        .var("SHADOW_SAMPLE")
+        .ok()
+        .and_then(|s| s.to_string().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_SHADOW_SAMPLE);
+
+    let rpc = rpc::RpcClient::from_env(env);
+    let now = Date::now().as_millis() / 1000;
+    let mut diverged_count = 0;
+    for transfer in transfers.iter().take(sample) {
+        let calldata = match rpc.transaction_input(&transfer.tx_hash).await {
+            Ok(Some(c)) => c,
+            Ok(None) => continue,
+            Err(e) => {
+                errors::record(
+                    db,
+                    IndexerError::RpcFailure(e.to_string()),
+                    "Fetching calldata for shadow decoding",
+                )
+                .await;
+                return;
+            }
+        };
+        let active_decoded = active.decode(&calldata);
+        let shadow_decoded = shadow.decode(&calldata);
+        let diverged = diverges(&active_decoded, &shadow_decoded);
+        if diverged {
+            diverged_count += 1;
+        }
+
+        let statement = worker::query!(
+            db,
+            "INSERT OR REPLACE INTO ShadowTransfers
+            (tx_hash, decoder, active_decoder, output, active_output, diverged, checked_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &transfer.tx_hash,
+            shadow.version(),
+            active.version(),
+            describe(&shadow_decoded),
+            describe(&active_decoded),
+            diverged as u8,
+            now
+        );
+        let stored = match statement {
+            Ok(s) => s.run().await.map(|r| r.success()).unwrap_or(false),
+            Err(_) => false,
+        };
+        if !stored {
+            errors::record(
+                db,
+                IndexerError::DbFailure(format!("shadow output for {}", transfer.tx_hash)),
+                "Storing shadow decoder output",
+            )
+            .await;
+        }
+    }
+    console_log!(
+        "Shadow decoder {} diverged on {} of {} sampled transfers",
+        shadow.version(),
+        diverged_count,
+        transfers.len().min(sample)
+    );
+}
+
+/// GET /admin/shadow reports how often each shadow decoder has disagreed with the active one,
+/// along with the most recent disagreements.
+pub(crate) async fn report(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let rates = d1
+        .prepare(
+            "
+            SELECT decoder, COUNT(*) AS compared, SUM(diverged) AS diverged
+            FROM ShadowTransfers
+            GROUP BY decoder
+        ",
+        )
+        .all()
+        .await?;
+    if !rates.success() {
+        return Response::error(rates.error().unwrap_or("No error given".to_string()), 500);
+    }
+    let mut rates = rates.results::<DivergenceRate>()?;
+    for rate in rates.iter_mut() {
+        rate.divergence_rate = rate.diverged as f64 / rate.compared.max(1) as f64;
+    }
+
+    let statement = worker::query!(
+        &d1,
+        "SELECT * FROM ShadowTransfers WHERE diverged = 1 ORDER BY checked_at DESC LIMIT ?1",
+        MAX_LISTED_DIVERGENCES
+    )?;
+    let recent_divergences = statement.all().await?.results::<ShadowTransfer>()?;
+
+    let report = ShadowReport {
+        shadow_decoder: shadow_decoder(&ctx.env).map(|d| d.version().to_string()),
+        active_decoder: decoder::ACTIVE_DECODER,
+        rates,
+        recent_divergences,
+    };
+    Response::from_json(&report)
+}
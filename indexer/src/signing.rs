@@ -0,0 +1,53 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use worker::{Env, Response, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub(crate) const SIGNATURE_HEADER: &str = "X-MRL-Signature";
+const SIGNING_KEY_SECRET: &str = "RESPONSE_SIGNING_KEY";
+
+/// Signing is only enabled for deployments that set the RESPONSE_SIGNING_KEY secret.
+pub(crate) fn signing_key(env: &Env) -> Option<String> {
+    env.secret(SIGNING_KEY_SECRET).ok().map(|k| k.to_string())
+}
+
+pub(crate) async fn sign_if_configured(key: &Option<String>, res: Response) -> Result<Response> {
+    match key {
+        Some(key) => sign_response(key, res).await,
+        None => Ok(res),
+    }
+}
+
+/// Adds an HMAC-SHA256 over the canonicalized body of a JSON response. Anything else is passed
+/// through untouched.
+pub(crate) async fn sign_response(key: &str, mut res: Response) -> Result<Response> {
+    let is_json = res
+        .headers()
+        .get("Content-Type")?
+        .map(|c| c.starts_with("application/json"))
+        .unwrap_or(false);
+    if !is_json {
+        return Ok(res);
+    }
+
+    // Canonical form is the compact serialization with object keys sorted, so the body we send
+    // is byte-for-byte what was signed
+    let body = res.text().await?;
+    let value: serde_json::Value = serde_json::from_str(&body)?;
+    let canonical = serde_json::to_string(&value)?;
+    let signature = sign(key.as_bytes(), canonical.as_bytes())?;
+
+    let mut headers = res.headers().clone();
+    headers.set(SIGNATURE_HEADER, &format!("sha256={signature}"))?;
+    Ok(Response::ok(canonical)?
+        .with_status(res.status_code())
+        .with_headers(headers))
+}
+
+pub(crate) fn sign(key: &[u8], message: &[u8]) -> Result<String> {
+    let mut mac = HmacSha256::new_from_slice(key)
+        .map_err(|e| worker::Error::RustError(e.to_string()))?;
+    mac.update(message);
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
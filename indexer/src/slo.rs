@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use worker::{D1Database, Date, Env, Request, Response, Result, RouteContext};
+
+use crate::{
+    alerts, d1, errors,
+    schemas::{Freshness, TokenFreshness},
+};
+
+// How long a transfer can take to show up in the API, unless FRESHNESS_SLA_SECONDS says otherwise
+const DEFAULT_SLA_SECONDS: u64 = 900;
+// How far back freshness is measured when no `since` is given
+const DEFAULT_LOOKBACK_SECONDS: u64 = 7 * 24 * 60 * 60;
+// The most recently indexed transfers percentiles are taken over
+const MAX_MEASURED_TRANSFERS: u32 = 50_000;
+// IndexerState key of the tokens last alerted on for breaching the SLA, as JSON
+const BREACHED_KEY: &str = "slo_breached";
+
+#[derive(Deserialize)]
+struct Lag {
+    token_addr: String,
+    token_sym: String,
+    lag_seconds: u64,
+}
+
+/// The SLA on how long a transfer takes from its block to the API, in seconds.
+fn sla_seconds(env: &Env) -> u64 {
+    env.var("FRESHNESS_SLA_SECONDS")
+        .ok()
+        .and_then(|t| t.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SLA_SECONDS)
+}
+
+/// The nearest-rank percentile `p` (0 to 100) of `sorted`, which must be sorted and not empty.
+fn percentile(sorted: &[u64], p: u64) -> u64 {
+    let rank = (sorted.len() as u64 * p).div_ceil(100).max(1);
+    sorted[rank as usize - 1]
+}
+
+/// Each token's freshness percentiles, given lags ordered by token.
+fn by_token(lags: Vec<Lag>, sla_seconds: u64) -> Vec<TokenFreshness> {
+    let mut tokens: Vec<(Lag, Vec<u64>)> = vec![];
+    for lag in lags {
+        match tokens.last_mut() {
+            Some((first, seconds)) if first.token_addr == lag.token_addr => {
+                seconds.push(lag.lag_seconds)
+            }
+            _ => {
+                let seconds = vec![lag.lag_seconds];
+                tokens.push((lag, seconds));
+            }
+        }
+    }
+    tokens
+        .into_iter()
+        .map(|(token, mut seconds)| {
+            seconds.sort_unstable();
+            let p95 = percentile(&seconds, 95);
+            TokenFreshness {
+                contract_addr: token.token_addr,
+                token_sym: token.token_sym,
+                transfers: seconds.len(),
+                p50_seconds: percentile(&seconds, 50),
+                p95_seconds: p95,
+                p99_seconds: percentile(&seconds, 99),
+                max_seconds: seconds[seconds.len() - 1],
+                within_sla: p95 <= sla_seconds,
+            }
+        })
+        .collect()
+}
+
+/// How long the transfers indexed since `since` took to be stored after their block, per token.
+async fn measure(d1: &D1Database, since: u64, sla_seconds: u64) -> Result<Vec<TokenFreshness>> {
+    // Transfers indexed before indexed_at was recorded, or without a timestamp, can't be measured
+    let statement = worker::query!(
+        d1,
+        "
+        SELECT token_addr, token_sym, lag_seconds FROM (
+            SELECT
+                tf.token_addr,
+                t.token_sym,
+                MAX(tf.indexed_at - tf.timestamp, 0) AS lag_seconds
+            FROM TransfersForward AS tf
+            INNER JOIN Token AS t ON t.contract_addr = tf.token_addr
+            WHERE tf.indexed_at >= ?1 AND tf.timestamp > 0
+            ORDER BY tf.indexed_at DESC
+            LIMIT ?2
+        )
+        ORDER BY token_addr
+        ",
+        since,
+        MAX_MEASURED_TRANSFERS
+    )?;
+    let result = statement.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+    Ok(by_token(result.results::<Lag>()?, sla_seconds))
+}
+
+/// GET /slo?since=TIMESTAMP reports, per token, how long transfers indexed since then took to
+/// appear in the API after their block, against the freshness SLA. Defaults to the last 7 days.
+pub(crate) async fn get(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut since = (Date::now().as_millis() / 1000).saturating_sub(DEFAULT_LOOKBACK_SECONDS);
+    for (k, v) in req.url()?.query_pairs() {
+        if k != "since" {
+            return Response::error("Unexpected query parameter", 400);
+        }
+        let Ok(s) = v.parse::<u64>() else {
+            return Response::error("since must be a unix timestamp", 400);
+        };
+        since = s;
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let sla_seconds = sla_seconds(&ctx.env);
+    let tokens = match measure(&d1, since, sla_seconds).await {
+        Ok(t) => t,
+        Err(e) => return Response::error(e.to_string(), 500),
+    };
+    let freshness = Freshness {
+        sla_seconds,
+        since,
+        within_sla: tokens.iter().all(|t| t.within_sla),
+        tokens,
+    };
+    Response::from_json(&freshness)
+}
+
+/// Alerts on tokens whose 95th percentile freshness over the last 7 days has gone past the SLA,
+/// and on those back within it. Each token is only alerted on when that changes.
+pub(crate) async fn check(env: &Env, db: &D1Database) {
+    let since = (Date::now().as_millis() / 1000).saturating_sub(DEFAULT_LOOKBACK_SECONDS);
+    let sla_seconds = sla_seconds(env);
+    let tokens = match measure(db, since, sla_seconds).await {
+        Ok(t) => t,
+        Err(e) => {
+            let e = d1::db_error(e.to_string());
+            errors::record(db, e, "Measuring freshness").await;
+            return;
+        }
+    };
+    // Symbols by address, since symbols aren't unique
+    let breached: BTreeMap<String, String> = tokens
+        .iter()
+        .filter(|t| !t.within_sla)
+        .map(|t| (t.contract_addr.clone(), t.token_sym.clone()))
+        .collect();
+
+    let previous = match db
+        .prepare("SELECT value FROM IndexerState WHERE key = ?1")
+        .bind(&[BREACHED_KEY.into()])
+    {
+        Ok(statement) => statement.first::<String>(Some("value")).await,
+        Err(e) => Err(e),
+    };
+    let previous: BTreeMap<String, String> = match previous {
+        Ok(Some(v)) => serde_json::from_str(&v).unwrap_or_default(),
+        _ => BTreeMap::new(),
+    };
+    if breached == previous {
+        return;
+    }
+
+    let newly = tokens
+        .iter()
+        .filter(|t| !t.within_sla && !previous.contains_key(&t.contract_addr));
+    for token in newly {
+        alerts::send_alert(
+            env,
+            &format!(
+                "{} freshness is past the {sla_seconds}s SLA: p95 {}s over {} transfers.",
+                token.token_sym, token.p95_seconds, token.transfers
+            ),
+        )
+        .await;
+    }
+    let recovered: Vec<&String> = previous
+        .iter()
+        .filter(|(addr, _)| !breached.contains_key(*addr))
+        .map(|(_, symbol)| symbol)
+        .collect();
+    if !recovered.is_empty() {
+        let message = format!("{recovered:?} freshness is back within the SLA.");
+        alerts::send_alert(env, &message).await;
+    }
+
+    let Ok(value) = serde_json::to_string(&breached) else {
+        return
+    };
+    let saved = match db
+        .prepare("INSERT OR REPLACE INTO IndexerState (key, value) VALUES (?1, ?2)")
+        .bind(&[BREACHED_KEY.into(), value.into()])
+    {
+        Ok(statement) => statement.run().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = saved {
+        let e = d1::db_error(e.to_string());
+        errors::record(db, e, "Saving freshness breaches").await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lag(token_addr: &str, lag_seconds: u64) -> Lag {
+        Lag {
+            token_addr: token_addr.to_string(),
+            token_sym: token_addr.to_uppercase(),
+            lag_seconds,
+        }
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let seconds: Vec<u64> = (1..=20).collect();
+        assert_eq!(percentile(&seconds, 50), 10);
+        assert_eq!(percentile(&seconds, 95), 19);
+        assert_eq!(percentile(&seconds, 99), 20);
+        assert_eq!(percentile(&[42], 0), 42);
+        assert_eq!(percentile(&[42], 95), 42);
+    }
+
+    #[test]
+    fn each_token_is_measured_against_the_sla() {
+        let mut lags: Vec<Lag> = (1..=20).map(|s| lag("weth", s * 60)).collect();
+        lags.extend([lag("wbtc", 30), lag("wbtc", 10)]);
+        let tokens = by_token(lags, 900);
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].token_sym, "WETH");
+        assert_eq!(tokens[0].transfers, 20);
+        assert_eq!(tokens[0].p50_seconds, 600);
+        assert_eq!(tokens[0].p95_seconds, 1140);
+        assert_eq!(tokens[0].max_seconds, 1200);
+        assert!(!tokens[0].within_sla);
+        assert_eq!(tokens[1].p50_seconds, 10);
+        assert!(tokens[1].within_sla);
+    }
+}
@@ -0,0 +1,190 @@
+use serde::Deserialize;
+use worker::{wasm_bindgen::JsValue, D1Database, Request, Response, Result, RouteContext};
+
+use crate::{
+    batch_with_retry, d1, errors, numeric,
+    schemas::{LiquidityHistory, LiquiditySnapshot},
+    usd::Usd,
+    Token,
+};
+
+// Longer ranges are downsampled to this many snapshots, enough for any chart
+const MAX_SNAPSHOTS: u64 = 1000;
+
+// Snapshots older than this are thinned to the last of each day by maintenance runs
+const FULL_DETAIL_SECONDS: u64 = 30 * SECONDS_PER_DAY;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Deserialize)]
+struct SnapshotRange {
+    first: Option<u64>,
+    last: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotRow {
+    taken_at: u64,
+    total_usd_cents: Usd,
+    // D1 hands back numbers as f64
+    total_tokens: f64,
+    number_of_transfers: u32,
+}
+
+/// Writes every token's cumulative totals as of `taken_at` (unix seconds) to the
+/// LiquiditySnapshots table, so that growth can be charted after the fact.
+pub(crate) async fn record(db: &D1Database, taken_at: u64) {
+    let statement = format!(
+        "
+        INSERT OR REPLACE INTO LiquiditySnapshots
+            (token_addr, taken_at, total_tokens, total_usd_cents, number_of_transfers)
+        SELECT token_addr, {taken_at}, SUM(token_count), SUM(usd_cents), COUNT(tx_hash)
+        FROM TransfersForward
+        GROUP BY token_addr
+        "
+    );
+    if let Err(e) = batch_with_retry(db, "Liquidity snapshot", &[statement]).await {
+        let e = d1::db_error(e.to_string());
+        errors::record(db, e, "Recording liquidity snapshots").await;
+    }
+}
+
+/// Keeps only the last snapshot of each day of those older than 30 days. A snapshot is taken every
+/// run, so the table would otherwise grow by a row per token every five minutes for good.
+pub(crate) async fn thin(db: &D1Database, now: u64) {
+    let before = now.saturating_sub(FULL_DETAIL_SECONDS);
+    let statement = format!(
+        "
+        DELETE FROM LiquiditySnapshots
+        WHERE taken_at < {before} AND (token_addr, taken_at) NOT IN (
+            SELECT token_addr, MAX(taken_at)
+            FROM LiquiditySnapshots
+            WHERE taken_at < {before}
+            GROUP BY token_addr, taken_at / {SECONDS_PER_DAY}
+        )
+        "
+    );
+    if let Err(e) = batch_with_retry(db, "Thinning liquidity snapshots", &[statement]).await {
+        let e = d1::db_error(e.to_string());
+        errors::record(db, e, "Thinning liquidity snapshots").await;
+    }
+}
+
+/// How many seconds each returned snapshot stands for, so that a range from `first` to `last`
+/// comes to at most MAX_SNAPSHOTS of them.
+fn bucket_seconds(first: u64, last: u64) -> u64 {
+    (last - first) / MAX_SNAPSHOTS + 1
+}
+
+/// GET /liquidityHistory?token=&from=&to= returns a token's snapshots, oldest first, to chart its
+/// cumulative liquidity over time. Long ranges are downsampled to the last snapshot of each of at
+/// most 1000 even intervals, so the newest is always included.
+pub(crate) async fn history(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut token = None;
+    let mut from = None;
+    let mut to = None;
+    for (k, v) in req.url()?.query_pairs() {
+        match k.as_ref() {
+            "token" => token = Some(v.to_string()),
+            "from" | "to" => {
+                let Ok(t) = v.parse::<u64>() else {
+                    return Response::error("from and to must be unix timestamps", 400)
+                };
+                if k == "from" {
+                    from = Some(t);
+                } else {
+                    to = Some(t);
+                }
+            }
+            _ => return Response::error("Unexpected query parameter", 400),
+        }
+    }
+    let Some(token) = token else {
+        return Response::error("token is required", 400)
+    };
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "SELECT * FROM Token WHERE LOWER(contract_addr) = LOWER(?1) OR token_sym = ?1 LIMIT 1",
+        &token
+    )?;
+    let Some(token) = statement.first::<Token>(None).await? else {
+        return Response::error("Unknown token", 404)
+    };
+
+    let mut bindings: Vec<JsValue> = vec![token.contract_addr.clone().into()];
+    let mut conditions = vec!["token_addr = ?1".to_string()];
+    if let Some(from) = from {
+        bindings.push((from as f64).into());
+        conditions.push(format!("taken_at >= ?{}", bindings.len()));
+    }
+    if let Some(to) = to {
+        bindings.push((to as f64).into());
+        conditions.push(format!("taken_at <= ?{}", bindings.len()));
+    }
+    let conditions = conditions.join(" AND ");
+    let range = format!(
+        "SELECT MIN(taken_at) AS first, MAX(taken_at) AS last \
+         FROM LiquiditySnapshots WHERE {conditions}"
+    );
+    let range = d1
+        .prepare(range)
+        .bind(&bindings)?
+        .first::<SnapshotRange>(None)
+        .await?;
+    let bucket = match range {
+        Some(SnapshotRange {
+            first: Some(first),
+            last: Some(last),
+        }) => bucket_seconds(first, last),
+        _ => 1,
+    };
+    // SQLite takes the other columns from the row with the MAX
+    let query = format!(
+        "
+        SELECT MAX(taken_at) AS taken_at, total_usd_cents, total_tokens, number_of_transfers
+        FROM LiquiditySnapshots
+        WHERE {conditions}
+        GROUP BY taken_at / {bucket}
+        ORDER BY taken_at
+        "
+    );
+    let result = d1.prepare(query).bind(&bindings)?.all().await?;
+    if !result.success() {
+        return Response::error(result.error().unwrap_or("No error given".to_string()), 500);
+    }
+
+    let snapshots = result
+        .results::<SnapshotRow>()?
+        .into_iter()
+        .map(|row| LiquiditySnapshot {
+            taken_at: row.taken_at,
+            total_usd: row.total_usd_cents,
+            total_tokens: numeric::normalize(row.total_tokens, token.decimals),
+            number_of_transfers: row.number_of_transfers,
+        })
+        .collect();
+    let history = LiquidityHistory {
+        contract_addr: token.contract_addr,
+        token_sym: token.token_sym,
+        decimals: token.decimals,
+        snapshots,
+    };
+    Response::from_json(&history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_ranges_are_downsampled() {
+        assert_eq!(bucket_seconds(1000, 1000), 1);
+        assert_eq!(bucket_seconds(0, MAX_SNAPSHOTS - 1), 1);
+        // A year of five minute snapshots comes to at most MAX_SNAPSHOTS buckets
+        let year = 365 * SECONDS_PER_DAY;
+        let bucket = bucket_seconds(0, year);
+        assert!(year / bucket < MAX_SNAPSHOTS);
+        assert!(bucket > 5 * 60);
+    }
+}
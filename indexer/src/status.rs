@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use worker::{console_error, D1Database, Request, Response, Result, RouteContext};
+
+use crate::{rpc, schemas::Status};
+
+// Moonbeam targets 12 second blocks
+const BLOCK_TIME_SECONDS: u64 = 12;
+// IndexerState key of the chain head as of the last run that caught up with it
+pub(crate) const SCANNED_THROUGH_KEY: &str = "scanned_through_block";
+const TABLES: [&str; 16] = [
+    "Token",
+    "TransfersForward",
+    "Chains",
+    "SentAlerts",
+    "Webhooks",
+    "IndexerState",
+    "IndexerRuns",
+    "IndexerErrors",
+    "ApiKeys",
+    "ApiKeyUsage",
+    "ShadowTransfers",
+    "LiquiditySnapshots",
+    "CorrectionProposals",
+    "AuditLog",
+    "ArchivedBatches",
+    "InsertChunks",
+];
+
+#[derive(Deserialize)]
+struct LastRun {
+    started_at: u64,
+    duration_ms: u64,
+    transfers: u64,
+}
+
+#[derive(Deserialize)]
+struct RowCount {
+    count: u64,
+}
+
+/// Records that every block up to `block` has been scanned for transfers, whether or not any of
+/// them held one.
+pub(crate) async fn record_scanned(db: &D1Database, block: u64) {
+    let statement = db
+        .prepare("INSERT OR REPLACE INTO IndexerState (key, value) VALUES (?1, ?2)")
+        .bind(&[SCANNED_THROUGH_KEY.into(), block.to_string().into()]);
+    let recorded = match statement {
+        Ok(s) => s.run().await.map(|r| r.success()).unwrap_or(false),
+        Err(_) => false,
+    };
+    if !recorded {
+        console_error!("Error recording the scanned block {}", block);
+    }
+}
+
+/// GET /status reports how far behind the chain head the indexer is, when it last ran, and how
+/// many rows each table holds.
+pub(crate) async fn get(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let d1 = ctx.env.d1("DB")?;
+
+    // Transfers are sparse, so the last one stored can be far behind the last block scanned
+    let last_stored_block = d1
+        .prepare("SELECT MAX(block_num) AS most_recent_block FROM TransfersForward")
+        .first::<u64>(Some("most_recent_block"))
+        .await?;
+    let scanned_through = d1
+        .prepare("SELECT value FROM IndexerState WHERE key = ?1")
+        .bind(&[SCANNED_THROUGH_KEY.into()])?
+        .first::<String>(Some("value"))
+        .await?
+        .and_then(|v| v.parse::<u64>().ok());
+    let last_processed_block = last_stored_block.max(scanned_through);
+    let last_run = d1
+        .prepare("SELECT * FROM IndexerRuns ORDER BY started_at DESC LIMIT 1")
+        .first::<LastRun>(None)
+        .await?;
+
+    // The head is informational, so an unreachable node shouldn't fail the whole response
+    let chain_head_block = rpc::RpcClient::from_env(&ctx.env)
+        .block_number()
+        .await
+        .ok();
+    let lag_blocks = match (chain_head_block, last_processed_block) {
+        (Some(head), Some(last)) => Some(head.saturating_sub(last)),
+        _ => None,
+    };
+
+    let statements = TABLES
+        .iter()
+        .map(|t| d1.prepare(format!("SELECT COUNT(*) AS count FROM {t}")))
+        .collect();
+    let mut row_counts = BTreeMap::new();
+    for (table, result) in TABLES.iter().zip(d1.batch(statements).await?) {
+        let count = result
+            .results::<RowCount>()?
+            .first()
+            .map(|c| c.count)
+            .unwrap_or(0);
+        row_counts.insert(table.to_string(), count);
+    }
+
+    let status = Status {
+        last_processed_block,
+        chain_head_block,
+        lag_blocks,
+        lag_minutes: lag_blocks.map(|b| b * BLOCK_TIME_SECONDS / 60),
+        last_run_at: last_run.as_ref().map(|r| r.started_at),
+        last_run_duration_ms: last_run.as_ref().map(|r| r.duration_ms),
+        last_run_transfers: last_run.as_ref().map(|r| r.transfers),
+        row_counts,
+    };
+    Response::from_json(&status)
+}
@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use worker::{Env, Result};
+
+const DEFAULT_SUBSCAN_URL: &str = "https://moonbeam.api.subscan.io";
+// Subscan answers lookups for hashes it hasn't seen with this code rather than a 404
+const RECORD_NOT_FOUND: i64 = 10004;
+
+#[derive(Deserialize)]
+struct SubscanResponse {
+    code: i64,
+    message: String,
+    data: Option<Extrinsic>,
+}
+
+#[derive(Deserialize)]
+struct Extrinsic {
+    #[serde(default)]
+    event: Vec<ExtrinsicEvent>,
+}
+
+#[derive(Deserialize)]
+struct ExtrinsicEvent {
+    module_id: String,
+    event_id: String,
+    // Either a list of params or that list encoded as a JSON string
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct EventParam {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    type_name: String,
+    value: Value,
+}
+
+/// Looks up Moonbeam extrinsics on Subscan, to map a substrate extrinsic hash to the Ethereum
+/// transaction it executed.
+pub(crate) struct SubscanClient {
+    url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl SubscanClient {
+    /// Connects with the SUBSCAN_API_KEY secret to SUBSCAN_URL, or the public Moonbeam endpoint if
+    /// it isn't set. Returns None without a key, since Subscan rejects anonymous requests.
+    pub(crate) fn from_env(env: &Env) -> Option<Self> {
+        let api_key = env.secret("SUBSCAN_API_KEY").ok()?.to_string();
+        let url = env
+            .var("SUBSCAN_URL")
+            .map(|u| u.to_string())
+            .unwrap_or(DEFAULT_SUBSCAN_URL.to_string());
+        Some(Self {
+            url,
+            api_key,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// The hash of the Ethereum transaction the extrinsic executed, or None if Subscan doesn't
+    /// know the extrinsic or it didn't execute one.
+    pub(crate) async fn ethereum_hash(&self, extrinsic_hash: &str) -> Result<Option<String>> {
+        let response = self
+            .client
+            .post(format!("{}/api/scan/extrinsic", self.url))
+            .header("X-API-Key", &self.api_key)
+            .json(&json!({ "hash": extrinsic_hash }))
+            .send()
+            .await
+            .map_err(|e| worker::Error::JsError(e.to_string()))?
+            .json::<SubscanResponse>()
+            .await
+            .map_err(|e| worker::Error::JsError(e.to_string()))?;
+
+        match response.code {
+            0 => Ok(response.data.and_then(executed_transaction)),
+            RECORD_NOT_FOUND => Ok(None),
+            code => Err(worker::Error::JsError(format!(
+                "Error: Subscan returned {code}: {}",
+                response.message
+            ))),
+        }
+    }
+}
+
+/// The transaction hash from the extrinsic's `ethereum.Executed` event.
+fn executed_transaction(extrinsic: Extrinsic) -> Option<String> {
+    let event = extrinsic
+        .event
+        .into_iter()
+        .find(|e| e.module_id == "ethereum" && e.event_id == "Executed")?;
+    let params: Vec<EventParam> = match event.params {
+        Value::String(s) => serde_json::from_str(&s).ok()?,
+        v => serde_json::from_value(v).ok()?,
+    };
+    let param = params
+        .into_iter()
+        .find(|p| p.name == "transaction_hash" || p.type_name == "H256")?;
+    let hash = param.value.as_str()?.to_lowercase();
+    Some(if hash.starts_with("0x") {
+        hash
+    } else {
+        format!("0x{hash}")
+    })
+}
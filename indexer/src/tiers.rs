@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use worker::{D1Database, Date, Env, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin,
+    quotas::{self, Usage},
+    schemas::{ApiKey, CreatedApiKey, NewApiKey},
+};
+
+pub(crate) const API_KEY_HEADER: &str = "X-API-Key";
+
+// Routes that are too expensive to leave open, matched as path prefixes. Everything else is public.
+pub(crate) const PARTNER_ROUTES: [&str; 3] = ["/transfers/export", "/graphql", "/proposals"];
+
+/// Access tiers, ordered so that a higher tier can use everything a lower one can.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Tier {
+    Public,
+    Partner,
+}
+
+/// The outcome of checking a request's API key.
+pub(crate) enum Access {
+    /// Let the request through. Keyed requests carry the key's usage for the rate limit headers.
+    Granted(Option<Usage>),
+    /// Send this response instead
+    Denied(Response),
+}
+
+pub(crate) fn required_tier(path: &str) -> Tier {
+    let partner = PARTNER_ROUTES
+        .iter()
+        .any(|r| path == *r || path.starts_with(&format!("{r}/")));
+    if partner {
+        Tier::Partner
+    } else {
+        Tier::Public
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Checks the request's API key against the tier its route needs and the key's daily quota.
+/// Requests without a key can only reach public routes, and aren't counted.
+pub(crate) async fn check(req: &Request, env: &Env) -> Result<Access> {
+    let required = required_tier(&req.path());
+
+    let Some(key) = req.headers().get(API_KEY_HEADER)? else {
+        if required == Tier::Public {
+            return Ok(Access::Granted(None));
+        }
+        let msg = format!("This endpoint needs a {API_KEY_HEADER} header with a partner key");
+        return Ok(Access::Denied(Response::error(msg, 401)?));
+    };
+    let d1 = env.d1("DB")?;
+    let Some(api_key) = find_key(&d1, &key).await? else {
+        return Ok(Access::Denied(Response::error("Unknown API key", 401)?));
+    };
+    if api_key.tier < required {
+        return Ok(Access::Denied(Response::error(
+            "This endpoint needs a partner key",
+            403,
+        )?));
+    }
+
+    let limit = quotas::daily_quota(env, api_key.tier, api_key.daily_quota);
+    let usage = quotas::record_request(&d1, api_key.id, limit).await?;
+    if usage.exceeded() {
+        let notices = quotas::Notices::from_env(env);
+        let res = quotas::exceeded_response(&usage, &notices)?;
+        return Ok(Access::Denied(res));
+    }
+    Ok(Access::Granted(Some(usage)))
+}
+
+async fn find_key(d1: &D1Database, key: &str) -> Result<Option<ApiKey>> {
+    let statement = worker::query!(
+        d1,
+        "SELECT * FROM ApiKeys WHERE key_hash = ?1",
+        hash_key(key)
+    )?;
+    statement.first::<ApiKey>(None).await
+}
+
+/// The API key a request was made with, if it has a known one.
+pub(crate) async fn caller(req: &Request, d1: &D1Database) -> Result<Option<ApiKey>> {
+    match req.headers().get(API_KEY_HEADER)? {
+        Some(key) => find_key(d1, &key).await,
+        None => Ok(None),
+    }
+}
+
+/// POST /admin/keys with `{ "name": ..., "tier": "public" | "partner", "daily_quota": ... }`
+/// issues an API key. `daily_quota` is optional. The key itself is only ever shown in this
+/// response.
+pub(crate) async fn create(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(new_key) = req.json::<NewApiKey>().await else {
+        return Response::error("Expected a JSON body with name and tier", 400);
+    };
+
+    let mut random = [0_u8; 32];
+    if getrandom::getrandom(&mut random).is_err() {
+        return Response::error("Error generating API key", 500);
+    }
+    let key = format!("mrl_{}", hex::encode(random));
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "INSERT INTO ApiKeys (key_hash, name, tier, daily_quota, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        RETURNING id, name, tier, daily_quota, created_at",
+        hash_key(&key),
+        &new_key.name,
+        new_key.tier,
+        new_key.daily_quota,
+        Date::now().as_millis() / 1000
+    )?;
+    match statement.first::<ApiKey>(None).await? {
+        Some(api_key) => Response::from_json(&CreatedApiKey { api_key, key }),
+        None => Response::error("Error when creating API key", 500),
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use serde::Deserialize;
+use worker::{console_log, console_warn, D1Database, Env, Result};
+
+use crate::{batch_with_retry, rpc::RpcClient, TransferForward};
+
+// Moonbeam targets 12 second blocks, but production has varied, hence the anchors
+const DEFAULT_BLOCK_TIME_SECONDS: u64 = 12;
+// Anchors are spread evenly over the indexed range, endpoints included
+const MAX_ANCHORS: u64 = 5;
+const DEFAULT_TOLERANCE_SECONDS: u64 = 600;
+
+/// Block timestamps read from the node, which a scan API timestamp is expected to lie close to.
+struct Anchors {
+    /// (block, timestamp), sorted by block
+    points: Vec<(u64, u64)>,
+    block_time: u64,
+}
+
+impl Anchors {
+    /// Interpolates between the anchors either side of `block`, or extrapolates with the average
+    /// block time beyond them.
+    fn expected(&self, block: u64) -> u64 {
+        let i = self.points.partition_point(|(b, _)| *b < block);
+        if i == 0 || i == self.points.len() {
+            let (b, t) = self.points[i.min(self.points.len() - 1)];
+            let offset = block.abs_diff(b) * self.block_time;
+            return if block >= b { t + offset } else { t.saturating_sub(offset) };
+        }
+
+        let ((b0, t0), (b1, t1)) = (self.points[i - 1], self.points[i]);
+        let elapsed = t1.saturating_sub(t0) as f64 * (block - b0) as f64 / (b1 - b0) as f64;
+        t0 + elapsed as u64
+    }
+}
+
+/// Flags transfers whose scan API timestamp isn't plausible for their block number, and replaces
+/// it with the block's timestamp from the node when the two disagree. Returns how many were
+/// corrected.
+pub(crate) async fn cross_check(env: &Env, transfers: &mut [TransferForward]) -> Result<usize> {
+    let (Some(first), Some(last)) = (
+        transfers.iter().map(|t| t.block_num).min(),
+        transfers.iter().map(|t| t.block_num).max(),
+    ) else {
+        return Ok(0);
+    };
+    let block_time = env
+        .var("BLOCK_TIME_SECONDS")
+        .ok()
+        .and_then(|b| b.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_BLOCK_TIME_SECONDS);
+    let tolerance = env
+        .var("TIMESTAMP_TOLERANCE_SECONDS")
+        .ok()
+        .and_then(|t| t.to_string().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_TOLERANCE_SECONDS);
+
+    let rpc = RpcClient::from_env(env);
+    let mut block_timestamps: HashMap<u64, u64> = HashMap::new();
+    let step = ((last - first) / (MAX_ANCHORS - 1)).max(1);
+    let mut points = vec![];
+    for block in (first..=last).step_by(step as usize).chain([last]) {
+        if let Entry::Vacant(e) = block_timestamps.entry(block) {
+            let timestamp = rpc.block_timestamp(block).await?;
+            e.insert(timestamp);
+            points.push((block, timestamp));
+        }
+    }
+    let anchors = Anchors { points, block_time };
+
+    let mut corrected = 0;
+    for transfer in transfers.iter_mut() {
+        let expected = anchors.expected(transfer.block_num);
+        if transfer.timestamp.abs_diff(expected) <= tolerance {
+            continue;
+        }
+
+        let actual = match block_timestamps.entry(transfer.block_num) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => *e.insert(rpc.block_timestamp(transfer.block_num).await?),
+        };
+        if transfer.timestamp != actual {
+            console_warn!(
+                "Timestamp {} of {} is implausible for block {}, using {} from the node",
+                transfer.timestamp,
+                transfer.tx_hash,
+                transfer.block_num,
+                actual
+            );
+            transfer.timestamp = actual;
+            transfer.timestamp_corrected = true;
+            corrected += 1;
+        }
+    }
+    Ok(corrected)
+}
+
+/// A row of `PRAGMA table_info`.
+#[derive(Deserialize)]
+pub(crate) struct ColumnInfo {
+    pub(crate) name: String,
+    #[serde(rename = "type")]
+    column_type: String,
+    #[serde(default)]
+    notnull: u32,
+    #[serde(default)]
+    dflt_value: Option<String>,
+    #[serde(default)]
+    pk: u32,
+}
+
+// Foreign keys of TransfersForward, which `PRAGMA table_info` doesn't report
+const REFERENCES: [(&str, &str); 1] = [("token_addr", "Token(contract_addr)")];
+
+/// The statements that copy TransfersForward into `table` with every column it has, timestamps
+/// converted to INTEGER unix seconds and zeroed where they don't parse.
+fn converted_copy(columns: &[ColumnInfo], table: &str) -> [String; 2] {
+    let definitions: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            let column_type = match c.name.as_str() {
+                "timestamp" => "INTEGER",
+                _ => &c.column_type,
+            };
+            let mut definition = format!("{} {column_type}", c.name);
+            if c.pk > 0 {
+                definition.push_str(" PRIMARY KEY");
+            }
+            if c.notnull > 0 {
+                definition.push_str(" NOT NULL");
+            }
+            if let Some(default) = &c.dflt_value {
+                definition.push_str(&format!(" DEFAULT {default}"));
+            }
+            if let Some((_, target)) = REFERENCES.iter().find(|(name, _)| *name == c.name) {
+                definition.push_str(&format!(" REFERENCES {target}"));
+            }
+            definition
+        })
+        .collect();
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+    let values: Vec<&str> = names
+        .iter()
+        .map(|name| match *name {
+            "timestamp" => "COALESCE(CAST(timestamp AS INTEGER), 0)",
+            _ => name,
+        })
+        .collect();
+    [
+        format!("CREATE TABLE {table} ({})", definitions.join(", ")),
+        format!(
+            "INSERT INTO {table} ({}) SELECT {} FROM TransfersForward",
+            names.join(", "),
+            values.join(", ")
+        ),
+    ]
+}
+
+/// Timestamps used to be stored as TEXT. SQLite can't change a column's type, so the first
+/// migration after the change rebuilds TransfersForward with INTEGER unix seconds, zeroing any that
+/// don't parse. Every column the table has by then is kept, including those added since.
+pub(crate) async fn convert_to_integer(db: &D1Database) -> Result<()> {
+    let columns = db
+        .prepare("PRAGMA table_info(TransfersForward)")
+        .all()
+        .await?
+        .results::<ColumnInfo>()?;
+    let is_text = columns
+        .iter()
+        .any(|c| c.name == "timestamp" && c.column_type.eq_ignore_ascii_case("TEXT"));
+    if !is_text {
+        return Ok(());
+    }
+
+    let [create, copy] = converted_copy(&columns, "TransfersForwardConverted");
+    let statements = [
+        create,
+        copy,
+        "DROP TABLE TransfersForward".to_string(),
+        "ALTER TABLE TransfersForwardConverted RENAME TO TransfersForward".to_string(),
+    ];
+    batch_with_retry(db, "Timestamp conversion", &statements).await?;
+    console_log!("Converted TransfersForward timestamps to integers");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, column_type: &str, notnull: u32, dflt_value: Option<&str>) -> ColumnInfo {
+        ColumnInfo {
+            name: name.to_string(),
+            column_type: column_type.to_string(),
+            notnull,
+            dflt_value: dflt_value.map(|d| d.to_string()),
+            pk: 0,
+        }
+    }
+
+    #[test]
+    fn conversion_keeps_every_column() {
+        let columns = [
+            ColumnInfo {
+                pk: 1,
+                ..column("tx_hash", "TEXT", 0, None)
+            },
+            column("token_addr", "TEXT", 1, None),
+            column("timestamp", "TEXT", 1, None),
+            column("timestamp_corrected", "INTEGER", 1, Some("0")),
+            // Added by later migrations, before the conversion runs
+            column("indexed_at", "INTEGER", 0, None),
+            column("gas_fee", "UNSIGNED INT", 0, None),
+            column("bridge_fee", "UNSIGNED INT", 0, None),
+        ];
+        let [create, copy] = converted_copy(&columns, "Converted");
+        assert_eq!(
+            create,
+            "CREATE TABLE Converted (tx_hash TEXT PRIMARY KEY, \
+             token_addr TEXT NOT NULL REFERENCES Token(contract_addr), \
+             timestamp INTEGER NOT NULL, timestamp_corrected INTEGER NOT NULL DEFAULT 0, \
+             indexed_at INTEGER, gas_fee UNSIGNED INT, bridge_fee UNSIGNED INT)"
+        );
+        assert_eq!(
+            copy,
+            "INSERT INTO Converted (tx_hash, token_addr, timestamp, timestamp_corrected, \
+             indexed_at, gas_fee, bridge_fee) \
+             SELECT tx_hash, token_addr, COALESCE(CAST(timestamp AS INTEGER), 0), \
+             timestamp_corrected, indexed_at, gas_fee, bridge_fee FROM TransfersForward"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use worker::{wasm_bindgen::JsValue, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin, cache, numeric, registry,
+    schemas::{ManagedToken, TokenOverride},
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+const RETURNING_TOKEN: &str =
+    "RETURNING contract_addr, token_name, token_sym, decimals, category, logo_url, overridden";
+
+fn text(value: &Option<String>) -> JsValue {
+    value.as_deref().map_or(JsValue::NULL, JsValue::from)
+}
+
+/// Why an override can't be applied, if it can't.
+fn invalid(token: &TokenOverride) -> Option<String> {
+    let names = [
+        ("token_name", &token.token_name),
+        ("token_sym", &token.token_sym),
+    ];
+    for (field, value) in names {
+        if let Some(v) = value {
+            if v.trim().is_empty() || v.len() > MAX_NAME_LENGTH {
+                return Some(format!(
+                    "{field} must be between 1 and {MAX_NAME_LENGTH} characters"
+                ));
+            }
+        }
+    }
+    if let Some(d) = token.decimals {
+        if numeric::scale(d).is_none() {
+            return Some(format!("decimals can't be more than 38, not {d}"));
+        }
+    }
+    None
+}
+
+/// PUT /admin/tokens/:addr with any of `token_name`, `token_sym`, `decimals` and `category`
+/// corrects a stored token's metadata and marks it as overridden, so that neither the registry nor
+/// explorer metadata overwrites it on later runs. Transfers that are already stored keep the USD
+/// value they were priced at until they're reindexed.
+pub(crate) async fn put(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Ok(token) = req.json::<TokenOverride>().await else {
+        let msg = "Expected a JSON body with token_name, token_sym, decimals or category";
+        return Response::error(msg, 400)
+    };
+    if let Some(msg) = invalid(&token) {
+        return Response::error(msg, 400);
+    }
+    let addr = ctx.param("addr").unwrap().to_lowercase();
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = d1
+        .prepare(format!(
+            "UPDATE Token SET
+                token_name = COALESCE(?2, token_name),
+                token_sym = COALESCE(?3, token_sym),
+                decimals = COALESCE(?4, decimals),
+                category = COALESCE(?5, category),
+                overridden = 1
+            WHERE contract_addr = ?1
+            {RETURNING_TOKEN}"
+        ))
+        .bind(&[
+            addr.into(),
+            text(&token.token_name.map(|n| n.trim().to_string())),
+            text(&token.token_sym.map(|s| s.trim().to_string())),
+            token.decimals.map_or(JsValue::NULL, |d| (d as f64).into()),
+            text(&token.category),
+        ])?;
+    let Some(updated) = statement.first::<ManagedToken>(None).await? else {
+        return Response::error("No token is stored at that address", 404)
+    };
+    cache::invalidate(&ctx.env).await;
+    Response::from_json(&updated)
+}
+
+/// DELETE /admin/tokens/:addr drops a token's override, so that indexing and the registry keep its
+/// metadata up to date again. Registry tokens get the registry's metadata back straight away,
+/// others keep their current values, which are no longer protected from being overwritten.
+pub(crate) async fn delete(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let addr = ctx.param("addr").unwrap().to_lowercase();
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = match registry::token(&addr) {
+        Some(t) => d1
+            .prepare(format!(
+                "UPDATE Token SET
+                    token_name = ?2,
+                    token_sym = ?3,
+                    decimals = ?4,
+                    category = ?5,
+                    logo_url = ?6,
+                    overridden = 0
+                WHERE contract_addr = ?1
+                {RETURNING_TOKEN}"
+            ))
+            .bind(&[
+                addr.into(),
+                t.token_name.into(),
+                t.token_sym.into(),
+                (t.decimals as f64).into(),
+                text(&t.category),
+                text(&t.logo_url),
+            ])?,
+        None => d1
+            .prepare(format!(
+                "UPDATE Token SET overridden = 0 WHERE contract_addr = ?1 {RETURNING_TOKEN}"
+            ))
+            .bind(&[addr.into()])?,
+    };
+    let Some(updated) = statement.first::<ManagedToken>(None).await? else {
+        return Response::error("No token is stored at that address", 404)
+    };
+    cache::invalidate(&ctx.env).await;
+    Response::from_json(&updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corrected(token_sym: &str, decimals: u32) -> TokenOverride {
+        TokenOverride {
+            token_name: None,
+            token_sym: Some(token_sym.to_string()),
+            decimals: Some(decimals),
+            category: None,
+        }
+    }
+
+    #[test]
+    fn overrides_need_usable_metadata() {
+        assert_eq!(invalid(&corrected("USDC", 6)), None);
+        assert_eq!(invalid(&corrected("USDC", 38)), None);
+        assert!(invalid(&corrected("USDC", 39)).is_some());
+        assert!(invalid(&corrected(" ", 6)).is_some());
+        assert!(invalid(&corrected(&"X".repeat(MAX_NAME_LENGTH + 1), 6)).is_some());
+    }
+}
@@ -0,0 +1,565 @@
+use futures_util::stream;
+use serde::Deserialize;
+use worker::{wasm_bindgen::JsValue, D1Database, Headers, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin, cache, decoder, int_as_bool,
+    pagination::{self, Page, PageParams},
+    rpc,
+    schemas::{
+        Destination, Payload, TransferDetail, TransferLookup, TransferNotIndexed, TransferResponse,
+    },
+    subscan,
+};
+
+// Rows fetched from D1 per chunk of an export
+const EXPORT_PAGE_SIZE: u32 = 500;
+// D1 binds at most 100 parameters per statement, which leaves room for the filter's
+const MAX_HASHES_PER_QUERY: usize = 90;
+const CSV_HEADER: &str = "tx_hash,token_addr,token_name,token_sym,decimals,token_count,usd,\
+                          block_num,timestamp,timestamp_iso,to_chain,price_uncertain,dest_account,\
+                          timestamp_corrected,sender,extrinsic_hash,usd_min,usd_max,gas_fee,\
+                          bridge_fee,to_chain_name\n";
+
+const SELECT_TRANSFERS: &str = "
+    SELECT 
+        tf.tx_hash,
+        tf.token_addr,
+        t.token_name,
+        t.token_sym,
+        t.decimals,
+        CAST(tf.token_count AS TEXT) AS token_count,
+        tf.usd_cents AS usd,
+        tf.usd_min_cents AS usd_min,
+        tf.usd_max_cents AS usd_max,
+        tf.block_num,
+        tf.timestamp,
+        strftime('%Y-%m-%dT%H:%M:%SZ', tf.timestamp, 'unixepoch') AS timestamp_iso,
+        tf.to_chain,
+        c.chain_name AS to_chain_name,
+        tf.price_uncertain,
+        tf.dest_account,
+        tf.timestamp_corrected,
+        tf.sender,
+        tf.extrinsic_hash,
+        CAST(tf.gas_fee AS TEXT) AS gas_fee,
+        CAST(tf.bridge_fee AS TEXT) AS bridge_fee,
+        tf.price,
+        tf.indexed_at,
+        tf.payload_checked
+    FROM TransfersForward AS tf
+    INNER JOIN Token AS t ON t.contract_addr = tf.token_addr
+    LEFT JOIN Chains AS c ON c.chain_id = tf.to_chain
+";
+
+/// GET /transfers/:hash returns a stored transfer, by either its Ethereum transaction hash or the
+/// hash of the substrate extrinsic that carried it, once that has been resolved. With
+/// `?include=payload` it also returns the transaction's raw calldata and what the active decoder
+/// makes of it.
+pub(crate) async fn get(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let hash = ctx.param("hash").unwrap().to_lowercase();
+
+    let mut include_payload = false;
+    for (k, v) in req.url()?.query_pairs() {
+        match (k.as_ref(), v.as_ref()) {
+            ("include", "payload") => include_payload = true,
+            _ => return Response::error("Unexpected query parameter", 400),
+        }
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    let Some(transfer) = find(&d1, &hash).await? else {
+        return Response::error("Transfer not found", 404);
+    };
+
+    let payload = if include_payload {
+        let rpc = rpc::RpcClient::from_env(&ctx.env);
+        let Some(calldata) = rpc.transaction_input(&transfer.tx_hash).await? else {
+            return Response::error("Transaction not found on chain", 502);
+        };
+        let (decoded, decode_error) = match decoder::active_decoder().decode(&calldata) {
+            Ok(d) => (Some(d), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Some(Payload {
+            calldata: format!("0x{}", hex::encode(&calldata[..])),
+            decoded,
+            decode_error,
+        })
+    } else {
+        None
+    };
+
+    Response::from_json(&TransferResponse { transfer, payload })
+}
+
+/// The columns of a stored transfer that only /transfer/:tx_hash reports.
+#[derive(Deserialize)]
+struct IndexingRecord {
+    price: Option<f64>,
+    indexed_at: Option<u64>,
+    #[serde(deserialize_with = "int_as_bool")]
+    payload_checked: bool,
+}
+
+/// GET /transfer/:tx_hash is for checking on a single bridge transaction. It returns the stored
+/// transfer with the price it was valued at, where it was routed to and when it was stored, or a
+/// JSON 404 with the last indexed block if the transaction hasn't been indexed.
+pub(crate) async fn lookup(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let tx_hash = ctx.param("tx_hash").unwrap().to_lowercase();
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        &format!("{SELECT_TRANSFERS} WHERE tf.tx_hash = ?1 OR tf.extrinsic_hash = ?1"),
+        &tx_hash
+    )?;
+    let result = statement.all().await?;
+    let (Some(transfer), Some(record)) = (
+        result.results::<TransferDetail>()?.pop(),
+        result.results::<IndexingRecord>()?.pop(),
+    ) else {
+        let last_indexed_block = d1
+            .prepare("SELECT MAX(block_num) AS most_recent_block FROM TransfersForward")
+            .first::<u64>(Some("most_recent_block"))
+            .await?;
+        let not_indexed = TransferNotIndexed {
+            error: "not_indexed",
+            tx_hash,
+            last_indexed_block,
+            message: "No MRL transfer with this hash has been indexed. If it is one, it may be in \
+                      a block after the last indexed one",
+        };
+        return Ok(Response::from_json(&not_indexed)?.with_status(404));
+    };
+
+    let lookup = TransferLookup {
+        price_usd: record.price,
+        destination: Destination {
+            chain_id: transfer.to_chain,
+            chain_name: transfer.to_chain_name.clone(),
+            account: transfer.dest_account.clone(),
+            payload_decoded: record.payload_checked,
+        },
+        indexed_at: record.indexed_at,
+        transfer,
+    };
+    Response::from_json(&lookup)
+}
+
+async fn find(d1: &D1Database, hash: &str) -> Result<Option<TransferDetail>> {
+    let statement = worker::query!(
+        d1,
+        &format!("{SELECT_TRANSFERS} WHERE tf.tx_hash = ?1 OR tf.extrinsic_hash = ?1"),
+        &hash
+    )?;
+    statement.first::<TransferDetail>(None).await
+}
+
+/// POST /admin/extrinsics/:hash asks Subscan which Ethereum transaction the extrinsic executed,
+/// and stores the extrinsic hash alongside that transfer so GET /transfers/:hash finds it by
+/// either. Returns the transfer.
+pub(crate) async fn resolve_extrinsic(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let hash = ctx.param("hash").unwrap().to_lowercase();
+    if hash.len() != 66 || !hash.starts_with("0x") || hex::decode(&hash[2..]).is_err() {
+        return Response::error("The extrinsic hash must be 32 bytes of hex with 0x", 400);
+    }
+    let Some(subscan) = subscan::SubscanClient::from_env(&ctx.env) else {
+        return Response::error("SUBSCAN_API_KEY isn't set", 501)
+    };
+    let Some(tx_hash) = subscan.ethereum_hash(&hash).await? else {
+        return Response::error("Subscan doesn't know an Ethereum transaction for it", 404)
+    };
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(
+        &d1,
+        "UPDATE TransfersForward SET extrinsic_hash = ?1 WHERE tx_hash = ?2",
+        &hash,
+        &tx_hash
+    )?;
+    let result = statement.run().await?;
+    if !result.success() {
+        let msg = result.error().unwrap_or("No error given".to_string());
+        return Response::error(format!("Failed to store the extrinsic hash: {msg}"), 500);
+    }
+    let Some(transfer) = find(&d1, &tx_hash).await? else {
+        return Response::error(format!("{tx_hash} hasn't been indexed"), 404)
+    };
+    cache::invalidate(&ctx.env).await;
+    Response::from_json(&transfer)
+}
+
+/// Filters shared by /transfers, /transfers/export, /transfers/stream and the GraphQL `transfers`
+/// root.
+#[derive(Default)]
+pub(crate) struct TransferFilter {
+    /// Token contract address or symbol
+    token: Option<String>,
+    to_chain: Option<u32>,
+    /// Unix timestamps, both inclusive
+    from: Option<u64>,
+    to: Option<u64>,
+    /// Sender or destination account, taken from the path rather than the query
+    pub(crate) address: Option<String>,
+}
+
+impl TransferFilter {
+    /// Takes a query parameter if it's a filter. Returns whether it was one, or why its value is
+    /// invalid.
+    pub(crate) fn apply(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> std::result::Result<bool, &'static str> {
+        match key {
+            "token" => self.token = Some(value.to_string()),
+            "to_chain" => {
+                let Ok(c) = value.parse::<u32>() else {
+                    return Err("to_chain must be a chain id");
+                };
+                self.to_chain = Some(c);
+            }
+            "from" | "to" => {
+                let Ok(t) = value.parse::<u64>() else {
+                    return Err("from and to must be unix timestamps");
+                };
+                if key == "from" {
+                    self.from = Some(t);
+                } else {
+                    self.to = Some(t);
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// SQL conditions for the filter, with their values pushed onto `bindings`.
+    fn conditions(&self, bindings: &mut Vec<JsValue>) -> Vec<String> {
+        let mut conditions = vec![];
+        if let Some(token) = &self.token {
+            bindings.push(token.clone().into());
+            let n = bindings.len();
+            conditions.push(format!(
+                "(LOWER(tf.token_addr) = LOWER(?{n}) OR t.token_sym = ?{n})"
+            ));
+        }
+        if let Some(address) = &self.address {
+            bindings.push(address.clone().into());
+            let n = bindings.len();
+            conditions.push(format!(
+                "(LOWER(tf.sender) = LOWER(?{n}) OR LOWER(tf.dest_account) = LOWER(?{n}))"
+            ));
+        }
+        if let Some(to_chain) = self.to_chain {
+            bindings.push((to_chain as f64).into());
+            conditions.push(format!("tf.to_chain = ?{}", bindings.len()));
+        }
+        if let Some(from) = self.from {
+            bindings.push((from as f64).into());
+            conditions.push(format!("tf.timestamp >= ?{}", bindings.len()));
+        }
+        if let Some(to) = self.to {
+            bindings.push((to as f64).into());
+            conditions.push(format!("tf.timestamp <= ?{}", bindings.len()));
+        }
+        conditions
+    }
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+/// GET /transfers lists stored transfers, newest first. Accepts `token`, `to_chain`, `from` and
+/// `to`, and is paged by block number and hash.
+pub(crate) async fn list(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    list_filtered(req, ctx, TransferFilter::default()).await
+}
+
+/// GET /transfers/byAddress/:addr lists the transfers an address sent or received, with the same
+/// filters and paging as /transfers. Only forward transfers are indexed so far.
+pub(crate) async fn by_address(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let filter = TransferFilter {
+        address: ctx.param("addr").cloned(),
+        ..Default::default()
+    };
+    list_filtered(req, ctx, filter).await
+}
+
+async fn list_filtered(
+    req: Request,
+    ctx: RouteContext<()>,
+    mut filter: TransferFilter,
+) -> Result<Response> {
+    let mut page = PageParams::<(u64, String)>::default();
+    for (k, v) in req.url()?.query_pairs() {
+        let taken = match filter.apply(&k, &v) {
+            Ok(false) => page.apply(&k, &v),
+            taken => taken,
+        };
+        match taken {
+            Ok(true) => {}
+            Ok(false) => return Response::error("Unexpected query parameter", 400),
+            Err(msg) => return Response::error(msg, 400),
+        }
+    }
+
+    let d1 = ctx.env.d1("DB")?;
+    match query_page(&d1, &filter, &page).await {
+        Ok(x) => Response::from_json(&x),
+        Err(e) => Response::error(e.to_string(), 500),
+    }
+}
+
+/// A page of the transfers matching the filter, newest first.
+pub(crate) async fn query_page(
+    d1: &D1Database,
+    filter: &TransferFilter,
+    page: &PageParams<(u64, String)>,
+) -> Result<Page<TransferDetail>> {
+    let mut bindings = vec![];
+    let mut conditions = filter.conditions(&mut bindings);
+    if let Some((block_num, tx_hash)) = &page.cursor {
+        bindings.push((*block_num as f64).into());
+        bindings.push(tx_hash.clone().into());
+        let (b, h) = (bindings.len() - 1, bindings.len());
+        conditions.push(format!(
+            "(tf.block_num < ?{b} OR (tf.block_num = ?{b} AND tf.tx_hash < ?{h}))"
+        ));
+    }
+    bindings.push(page.query_limit().into());
+    let query = format!(
+        "{SELECT_TRANSFERS} {} ORDER BY tf.block_num DESC, tf.tx_hash DESC LIMIT ?{}",
+        where_clause(&conditions),
+        bindings.len()
+    );
+
+    let result = d1.prepare(query).bind(&bindings)?.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+
+    Ok(pagination::paginate(
+        result.results::<TransferDetail>()?,
+        page.limit,
+        |t| (t.block_num, t.tx_hash.clone()),
+    ))
+}
+
+/// The stored transfers among `hashes` that match the filter, oldest first.
+pub(crate) async fn find_all(
+    d1: &D1Database,
+    filter: &TransferFilter,
+    hashes: &[String],
+) -> Result<Vec<TransferDetail>> {
+    let mut transfers = vec![];
+    for chunk in hashes.chunks(MAX_HASHES_PER_QUERY) {
+        let mut bindings = vec![];
+        let mut conditions = filter.conditions(&mut bindings);
+        let placeholders: Vec<String> = chunk
+            .iter()
+            .map(|hash| {
+                bindings.push(hash.clone().into());
+                format!("?{}", bindings.len())
+            })
+            .collect();
+        conditions.push(format!("tf.tx_hash IN ({})", placeholders.join(", ")));
+        let query = format!(
+            "{SELECT_TRANSFERS} {} ORDER BY tf.block_num ASC, tf.tx_hash ASC",
+            where_clause(&conditions)
+        );
+
+        let result = d1.prepare(query).bind(&bindings)?.all().await?;
+        if !result.success() {
+            return Err(worker::Error::JsError(
+                result.error().unwrap_or("No error given".to_string()),
+            ));
+        }
+        transfers.extend(result.results::<TransferDetail>()?);
+    }
+    Ok(transfers)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "csv" => Some(ExportFormat::Csv),
+            "ndjson" => Some(ExportFormat::Ndjson),
+            _ => None,
+        }
+    }
+
+    fn line(&self, transfer: &TransferDetail) -> Result<String> {
+        match self {
+            ExportFormat::Csv => Ok(csv_line(transfer)),
+            ExportFormat::Ndjson => serde_json::to_string(transfer)
+                .map(|l| l + "\n")
+                .map_err(|e| worker::Error::JsError(e.to_string())),
+        }
+    }
+}
+
+fn csv_line(t: &TransferDetail) -> String {
+    let fields = [
+        t.tx_hash.clone(),
+        t.token_addr.clone(),
+        csv_field(&t.token_name),
+        csv_field(&t.token_sym),
+        t.decimals.to_string(),
+        t.token_count.clone(),
+        t.usd.to_string(),
+        t.block_num.to_string(),
+        t.timestamp.to_string(),
+        t.timestamp_iso.clone(),
+        t.to_chain.to_string(),
+        t.price_uncertain.to_string(),
+        t.dest_account.clone().unwrap_or_default(),
+        t.timestamp_corrected.to_string(),
+        t.sender.clone().unwrap_or_default(),
+        t.extrinsic_hash.clone().unwrap_or_default(),
+        t.usd_min.map(|u| u.to_string()).unwrap_or_default(),
+        t.usd_max.map(|u| u.to_string()).unwrap_or_default(),
+        t.gas_fee.clone().unwrap_or_default(),
+        t.bridge_fee.clone().unwrap_or_default(),
+        t.to_chain_name
+            .as_deref()
+            .map(csv_field)
+            .unwrap_or_default(),
+    ];
+    fields.join(",") + "\n"
+}
+
+// Token names and symbols come from the token contracts, so they can contain anything
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+struct ExportState {
+    d1: D1Database,
+    filter: TransferFilter,
+    format: ExportFormat,
+    /// Block number and hash of the last row sent, rows are exported in that order
+    cursor: Option<(u64, String)>,
+    header_sent: bool,
+    done: bool,
+}
+
+/// GET /transfers/export?format=csv|ndjson streams every transfer matching the /transfers filters,
+/// oldest first. Rows are read from D1 a page at a time as the client consumes them.
+pub(crate) async fn export(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let mut filter = TransferFilter::default();
+    let mut format = ExportFormat::Csv;
+    for (k, v) in req.url()?.query_pairs() {
+        match filter.apply(&k, &v) {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(msg) => return Response::error(msg, 400),
+        }
+        if k != "format" {
+            return Response::error("Unexpected query parameter", 400);
+        }
+        let Some(f) = ExportFormat::parse(&v) else {
+            return Response::error("format must be csv or ndjson", 400);
+        };
+        format = f;
+    }
+
+    let state = ExportState {
+        d1: ctx.env.d1("DB")?,
+        filter,
+        format,
+        cursor: None,
+        header_sent: false,
+        done: false,
+    };
+    let body = stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+        let chunk = next_chunk(&mut state).await;
+        if chunk.is_err() {
+            state.done = true;
+        }
+        match chunk {
+            Ok(c) if c.is_empty() => None,
+            c => Some((c.map(String::into_bytes), state)),
+        }
+    });
+
+    let mut headers = Headers::new();
+    let (content_type, extension) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+    headers.set("Content-Type", content_type)?;
+    headers.set(
+        "Content-Disposition",
+        &format!("attachment; filename=\"transfers.{extension}\""),
+    )?;
+    Ok(Response::from_stream(body)?.with_headers(headers))
+}
+
+/// Reads the page after the cursor and renders it. Empty once there is nothing left to send.
+async fn next_chunk(state: &mut ExportState) -> Result<String> {
+    let mut bindings = vec![];
+    let mut conditions = state.filter.conditions(&mut bindings);
+    if let Some((block_num, tx_hash)) = &state.cursor {
+        bindings.push((*block_num as f64).into());
+        bindings.push(tx_hash.clone().into());
+        let (b, h) = (bindings.len() - 1, bindings.len());
+        conditions.push(format!(
+            "(tf.block_num > ?{b} OR (tf.block_num = ?{b} AND tf.tx_hash > ?{h}))"
+        ));
+    }
+    bindings.push(EXPORT_PAGE_SIZE.into());
+    let query = format!(
+        "{SELECT_TRANSFERS} {} ORDER BY tf.block_num ASC, tf.tx_hash ASC LIMIT ?{}",
+        where_clause(&conditions),
+        bindings.len()
+    );
+
+    let result = state.d1.prepare(query).bind(&bindings)?.all().await?;
+    if !result.success() {
+        return Err(worker::Error::JsError(
+            result.error().unwrap_or("No error given".to_string()),
+        ));
+    }
+    let page = result.results::<TransferDetail>()?;
+    if page.len() < EXPORT_PAGE_SIZE as usize {
+        state.done = true;
+    }
+    if let Some(last) = page.last() {
+        state.cursor = Some((last.block_num, last.tx_hash.clone()));
+    }
+
+    let mut chunk = String::new();
+    if !state.header_sent && state.format == ExportFormat::Csv {
+        chunk.push_str(CSV_HEADER);
+    }
+    state.header_sent = true;
+    for transfer in &page {
+        chunk.push_str(&state.format.line(transfer)?);
+    }
+    Ok(chunk)
+}
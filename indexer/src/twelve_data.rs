@@ -1,6 +1,11 @@
+use async_trait::async_trait;
 use serde::Deserialize;
 use worker::{ Result, Date, DateInit, console_log};
 
+use mrl_indexer_core::prices::{base_symbol, PriceProvider};
+pub(crate) use mrl_indexer_core::prices::TimeSeries;
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct TwelveDataTimeSeriesRaw {
     meta: TwelveDataTimeMeta,
@@ -8,6 +13,7 @@ struct TwelveDataTimeSeriesRaw {
     status: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct TwelveDataTimeMeta {
     symbol: String,
@@ -27,35 +33,17 @@ struct TimeSeriesRaw {
     close: String,
 }
 
-#[derive(Default)]
-pub(crate) struct TimeSeries {
-    pub(crate) timestamp: u64,
-    pub(crate) open: f32,
-    pub(crate) high: f32,
-    pub(crate) low: f32,
-    // We really only care about the close
-    pub(crate) close: f32,
-}
-
-impl TimeSeries {
-    pub(crate) fn estimate(&self) -> f32 {
-        (self.open + self.close) / 2.
-    }
-}
-
-pub(crate) async fn get_twelve_data(api_key: String, symbol: String) -> Result<Vec<TimeSeries>> {
-    // Ensure that the symbol string isn't a wrapped variant. Will fail if there is ever a normal coin that starts with "W"
-    let sanitized_symbol = if symbol.starts_with("W") {
-        let mut c = symbol.chars();
-        c.next();
-        c.as_str().to_owned()
-    } else {
-        symbol.clone()
-    };
+pub(crate) async fn get_twelve_data(
+    api_key: String,
+    symbol: String,
+    quote: &str,
+) -> Result<Vec<TimeSeries>> {
+    // Ensure that the symbol string isn't a wrapped variant or an XC-20
+    let sanitized_symbol = base_symbol(&symbol);
 
     // Send endpoint
-    console_log!("Getting data from twelvedata for symbol {sanitized_symbol}/USD. Input was {symbol}");
-    let endpoint = format!("https://api.twelvedata.com/time_series?apikey={api_key}&symbol={sanitized_symbol}/USD&interval=2h&outputsize=5000");
+    console_log!("Getting data from twelvedata for symbol {sanitized_symbol}/{quote}. Input was {symbol}");
+    let endpoint = format!("https://api.twelvedata.com/time_series?apikey={api_key}&symbol={sanitized_symbol}/{quote}&interval=2h&outputsize=5000");
 
     // Get the response
     let twelve_key_response = reqwest::get(endpoint)
@@ -71,7 +59,7 @@ pub(crate) async fn get_twelve_data(api_key: String, symbol: String) -> Result<V
             twelve_key_response.status
         )));
     }
-    else if twelve_key_response.values.len() == 0 {
+    else if twelve_key_response.values.is_empty() {
         return Err(worker::Error::JsError(
             "Error: TwelveData returned no data!".to_owned()
         ));
@@ -94,3 +82,22 @@ pub(crate) async fn get_twelve_data(api_key: String, symbol: String) -> Result<V
 
     Ok(data)
 }
+
+/// Twelve Data, which needs an API key.
+pub(crate) struct TwelveData {
+    pub(crate) api_key: String,
+    pub(crate) quote: String,
+}
+
+#[async_trait(?Send)]
+impl PriceProvider for TwelveData {
+    fn name(&self) -> &str {
+        "Twelve Data"
+    }
+
+    async fn time_series(&self, symbol: &str) -> std::result::Result<Vec<TimeSeries>, String> {
+        get_twelve_data(self.api_key.clone(), symbol.to_string(), &self.quote)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,41 @@
+use worker::{console_log, D1Database, Result};
+
+pub(crate) use mrl_indexer_core::usd::Usd;
+
+use crate::{batch_with_retry, timestamps::ColumnInfo};
+
+/// USD used to be stored as REAL dollars in `usd`, `usd_min` and `usd_max`. The first migration
+/// after the change moves them into the INTEGER cent columns and drops them.
+pub(crate) async fn convert_to_cents(db: &D1Database) -> Result<()> {
+    let columns = db
+        .prepare("PRAGMA table_info(TransfersForward)")
+        .all()
+        .await?
+        .results::<ColumnInfo>()?;
+    let has = |name: &str| columns.iter().any(|c| c.name == name);
+    if !has("usd") {
+        return Ok(());
+    }
+
+    let converted = [
+        ("usd", "usd_cents"),
+        ("usd_min", "usd_min_cents"),
+        ("usd_max", "usd_max_cents"),
+    ]
+    .into_iter()
+    .filter(|(dollars, _)| has(dollars));
+    let mut statements = vec![];
+    let mut drops = vec![];
+    for (dollars, cents) in converted {
+        statements.push(format!(
+            "UPDATE TransfersForward SET {cents} = CAST(ROUND({dollars} * 100) AS INTEGER)"
+        ));
+        drops.push(format!(
+            "ALTER TABLE TransfersForward DROP COLUMN {dollars}"
+        ));
+    }
+    statements.extend(drops);
+    batch_with_retry(db, "USD conversion", &statements).await?;
+    console_log!("Converted TransfersForward USD to integer cents");
+    Ok(())
+}
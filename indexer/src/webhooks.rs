@@ -0,0 +1,151 @@
+use serde::Serialize;
+use worker::{D1Database, Date, Env, Request, Response, Result, RouteContext};
+
+use crate::{
+    admin, batch_with_retry,
+    schemas::{CreatedWebhook, NewWebhook, Webhook, WebhookTestReport},
+    signing,
+    usd::Usd,
+    TransferForward,
+};
+
+// Only the start of a receiver's reply is echoed back
+const MAX_ECHOED_BODY: usize = 1000;
+// The worker secret webhook secrets are derived from
+const WEBHOOK_KEY_SECRET: &str = "WEBHOOK_KEY";
+
+#[derive(Serialize)]
+struct WebhookEvent<'a> {
+    event: &'a str,
+    webhook_id: u32,
+    sent_at: u64,
+    transfer: TransferForward,
+}
+
+fn webhook_key(env: &Env) -> Option<String> {
+    env.secret(WEBHOOK_KEY_SECRET).ok().map(|k| k.to_string())
+}
+
+/// The secret events sent to webhook `id` are signed with. It is derived from the WEBHOOK_KEY
+/// secret rather than stored, so the only place it ever shows is the registration's response.
+fn secret(key: &str, id: u32) -> Result<String> {
+    signing::sign(key.as_bytes(), format!("webhook:{id}").as_bytes())
+}
+
+/// Blanks the secrets webhooks registered before secrets were derived were stored with, so that
+/// none are left in plaintext. Their events are signed with derived secrets from then on, so those
+/// webhooks have to be registered again.
+pub(crate) async fn forget_stored_secrets(db: &D1Database) -> Result<()> {
+    let statements = ["UPDATE Webhooks SET secret = '' WHERE secret != ''".to_string()];
+    batch_with_retry(db, "Stored webhook secret removal", &statements).await?;
+    Ok(())
+}
+
+/// POST /admin/webhooks with `{ "url": ... }` registers a webhook receiver, returning the secret
+/// its events will be signed with.
+pub(crate) async fn register(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Some(key) = webhook_key(&ctx.env) else {
+        return Response::error("WEBHOOK_KEY isn't set", 501)
+    };
+    let Ok(new_webhook) = req.json::<NewWebhook>().await else {
+        return Response::error("Expected a JSON body with url", 400);
+    };
+
+    let d1 = ctx.env.d1("DB")?;
+    // The secret column is left empty, since secrets are derived instead
+    let statement = worker::query!(
+        &d1,
+        "INSERT INTO Webhooks (url, secret) VALUES (?1, '') RETURNING id, url",
+        &new_webhook.url
+    )?;
+    match statement.first::<Webhook>(None).await? {
+        Some(webhook) => Response::from_json(&CreatedWebhook {
+            secret: secret(&key, webhook.id)?,
+            id: webhook.id,
+            url: webhook.url,
+        }),
+        None => Response::error("Error when registering webhook", 500),
+    }
+}
+
+/// POST /admin/webhooks/:id/test sends a synthetic, signed transfer event to a registered webhook
+/// and reports how the receiver responded.
+pub(crate) async fn test(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !admin::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+    let Some(Ok(id)) = ctx.param("id").map(|id| id.parse::<u32>()) else {
+        return Response::error("Webhook id must be a number", 400);
+    };
+    let Some(key) = webhook_key(&ctx.env) else {
+        return Response::error("WEBHOOK_KEY isn't set", 501)
+    };
+
+    let d1 = ctx.env.d1("DB")?;
+    let statement = worker::query!(&d1, "SELECT id, url FROM Webhooks WHERE id = ?1", id)?;
+    let Some(webhook) = statement.first::<Webhook>(None).await? else {
+        return Response::error("Webhook not found", 404);
+    };
+
+    let now = Date::now().as_millis() / 1000;
+    let event = WebhookEvent {
+        event: "test",
+        webhook_id: webhook.id,
+        sent_at: now,
+        transfer: TransferForward {
+            tx_hash: format!("0x{}", "0".repeat(64)),
+            token_addr: format!("0x{}", "0".repeat(40)),
+            token_count: 1_000_000_000_000_000_000,
+            usd: Usd(100),
+            usd_min: Usd(100),
+            usd_max: Usd(100),
+            block_num: 0,
+            timestamp: now,
+            to_chain: 1000,
+            price_uncertain: false,
+            dest_account: None,
+            timestamp_corrected: false,
+            price: None,
+        },
+    };
+    let body = serde_json::to_string(&event)?;
+    let signature = signing::sign(secret(&key, webhook.id)?.as_bytes(), body.as_bytes())?;
+
+    let res = reqwest::Client::new()
+        .post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header(signing::SIGNATURE_HEADER, format!("sha256={signature}"))
+        .body(body)
+        .send()
+        .await;
+    let report = match res {
+        Ok(r) => {
+            let status = r.status();
+            let response_body = r
+                .text()
+                .await
+                .ok()
+                .map(|b| b.chars().take(MAX_ECHOED_BODY).collect());
+            WebhookTestReport {
+                webhook_id: webhook.id,
+                url: webhook.url,
+                delivered: status.is_success(),
+                status: Some(status.as_u16()),
+                response_body,
+                error: None,
+            }
+        }
+        Err(e) => WebhookTestReport {
+            webhook_id: webhook.id,
+            url: webhook.url,
+            delivered: false,
+            status: None,
+            response_body: None,
+            error: Some(e.to_string()),
+        },
+    };
+    Response::from_json(&report)
+}